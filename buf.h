@@ -4,8 +4,6 @@ struct buf {
   uint blockno;
   struct sleeplock lock;
   uint refcnt;
-  struct buf *prev; // LRU cache list
-  struct buf *next;
   struct buf *qnext; // disk queue
   uchar data[BSIZE];
 };
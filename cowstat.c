@@ -0,0 +1,43 @@
+/**
+ * @file cowstat.c
+ * @brief User-level program reporting copy-on-write fork counters.
+ */
+#include "types.h"
+#include "user.h"
+#include "cowstat.h"
+
+/**
+ * @brief Entry point for the cowstat utility.
+ *
+ * With no arguments prints the counters since boot. Given a command, runs
+ * it and prints how much the counters moved while it ran, e.g.
+ * `cowstat forktest` or `cowstat usertests`.
+ */
+int
+main(int argc, char *argv[])
+{
+  struct cowstat before, after;
+
+  if(cowstat(&before) < 0){
+    printf(2, "cowstat: system call failed\n");
+    exit();
+  }
+  if(argc < 2){
+    printf(1, "faults %d copies %d shared %d saved %d\n",
+           before.faults, before.copies, before.shared, before.saved);
+    exit();
+  }
+
+  if(fork() == 0){
+    exec(argv[1], argv + 1);
+    printf(2, "cowstat: exec %s failed\n", argv[1]);
+    exit();
+  }
+  wait();
+
+  cowstat(&after);
+  printf(1, "%s: faults %d copies %d shared %d saved %d\n", argv[1],
+         after.faults - before.faults, after.copies - before.copies,
+         after.shared - before.shared, after.saved - before.saved);
+  exit();
+}
@@ -0,0 +1,7 @@
+// Copy-on-write fork counters, filled in by the cowstat system call.
+struct cowstat {
+  uint faults;  // write faults on copy-on-write pages
+  uint copies;  // faults that had to copy the page
+  uint shared;  // pages fork shared instead of copying
+  uint saved;   // shared pages never copied (shared - copies)
+};
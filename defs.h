@@ -3,6 +3,7 @@ struct buf;
 struct context;
 struct file;
 struct inode;
+struct log;
 struct pipe;
 struct proc;
 struct rtcdate;
@@ -11,8 +12,7 @@ struct sleeplock;
 struct stat;
 struct superblock;
 
-// bio.c
-void            binit(void);
+// bio.rs
 struct buf*     bread(uint, uint);
 void            brelse(struct buf*);
 void            bwrite(struct buf*);
@@ -23,9 +23,22 @@ void            cprintf(char*, ...);
 void            consoleintr(int(*)(void));
 void            panic(char*) __attribute__((noreturn));
 
+// cow.rs
+pde_t*          copyuvm(pde_t*, uint);
+void            kref_alloc(uint);
+uint            kref_put(uint);
+
 // exec.c
 int             exec(char*, char**);
 
+// fault.rs
+int             pgfault(uint, uint);
+
+// fpu_state.rs
+void            fpuinit(void);
+void            fpufault(void);
+void            fpureset(struct proc*);
+
 // file.c
 struct file*    filealloc(void);
 void            fileclose(struct file*);
@@ -37,23 +50,27 @@ int             filewrite(struct file*, char*, int n);
 
 // fs.c
 void            readsb(int dev, struct superblock *sb);
-int             dirlink(struct inode*, char*, uint);
-struct inode*   dirlookup(struct inode*, char*, uint*);
-struct inode*   ialloc(uint, short);
-struct inode*   idup(struct inode*);
-void            iinit(int dev);
-void            ilock(struct inode*);
-void            iput(struct inode*);
-void            iunlock(struct inode*);
-void            iunlockput(struct inode*);
-void            iupdate(struct inode*);
+uint            balloc(uint);
+void            bfree(uint, uint);
+struct inode*   iget(uint, uint);
+void            iinit(void);
 int             namecmp(const char*, const char*);
-struct inode*   namei(char*);
-struct inode*   nameiparent(char*, char*);
-int             readi(struct inode*, char*, uint, uint);
-void            stati(struct inode*, struct stat*);
-int             writei(struct inode*, char*, uint, uint);
-
+int             xv6fs_busy(uint, struct inode*);
+int             xv6fs_dirlink(struct inode*, char*, uint);
+struct inode*   xv6fs_dirlookup(struct inode*, char*, uint*);
+struct inode*   xv6fs_ialloc(uint, short);
+struct inode*   xv6fs_idup(struct inode*);
+void            xv6fs_ilock(struct inode*);
+void            xv6fs_iput(struct inode*);
+void            xv6fs_iunlock(struct inode*);
+void            xv6fs_iupdate(struct inode*);
+int             xv6fs_readi(struct inode*, char*, uint, uint);
+void            xv6fs_stati(struct inode*, struct stat*);
+int             xv6fs_writei(struct inode*, char*, uint, uint);
+
+// fs.rs
+uint            bmap(struct inode*, uint);
+void            itrunc(struct inode*);
 
 // ide.c
 void            ideinit(void);
@@ -79,12 +96,16 @@ void            cmostime(struct rtcdate *r);
 int             lapicid(void);
 extern volatile uint*    lapic;
 void            lapiceoi(void);
+void            lapicipi(int);
 void            lapicinit(void);
 void            lapicstartap(uchar, uint);
 void            microdelay(int);
 
 // log.c
-void            initlog(int dev);
+void            loginit(void);
+struct log*     initlog(int dev, struct superblock *sb);
+void            closelog(struct log*);
+int             logdirty(int dev, struct superblock *sb);
 void            log_write(struct buf*);
 void            begin_op();
 void            end_op();
@@ -93,10 +114,18 @@ void            end_op();
 extern int      ismp;
 void            mpinit(void);
 
+// namei.rs
+struct inode*   namei(char*);
+struct inode*   namei_nofollow(char*);
+struct inode*   nameiparent(char*, char*);
+
 // picirq.c
 void            picenable(int);
 void            picinit(void);
 
+// mmap.rs
+void            munmap_all(struct proc*);
+
 // pipe.c
 int             pipealloc(struct file**, struct file**);
 void            pipeclose(struct pipe*, int);
@@ -115,7 +144,6 @@ struct proc*    myproc();
 void            pinit(void);
 void            procdump(void);
 void            scheduler(void) __attribute__((noreturn));
-void            sched(void);
 void            setproc(struct proc*);
 void            sleep(void*, struct spinlock*);
 void            userinit(void);
@@ -127,13 +155,19 @@ void            yield(void);
 void            swtch(struct context**, struct context*);
 
 // spinlock.c
-void            acquire(struct spinlock*);
 void            getcallerpcs(void*, uint*);
+
+// spinlock.rs
+void            acquire(struct spinlock*);
 int             holding(struct spinlock*);
 void            initlock(struct spinlock*, char*);
-void            release(struct spinlock*);
-void            pushcli(void);
 void            popcli(void);
+void            pushcli(void);
+void            release(struct spinlock*);
+
+// lockdep.rs
+void            lockdep_acquire_sleep(void*, char*);
+void            lockdep_release_sleep(void*);
 
 // sleeplock.c
 void            acquiresleep(struct sleeplock*);
@@ -141,6 +175,10 @@ void            releasesleep(struct sleeplock*);
 int             holdingsleep(struct sleeplock*);
 void            initsleeplock(struct sleeplock*, char*);
 
+// simd_integration.rs
+void            kernel_fpu_begin(void);
+void            kernel_fpu_end(void);
+
 // string.c
 int             memcmp(const void*, const void*, uint);
 void*           memmove(void*, const void*, uint);
@@ -153,14 +191,16 @@ char*           strncpy(char*, const char*, int);
 // syscall.c
 int             argint(int, int*);
 int             argptr(int, char**, int);
-int             argstr(int, char**);
+int             argptr_ro(int, char**, int);
+int             argstr(int, char*, int);
 int             fetchint(uint, int*);
-int             fetchstr(uint, char**);
+int             fetchstr(uint, char*, int);
 void            syscall(void);
 
-// trap.c
+// trap.rs
+void            clockintr(void);
 void            idtinit(void);
-extern uint     ticks;
+uint            ticks(void);
 void            tvinit(void);
 
 // uart.rs
@@ -168,21 +208,40 @@ extern void uartinit(void);
 extern void uartintr(void);
 extern void uartputc(int);
 
+// vfs/mod.rs
+int             dirlink(struct inode*, char*, uint);
+struct inode*   dirlookup(struct inode*, char*, uint*);
+struct inode*   idup(struct inode*);
+void            ilock(struct inode*);
+void            iput(struct inode*);
+void            iunlock(struct inode*);
+void            iunlockput(struct inode*);
+void            iupdate(struct inode*);
+int             readi(struct inode*, char*, uint, uint);
+void            stati(struct inode*, struct stat*);
+int             writei(struct inode*, char*, uint, uint);
+
+// vfs/xv6fs.rs
+struct superblock* xv6fs_sb(uint);
+
 // vm.c
-void            seginit(void);
 void            kvmalloc(void);
-pde_t*          setupkvm(void);
 char*           uva2ka(pde_t*, char*);
-int             allocuvm(pde_t*, uint, uint);
-int             deallocuvm(pde_t*, uint, uint);
-void            freevm(pde_t*);
 void            inituvm(pde_t*, char*, uint);
 int             loaduvm(pde_t*, char*, struct inode*, uint, uint);
-pde_t*          copyuvm(pde_t*, uint);
-void            switchuvm(struct proc*);
 void            switchkvm(void);
 int             copyout(pde_t*, uint, void*, uint);
 void            clearpteu(pde_t *pgdir, char *uva);
 
+// vm.rs
+void            seginit(void);
+void            switchuvm(struct proc*);
+pde_t*          setupkvm(void);
+uint*           walkpgdir(pde_t*, const void*, int);
+int             mappages(pde_t*, void*, uint, uint, int);
+int             allocuvm(pde_t*, uint, uint);
+int             deallocuvm(pde_t*, uint, uint);
+void            freevm(pde_t*);
+
 // number of elements in fixed-size array
 #define NELEM(x) (sizeof(x)/sizeof((x)[0]))
@@ -9,6 +9,7 @@
 #include "spinlock.h"
 #include "sleeplock.h"
 #include "file.h"
+#include "errno.h"
 
 struct devsw devsw[NDEV];
 struct {
@@ -89,7 +90,7 @@ filestat(struct file *f, struct stat *st)
     iunlock(f->ip);
     return 0;
   }
-  return -1;
+  return -EINVAL;
 }
 
 // Read from file f.
@@ -99,7 +100,7 @@ fileread(struct file *f, char *addr, int n)
   int r;
 
   if(f->readable == 0)
-    return -1;
+    return -EBADF;
   if(f->type == FD_PIPE)
     return piperead(f->pipe, addr, n);
   if(f->type == FD_INODE){
@@ -120,17 +121,18 @@ filewrite(struct file *f, char *addr, int n)
   int r;
 
   if(f->writable == 0)
-    return -1;
+    return -EBADF;
   if(f->type == FD_PIPE)
     return pipewrite(f->pipe, addr, n);
   if(f->type == FD_INODE){
     // write a few blocks at a time to avoid exceeding
     // the maximum log transaction size, including
-    // i-node, indirect block, allocation blocks,
+    // i-node, up to 5 indirect blocks and their
+    // allocation blocks, data and allocation blocks,
     // and 2 blocks of slop for non-aligned writes.
     // this really belongs lower down, since writei()
     // might be writing a device like the console.
-    int max = ((MAXOPBLOCKS-1-1-2) / 2) * 512;
+    int max = ((MAXOPBLOCKS-1-2*5-2) / 2) * 512;
     int i = 0;
     while(i < n){
       int n1 = n - i;
@@ -150,7 +152,7 @@ filewrite(struct file *f, char *addr, int n)
         panic("short filewrite");
       i += r;
     }
-    return i == n ? n : -1;
+    return i == n ? n : r;
   }
   panic("filewrite");
 }
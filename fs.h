@@ -19,11 +19,22 @@ struct superblock {
   uint logstart;     // Block number of first log block
   uint inodestart;   // Block number of first inode block
   uint bmapstart;    // Block number of first free map block
+  uint magic;        // FSMAGIC; 0 on version 1 images
+  uint version;      // On-disk format version
 };
 
-#define NDIRECT 12
+#define FSMAGIC   0x10203040
+#define FSVERSION 2
+
+// Version 2 inodes hold NDIRECT direct block addresses followed by
+// the roots of a singly-, a doubly- and a triply-indirect tree.
+// Version 1 inodes held 12 direct addresses and one indirect block;
+// the kernel still reads them (see src/fs.rs), but never writes them.
+#define NDIRECT 10
+#define NADDRS (NDIRECT+3)
 #define NINDIRECT (BSIZE / sizeof(uint))
-#define MAXFILE (NDIRECT + NINDIRECT)
+#define MAXFILE (NDIRECT + NINDIRECT + NINDIRECT*NINDIRECT + \
+                 NINDIRECT*NINDIRECT*NINDIRECT)
 
 
 // On-disk inode structure
@@ -33,7 +44,7 @@ struct dinode {
   short minor;          // Minor device number (T_DEV only)
   short nlink;          // Number of links to inode in file system
   uint size;            // Size of file (bytes)
-  uint addrs[NDIRECT+1];   // Data block addresses
+  uint addrs[NADDRS];   // Data block addresses
 };
 
 // Inodes per block.
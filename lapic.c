@@ -9,6 +9,7 @@
 #include "traps.h"
 #include "mmu.h"
 #include "x86.h"
+#include "proc.h"
 
 // Local APIC registers, divided by 4 for use as uint[] indices.
 #define ID      (0x0020/4)   // ID
@@ -117,6 +118,19 @@ lapiceoi(void)
     lapicw(EOI, 0);
 }
 
+// Interrupt CPU c, an index in cpus, so that it leaves hlt
+// and looks at its run queue again.
+void
+lapicipi(int c)
+{
+  if(!lapic)
+    return;
+  lapicw(ICRHI, cpus[c].apicid<<24);
+  lapicw(ICRLO, FIXED | ASSERT | (T_IRQ0 + IRQ_WAKEUP));
+  while(lapic[ICRLO] & DELIVS)
+    ;
+}
+
 // Spin for a given number of microseconds.
 // On real hardware would want to tune this dynamically.
 void
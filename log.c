@@ -43,37 +43,115 @@ struct log {
   int outstanding; // how many FS sys calls are executing.
   int committing;  // in commit(), please wait.
   int dev;
+  int used;        // logging a mounted file system.
   struct logheader lh;
 };
-struct log log;
 
-static void recover_from_log(void);
-static void commit();
+// One log per mounted xv6 file system, held by its mount in
+// src/vfs/xv6fs.rs. A system call may touch several file systems
+// (a path can cross a mount point), so begin_op() and end_op()
+// bracket an operation on every log. Logs are only added and
+// removed while no operation is in progress.
+struct {
+  struct spinlock lock;
+  int ops;         // operations between begin_op() and end_op().
+  int changing;    // a log is being added or removed.
+  struct log log[NMOUNT];
+} logs;
+
+static void recover_from_log(struct log*);
+static void commit(struct log*);
 
 void
-initlog(int dev)
+loginit(void)
 {
   if (sizeof(struct logheader) >= BSIZE)
-    panic("initlog: too big logheader");
+    panic("loginit: too big logheader");
+  initlock(&logs.lock, "logs");
+}
+
+// Wait until no operation is in progress, and hold new ones
+// off until resume().
+static void
+quiesce(void)
+{
+  acquire(&logs.lock);
+  while(logs.changing)
+    sleep(&logs, &logs.lock);
+  logs.changing = 1;
+  while(logs.ops > 0)
+    sleep(&logs, &logs.lock);
+  release(&logs.lock);
+}
+
+static void
+resume(void)
+{
+  acquire(&logs.lock);
+  logs.changing = 0;
+  wakeup(&logs);
+  release(&logs.lock);
+}
+
+// Recover the log of the file system on dev described by sb,
+// and log its operations from now on.
+// Called when it is mounted, outside any transaction.
+struct log*
+initlog(int dev, struct superblock *sb)
+{
+  struct log *log;
+
+  quiesce();
+  for(log = logs.log; log < &logs.log[NMOUNT]; log++)
+    if(!log->used)
+      break;
+  if(log == &logs.log[NMOUNT])
+    panic("initlog: no log");
+  initlock(&log->lock, "log");
+  log->start = sb->logstart;
+  log->size = sb->nlog;
+  log->dev = dev;
+  log->outstanding = 0;
+  log->committing = 0;
+  recover_from_log(log);
+  log->used = 1;
+  resume();
+  return log;
+}
+
+// Whether the log of the file system on dev holds a committed
+// transaction not yet installed; for file systems mounted
+// read-only, which are not logged.
+int
+logdirty(int dev, struct superblock *sb)
+{
+  struct buf *buf = bread(dev, sb->logstart);
+  int n = ((struct logheader *) (buf->data))->n;
+  brelse(buf);
+  return n != 0;
+}
 
-  struct superblock sb;
-  initlock(&log.lock, "log");
-  readsb(dev, &sb);
-  log.start = sb.logstart;
-  log.size = sb.nlog;
-  log.dev = dev;
-  recover_from_log();
+// Stop logging a file system being unmounted.
+// Called outside any transaction, so its log is committed.
+void
+closelog(struct log *log)
+{
+  quiesce();
+  if(log->lh.n != 0)
+    panic("closelog: uncommitted");
+  log->used = 0;
+  resume();
 }
 
 // Copy committed blocks from log to their home location
 static void
-install_trans(void)
+install_trans(struct log *log)
 {
   int tail;
 
-  for (tail = 0; tail < log.lh.n; tail++) {
-    struct buf *lbuf = bread(log.dev, log.start+tail+1); // read log block
-    struct buf *dbuf = bread(log.dev, log.lh.block[tail]); // read dst
+  for (tail = 0; tail < log->lh.n; tail++) {
+    struct buf *lbuf = bread(log->dev, log->start+tail+1); // read log block
+    struct buf *dbuf = bread(log->dev, log->lh.block[tail]); // read dst
     memmove(dbuf->data, lbuf->data, BSIZE);  // copy block to dst
     bwrite(dbuf);  // write dst to disk
     brelse(lbuf);
@@ -83,14 +161,14 @@ install_trans(void)
 
 // Read the log header from disk into the in-memory log header
 static void
-read_head(void)
+read_head(struct log *log)
 {
-  struct buf *buf = bread(log.dev, log.start);
+  struct buf *buf = bread(log->dev, log->start);
   struct logheader *lh = (struct logheader *) (buf->data);
   int i;
-  log.lh.n = lh->n;
-  for (i = 0; i < log.lh.n; i++) {
-    log.lh.block[i] = lh->block[i];
+  log->lh.n = lh->n;
+  for (i = 0; i < log->lh.n; i++) {
+    log->lh.block[i] = lh->block[i];
   }
   brelse(buf);
 }
@@ -99,89 +177,122 @@ read_head(void)
 // This is the true point at which the
 // current transaction commits.
 static void
-write_head(void)
+write_head(struct log *log)
 {
-  struct buf *buf = bread(log.dev, log.start);
+  struct buf *buf = bread(log->dev, log->start);
   struct logheader *hb = (struct logheader *) (buf->data);
   int i;
-  hb->n = log.lh.n;
-  for (i = 0; i < log.lh.n; i++) {
-    hb->block[i] = log.lh.block[i];
+  hb->n = log->lh.n;
+  for (i = 0; i < log->lh.n; i++) {
+    hb->block[i] = log->lh.block[i];
   }
   bwrite(buf);
   brelse(buf);
 }
 
 static void
-recover_from_log(void)
+recover_from_log(struct log *log)
 {
-  read_head();
-  install_trans(); // if committed, copy from log to disk
-  log.lh.n = 0;
-  write_head(); // clear the log
+  read_head(log);
+  install_trans(log); // if committed, copy from log to disk
+  log->lh.n = 0;
+  write_head(log); // clear the log
 }
 
-// called at the start of each FS system call.
-void
-begin_op(void)
+// Start an operation on one log.
+static void
+log_begin(struct log *log)
 {
-  acquire(&log.lock);
+  acquire(&log->lock);
   while(1){
-    if(log.committing){
-      sleep(&log, &log.lock);
-    } else if(log.lh.n + (log.outstanding+1)*MAXOPBLOCKS > LOGSIZE){
+    if(log->committing){
+      sleep(log, &log->lock);
+    } else if(log->lh.n + (log->outstanding+1)*MAXOPBLOCKS > LOGSIZE){
       // this op might exhaust log space; wait for commit.
-      sleep(&log, &log.lock);
+      sleep(log, &log->lock);
     } else {
-      log.outstanding += 1;
-      release(&log.lock);
+      log->outstanding += 1;
+      release(&log->lock);
       break;
     }
   }
 }
 
-// called at the end of each FS system call.
-// commits if this was the last outstanding operation.
-void
-end_op(void)
+// End an operation on one log; commits if this was
+// the last outstanding operation.
+static void
+log_end(struct log *log)
 {
   int do_commit = 0;
 
-  acquire(&log.lock);
-  log.outstanding -= 1;
-  if(log.committing)
+  acquire(&log->lock);
+  log->outstanding -= 1;
+  if(log->committing)
     panic("log.committing");
-  if(log.outstanding == 0){
+  if(log->outstanding == 0){
     do_commit = 1;
-    log.committing = 1;
+    log->committing = 1;
   } else {
-    // begin_op() may be waiting for log space,
-    // and decrementing log.outstanding has decreased
+    // log_begin() may be waiting for log space,
+    // and decrementing log->outstanding has decreased
     // the amount of reserved space.
-    wakeup(&log);
+    wakeup(log);
   }
-  release(&log.lock);
+  release(&log->lock);
 
   if(do_commit){
     // call commit w/o holding locks, since not allowed
     // to sleep with locks.
-    commit();
-    acquire(&log.lock);
-    log.committing = 0;
-    wakeup(&log);
-    release(&log.lock);
+    commit(log);
+    acquire(&log->lock);
+    log->committing = 0;
+    wakeup(log);
+    release(&log->lock);
   }
 }
 
+// called at the start of each FS system call.
+void
+begin_op(void)
+{
+  struct log *log;
+
+  acquire(&logs.lock);
+  while(logs.changing)
+    sleep(&logs, &logs.lock);
+  logs.ops++;
+  release(&logs.lock);
+
+  for(log = logs.log; log < &logs.log[NMOUNT]; log++)
+    if(log->used)
+      log_begin(log);
+}
+
+// called at the end of each FS system call.
+void
+end_op(void)
+{
+  struct log *log;
+
+  for(log = logs.log; log < &logs.log[NMOUNT]; log++)
+    if(log->used)
+      log_end(log);
+
+  acquire(&logs.lock);
+  if(--logs.ops == 0)
+    wakeup(&logs);
+  release(&logs.lock);
+}
+
 // Copy modified blocks from cache to log.
 static void
-write_log(void)
+write_log(struct log *log)
 {
   int tail;
 
-  for (tail = 0; tail < log.lh.n; tail++) {
-    struct buf *to = bread(log.dev, log.start+tail+1); // log block
-    struct buf *from = bread(log.dev, log.lh.block[tail]); // cache block
+  for (tail = 0; tail < log->lh.n; tail++) {
+    struct buf *to = bread(log->dev, log->start+tail+1); // log block
+    struct buf *from = bread(log->dev, log->lh.block[tail]); // cache block
     memmove(to->data, from->data, BSIZE);
     bwrite(to);  // write the log
     brelse(from);
@@ -190,14 +301,14 @@ write_log(void)
 }
 
 static void
-commit()
-{
-  if (log.lh.n > 0) {
-    write_log();     // Write modified blocks from cache to log
-    write_head();    // Write header to disk -- the real commit
-    install_trans(); // Now install writes to home locations
-    log.lh.n = 0;
-    write_head();    // Erase the transaction from the log
+commit(struct log *log)
+{
+  if (log->lh.n > 0) {
+    write_log(log);     // Write modified blocks from cache to log
+    write_head(log);    // Write header to disk -- the real commit
+    install_trans(log); // Now install writes to home locations
+    log->lh.n = 0;
+    write_head(log);    // Erase the transaction from the log
   }
 }
 
@@ -213,22 +324,28 @@ commit()
 void
 log_write(struct buf *b)
 {
+  struct log *log;
   int i;
 
-  if (log.lh.n >= LOGSIZE || log.lh.n >= log.size - 1)
+  for(log = logs.log; log < &logs.log[NMOUNT]; log++)
+    if(log->used && log->dev == b->dev)
+      break;
+  if(log == &logs.log[NMOUNT])
+    panic("log_write: no log");
+  if (log->lh.n >= LOGSIZE || log->lh.n >= log->size - 1)
     panic("too big a transaction");
-  if (log.outstanding < 1)
+  if (log->outstanding < 1)
     panic("log_write outside of trans");
 
-  acquire(&log.lock);
-  for (i = 0; i < log.lh.n; i++) {
-    if (log.lh.block[i] == b->blockno)   // log absorbtion
+  acquire(&log->lock);
+  for (i = 0; i < log->lh.n; i++) {
+    if (log->lh.block[i] == b->blockno)   // log absorbtion
       break;
   }
-  log.lh.block[i] = b->blockno;
-  if (i == log.lh.n)
-    log.lh.n++;
+  log->lh.block[i] = b->blockno;
+  if (i == log->lh.n)
+    log->lh.n++;
   b->flags |= B_DIRTY; // prevent eviction
-  release(&log.lock);
+  release(&log->lock);
 }
 
@@ -35,14 +35,15 @@ int main(void) {
   ioapicinit();                               // another interrupt controller
   consoleinit();                              // console hardware
   uartinit();                                 // serial port
+  kmain();                                    // CPU features, early FPU setup
   pinit();                                    // process table
   tvinit();                                   // trap vectors
-  binit();                                    // buffer cache
   fileinit();                                 // file table
+  iinit();                                    // inode cache
+  loginit();                                  // file system logs
   ideinit();                                  // disk
   startothers();                              // start other processors
   kinit2(P2V(4 * 1024 * 1024), P2V(PHYSTOP)); // must come after startothers()
-  kmain();
   userinit(); // first user process
   mpmain();   // finish this processor's setup
 }
@@ -69,6 +70,7 @@ static void mpenter(void) {
  */
 static void mpmain(void) {
   cprintf("cpu%d: starting %d\n", cpuid(), cpuid());
+  fpuinit();                    // FPU/SSE, switched lazily
   idtinit();                    // load idt register
   xchg(&(mycpu()->started), 1); // tell startothers() we're up
   scheduler();                  // start running processes
@@ -39,6 +39,7 @@ void winode(uint, struct dinode*);
 void rinode(uint inum, struct dinode *ip);
 void rsect(uint sec, void *buf);
 uint ialloc(ushort type);
+uint bmap(struct dinode *din, uint fbn);
 void iappend(uint inum, void *p, int n);
 
 // convert to intel byte order
@@ -101,6 +102,8 @@ main(int argc, char *argv[])
   sb.logstart = xint(2);
   sb.inodestart = xint(2+nlog);
   sb.bmapstart = xint(2+nlog+ninodeblocks);
+  sb.magic = xint(FSMAGIC);
+  sb.version = xint(FSVERSION);
 
   printf("nmeta %d (boot, super, log blocks %u inode blocks %u, bitmap blocks %u) blocks %d total %d\n",
          nmeta, nlog, ninodeblocks, nbitmap, nblocks, FSSIZE);
@@ -252,6 +255,43 @@ balloc(int used)
 
 #define min(a, b) ((a) < (b) ? (a) : (b))
 
+// Return the block holding block fbn of the file, allocating it
+// and the indirect blocks leading to it if they are missing.
+uint
+bmap(struct dinode *din, uint fbn)
+{
+  uint slot, depth, span, i, x;
+  uint indirect[NINDIRECT];
+
+  assert(fbn < MAXFILE);
+  span = 1;
+  if(fbn < NDIRECT){
+    slot = fbn;
+    depth = 0;
+  } else {
+    fbn -= NDIRECT;
+    for(depth = 1, span = NINDIRECT; fbn >= span; depth++, span *= NINDIRECT)
+      fbn -= span;
+    slot = NDIRECT + depth - 1;
+  }
+  if(xint(din->addrs[slot]) == 0){
+    din->addrs[slot] = xint(freeblock++);
+  }
+  x = xint(din->addrs[slot]);
+  for(; depth > 0; depth--){
+    span /= NINDIRECT;
+    rsect(x, (char*)indirect);
+    i = fbn / span;
+    fbn %= span;
+    if(indirect[i] == 0){
+      indirect[i] = xint(freeblock++);
+      wsect(x, (char*)indirect);
+    }
+    x = xint(indirect[i]);
+  }
+  return x;
+}
+
 void
 iappend(uint inum, void *xp, int n)
 {
@@ -259,7 +299,6 @@ iappend(uint inum, void *xp, int n)
   uint fbn, off, n1;
   struct dinode din;
   char buf[BSIZE];
-  uint indirect[NINDIRECT];
   uint x;
 
   rinode(inum, &din);
@@ -267,23 +306,7 @@ iappend(uint inum, void *xp, int n)
   // printf("append inum %d at off %d sz %d\n", inum, off, n);
   while(n > 0){
     fbn = off / BSIZE;
-    assert(fbn < MAXFILE);
-    if(fbn < NDIRECT){
-      if(xint(din.addrs[fbn]) == 0){
-        din.addrs[fbn] = xint(freeblock++);
-      }
-      x = xint(din.addrs[fbn]);
-    } else {
-      if(xint(din.addrs[NDIRECT]) == 0){
-        din.addrs[NDIRECT] = xint(freeblock++);
-      }
-      rsect(xint(din.addrs[NDIRECT]), (char*)indirect);
-      if(indirect[fbn - NDIRECT] == 0){
-        indirect[fbn - NDIRECT] = xint(freeblock++);
-        wsect(xint(din.addrs[NDIRECT]), (char*)indirect);
-      }
-      x = xint(indirect[fbn-NDIRECT]);
-    }
+    x = bmap(&din, fbn);
     n1 = min(n, (fbn + 1) * BSIZE - off);
     rsect(x, buf);
     bcopy(p, buf + off - (fbn * BSIZE), n1);
@@ -0,0 +1,11 @@
+// Protection and flag bits for mmap (see mmap.rs).
+#define PROT_NONE     0x0   // Pages may not be accessed
+#define PROT_READ     0x1   // Pages may be read
+#define PROT_WRITE    0x2   // Pages may be written
+#define PROT_EXEC     0x4   // Pages may be executed
+
+#define MAP_SHARED    0x01  // Changes go back to the file and to children
+#define MAP_PRIVATE   0x02  // Changes are private copy-on-write
+#define MAP_ANONYMOUS 0x20  // Not backed by a file; fd is ignored
+
+#define MAP_FAILED    ((void*)-1)
@@ -0,0 +1,159 @@
+/**
+ * @file mmaptest.c
+ * @brief User-level tests for mmap and munmap.
+ */
+#include "types.h"
+#include "stat.h"
+#include "user.h"
+#include "fcntl.h"
+#include "mman.h"
+
+#define PGSIZE 4096
+
+static void
+fail(char *what)
+{
+  printf(1, "mmaptest: %s FAILED\n", what);
+  exit();
+}
+
+/** @brief Anonymous private memory is zeroed, writeable and unmappable. */
+static void
+anonymous(void)
+{
+  char *p = mmap(0, 2*PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+  int i;
+
+  if(p == MAP_FAILED)
+    fail("anonymous mmap");
+  for(i = 0; i < 2*PGSIZE; i++)
+    if(p[i] != 0)
+      fail("anonymous zero fill");
+  p[0] = 'a';
+  p[2*PGSIZE-1] = 'b';
+  if(munmap(p, 2*PGSIZE) < 0)
+    fail("anonymous munmap");
+}
+
+/** @brief Private file mappings see the file but never change it. */
+static void
+private_file(void)
+{
+  int fd;
+  char *p, buf[4];
+
+  fd = open("mmap.tmp", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "xyz", 3) != 3)
+    fail("create file");
+  p = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE, fd, 0);
+  if(p == MAP_FAILED)
+    fail("private mmap");
+  if(p[0] != 'x' || p[2] != 'z' || p[3] != 0)
+    fail("private contents");
+  p[0] = 'Q';
+  munmap(p, PGSIZE);
+  close(fd);
+
+  fd = open("mmap.tmp", O_RDONLY);
+  if(read(fd, buf, 3) != 3 || buf[0] != 'x')
+    fail("private write-back");
+  close(fd);
+}
+
+/** @brief Shared file mappings are written back on munmap, within the file size. */
+static void
+shared_file(void)
+{
+  int fd;
+  char *p, buf[4];
+  struct stat st;
+
+  fd = open("mmap.tmp", O_RDWR);
+  p = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0);
+  if(p == MAP_FAILED)
+    fail("shared mmap");
+  p[1] = 'Y';
+  p[100] = 'n';
+  munmap(p, PGSIZE);
+  close(fd);
+
+  fd = open("mmap.tmp", O_RDONLY);
+  if(read(fd, buf, 3) != 3 || buf[1] != 'Y')
+    fail("shared write-back");
+  if(fstat(fd, &st) < 0 || st.size != 3)
+    fail("shared file size");
+  close(fd);
+
+  fd = open("mmap.tmp", O_RDONLY);
+  if(mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED, fd, 0) != MAP_FAILED)
+    fail("writeable shared mapping of read-only file");
+  close(fd);
+  unlink("mmap.tmp");
+}
+
+/**
+ * @brief Shared pages are shared with a child, private ones are copied.
+ *
+ * The second shared page is first touched by the child, after the fork.
+ */
+static void
+fork_sharing(void)
+{
+  char *s = mmap(0, 2*PGSIZE, PROT_READ|PROT_WRITE, MAP_SHARED|MAP_ANONYMOUS, -1, 0);
+  char *q = mmap(0, PGSIZE, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+
+  s[0] = 1;
+  q[0] = 1;
+  if(fork() == 0){
+    s[0] = 2;
+    s[PGSIZE] = 3;
+    q[0] = 2;
+    exit();
+  }
+  wait();
+  if(s[0] != 2)
+    fail("MAP_SHARED after fork");
+  if(s[PGSIZE] != 3)
+    fail("MAP_SHARED page first touched after fork");
+  if(q[0] != 1)
+    fail("MAP_PRIVATE after fork");
+  munmap(s, 2*PGSIZE);
+  munmap(q, PGSIZE);
+}
+
+/** @brief A read-only page or an unmapped page kills the process on write. */
+static void
+protection(void)
+{
+  char *p = mmap(0, PGSIZE, PROT_READ, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+  int pid;
+
+  if(p[0] != 0)
+    fail("read-only read");
+  if((pid = fork()) == 0){
+    p[0] = 1;
+    exit();
+  }
+  wait();
+  if(read(0, p, 1) >= 0)
+    fail("read into read-only mapping");
+  munmap(p, PGSIZE);
+  if((pid = fork()) == 0){
+    p[0] = 1;
+    fail("write after munmap");
+  }
+  wait();
+}
+
+int
+main(void)
+{
+  printf(1, "mmaptest starting\n");
+  anonymous();
+  private_file();
+  shared_file();
+  fork_sharing();
+  protection();
+  printf(1, "mmaptest OK\n");
+  exit();
+}
@@ -7,6 +7,7 @@
 #include "spinlock.h"
 #include "sleeplock.h"
 #include "file.h"
+#include "errno.h"
 
 #define PIPESIZE 512
 
@@ -23,11 +24,14 @@ int
 pipealloc(struct file **f0, struct file **f1)
 {
   struct pipe *p;
+  int r;
 
   p = 0;
   *f0 = *f1 = 0;
+  r = -ENFILE;
   if((*f0 = filealloc()) == 0 || (*f1 = filealloc()) == 0)
     goto bad;
+  r = -ENOMEM;
   if((p = (struct pipe*)kalloc()) == 0)
     goto bad;
   p->readopen = 1;
@@ -53,7 +57,7 @@ pipealloc(struct file **f0, struct file **f1)
     fileclose(*f0);
   if(*f1)
     fileclose(*f1);
-  return -1;
+  return r;
 }
 
 void
@@ -85,7 +89,7 @@ pipewrite(struct pipe *p, char *addr, int n)
     while(p->nwrite == p->nread + PIPESIZE){  //DOC: pipewrite-full
       if(p->readopen == 0 || myproc()->killed){
         release(&p->lock);
-        return -1;
+        return p->readopen ? -EINTR : -EPIPE;
       }
       wakeup(&p->nread);
       sleep(&p->nwrite, &p->lock);  //DOC: pipewrite-sleep
@@ -106,7 +110,7 @@ piperead(struct pipe *p, char *addr, int n)
   while(p->nread == p->nwrite && p->writeopen){  //DOC: pipe-empty
     if(myproc()->killed){
       release(&p->lock);
-      return -1;
+      return -EINTR;
     }
     sleep(&p->nread, &p->lock); //DOC: piperead-sleep
   }
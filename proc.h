@@ -1,13 +1,12 @@
 // Per-CPU state
 struct cpu {
   uchar apicid;                // Local APIC ID
-  struct context *scheduler;   // swtch() here to enter scheduler
   struct taskstate ts;         // Used by x86 to find stack for interrupt
   struct segdesc gdt[NSEGS];   // x86 global descriptor table
   volatile uint started;       // Has the CPU started?
   int ncli;                    // Depth of pushcli nesting.
   int intena;                  // Were interrupts enabled before pushcli?
-  struct proc *proc;           // The process running on this cpu or null
+  uint id;                     // Index in cpus[], read through %gs
 };
 
 extern struct cpu cpus[NCPU];
@@ -34,6 +33,16 @@ struct context {
 
 enum procstate { UNUSED, EMBRYO, SLEEPING, RUNNABLE, RUNNING, ZOMBIE };
 
+// Region set up by mmap (see mmap.rs)
+struct vma {
+  uint start;                  // First address, page aligned
+  uint len;                    // Bytes, page multiple; 0 if slot is free
+  uint prot;                   // PROT_* bits
+  uint flags;                  // MAP_* bits
+  struct file *file;           // Backing file, 0 if anonymous
+  uint off;                    // File offset of start
+};
+
 // Per-process state
 struct proc {
   uint sz;                     // Size of process memory (bytes)
@@ -49,6 +58,13 @@ struct proc {
   struct file *ofile[NOFILE];  // Open files
   struct inode *cwd;           // Current directory
   char name[16];               // Process name (debugging)
+  uint priority;               // Scheduling priority, 0 is highest
+  int budget;                  // Ticks left before demotion
+  uint ticks_in;               // ticks when last dispatched
+  uint affinity;               // Bit c set if allowed to run on cpu c
+  uint cpu;                    // Cpu whose run queue holds or last ran it
+  struct vma vmas[NVMA];       // Regions set up by mmap
+  char *fpu;                   // Page holding saved FPU/SSE registers
 };
 
 // Process memory is laid out contiguously, low addresses first:
@@ -56,3 +72,4 @@ struct proc {
 //   original data and bss
 //   fixed-size stack
 //   expandable heap
+// with mmap regions placed between MMAPBASE and KERNBASE.
@@ -22,6 +22,7 @@ initsleeplock(struct sleeplock *lk, char *name)
 void
 acquiresleep(struct sleeplock *lk)
 {
+  lockdep_acquire_sleep(lk, lk->name);
   acquire(&lk->lk);
   while (lk->locked) {
     sleep(lk, &lk->lk);
@@ -39,6 +40,7 @@ releasesleep(struct sleeplock *lk)
   lk->pid = 0;
   wakeup(lk);
   release(&lk->lk);
+  lockdep_release_sleep(lk);
 }
 
 int
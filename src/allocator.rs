@@ -44,13 +44,13 @@ macro_rules! log {
 cfg_if! {
     if #[cfg(feature = "alloc_linked_list")] {
         // linked_list_allocator::LockedHeap uses spin::Mutex internally.
-        #[global_allocator]
+        #[cfg_attr(not(feature = "hosted"), global_allocator)]
         static ALLOCATOR: linked_list_allocator::LockedHeap = linked_list_allocator::LockedHeap::empty();
 
     } else if #[cfg(feature = "alloc_buddy_system")] {
         // For buddy_system_allocator v0.9.1, with 'use_spin' feature,
         // its LockedHeap takes only the ORDER. The spinlock type is internal.
-        #[global_allocator]
+        #[cfg_attr(not(feature = "hosted"), global_allocator)]
         static ALLOCATOR: buddy_system_allocator::LockedHeap<32> =
             buddy_system_allocator::LockedHeap::<32>::new();
 
@@ -64,7 +64,7 @@ cfg_if! {
                 // panic!("dummy allocator dealloc should not be called");
             }
         }
-        #[global_allocator]
+        #[cfg_attr(not(feature = "hosted"), global_allocator)]
         static ALLOCATOR: DummyAllocator = DummyAllocator;
     }
 }
@@ -160,3 +160,70 @@ fn alloc_error_handler(layout: Layout) -> ! {
 fn new_non_null<T>(ptr: *mut T) -> Option<NonNull<T>> {
     NonNull::new(ptr)
 }
+
+#[cfg(all(test, feature = "alloc_linked_list"))]
+mod tests {
+    use super::*;
+    use std::sync::Once;
+
+    const HEAP_SIZE: usize = 64 * 1024;
+
+    /// Hand the shared test heap its backing store exactly once.
+    fn heap() -> &'static linked_list_allocator::LockedHeap {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let region = std::boxed::Box::leak(std::vec![0u8; HEAP_SIZE].into_boxed_slice());
+            unsafe { init_rust_heap(region.as_mut_ptr() as usize, HEAP_SIZE) };
+        });
+        &ALLOCATOR
+    }
+
+    #[test]
+    fn init_accounts_for_whole_region() {
+        let h = heap().lock();
+        assert_eq!(h.size(), HEAP_SIZE);
+        assert_eq!(h.used() + h.free(), HEAP_SIZE);
+    }
+
+    #[test]
+    fn alloc_respects_alignment() {
+        let heap = heap();
+        for align in [1, 2, 4, 8, 16, 64, 256] {
+            let layout = Layout::from_size_align(24, align).unwrap();
+            let p = unsafe { heap.alloc(layout) };
+            assert!(!p.is_null());
+            assert_eq!(p as usize % align, 0);
+            unsafe { heap.dealloc(p, layout) };
+        }
+    }
+
+    #[test]
+    fn allocations_do_not_overlap() {
+        let heap = heap();
+        let layout = Layout::from_size_align(128, 16).unwrap();
+        let ptrs: Vec<*mut u8> = (0..16).map(|_| unsafe { heap.alloc(layout) }).collect();
+        for (i, &p) in ptrs.iter().enumerate() {
+            assert!(!p.is_null());
+            unsafe { ptr::write_bytes(p, i as u8, 128) };
+        }
+        for (i, &p) in ptrs.iter().enumerate() {
+            let block = unsafe { core::slice::from_raw_parts(p, 128) };
+            assert!(block.iter().all(|&b| b == i as u8));
+            unsafe { heap.dealloc(p, layout) };
+        }
+    }
+
+    #[test]
+    fn oversized_request_returns_null() {
+        let layout = Layout::from_size_align(HEAP_SIZE * 2, 8).unwrap();
+        assert!(unsafe { heap().alloc(layout) }.is_null());
+    }
+
+    #[test]
+    fn stats_do_not_disturb_heap() {
+        let heap = heap();
+        unsafe { rust_heap_stats() };
+        let h = heap.lock();
+        assert_eq!(h.used() + h.free(), HEAP_SIZE);
+    }
+}
@@ -0,0 +1,566 @@
+//! \file bio.rs
+//! \brief Buffer cache: cached copies of disk blocks, replacing `bio.c`.
+//!
+//! Caching disk blocks in memory reduces the number of disk reads and also
+//! provides a synchronization point for blocks used by several processes:
+//! only one process at a time holds a given [`Buf`], through its sleep lock.
+//!
+//! Buffers are found through a hash table keyed by `(dev, blockno)` with a
+//! spinlock per bucket, so lookups of different blocks do not contend.
+//! Unreferenced buffers sit on a list in release order, and a miss recycles
+//! the one released longest ago. A buffer that is dirty is never recycled,
+//! even unreferenced, because `log.c` has changed it but not yet committed
+//! it.
+//!
+//! Rust code holds a buffer through a [`BufGuard`], which releases it when
+//! dropped; C keeps using `bread`, `bwrite` and `brelse`.
+
+use crate::fs::BSIZE;
+use crate::param::NBUF;
+use crate::sync::sleep_lock::Sleeplock;
+use crate::sync::SpinLock;
+use core::cell::UnsafeCell;
+use core::ffi::CStr;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// \brief The buffer holds the block's contents.
+pub const B_VALID: i32 = 0x2;
+/// \brief The buffer must be written to disk.
+pub const B_DIRTY: i32 = 0x4;
+
+/// \brief Number of hash buckets; prime, so block runs spread evenly.
+pub const NBUCKET: usize = 31;
+
+/// \brief No buffer, or no bucket.
+const NONE: u16 = u16::MAX;
+
+/// \brief A cached disk block, laid out like `struct buf` in `buf.h`.
+#[repr(C)]
+pub struct Buf {
+    /// \brief `B_VALID` and `B_DIRTY`.
+    pub flags: i32,
+    /// \brief Device of the block.
+    pub dev: u32,
+    /// \brief Block number on the device.
+    pub blockno: u32,
+    /// \brief Held by the process using the buffer.
+    pub lock: Sleeplock,
+    /// \brief Number of users, waiting ones included.
+    pub refcnt: u32,
+    /// \brief Next request in the disk queue of `ide.c`.
+    pub qnext: *mut Buf,
+    /// \brief The block's contents.
+    pub data: [u8; BSIZE],
+}
+
+// Same layout as `struct buf` on the kernel target; `fs.c` checks the C side.
+#[cfg(target_arch = "x86")]
+const _: () = {
+    assert!(mem::size_of::<Buf>() == 596);
+    assert!(mem::offset_of!(Buf, refcnt) == 76 && mem::offset_of!(Buf, data) == 84);
+};
+
+impl Buf {
+    const fn new() -> Self {
+        Buf {
+            flags: 0,
+            dev: 0,
+            blockno: 0,
+            lock: Sleeplock::new(c"buffer"),
+            refcnt: 0,
+            qnext: ptr::null_mut(),
+            data: [0; BSIZE],
+        }
+    }
+}
+
+/// \brief Unreferenced buffers, most recently released first.
+struct Lru {
+    next: [u16; NBUF],
+    prev: [u16; NBUF],
+    /// Most recently released.
+    head: u16,
+    /// Released longest ago.
+    tail: u16,
+    listed: [bool; NBUF],
+}
+
+impl Lru {
+    /// \brief Every buffer, unused, with buffer 0 the most recent.
+    const fn full() -> Self {
+        let mut l = Lru { next: [NONE; NBUF], prev: [NONE; NBUF], head: 0, tail: NBUF as u16 - 1, listed: [true; NBUF] };
+        let mut i = 0;
+        while i < NBUF {
+            if i > 0 {
+                l.prev[i] = i as u16 - 1;
+            }
+            if i + 1 < NBUF {
+                l.next[i] = i as u16 + 1;
+            }
+            i += 1;
+        }
+        l
+    }
+
+    fn unlink(&mut self, i: u16) {
+        let (p, n) = (self.prev[i as usize], self.next[i as usize]);
+        match p {
+            NONE => self.head = n,
+            p => self.next[p as usize] = n,
+        }
+        match n {
+            NONE => self.tail = p,
+            n => self.prev[n as usize] = p,
+        }
+        self.listed[i as usize] = false;
+    }
+
+    fn push_front(&mut self, i: u16) {
+        self.prev[i as usize] = NONE;
+        self.next[i as usize] = self.head;
+        match self.head {
+            NONE => self.tail = i,
+            h => self.prev[h as usize] = i,
+        }
+        self.head = i;
+        self.listed[i as usize] = true;
+    }
+
+    /// \brief The buffer released longest ago that `ok` accepts.
+    fn oldest(&self, ok: impl Fn(u16) -> bool) -> Option<u16> {
+        let mut i = self.tail;
+        while i != NONE {
+            if ok(i) {
+                return Some(i);
+            }
+            i = self.prev[i as usize];
+        }
+        None
+    }
+}
+
+/// \brief Lookups served from the cache and from the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufStats {
+    /// \brief Blocks found in the cache.
+    pub hits: u32,
+    /// \brief Blocks that needed a buffer recycled.
+    pub misses: u32,
+}
+
+/// \brief The buffer cache; see the module documentation.
+pub struct BufCache {
+    bufs: [UnsafeCell<Buf>; NBUF],
+    /// Next buffer in the same bucket, under that bucket's lock.
+    chain: [UnsafeCell<u16>; NBUF],
+    /// Bucket of each buffer, or `NONE` if never used. Changed only with
+    /// the old and new buckets and `lru` locked, and read under either.
+    home: [UnsafeCell<u16>; NBUF],
+    /// First buffer of each bucket. Taken in index order, before `lru`.
+    buckets: [SpinLock<u16>; NBUCKET],
+    lru: SpinLock<Lru>,
+    hits: AtomicU32,
+    misses: AtomicU32,
+}
+
+// Buffer fields are protected as documented on `BufCache` and `Buf`.
+unsafe impl Send for BufCache {}
+unsafe impl Sync for BufCache {}
+
+impl Default for BufCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BufCache {
+    /// \brief An empty cache, suitable for a `static`.
+    pub const fn new() -> Self {
+        const BUCKET: &CStr = c"bcache.bucket";
+        BufCache {
+            bufs: [const { UnsafeCell::new(Buf::new()) }; NBUF],
+            chain: [const { UnsafeCell::new(NONE) }; NBUF],
+            home: [const { UnsafeCell::new(NONE) }; NBUF],
+            buckets: [const { SpinLock::new(BUCKET, NONE) }; NBUCKET],
+            lru: SpinLock::new(c"bcache.lru", Lru::full()),
+            hits: AtomicU32::new(0),
+            misses: AtomicU32::new(0),
+        }
+    }
+
+    /// \brief A locked buffer for the block, read from disk if need be.
+    pub fn read(&self, dev: u32, blockno: u32) -> BufGuard<'_> {
+        let b = self.get(dev, blockno);
+        if b.flags() & B_VALID == 0 {
+            unsafe { iderw(b.buf) };
+        }
+        b
+    }
+
+    /// \brief A locked buffer for the block, whose data may not be valid.
+    pub fn get(&self, dev: u32, blockno: u32) -> BufGuard<'_> {
+        let h = bucket_of(dev, blockno);
+        loop {
+            let head = self.buckets[h].lock();
+            if let Some(i) = self.find(*head, dev, blockno) {
+                self.take(i);
+                drop(head);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return self.locked(i);
+            }
+            drop(head);
+
+            // Not cached: pick the buffer to recycle, then lock what it
+            // takes to move it and check nothing changed meanwhile.
+            let (v, old) = {
+                let lru = self.lru.lock();
+                let Some(v) = lru.oldest(|i| !self.dirty(i)) else {
+                    panic!("bget: no buffers");
+                };
+                (v, unsafe { *self.home[v as usize].get() } as usize)
+            };
+            let (mut new, mut prev) = if old == NONE as usize || old == h {
+                (self.buckets[h].lock(), None)
+            } else if old < h {
+                let p = self.buckets[old].lock();
+                (self.buckets[h].lock(), Some(p))
+            } else {
+                let n = self.buckets[h].lock();
+                (n, Some(self.buckets[old].lock()))
+            };
+            let mut lru = self.lru.lock();
+            let moved = unsafe { *self.home[v as usize].get() } as usize != old;
+            if self.find(*new, dev, blockno).is_some() || !lru.listed[v as usize] || moved || self.dirty(v) {
+                continue;
+            }
+
+            lru.unlink(v);
+            if old != NONE as usize {
+                let old_head = match prev.as_mut() {
+                    Some(p) => &mut **p,
+                    None => &mut *new,
+                };
+                unsafe { self.unchain(old_head, v) };
+            }
+            unsafe {
+                let b = self.buf(v);
+                (*b).dev = dev;
+                (*b).blockno = blockno;
+                (*b).flags = 0;
+                (*b).refcnt = 1;
+                *self.chain[v as usize].get() = *new;
+                *new = v;
+                *self.home[v as usize].get() = h as u16;
+            }
+            drop(lru);
+            drop(prev);
+            drop(new);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return self.locked(v);
+        }
+    }
+
+    /// \brief Hits and misses since boot.
+    pub fn stats(&self) -> BufStats {
+        BufStats { hits: self.hits.load(Ordering::Relaxed), misses: self.misses.load(Ordering::Relaxed) }
+    }
+
+    fn buf(&self, i: u16) -> *mut Buf {
+        self.bufs[i as usize].get()
+    }
+
+    fn index(&self, b: *const Buf) -> u16 {
+        let i = (b as usize).wrapping_sub(self.bufs.as_ptr() as usize) / mem::size_of::<Buf>();
+        assert!(i < NBUF && ptr::eq(self.buf(i as u16), b), "brelse: not a buffer");
+        i as u16
+    }
+
+    /// \brief The buffer holding the block in the bucket starting at `head`.
+    fn find(&self, head: u16, dev: u32, blockno: u32) -> Option<u16> {
+        let mut i = head;
+        while i != NONE {
+            let b = unsafe { &*self.buf(i) };
+            if b.dev == dev && b.blockno == blockno {
+                return Some(i);
+            }
+            i = unsafe { *self.chain[i as usize].get() };
+        }
+        None
+    }
+
+    /// \brief Remove buffer `i` from the bucket starting at `head`.
+    ///
+    /// # Safety
+    /// `head` must be the locked bucket holding `i`.
+    unsafe fn unchain(&self, head: &mut u16, i: u16) {
+        let next = *self.chain[i as usize].get();
+        if *head == i {
+            *head = next;
+            return;
+        }
+        let mut j = *head;
+        while *self.chain[j as usize].get() != i {
+            j = *self.chain[j as usize].get();
+        }
+        *self.chain[j as usize].get() = next;
+    }
+
+    /// \brief Count a new user of buffer `i`, whose bucket is locked.
+    fn take(&self, i: u16) {
+        let b = self.buf(i);
+        unsafe {
+            if (*b).refcnt == 0 {
+                self.lru.lock().unlink(i);
+            }
+            (*b).refcnt += 1;
+        }
+    }
+
+    fn dirty(&self, i: u16) -> bool {
+        // Only a holder, or the disk on its behalf, changes the flags.
+        unsafe { ptr::read_volatile(ptr::addr_of!((*self.buf(i)).flags)) & B_DIRTY != 0 }
+    }
+
+    fn locked(&self, i: u16) -> BufGuard<'_> {
+        let b = self.buf(i);
+        unsafe { acquiresleep(ptr::addr_of_mut!((*b).lock)) };
+        BufGuard { cache: self, buf: b }
+    }
+
+    /// \brief Give up a buffer taken by [`BufCache::get`].
+    fn release(&self, b: *mut Buf) {
+        let i = self.index(b);
+        unsafe {
+            if holdingsleep(ptr::addr_of_mut!((*b).lock)) == 0 {
+                panic!("brelse");
+            }
+            releasesleep(ptr::addr_of_mut!((*b).lock));
+            // Referenced buffers are never moved, so `home` is stable.
+            let _bucket = self.buckets[*self.home[i as usize].get() as usize].lock();
+            (*b).refcnt -= 1;
+            if (*b).refcnt == 0 {
+                self.lru.lock().push_front(i);
+            }
+        }
+    }
+}
+
+fn bucket_of(dev: u32, blockno: u32) -> usize {
+    (blockno as usize).wrapping_add((dev as usize).wrapping_mul(7)) % NBUCKET
+}
+
+/// \brief A buffer held by this process; released when dropped.
+pub struct BufGuard<'a> {
+    cache: &'a BufCache,
+    buf: *mut Buf,
+}
+
+impl<'a> BufGuard<'a> {
+    /// \brief Device of the block.
+    pub fn dev(&self) -> u32 {
+        unsafe { (*self.buf).dev }
+    }
+
+    /// \brief Block number on the device.
+    pub fn blockno(&self) -> u32 {
+        unsafe { (*self.buf).blockno }
+    }
+
+    /// \brief `B_VALID` and `B_DIRTY`.
+    pub fn flags(&self) -> i32 {
+        unsafe { (*self.buf).flags }
+    }
+
+    /// \brief The block's contents.
+    pub fn data(&self) -> &[u8; BSIZE] {
+        unsafe { &(*self.buf).data }
+    }
+
+    /// \brief The block's contents, to change before [`BufGuard::write`].
+    pub fn data_mut(&mut self) -> &mut [u8; BSIZE] {
+        unsafe { &mut (*self.buf).data }
+    }
+
+    /// \brief Write the contents to disk.
+    pub fn write(&mut self) {
+        unsafe { bwrite(self.buf) };
+    }
+
+    /// \brief Add the block to the current log transaction, which writes it
+    /// on commit; the `log_write` of `log.c`.
+    pub fn log_write(&mut self) {
+        unsafe { log_write(self.buf) };
+    }
+
+    /// \brief Hand the buffer to C, which must give it back to `brelse`.
+    pub fn into_raw(self) -> *mut Buf {
+        let b = self.buf;
+        mem::forget(self);
+        b
+    }
+
+    /// \brief Take back a buffer passed out by [`BufGuard::into_raw`].
+    ///
+    /// # Safety
+    /// `b` must come from `into_raw` on a guard of `cache`, and be used
+    /// through the returned guard only.
+    pub unsafe fn from_raw(cache: &'a BufCache, b: *mut Buf) -> Self {
+        BufGuard { cache, buf: b }
+    }
+}
+
+impl Drop for BufGuard<'_> {
+    fn drop(&mut self) {
+        self.cache.release(self.buf);
+    }
+}
+
+/// \brief The kernel's buffer cache.
+pub static BCACHE: BufCache = BufCache::new();
+
+/// \brief Return a locked buffer with the contents of the block.
+///
+/// # Safety
+/// The buffer must be given back to [`brelse`].
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn bread(dev: u32, blockno: u32) -> *mut Buf {
+    BCACHE.read(dev, blockno).into_raw()
+}
+
+/// \brief Write `b`'s contents to disk; the caller must hold `b`.
+///
+/// # Safety
+/// `b` must be a buffer of the cache.
+#[no_mangle]
+pub unsafe extern "C" fn bwrite(b: *mut Buf) {
+    if holdingsleep(ptr::addr_of_mut!((*b).lock)) == 0 {
+        panic!("bwrite");
+    }
+    (*b).flags |= B_DIRTY;
+    iderw(b);
+}
+
+/// \brief Release a buffer returned by [`bread`].
+///
+/// # Safety
+/// `b` must come from `bread` and not be used afterwards.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn brelse(b: *mut Buf) {
+    drop(BufGuard::from_raw(&BCACHE, b));
+}
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    fn iderw(b: *mut Buf);
+    fn log_write(b: *mut Buf);
+    fn acquiresleep(lk: *mut Sleeplock);
+    fn releasesleep(lk: *mut Sleeplock);
+    fn holdingsleep(lk: *mut Sleeplock) -> i32;
+}
+
+#[cfg(feature = "hosted")]
+use crate::hosted::{acquiresleep, holdingsleep, iderw, log_write, releasesleep};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn cache() -> Box<BufCache> {
+        Box::new(BufCache::new())
+    }
+
+    #[test]
+    fn second_read_is_a_hit() {
+        let c = cache();
+        drop(c.read(1, 7));
+        let b = c.read(1, 7);
+        assert_eq!((b.dev(), b.blockno()), (1, 7));
+        assert_eq!(c.stats(), BufStats { hits: 1, misses: 1 });
+    }
+
+    #[test]
+    fn written_data_survives_eviction() {
+        let c = cache();
+        let mut b = c.read(2, 0);
+        b.data_mut()[..3].copy_from_slice(b"abc");
+        b.write();
+        drop(b);
+        for blockno in 1..=NBUF as u32 {
+            drop(c.read(2, blockno));
+        }
+        let b = c.read(2, 0);
+        assert_eq!(&b.data()[..3], b"abc");
+        assert_eq!(c.stats().hits, 0);
+    }
+
+    #[test]
+    fn least_recently_released_is_recycled() {
+        let c = cache();
+        for blockno in 0..NBUF as u32 {
+            drop(c.read(3, blockno));
+        }
+        drop(c.read(3, 0));
+        drop(c.read(3, 1000));
+        let before = c.stats().misses;
+        drop(c.read(3, 0));
+        drop(c.read(3, 2));
+        assert_eq!(c.stats().misses, before);
+        drop(c.read(3, 1));
+        assert_eq!(c.stats().misses, before + 1);
+    }
+
+    #[test]
+    fn dirty_and_held_buffers_stay() {
+        let c = cache();
+        let held = c.read(4, 0);
+        let dirty = c.read(4, 1);
+        unsafe { (*dirty.buf).flags |= B_DIRTY };
+        drop(dirty);
+        for blockno in 2..NBUF as u32 + 10 {
+            drop(c.read(4, blockno));
+        }
+        assert_eq!(held.blockno(), 0);
+        let misses = c.stats().misses;
+        drop(c.read(4, 1));
+        assert_eq!(c.stats().misses, misses);
+    }
+
+    #[test]
+    fn raw_handles_round_trip() {
+        let c = cache();
+        let b = c.read(5, 9).into_raw();
+        let g = unsafe { BufGuard::from_raw(&c, b) };
+        assert_eq!(g.blockno(), 9);
+        drop(g);
+        assert!(c.lru.lock().listed[c.index(b) as usize]);
+    }
+
+    #[test]
+    fn concurrent_users_share_blocks() {
+        let c: Arc<BufCache> = Arc::from(cache());
+        let handles: Vec<_> = (0..4u32)
+            .map(|t| {
+                let c = Arc::clone(&c);
+                thread::spawn(move || {
+                    for n in 0..300u32 {
+                        let blockno = (n * 7 + t) % (NBUF as u32 * 2);
+                        let mut b = c.read(6, blockno);
+                        assert_eq!(b.blockno(), blockno);
+                        b.data_mut()[0] = b.data()[0].wrapping_add(1);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        let s = c.stats();
+        assert_eq!(s.hits + s.misses, 1200);
+    }
+}
@@ -0,0 +1,251 @@
+//! \file cow.rs
+//! \brief Copy-on-write fork: shared user pages and their reference counts.
+//!
+//! `fork` no longer copies the parent's memory. [`copyuvm`] maps every user
+//! page into the child as well, clears `W` in both page tables and tags the
+//! entries `COW`. The first write to such a page raises a page fault that
+//! [`crate::fault`] hands to [`fault`], which gives the writer its own copy,
+//! or simply makes the page writeable again when no one else maps it.
+//!
+//! `kalloc` and `kfree` keep a reference count per physical page through
+//! [`kref_alloc`] and [`kref_put`], so a shared page only goes back on the
+//! free list when its last mapping is dropped.
+
+use crate::memlayout::PHYSTOP;
+use crate::mmu::{Pte, PteFlags, PGSIZE};
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+#[cfg(not(feature = "hosted"))]
+use crate::{
+    fault,
+    memlayout::{p2v, v2p},
+    mmu::{pg_round_down, PageDirectory},
+    simd_integration::rust_copy_page,
+    vm::{self, freevm, kalloc, kfree, setupkvm, KernelFrames},
+};
+#[cfg(not(feature = "hosted"))]
+use core::ffi::c_char;
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    fn panic(s: *const c_char) -> !;
+}
+
+/// \brief Number of physical pages below `PHYSTOP`.
+const NPAGES: usize = (PHYSTOP / PGSIZE) as usize;
+
+/// \brief Mappings of each physical page; 0 while the page is free.
+static REFS: [AtomicU16; NPAGES] = [const { AtomicU16::new(0) }; NPAGES];
+
+fn refs(pa: u32) -> &'static AtomicU16 {
+    &REFS[(pa / PGSIZE) as usize]
+}
+
+/// \brief Start counting references to a freshly allocated page.
+#[no_mangle]
+pub extern "C" fn kref_alloc(pa: u32) {
+    refs(pa).store(1, Ordering::Release);
+}
+
+/// \brief Record one more mapping of page `pa`.
+pub fn kref_get(pa: u32) {
+    refs(pa).fetch_add(1, Ordering::AcqRel);
+}
+
+/// \brief Drop one mapping of page `pa` and return how many are left.
+///
+/// Pages that were never counted (those handed to `kfree` by `freerange`
+/// while the allocator is being filled) report 0, so they are freed.
+#[no_mangle]
+pub extern "C" fn kref_put(pa: u32) -> u32 {
+    let r = refs(pa);
+    let mut n = r.load(Ordering::Acquire);
+    while n != 0 {
+        match r.compare_exchange_weak(n, n - 1, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return (n - 1) as u32,
+            Err(cur) => n = cur,
+        }
+    }
+    0
+}
+
+/// \brief Current number of mappings of page `pa`.
+pub fn kref_count(pa: u32) -> u32 {
+    refs(pa).load(Ordering::Acquire) as u32
+}
+
+/// \brief Entry to install in both parent and child when `pte` is shared.
+///
+/// Writeable pages become read-only copy-on-write; read-only pages are
+/// shared as they are.
+pub const fn share_pte(pte: Pte) -> Pte {
+    if pte.has(PteFlags::W) {
+        pte.with_flags(pte.flags().difference(PteFlags::W).union(PteFlags::COW))
+    } else {
+        pte
+    }
+}
+
+/// \brief Entry for a copy-on-write page once the writer owns frame `pa`.
+pub const fn owned_pte(pte: Pte, pa: u32) -> Pte {
+    Pte::new(pa, pte.flags().difference(PteFlags::COW).union(PteFlags::W))
+}
+
+/// \brief Copy-on-write counters, as returned by the `cowstat` system call.
+///
+/// Layout mirrors `struct cowstat` in `cowstat.h`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CowStat {
+    /// Write faults on copy-on-write pages.
+    pub faults: u32,
+    /// Faults that had to copy the page.
+    pub copies: u32,
+    /// Pages `fork` shared instead of copying.
+    pub shared: u32,
+    /// Shared pages never copied: `shared - copies`.
+    pub saved: u32,
+}
+
+static FAULTS: AtomicU32 = AtomicU32::new(0);
+static COPIES: AtomicU32 = AtomicU32::new(0);
+static SHARED: AtomicU32 = AtomicU32::new(0);
+
+/// \brief Snapshot of the counters since boot.
+pub fn stats() -> CowStat {
+    let faults = FAULTS.load(Ordering::Relaxed);
+    let copies = COPIES.load(Ordering::Relaxed);
+    let shared = SHARED.load(Ordering::Relaxed);
+    CowStat { faults, copies, shared, saved: shared.saturating_sub(copies) }
+}
+
+/// \brief Give a child a copy-on-write view of the first `sz` bytes of `pgdir`.
+///
+/// Replaces the copying `copyuvm` from `vm.c`. Returns the child's page
+/// directory, or null if a page table could not be allocated. Heap pages
+/// not yet touched under `lazy_sbrk` stay unmapped in both.
+///
+/// # Safety
+/// `pgdir` must be the current process's page directory.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn copyuvm(pgdir: *mut PageDirectory, sz: u32) -> *mut PageDirectory {
+    let d = setupkvm();
+    if d.is_null() {
+        return d;
+    }
+    let parent = PageDirectory::from_ptr(pgdir);
+    let mut ok = true;
+    for va in (0..sz).step_by(PGSIZE as usize) {
+        let Some(pte) = parent.walk(va, &mut KernelFrames, false).filter(|e| e.is_present()) else {
+            if fault::LAZY {
+                continue;
+            }
+            panic(c"copyuvm: page not present".as_ptr());
+        };
+        if !share(pte, PageDirectory::from_ptr(d), va, true) {
+            ok = false;
+            break;
+        }
+    }
+    // The parent's entries lost PTE_W, so stale writeable TLB entries must go.
+    x86::tlb::flush_all();
+    if !ok {
+        freevm(d);
+        return core::ptr::null_mut();
+    }
+    d
+}
+
+/// \brief Map the page behind `pte` at `va` in `child` as well.
+///
+/// With `cow` a writeable page turns copy-on-write in both page tables;
+/// otherwise both map it as it is. Returns false if the child's page table
+/// could not be allocated. The caller flushes the TLB. `pte` is the
+/// present entry for `va` in the current page table.
+#[cfg(not(feature = "hosted"))]
+pub fn share(pte: &mut Pte, child: &mut PageDirectory, va: u32, cow: bool) -> bool {
+    if cow {
+        *pte = share_pte(*pte);
+    }
+    let pa = pte.addr();
+    if vm::map(child, va, PGSIZE, pa, pte.flags()).is_err() {
+        return false;
+    }
+    kref_get(pa);
+    SHARED.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+/// \brief Resolve a write fault at `va` on the page mapped by `pte`.
+///
+/// Returns false unless `pte` is a present user copy-on-write entry. Runs
+/// with interrupts disabled, from user mode or from the kernel writing to
+/// user memory (`CR0_WP` is set).
+///
+/// # Safety
+/// `pte` must be the current page table's entry for `va`.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn fault(pte: &mut Pte, va: u32) -> bool {
+    if !pte.has(PteFlags::P | PteFlags::U | PteFlags::COW) {
+        return false;
+    }
+    FAULTS.fetch_add(1, Ordering::Relaxed);
+    let pa = pte.addr();
+    if kref_count(pa) == 1 {
+        *pte = owned_pte(*pte, pa);
+    } else {
+        let mem = kalloc();
+        if mem.is_null() {
+            return false;
+        }
+        rust_copy_page(mem, p2v::<u8>(pa));
+        *pte = owned_pte(*pte, v2p(mem));
+        kfree(p2v(pa));
+        COPIES.fetch_add(1, Ordering::Relaxed);
+    }
+    x86::tlb::flush(pg_round_down(va) as usize);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P: PteFlags = PteFlags::P;
+    const W: PteFlags = PteFlags::W;
+    const U: PteFlags = PteFlags::U;
+    const COW: PteFlags = PteFlags::COW;
+
+    #[test]
+    fn refcount_tracks_mappings() {
+        let pa = 0x0030_0000;
+        kref_alloc(pa);
+        kref_get(pa);
+        kref_get(pa);
+        assert_eq!(kref_count(pa), 3);
+        assert_eq!(kref_put(pa), 2);
+        assert_eq!(kref_put(pa), 1);
+        assert_eq!(kref_put(pa), 0);
+        assert_eq!(kref_put(pa), 0, "uncounted pages stay free");
+        assert_eq!(kref_count(pa), 0);
+    }
+
+    #[test]
+    fn sharing_write_protects_writeable_pages() {
+        let rw = Pte::new(0x0040_0000, P | W | U);
+        let shared = share_pte(rw);
+        assert_eq!(shared, Pte::new(0x0040_0000, P | U | COW));
+        assert_eq!(share_pte(shared), shared);
+
+        let ro = Pte::new(0x0041_0000, P | U);
+        assert_eq!(share_pte(ro), ro);
+    }
+
+    #[test]
+    fn owning_restores_write_and_moves_frame() {
+        let shared = share_pte(Pte::new(0x0040_0000, P | W | U));
+        assert_eq!(owned_pte(shared, 0x0040_0000), Pte::new(0x0040_0000, P | W | U));
+        assert_eq!(owned_pte(shared, 0x0050_0000), Pte::new(0x0050_0000, P | W | U));
+    }
+}
@@ -20,11 +20,21 @@ static HAS_AVX512VNNI: AtomicBool = AtomicBool::new(false);
 static HAS_AVX_VNNI: AtomicBool = AtomicBool::new(false);
 static HAS_FPU: AtomicBool = AtomicBool::new(false);
 
+/// \brief Check if the `CPUID` instruction is available.
+///
+/// Every x86-64 processor implements `CPUID`, so hosted builds skip the
+/// EFLAGS probe used on i386.
+#[cfg(target_arch = "x86_64")]
+pub fn cpuid_supported() -> bool {
+    true
+}
+
 /// \brief Check if the `CPUID` instruction is available.
 ///
 /// The 386 CPU lacked the `CPUID` instruction. Later processors set the
 /// ID flag in EFLAGS to indicate support. This routine toggles the flag
 /// and verifies whether the change persists.
+#[cfg(target_arch = "x86")]
 pub fn cpuid_supported() -> bool {
     let original: u32;
     let toggled: u32;
@@ -0,0 +1,262 @@
+//! \file errno.rs
+//! \brief Error codes reported by system call handlers.
+//!
+//! Values follow the Linux numbering so user programs ported from elsewhere
+//! see familiar codes, and must stay in sync with `errno.h`, which user
+//! programs include. By default a failing system call still returns `-1`;
+//! with the `errno_abi` feature it returns `-errno` and the user-space stubs
+//! in `usys.S` move the code into `errno`.
+
+/// \brief Reason a system call failed.
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Errno {
+    /// \brief Operation not permitted.
+    EPERM = 1,
+    /// \brief No such file or directory.
+    ENOENT = 2,
+    /// \brief No such process.
+    ESRCH = 3,
+    /// \brief Interrupted system call.
+    EINTR = 4,
+    /// \brief I/O error.
+    EIO = 5,
+    /// \brief No such device or address.
+    ENXIO = 6,
+    /// \brief Argument list too long.
+    E2BIG = 7,
+    /// \brief Exec format error.
+    ENOEXEC = 8,
+    /// \brief Bad file descriptor.
+    EBADF = 9,
+    /// \brief No child processes.
+    ECHILD = 10,
+    /// \brief Resource temporarily unavailable.
+    EAGAIN = 11,
+    /// \brief Out of memory.
+    ENOMEM = 12,
+    /// \brief Permission denied.
+    EACCES = 13,
+    /// \brief Bad address.
+    EFAULT = 14,
+    /// \brief Device or resource busy.
+    EBUSY = 16,
+    /// \brief File exists.
+    EEXIST = 17,
+    /// \brief Cross-device link.
+    EXDEV = 18,
+    /// \brief No such device.
+    ENODEV = 19,
+    /// \brief Not a directory.
+    ENOTDIR = 20,
+    /// \brief Is a directory.
+    EISDIR = 21,
+    /// \brief Invalid argument.
+    EINVAL = 22,
+    /// \brief Too many open files in system.
+    ENFILE = 23,
+    /// \brief Too many open files.
+    EMFILE = 24,
+    /// \brief Not a typewriter.
+    ENOTTY = 25,
+    /// \brief File too large.
+    EFBIG = 27,
+    /// \brief No space left on device.
+    ENOSPC = 28,
+    /// \brief Illegal seek.
+    ESPIPE = 29,
+    /// \brief Read-only file system.
+    EROFS = 30,
+    /// \brief Too many links.
+    EMLINK = 31,
+    /// \brief Broken pipe.
+    EPIPE = 32,
+    /// \brief Result too large.
+    ERANGE = 34,
+    /// \brief File name too long.
+    ENAMETOOLONG = 36,
+    /// \brief Function not implemented.
+    ENOSYS = 38,
+    /// \brief Directory not empty.
+    ENOTEMPTY = 39,
+    /// \brief Too many levels of symbolic links.
+    ELOOP = 40,
+}
+
+impl Errno {
+    /// \brief Every defined code, in numeric order.
+    pub const ALL: [Errno; 35] = [
+        Errno::EPERM,
+        Errno::ENOENT,
+        Errno::ESRCH,
+        Errno::EINTR,
+        Errno::EIO,
+        Errno::ENXIO,
+        Errno::E2BIG,
+        Errno::ENOEXEC,
+        Errno::EBADF,
+        Errno::ECHILD,
+        Errno::EAGAIN,
+        Errno::ENOMEM,
+        Errno::EACCES,
+        Errno::EFAULT,
+        Errno::EBUSY,
+        Errno::EEXIST,
+        Errno::EXDEV,
+        Errno::ENODEV,
+        Errno::ENOTDIR,
+        Errno::EISDIR,
+        Errno::EINVAL,
+        Errno::ENFILE,
+        Errno::EMFILE,
+        Errno::ENOTTY,
+        Errno::EFBIG,
+        Errno::ENOSPC,
+        Errno::ESPIPE,
+        Errno::EROFS,
+        Errno::EMLINK,
+        Errno::EPIPE,
+        Errno::ERANGE,
+        Errno::ENAMETOOLONG,
+        Errno::ENOSYS,
+        Errno::ENOTEMPTY,
+        Errno::ELOOP,
+    ];
+
+    /// \brief Look up the code with numeric value `n`.
+    pub fn from_i32(n: i32) -> Option<Errno> {
+        Self::ALL.iter().copied().find(|&e| e as i32 == n)
+    }
+
+    /// \brief Human-readable message, identical to `strerror` in `ulib.c`.
+    pub fn description(self) -> &'static str {
+        match self {
+            Errno::EPERM => "Operation not permitted",
+            Errno::ENOENT => "No such file or directory",
+            Errno::ESRCH => "No such process",
+            Errno::EINTR => "Interrupted system call",
+            Errno::EIO => "I/O error",
+            Errno::ENXIO => "No such device or address",
+            Errno::E2BIG => "Argument list too long",
+            Errno::ENOEXEC => "Exec format error",
+            Errno::EBADF => "Bad file descriptor",
+            Errno::ECHILD => "No child processes",
+            Errno::EAGAIN => "Resource temporarily unavailable",
+            Errno::ENOMEM => "Out of memory",
+            Errno::EACCES => "Permission denied",
+            Errno::EFAULT => "Bad address",
+            Errno::EBUSY => "Device or resource busy",
+            Errno::EEXIST => "File exists",
+            Errno::EXDEV => "Cross-device link",
+            Errno::ENODEV => "No such device",
+            Errno::ENOTDIR => "Not a directory",
+            Errno::EISDIR => "Is a directory",
+            Errno::EINVAL => "Invalid argument",
+            Errno::ENFILE => "Too many open files in system",
+            Errno::EMFILE => "Too many open files",
+            Errno::ENOTTY => "Not a typewriter",
+            Errno::EFBIG => "File too large",
+            Errno::ENOSPC => "No space left on device",
+            Errno::ESPIPE => "Illegal seek",
+            Errno::EROFS => "Read-only file system",
+            Errno::EMLINK => "Too many links",
+            Errno::EPIPE => "Broken pipe",
+            Errno::ERANGE => "Result too large",
+            Errno::ENAMETOOLONG => "File name too long",
+            Errno::ENOSYS => "Function not implemented",
+            Errno::ENOTEMPTY => "Directory not empty",
+            Errno::ELOOP => "Too many levels of symbolic links",
+        }
+    }
+
+    /// \brief Value returned to user space for this error.
+    ///
+    /// `-errno` when built with the `errno_abi` feature, otherwise the
+    /// traditional xv6 `-1`.
+    #[inline]
+    pub fn to_user(self) -> i32 {
+        if cfg!(feature = "errno_abi") {
+            -(self as i32)
+        } else {
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `#define EXXX n` lines out of the user-space header.
+    fn header_codes() -> Vec<(String, i32)> {
+        include_str!("../errno.h")
+            .lines()
+            .filter_map(|l| {
+                let mut it = l.split_whitespace();
+                match (it.next(), it.next(), it.next()) {
+                    (Some("#define"), Some(name), Some(val)) if name.starts_with('E') => {
+                        Some((name.to_string(), val.parse().ok()?))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn all_is_sorted_and_unique() {
+        for w in Errno::ALL.windows(2) {
+            assert!((w[0] as i32) < (w[1] as i32));
+        }
+    }
+
+    #[test]
+    fn from_i32_round_trips() {
+        for e in Errno::ALL {
+            assert_eq!(Errno::from_i32(e as i32), Some(e));
+        }
+        assert_eq!(Errno::from_i32(0), None);
+        assert_eq!(Errno::from_i32(15), None);
+        assert_eq!(Errno::from_i32(-2), None);
+    }
+
+    #[test]
+    fn header_matches_enum() {
+        let codes = header_codes();
+        assert_eq!(codes.len(), Errno::ALL.len());
+        for (name, val) in codes {
+            let e = Errno::from_i32(val).unwrap_or_else(|| panic!("{} = {} not in Errno", name, val));
+            assert_eq!(format!("{:?}", e), name);
+        }
+    }
+
+    #[test]
+    fn strerror_table_matches_descriptions() {
+        let ulib = include_str!("../ulib.c");
+        for e in Errno::ALL {
+            let entry = format!("[{:?}]", e);
+            let line = ulib
+                .lines()
+                .find(|l| l.trim_start().starts_with(&entry))
+                .unwrap_or_else(|| panic!("{} missing from errstr", entry));
+            assert!(line.contains(&format!("\"{}\"", e.description())), "{}", line);
+        }
+    }
+
+    #[test]
+    fn descriptions_are_distinct() {
+        for (i, a) in Errno::ALL.iter().enumerate() {
+            assert!(!a.description().is_empty());
+            for b in &Errno::ALL[i + 1..] {
+                assert_ne!(a.description(), b.description());
+            }
+        }
+    }
+
+    #[test]
+    fn user_value_is_negative() {
+        for e in Errno::ALL {
+            assert!(e.to_user() < 0);
+        }
+    }
+}
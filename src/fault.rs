@@ -0,0 +1,103 @@
+//! \file fault.rs
+//! \brief Page faults on user memory and lazily allocated heap pages.
+//!
+//! `trap()` passes every `T_PGFLT` to [`pgfault`]. A write to a shared
+//! page is resolved by [`crate::cow`]. With the `lazy_sbrk` feature `sbrk`
+//! only moves `Proc::sz`, and a fault below `sz` on a page that is not
+//! mapped yet gets a fresh zeroed page. At or past `sz` only the regions
+//! set up by `mmap` are valid, and [`crate::mmap`] fills their pages. Any
+//! other fault is left to the caller, which kills the process.
+//!
+//! The kernel touches user memory directly through the pointers checked in
+//! [`crate::syscall`]; those checks call [`populate`] first so that running
+//! out of memory fails the system call instead of faulting in the kernel.
+
+use crate::cow;
+use crate::errno::Errno;
+use crate::mmap::{self, MMAPBASE};
+use crate::mmu::{pg_round_down, Frames, PageDirectory, PteFlags, PGSIZE};
+use crate::proc::{myproc, Proc};
+use crate::vm::{self, deallocuvm, switchuvm, KernelFrames};
+
+/// \brief True when heap pages are allocated on first touch.
+pub const LAZY: bool = cfg!(feature = "lazy_sbrk");
+
+/// \brief Page fault error code bit: the access was a write.
+const FEC_WR: u32 = 0x2;
+
+/// \brief Resolve a page fault at `va` with error code `err`.
+///
+/// Called by `trap()` for `T_PGFLT` from user or kernel mode. Returns 0
+/// once the access can be retried and -1 for a bad access.
+///
+/// # Safety
+/// Must be called from the page fault handler with interrupts disabled.
+#[no_mangle]
+pub unsafe extern "C" fn pgfault(va: u32, err: u32) -> i32 {
+    let p = myproc();
+    if !p.is_null() && resolve(&*p, va, err & FEC_WR != 0) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// \brief Make the page at `va` accessible to `p` for a read or a write.
+///
+/// Returns true if it already was. A present page is only ever fixed up by
+/// breaking copy-on-write; a missing one is allocated on demand.
+unsafe fn resolve(p: &Proc, va: u32, write: bool) -> bool {
+    match PageDirectory::from_ptr(p.pgdir).walk(va, &mut KernelFrames, false) {
+        Some(pte) if pte.is_present() => {
+            pte.has(PteFlags::U) && (!write || pte.has(PteFlags::W) || cow::fault(pte, va))
+        }
+        _ if va < p.sz => LAZY && map_zero(p, va).is_ok(),
+        _ => mmap::fault(p, va, write),
+    }
+}
+
+/// \brief Map a zeroed, writeable user page at the page containing `va`.
+unsafe fn map_zero(p: &Proc, va: u32) -> Result<(), Errno> {
+    let pa = KernelFrames.alloc().ok_or(Errno::ENOMEM)?;
+    let pgdir = PageDirectory::from_ptr(p.pgdir);
+    vm::map(pgdir, pg_round_down(va), PGSIZE, pa, PteFlags::W | PteFlags::U).inspect_err(|_| KernelFrames.free(pa))
+}
+
+/// \brief Make every page of `[addr, addr + len)` accessible to the kernel.
+///
+/// The range must already be checked against the process's memory. Lazy
+/// and mapped-region pages are allocated and, for a `write`, copy-on-write
+/// pages copied, so the kernel never faults on them. Fails with `EFAULT`
+/// when a page cannot be made accessible.
+///
+/// # Safety
+/// `p` must be the current process.
+pub unsafe fn populate(p: &Proc, addr: u32, len: u32, write: bool) -> Result<(), Errno> {
+    let mut va = pg_round_down(addr);
+    while va < addr + len {
+        if !resolve(p, va, write) {
+            return Err(Errno::EFAULT);
+        }
+        va += PGSIZE;
+    }
+    Ok(())
+}
+
+/// \brief Move the end of the heap by `n` bytes without allocating.
+///
+/// Growing only raises `sz`; the pages are mapped by [`pgfault`] when first
+/// touched. Shrinking frees whatever was mapped above the new end. Fails
+/// with `ENOMEM` if the heap would end below zero or pass `MMAPBASE`.
+///
+/// # Safety
+/// `p` must be the current process.
+pub unsafe fn grow_lazy(p: *mut Proc, n: i32) -> Result<(), Errno> {
+    let sz = (*p).sz;
+    let newsz = sz.checked_add_signed(n).filter(|&s| s <= MMAPBASE).ok_or(Errno::ENOMEM)?;
+    if newsz < sz {
+        deallocuvm((*p).pgdir, sz, newsz);
+        switchuvm(p);
+    }
+    (*p).sz = newsz;
+    Ok(())
+}
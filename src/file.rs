@@ -1,10 +1,24 @@
 //! \file file.rs
 //! \brief Kernel file and inode structures.
 
-use crate::fs::NDIRECT;
+use crate::fs::{Dinode, NADDRS};
 use crate::pipe::Pipe;
+use crate::sync::sleep_lock::Sleeplock;
 use bytemuck::Zeroable;
-use zerocopy::{FromBytes, IntoBytes as AsBytes};
+#[cfg(target_arch = "x86")]
+use core::mem::{offset_of, size_of};
+
+/// \brief `Inode::itype` of a directory.
+pub const T_DIR: i16 = 1;
+/// \brief `Inode::itype` of a regular file.
+pub const T_FILE: i16 = 2;
+/// \brief `Inode::itype` of a device.
+pub const T_DEV: i16 = 3;
+/// \brief `Inode::itype` of a symbolic link; the contents are its target.
+pub const T_SYMLINK: i16 = 4;
+
+/// \brief `File::itype` of a file open on an inode.
+pub const FD_INODE: i32 = 2;
 
 /// \brief Open file description (in-memory).
 #[repr(C)]
@@ -26,27 +40,80 @@ pub struct File {
     pub off:      u32,
 }
 
-/// \brief On-disk inode structure.
+/// \brief In-memory copy of an inode, laid out like `struct inode` in `file.h`.
+///
+/// Everything from `valid` on is protected by `lock`; the rest by the
+/// inode table lock in `fs.c`.
 #[repr(C)]
-#[repr(packed)]
-#[derive(Debug, Copy, Clone, Default, FromBytes, AsBytes)]
+#[derive(Debug, Default)]
 pub struct Inode {
     /// \brief Device number containing the inode.
     pub dev:    u32,
     /// \brief Inode number.
     pub inum:   u32,
-    /// \brief In-memory reference count.
-    pub refc:   u32,
-    /// \brief Indicates whether this inode's data is valid.
+    /// \brief Reference count.
+    pub refc:   i32,
+    /// \brief Protects everything below.
+    pub lock:   Sleeplock,
+    /// \brief Whether the fields below have been read from disk.
     pub valid:  i32,
-    /// \brief Inode type (file, directory, etc.).
+    /// \brief Copy of the disk inode: file type.
     pub itype:  i16,
     /// \brief Major device number (for device files).
     pub major:  i16,
+    /// \brief Minor device number (for device files).
+    pub minor:  i16,
     /// \brief Number of links to this inode in the filesystem.
     pub nlink:  i16,
     /// \brief Size of file in bytes.
     pub size:   u32,
-    /// \brief Data block addresses (direct plus one indirect).
-    pub addrs:  [u32; NDIRECT + 1],
+    /// \brief Data block addresses, then indirect tree roots.
+    pub addrs:  [u32; NADDRS],
+}
+
+// Same layout as `struct inode` on the kernel target; `fs.c` checks the C side.
+#[cfg(target_arch = "x86")]
+const _: () = {
+    assert!(size_of::<Inode>() == 144);
+    assert!(offset_of!(Inode, lock) == 12 && offset_of!(Inode, valid) == 76);
+    assert!(offset_of!(Inode, itype) == 80 && offset_of!(Inode, size) == 88);
+    assert!(offset_of!(Inode, addrs) == 92);
+};
+
+impl Inode {
+    /// \brief The fields kept on disk.
+    pub fn dinode(&self) -> Dinode {
+        Dinode {
+            itype: self.itype,
+            major: self.major,
+            minor: self.minor,
+            nlink: self.nlink,
+            size: self.size,
+            addrs: self.addrs,
+        }
+    }
+
+    /// \brief Take the on-disk fields from `d`, as `ilock` does.
+    pub fn load(&mut self, d: &Dinode) {
+        self.itype = d.itype;
+        self.major = d.major;
+        self.minor = d.minor;
+        self.nlink = d.nlink;
+        self.size = d.size;
+        self.addrs = d.addrs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_fields_round_trip() {
+        let d = Dinode { itype: 3, major: 1, minor: 2, nlink: 1, size: 9, addrs: [7; NADDRS] };
+        let mut ip = Inode { dev: 1, inum: 4, refc: 1, ..Default::default() };
+        ip.load(&d);
+        assert_eq!(ip.dinode(), d);
+        assert_eq!((ip.dev, ip.inum), (1, 4));
+    }
 }
@@ -1,8 +1,22 @@
 #![allow(dead_code)]
 
 use crate::cpu_features::{has_fxsr, has_sse, has_xsave}; // Note: has_fxsr needs to be added
+use crate::mmu::PGSIZE;
+use crate::percpu::PreemptGuard;
+use crate::proc::{myproc, Proc, ProcState};
+use crate::spinlock::{popcli, pushcli};
 use bitfield::bitfield;
 use core::arch::asm;
+use core::cell::Cell;
+use core::ffi::c_char;
+use core::mem;
+use core::ptr::{self, addr_of_mut};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use x86::controlregs::{cr0, cr0_write, Cr0};
+
+extern "C" {
+    fn panic(s: *const c_char) -> !;
+}
 
 // --- FPU Status Word ---
 bitfield! {
@@ -55,49 +69,75 @@ pub enum FpuStateFormat {
     XSAVE = 2,  // x87, MMX, SSE, AVX, etc. Variable size, 16-byte aligned (or 64 for AVX512).
 }
 
+/// \brief Bytes of an [`FpuState`] available to `xsave`.
+pub const FPU_AREA_SIZE: usize = PGSIZE as usize - 64;
+
+/// \brief A saved register image, exactly one page so it fits `Proc::fpu`.
+///
+/// The XSAVE area needed by the enabled components is reported by CPUID
+/// leaf 0xD and checked against [`FPU_AREA_SIZE`] by [`init_fpu`].
 #[repr(C, align(64))] // Align to 64 for XSAVE (AVX512 requires this)
 pub struct FpuState {
-    data: [u8; 512 + 64], // 512 for FXSAVE area, + space for XSAVE header/extended states if needed.
-                          // Actual XSAVE size can be larger and determined by CPUID.
-                          // For simplicity, we use a fixed-size buffer that's large enough for common cases.
+    data: [u8; FPU_AREA_SIZE],
     format: FpuStateFormat,
 }
 
+const _: () = assert!(mem::size_of::<FpuState>() == PGSIZE as usize);
+
 impl Default for FpuState {
     fn default() -> Self {
         Self {
-            data: [0; 512 + 64],
+            data: [0; FPU_AREA_SIZE],
             format: FpuStateFormat::FSAVE, // Default to basic format
         }
     }
 }
 
 impl FpuState {
-    pub fn new() -> Self {
-        let mut state: Self = Default::default();
-        // Determine the best format supported by the CPU
+    /// \brief The best save format this CPU supports.
+    fn best_format() -> FpuStateFormat {
         if has_xsave() {
-            state.format = FpuStateFormat::XSAVE;
+            FpuStateFormat::XSAVE
         } else if has_fxsr() { // FXSR is implied by SSE, but good to check explicitly
-            state.format = FpuStateFormat::FXSAVE;
+            FpuStateFormat::FXSAVE
         } else {
-            state.format = FpuStateFormat::FSAVE;
+            FpuStateFormat::FSAVE
         }
-        state
+    }
+
+    pub fn new() -> Self {
+        Self { format: Self::best_format(), ..Default::default() }
+    }
+
+    /// \brief Initialise the page at `this` like [`FpuState::new`] followed by
+    /// [`FpuState::reset`], without building a page-sized value on the stack.
+    ///
+    /// # Safety
+    /// `this` must be valid for writes and aligned to 64 bytes.
+    pub unsafe fn init_at(this: *mut Self) {
+        addr_of_mut!((*this).format).write(Self::best_format());
+        (*this).reset();
     }
 
     pub unsafe fn save(&mut self) {
         match self.format {
+            // EDX:EAX selects every component; the CPU saves those in XCR0.
+            FpuStateFormat::XSAVE if HAS_XSAVEOPT.load(Ordering::Relaxed) => {
+                asm!(
+                    "xsaveopt [{}]",
+                    in(reg) self.data.as_mut_ptr(),
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags)
+                );
+            }
             FpuStateFormat::XSAVE => {
-                // Simplified XSAVE: uses fxsave portion. XCR0 should be set to save relevant states.
-                // To save all user states: xcr0 = 0x0000_0007 (x87, SSE, AVX) or more.
-                // For now, we rely on fxsave behavior for compatibility.
-                // A full XSAVE would query XCR0, use `xsave` or `xsaveopt`.
                 asm!(
-                    "fxsave [{}]", // Changed to fxsave for 32-bit target
+                    "xsave [{}]",
                     in(reg) self.data.as_mut_ptr(),
-                    options(nostack, preserves_flags),
-                    clobber_abi("C")
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags)
                 );
             }
             FpuStateFormat::FXSAVE => {
@@ -122,13 +162,12 @@ impl FpuState {
     pub unsafe fn restore(&self) {
         match self.format {
             FpuStateFormat::XSAVE => {
-                // Simplified XRSTOR: uses fxrstor portion.
-                // A full XRSTOR would query XCR0.
                 asm!(
-                    "fxrstor [{}]", // Changed to fxrstor
+                    "xrstor [{}]",
                     in(reg) self.data.as_ptr(),
-                    options(nostack, preserves_flags),
-                    clobber_abi("C")
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags)
                 );
             }
             FpuStateFormat::FXSAVE => {
@@ -153,6 +192,22 @@ impl FpuState {
     pub fn is_xsave_format(&self) -> bool {
         self.format == FpuStateFormat::XSAVE
     }
+
+    /// \brief Replace the saved image with the registers `fninit` leaves.
+    ///
+    /// All x87 exceptions are masked and, for the FXSAVE layouts, so are
+    /// the SSE ones (MXCSR = 0x1F80), as a freshly started program expects.
+    /// The zeroed XSAVE header marks every other component as being in its
+    /// initial state, so `xrstor` clears the AVX registers too.
+    pub fn reset(&mut self) {
+        self.data.fill(0);
+        self.data[0..2].copy_from_slice(&0x037Fu16.to_le_bytes()); // FCW
+        match self.format {
+            // FSAVE keeps the full tag word: every register empty.
+            FpuStateFormat::FSAVE => self.data[8..10].copy_from_slice(&0xFFFFu16.to_le_bytes()),
+            _ => self.data[24..28].copy_from_slice(&0x1F80u32.to_le_bytes()),
+        }
+    }
 }
 
 
@@ -185,87 +240,273 @@ pub unsafe fn init_fpu() {
     }
     if has_xsave() {
         cr4 |= 1 << 18; // Set OSXSAVE
-        // Also, XCR0 needs to be configured to enable saving specific states (x87, SSE, AVX).
-        // Default is usually 0x1 (x87) or 0x3 (x87+SSE) after boot.
-        // To enable x87, SSE, AVX: asm!("mov ecx, 0", "mov eax, 7", "mov edx, 0", "xsetbv", options(nostack, preserves_flags));
-        // This is complex and requires privileges. Usually set once by OS.
-        // For now, assume XCR0 is reasonably configured by firmware/bootloader or needs specific setup.
     }
     asm!("mov cr4, {0}", in(reg) cr4, options(nomem, nostack, preserves_flags));
 
-    // 3. Initialize FPU state (FINIT/FNINIT)
+    // 3. Tell the CPU, through XCR0, which components XSAVE manages.
+    if has_xsave() {
+        init_xcr0();
+    }
+
+    // 4. Initialize FPU state (FINIT/FNINIT)
     // FNINIT is preferred as it doesn't wait for pending unmasked x87 exceptions.
     asm!("fninit", options(nomem, nostack, preserves_flags));
+}
+
+// --- XSAVE components ---
+
+/// \brief XCR0 bits for the x87, SSE and AVX (upper YMM) components.
+const XCR0_X87: u32 = 1 << 0;
+const XCR0_SSE: u32 = 1 << 1;
+const XCR0_AVX: u32 = 1 << 2;
+/// \brief XCR0 bits for AVX-512: opmask, upper ZMM0-15 and ZMM16-31.
+const XCR0_AVX512: u32 = (1 << 5) | (1 << 6) | (1 << 7);
+
+/// \brief The components enabled in XCR0, once [`init_fpu`] has run.
+static XCR0: AtomicU32 = AtomicU32::new(0);
+/// \brief Bytes `xsave` writes for the components in [`XCR0`].
+static XSAVE_SIZE: AtomicU32 = AtomicU32::new(0);
+/// \brief Whether saves use `xsaveopt`, which skips components unchanged
+/// since the last `xrstor` from the same area. That holds here because an
+/// area is never written while its process owns the FPU.
+static HAS_XSAVEOPT: AtomicBool = AtomicBool::new(false);
 
-    // If using XSAVE, XCR0 should be set up. For example, to enable x87, SSE, and AVX:
-    // if has_xsave() {
-    //     unsafe {
-    //         let xcr0_val: u64 = 0b111; // x87, SSE, AVX
-    //         asm!(
-    //             "mov ecx, 0", // XCR0 register index
-    //             "mov eax, {val_low}",
-    //             "mov edx, {val_high}",
-    //             "xsetbv",
-    //             val_low = in(reg) (xcr0_val & 0xFFFFFFFF) as u32,
-    //             val_high = in(reg) (xcr0_val >> 32) as u32,
-    //             options(nostack, preserves_flags)
-    //         );
-    //     }
-    // }
+/// \brief Write extended control register `xcr`.
+unsafe fn xsetbv(xcr: u32, val: u64) {
+    asm!(
+        "xsetbv",
+        in("ecx") xcr,
+        in("eax") val as u32,
+        in("edx") (val >> 32) as u32,
+        options(nomem, nostack, preserves_flags)
+    );
 }
 
-/// Manages FPU state for a task, ensuring it's saved and restored correctly.
-/// This is a simplified manager; a real one might handle lazy save/restore.
-pub struct FpuManager {
-    fpu_state: FpuState,
-    active: bool, // Is this FPU state currently loaded into the FPU?
+/// \brief Enable every user component this kernel knows and the CPU has.
+///
+/// The supported mask and the area size come from CPUID leaf 0xD; the size
+/// it reports after `xsetbv` covers exactly the enabled components. AVX-512
+/// is all or nothing, and is dropped again if its state would not fit an
+/// [`FpuState`]. Every CPU computes the same mask.
+unsafe fn init_xcr0() {
+    let supported = raw_cpuid::cpuid!(0xD, 0).eax;
+    let mut mask = supported & (XCR0_X87 | XCR0_SSE | XCR0_AVX);
+    if supported & XCR0_AVX512 == XCR0_AVX512 && mask & XCR0_AVX != 0 {
+        mask |= XCR0_AVX512;
+    }
+    xsetbv(0, mask as u64);
+    let mut size = raw_cpuid::cpuid!(0xD, 0).ebx;
+    if size as usize > FPU_AREA_SIZE {
+        mask &= !XCR0_AVX512;
+        xsetbv(0, mask as u64);
+        size = raw_cpuid::cpuid!(0xD, 0).ebx;
+    }
+    XCR0.store(mask, Ordering::Relaxed);
+    XSAVE_SIZE.store(size, Ordering::Relaxed);
+    HAS_XSAVEOPT.store(raw_cpuid::cpuid!(0xD, 1).eax & 1 != 0, Ordering::Relaxed);
 }
 
-impl FpuManager {
-    pub fn new() -> Self {
-        Self {
-            fpu_state: FpuState::new(), // Determines format based on CPU
-            active: false,
+/// \brief The XSAVE components enabled in XCR0, or 0 without XSAVE.
+pub fn xcr0() -> u32 {
+    XCR0.load(Ordering::Relaxed)
+}
+
+/// \brief Size in bytes of the XSAVE area in use, or 0 without XSAVE.
+pub fn xsave_size() -> usize {
+    XSAVE_SIZE.load(Ordering::Relaxed) as usize
+}
+
+// --- Lazy per-process switching ---
+//
+// Each process keeps its registers in the page at `Proc::fpu`, allocated
+// with `kalloc` by `allocproc`; with XSAVE this includes the AVX and, when
+// it fits, AVX-512 state enabled in XCR0. `switchuvm`
+// sets CR0.TS, so the first FPU/SSE instruction after a switch raises #NM
+// (`T_DEVICE`) and [`fpufault`] loads the process's registers, recording it
+// as the owner of this CPU's FPU. Processes that never touch the FPU never pay
+// for a save or a restore. The owner's registers are saved again when it
+// is switched out in `sched`, so that it can resume on any CPU.
+//
+// Kernel code that uses SSE while a process owns the registers clobbers
+// them; it must bracket such code with [`kernel_begin`]/[`kernel_end`],
+// usually through `simd_integration::KernelFpuGuard`.
+
+/// \brief FPU bookkeeping of one CPU.
+struct FpuCpu {
+    /// Process whose registers are loaded in this CPU's FPU, if any.
+    owner: Cell<*mut Proc>,
+    /// Depth of kernel FPU section nesting.
+    nest: Cell<u32>,
+    /// CR0.TS is to be set again when the outermost kernel section ends.
+    ts: Cell<bool>,
+}
+
+percpu! {
+    static FPU: FpuCpu = FpuCpu { owner: Cell::new(ptr::null_mut()), nest: Cell::new(0), ts: Cell::new(false) };
+}
+
+/// \brief Make the next FPU instruction on this CPU raise #NM.
+unsafe fn stts() {
+    cr0_write(cr0() | Cr0::CR0_TASK_SWITCHED);
+}
+
+/// \brief Allow FPU instructions on this CPU again.
+unsafe fn clts() {
+    asm!("clts", options(nomem, nostack, preserves_flags));
+}
+
+/// \brief The saved registers of `p`.
+unsafe fn area<'a>(p: *mut Proc) -> &'a mut FpuState {
+    &mut *((*p).fpu as *mut FpuState)
+}
+
+/// \brief Set up the FPU on this CPU; it starts out owned by no process.
+///
+/// # Safety
+/// Must run once per CPU, after `cpu_features::init`.
+#[no_mangle]
+pub unsafe extern "C" fn fpuinit() {
+    init_fpu();
+    FPU.get(&PreemptGuard::new()).owner.set(ptr::null_mut());
+    stts();
+}
+
+/// \brief Give a new process the register state of a freshly started program.
+///
+/// # Safety
+/// `(*p).fpu` must point to a page that nothing else uses.
+pub unsafe fn init_proc(p: *mut Proc) {
+    FpuState::init_at((*p).fpu as *mut FpuState);
+}
+
+/// \brief Handle #NM: load the current process's registers into the FPU.
+///
+/// The previous owner, if any, gets its registers saved first. Without a
+/// current process the FPU is simply enabled for the kernel.
+///
+/// # Safety
+/// Must be called from the trap handler with interrupts disabled.
+#[no_mangle]
+pub unsafe extern "C" fn fpufault() {
+    clts();
+    let g = PreemptGuard::new();
+    let owner = &FPU.get(&g).owner;
+    let p = myproc();
+    if owner.get() == p {
+        return;
+    }
+    if !owner.get().is_null() {
+        area(owner.get()).save();
+        owner.set(ptr::null_mut());
+    }
+    if !p.is_null() {
+        area(p).restore();
+        owner.set(p);
+    }
+}
+
+/// \brief Mark the FPU unavailable; called by `switchuvm` on every switch.
+///
+/// # Safety
+/// Interrupts must be disabled.
+pub unsafe fn switch_in() {
+    stts();
+}
+
+/// \brief Save `p`'s registers if it used the FPU during this time slice.
+///
+/// Called by `sched` just before leaving `p`. A zombie's registers are
+/// dropped instead.
+///
+/// # Safety
+/// Interrupts must be disabled and `p` must be the current process.
+pub unsafe fn switch_out(p: *mut Proc) {
+    let g = PreemptGuard::new();
+    let owner = &FPU.get(&g).owner;
+    if owner.get() == p {
+        if (*p).state() != ProcState::Zombie {
+            area(p).save();
         }
+        owner.set(ptr::null_mut());
+        stts();
     }
+}
 
-    /// Call when a task is about to use FPU/SIMD.
-    /// Restores its FPU state if not already active.
-    pub unsafe fn begin_use(&mut self) {
-        if !self.active {
-            // TODO: Disable interrupts here if in a preemptive kernel
-            self.fpu_state.restore();
-            self.active = true;
-            // TODO: Re-enable interrupts
+/// \brief Write `p`'s live registers back to its page, if this CPU holds them.
+unsafe fn flush(p: *mut Proc, keep: bool) {
+    let g = PreemptGuard::new();
+    let owner = &FPU.get(&g).owner;
+    if owner.get() == p {
+        if keep {
+            area(p).save();
         }
+        owner.set(ptr::null_mut());
+        stts();
     }
+}
+
+/// \brief Give `child` a copy of `parent`'s FPU registers.
+///
+/// # Safety
+/// `parent` must be the current process and `child` a new process set up
+/// by [`init_proc`].
+pub unsafe fn fork(parent: *mut Proc, child: *mut Proc) {
+    flush(parent, true);
+    ptr::copy_nonoverlapping(area(parent), area(child), 1);
+}
 
-    /// Call when a task is done with FPU/SIMD for now (e.g., before context switch out).
-    /// Saves the FPU state if it was active.
-    pub unsafe fn end_use(&mut self) {
-        if self.active {
-            // TODO: Disable interrupts
-            self.fpu_state.save();
-            self.active = false; // Mark as no longer loaded
-            // TODO: Re-enable interrupts
-
-            // Clear MMX state with EMMS if MMX was used and FSAVE wasn't the last save op.
-            // This is tricky. If FSAVE was used, EMMS is implicit.
-            // If FXSAVE/XSAVE, and MMX instructions were executed, EMMS is needed before
-            // other FPU (x87) instructions or if switching to a task that might use x87.
-            // For simplicity, if MMX is supported, and we just saved a potentially MMX-dirty state,
-            // calling EMMS might be a safe bet if the next FPU user is unknown.
-            // However, this is more about MMX instruction usage than state save/restore itself.
-            // If the task itself is responsible for EMMS before calling FPU ops, then not needed here.
-            // If this task was the last MMX user, EMMS.
-            // if self.fpu_state.format != FpuStateFormat::FSAVE && has_mmx() {
-            //    asm!("emms", options(nomem, nostack, preserves_flags));
-            // }
+/// \brief Start `p` over with clean FPU registers, for `exec`.
+///
+/// # Safety
+/// `p` must be the current process.
+#[no_mangle]
+pub unsafe extern "C" fn fpureset(p: *mut Proc) {
+    flush(p, false);
+    area(p).reset();
+}
+
+/// \brief Start a section of kernel code that uses the FPU.
+///
+/// Interrupts stay disabled until the matching [`kernel_end`], so the
+/// section cannot migrate or be preempted. The outermost section saves the
+/// registers of the process that owns the FPU, which then reloads them
+/// through #NM. Sections nest.
+///
+/// # Safety
+/// Must be paired with [`kernel_end`] on the same CPU.
+pub unsafe fn kernel_begin() {
+    pushcli(); // Undone by `kernel_end`.
+    let g = PreemptGuard::new();
+    let f = FPU.get(&g);
+    if f.nest.get() == 0 {
+        f.ts.set(cr0().contains(Cr0::CR0_TASK_SWITCHED));
+        clts();
+        if !f.owner.get().is_null() {
+            area(f.owner.get()).save();
+            f.owner.set(ptr::null_mut());
+            f.ts.set(true);
         }
     }
+    f.nest.set(f.nest.get() + 1);
+}
 
-    /// Returns the format of the saved FPU state.
-    pub fn format(&self) -> FpuStateFormat {
-        self.fpu_state.format
+/// \brief End a section started by [`kernel_begin`].
+///
+/// The outermost section puts CR0.TS back, so the next process to use the
+/// FPU reloads its own registers.
+///
+/// # Safety
+/// Must follow a [`kernel_begin`] on the same CPU.
+pub unsafe fn kernel_end() {
+    let g = PreemptGuard::new();
+    let f = FPU.get(&g);
+    if f.nest.get() < 1 {
+        panic(c"kernel_fpu_end".as_ptr());
+    }
+    f.nest.set(f.nest.get() - 1);
+    if f.nest.get() == 0 && f.ts.get() {
+        stts();
     }
+    drop(g);
+    popcli();
 }
@@ -1 +1,413 @@
-pub const NDIRECT: usize = 12;
+//! \file fs.rs
+//! \brief On-disk file system format, as in `fs.h`, and the mapping of
+//! file blocks to disk blocks that replaces `bmap` and `itrunc` in `fs.c`.
+//!
+//! An inode's `addrs` start with direct block addresses; each remaining
+//! entry is the root of a tree of indirect blocks, one level deeper than the
+//! one before. Version 2 file systems have `NDIRECT` direct blocks followed
+//! by a singly-, a doubly- and a triply-indirect tree. Version 1 file
+//! systems, made before the superblock recorded a version, have 12 direct
+//! blocks and a single indirect block; they are mounted read-only.
+
+use crate::bio::BCACHE;
+use crate::file::Inode;
+use crate::param::LOGSIZE;
+#[cfg(not(feature = "hosted"))]
+use crate::vfs::xv6fs;
+use core::mem::{offset_of, size_of};
+use zerocopy::{FromBytes, Immutable, IntoBytes as AsBytes, KnownLayout};
+
+/// \brief Inode number of the root directory.
+pub const ROOTINO: u32 = 1;
+/// \brief Block size in bytes.
+pub const BSIZE: usize = 512;
+/// \brief Block addresses in an inode: direct ones, then indirect roots.
+pub const NADDRS: usize = 13;
+/// \brief Block addresses held directly in an inode.
+pub const NDIRECT: usize = NADDRS - 3;
+/// \brief Block addresses held in an indirect block.
+pub const NINDIRECT: usize = BSIZE / size_of::<u32>();
+/// \brief Largest file, in blocks.
+pub const MAXFILE: usize = Format::V2.maxfile();
+
+/// \brief `Superblock::magic` of file systems that record their version.
+pub const FSMAGIC: u32 = 0x1020_3040;
+/// \brief Format version written by `mkfs`.
+pub const FSVERSION: u32 = 2;
+
+/// \brief Disk layout, byte for byte `struct superblock`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, FromBytes, AsBytes, Immutable, KnownLayout)]
+pub struct Superblock {
+    /// \brief Size of file system image (blocks).
+    pub size: u32,
+    /// \brief Number of data blocks.
+    pub nblocks: u32,
+    /// \brief Number of inodes.
+    pub ninodes: u32,
+    /// \brief Number of log blocks.
+    pub nlog: u32,
+    /// \brief Block number of first log block.
+    pub logstart: u32,
+    /// \brief Block number of first inode block.
+    pub inodestart: u32,
+    /// \brief Block number of first free map block.
+    pub bmapstart: u32,
+    /// \brief [`FSMAGIC`]; 0 on version 1 file systems.
+    pub magic: u32,
+    /// \brief On-disk format version.
+    pub version: u32,
+}
+
+impl Superblock {
+    /// \brief The file system's format, or `None` if it is too new.
+    pub fn format(&self) -> Option<Format> {
+        match (self.magic, self.version) {
+            (FSMAGIC, 2) => Some(Format::V2),
+            (FSMAGIC, _) => None,
+            _ => Some(Format::V1),
+        }
+    }
+
+    /// \brief Whether the regions `mkfs` lays out fit in order in `size`
+    /// blocks: boot block, superblock, log, inodes, free map, then data.
+    /// A device holding anything else is not taken for a file system.
+    pub fn fits(&self) -> bool {
+        let [size, nblocks, ninodes, nlog, logstart, inodestart, bmapstart] =
+            [self.size, self.nblocks, self.ninodes, self.nlog, self.logstart, self.inodestart, self.bmapstart]
+                .map(u64::from);
+        let (ipb, bpb) = (IPB as u64, BPB as u64);
+        logstart >= 2
+            && nlog >= LOGSIZE as u64
+            && logstart + nlog <= inodestart
+            && ninodes > u64::from(ROOTINO)
+            && inodestart + (ninodes - 1) / ipb < bmapstart
+            && bmapstart < size
+            && bmapstart + (size - 1) / bpb < size
+            && nblocks <= size
+    }
+}
+
+/// \brief How an inode's `addrs` map file blocks to disk blocks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// \brief 12 direct blocks and one indirect block; read-only.
+    V1,
+    /// \brief [`NDIRECT`] direct blocks and indirect trees of depth 1 to 3.
+    V2,
+}
+
+impl Format {
+    /// \brief Direct block addresses in an inode.
+    pub const fn ndirect(self) -> usize {
+        match self {
+            Format::V1 => 12,
+            Format::V2 => NDIRECT,
+        }
+    }
+
+    /// \brief Depth of the deepest indirect tree.
+    pub const fn depth(self) -> u32 {
+        match self {
+            Format::V1 => 1,
+            Format::V2 => 3,
+        }
+    }
+
+    /// \brief Largest file, in blocks.
+    pub const fn maxfile(self) -> usize {
+        let mut n = self.ndirect();
+        let mut depth = 1;
+        while depth <= self.depth() {
+            n += NINDIRECT.pow(depth);
+            depth += 1;
+        }
+        n
+    }
+
+    /// \brief Whether this kernel writes file systems of this format.
+    pub const fn writable(self) -> bool {
+        matches!(self, Format::V2)
+    }
+
+    /// \brief Depth of the tree rooted at `addrs[slot]`; 0 for a data block.
+    fn depth_of(self, slot: usize) -> u32 {
+        slot.saturating_sub(self.ndirect() - 1) as u32
+    }
+
+    /// \brief Where file block `bn` lives: the slot of `addrs` holding its
+    /// tree, and its index among the tree's data blocks.
+    fn locate(self, bn: usize) -> Option<(usize, usize)> {
+        if bn < self.ndirect() {
+            return Some((bn, 0));
+        }
+        let mut bn = bn - self.ndirect();
+        for depth in 1..=self.depth() {
+            let span = NINDIRECT.pow(depth);
+            if bn < span {
+                return Some((self.ndirect() + depth as usize - 1, bn));
+            }
+            bn -= span;
+        }
+        None
+    }
+}
+
+/// \brief On-disk inode, byte for byte `struct dinode`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, FromBytes, AsBytes, Immutable, KnownLayout)]
+pub struct Dinode {
+    /// \brief File type; 0 marks a free inode.
+    pub itype: i16,
+    /// \brief Major device number (`T_DEV` only).
+    pub major: i16,
+    /// \brief Minor device number (`T_DEV` only).
+    pub minor: i16,
+    /// \brief Number of directory entries linking to the inode.
+    pub nlink: i16,
+    /// \brief Size of file in bytes.
+    pub size: u32,
+    /// \brief Data block addresses, then indirect tree roots.
+    pub addrs: [u32; NADDRS],
+}
+
+/// \brief Longest directory entry name.
+pub const DIRSIZ: usize = 14;
+
+/// \brief Directory entry, byte for byte `struct dirent`; a directory is
+/// a sequence of them.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, FromBytes, AsBytes, Immutable, KnownLayout)]
+pub struct Dirent {
+    /// \brief Inode number; 0 marks a free entry.
+    pub inum: u16,
+    /// \brief Name, NUL-padded if shorter than `DIRSIZ`.
+    pub name: [u8; DIRSIZ],
+}
+
+/// \brief Inodes per block.
+pub const IPB: usize = BSIZE / size_of::<Dinode>();
+/// \brief Bits of the free map per block.
+pub const BPB: usize = BSIZE * 8;
+
+// Same layout as `struct dinode`; `fs.c` checks the C side.
+const _: () = assert!(size_of::<Dinode>() == 64);
+const _: () = assert!(offset_of!(Dinode, nlink) == 6 && offset_of!(Dinode, size) == 8);
+const _: () = assert!(offset_of!(Dinode, addrs) == 12);
+const _: () = assert!(BSIZE.is_multiple_of(size_of::<Dinode>()));
+const _: () = assert!(size_of::<Superblock>() == 36);
+const _: () = assert!(size_of::<Dirent>() == 16);
+// Both formats fit the same inode, and file sizes fit in `Dinode::size`.
+const _: () = assert!(Format::V1.ndirect() + Format::V1.depth() as usize == NADDRS);
+const _: () = assert!(NDIRECT + Format::V2.depth() as usize == NADDRS);
+const _: () = assert!(MAXFILE * BSIZE <= u32::MAX as usize);
+
+/// \brief Disk block holding block `bn` of `ip` in format `fmt`, allocating
+/// it and the indirect blocks leading to it if they are missing.
+///
+/// The caller holds `ip.lock`, and is inside a transaction if anything may
+/// be allocated.
+pub fn map_block(ip: &mut Inode, bn: usize, fmt: Format) -> u32 {
+    let Some((slot, mut idx)) = fmt.locate(bn) else {
+        panic!("bmap: out of range");
+    };
+    let dev = ip.dev;
+    if ip.addrs[slot] == 0 {
+        ip.addrs[slot] = alloc(dev, fmt);
+    }
+    let mut addr = ip.addrs[slot];
+    for level in (0..fmt.depth_of(slot)).rev() {
+        let span = NINDIRECT.pow(level);
+        let mut b = BCACHE.read(dev, addr);
+        let entries = <[u32; NINDIRECT]>::mut_from_bytes(b.data_mut()).unwrap();
+        let e = idx / span;
+        idx %= span;
+        addr = entries[e];
+        if addr == 0 {
+            addr = alloc(dev, fmt);
+            entries[e] = addr;
+            b.log_write();
+        }
+    }
+    addr
+}
+
+/// \brief Free every block of `ip` and set its size to 0, as
+/// `itrunc` did.
+///
+/// Only called when the inode has no links to it (no directory entries
+/// referring to it) and no in-memory reference to it (is not an open file
+/// or current directory). The caller holds `ip.lock` inside a transaction.
+pub fn truncate(ip: &mut Inode, fmt: Format) {
+    if !fmt.writable() {
+        panic!("itrunc: read-only file system");
+    }
+    let dev = ip.dev;
+    for (slot, a) in ip.addrs.iter_mut().enumerate() {
+        if *a != 0 {
+            free_tree(dev, *a, fmt.depth_of(slot));
+            *a = 0;
+        }
+    }
+    ip.size = 0;
+    unsafe { xv6fs_iupdate(ip) };
+}
+
+/// \brief Allocate a zeroed block for a file.
+fn alloc(dev: u32, fmt: Format) -> u32 {
+    if !fmt.writable() {
+        panic!("bmap: read-only file system");
+    }
+    unsafe { balloc(dev) }
+}
+
+/// \brief Free block `addr` and, if it is an indirect block of a tree of
+/// depth `depth`, the blocks below it.
+fn free_tree(dev: u32, addr: u32, depth: u32) {
+    if depth > 0 {
+        let b = BCACHE.read(dev, addr);
+        let entries = <[u32; NINDIRECT]>::ref_from_bytes(b.data()).unwrap();
+        for &e in entries.iter().filter(|&&e| e != 0) {
+            free_tree(dev, e, depth - 1);
+        }
+    }
+    unsafe { bfree(dev, addr) };
+}
+
+/// \brief Disk block holding block `bn` of `ip`, in the format of the
+/// file system on its device; see [`map_block`].
+///
+/// # Safety
+/// `ip` must point to an inode locked by the caller.
+#[no_mangle]
+#[cfg(not(feature = "hosted"))]
+pub unsafe extern "C" fn bmap(ip: *mut Inode, bn: u32) -> u32 {
+    let fmt = xv6fs::mounted((*ip).dev).format();
+    map_block(&mut *ip, bn as usize, fmt)
+}
+
+/// \brief Discard the contents of `ip`; see [`truncate`].
+///
+/// # Safety
+/// `ip` must point to an inode locked by the caller.
+#[no_mangle]
+#[cfg(not(feature = "hosted"))]
+pub unsafe extern "C" fn itrunc(ip: *mut Inode) {
+    let fmt = xv6fs::mounted((*ip).dev).format();
+    truncate(&mut *ip, fmt);
+}
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    fn balloc(dev: u32) -> u32;
+    fn bfree(dev: u32, b: u32);
+    fn xv6fs_iupdate(ip: *mut Inode);
+}
+
+#[cfg(feature = "hosted")]
+use crate::hosted::{balloc, bfree, xv6fs_iupdate};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hosted::allocated;
+
+    #[test]
+    fn dinode_reads_the_c_byte_layout() {
+        let mut block = [0u8; BSIZE];
+        let second = size_of::<Dinode>()..2 * size_of::<Dinode>();
+        let raw = &mut block[second.clone()];
+        raw[0..8].copy_from_slice(&[3, 0, 1, 0, 2, 0, 1, 0]);
+        raw[8..12].copy_from_slice(&1234u32.to_le_bytes());
+        raw[60..64].copy_from_slice(&77u32.to_le_bytes());
+
+        let inodes = <[Dinode; IPB]>::ref_from_bytes(&block).unwrap();
+        let d = &inodes[1];
+        assert_eq!((d.itype, d.major, d.minor, d.nlink, d.size), (3, 1, 2, 1, 1234));
+        assert_eq!(d.addrs[NADDRS - 1], 77);
+        assert_eq!(d.as_bytes(), &block[second]);
+    }
+
+    #[test]
+    fn superblock_records_the_format() {
+        let old = Superblock { size: 1000, ninodes: 200, ..Default::default() };
+        assert_eq!(old.format(), Some(Format::V1));
+        let new = Superblock { magic: FSMAGIC, version: FSVERSION, ..old };
+        assert_eq!(new.format(), Some(Format::V2));
+        assert_eq!(Superblock { version: 3, ..new }.format(), None);
+        assert_eq!(Format::V1.maxfile(), 12 + 128);
+        assert_eq!(MAXFILE, 10 + 128 + 128 * 128 + 128 * 128 * 128);
+    }
+
+    #[test]
+    fn superblock_layout_must_fit() {
+        // As `mkfs` lays out 1000 blocks with 200 inodes.
+        let sb = Superblock {
+            size: 1000,
+            nblocks: 911,
+            ninodes: 200,
+            nlog: LOGSIZE as u32,
+            logstart: 2,
+            inodestart: 2 + LOGSIZE as u32,
+            bmapstart: 2 + LOGSIZE as u32 + 26,
+            magic: FSMAGIC,
+            version: FSVERSION,
+        };
+        assert!(sb.fits());
+        assert!(!Superblock { logstart: 1, ..sb }.fits());
+        assert!(!Superblock { nlog: 2, ..sb }.fits());
+        assert!(!Superblock { ninodes: 1000, ..sb }.fits());
+        assert!(!Superblock { size: sb.bmapstart, ..sb }.fits());
+        assert!(!Superblock { size: 0, ..sb }.fits());
+        assert!(!Superblock { inodestart: u32::MAX, ..sb }.fits());
+        assert!(!Superblock::default().fits());
+    }
+
+    #[test]
+    fn blocks_map_through_every_tree() {
+        let mut ip = Inode { dev: 20, ..Default::default() };
+        let far = [0, NDIRECT - 1, NDIRECT, NDIRECT + NINDIRECT, MAXFILE - 1];
+        let addrs: Vec<u32> = far.iter().map(|&bn| map_block(&mut ip, bn, Format::V2)).collect();
+        // Direct: 2 blocks. Single: 1 + 1. Double: 1 + 1 + 1. Triple: 1 + 3.
+        assert_eq!(allocated(20), 2 + 2 + 3 + 4);
+        assert!(ip.addrs[NDIRECT..].iter().all(|&a| a != 0));
+        let again: Vec<u32> = far.iter().map(|&bn| map_block(&mut ip, bn, Format::V2)).collect();
+        assert_eq!(addrs, again);
+        assert_eq!(allocated(20), 11);
+
+        truncate(&mut ip, Format::V2);
+        assert_eq!(allocated(20), 0);
+        assert_eq!((ip.size, ip.addrs), (0, [0; NADDRS]));
+    }
+
+    #[test]
+    fn version_1_inodes_have_12_direct_blocks() {
+        let mut ip = Inode { dev: 21, ..Default::default() };
+        for (i, a) in ip.addrs.iter_mut().enumerate() {
+            *a = 100 + i as u32;
+        }
+        let mut ind = BCACHE.read(21, 112);
+        ind.data_mut()[..8].copy_from_slice([500u32, 501].as_bytes());
+        ind.write();
+        drop(ind);
+
+        assert_eq!(map_block(&mut ip, 11, Format::V1), 111);
+        assert_eq!(map_block(&mut ip, 12, Format::V1), 500);
+        assert_eq!(map_block(&mut ip, 13, Format::V1), 501);
+        assert_eq!(allocated(21), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "read-only")]
+    fn version_1_blocks_are_never_allocated() {
+        let mut ip = Inode { dev: 22, ..Default::default() };
+        map_block(&mut ip, 0, Format::V1);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn blocks_past_maxfile_panic() {
+        let mut ip = Inode { dev: 23, ..Default::default() };
+        map_block(&mut ip, Format::V1.maxfile(), Format::V1);
+    }
+}
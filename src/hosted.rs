@@ -0,0 +1,287 @@
+//! \file hosted.rs
+//! \brief Stand-ins for C kernel symbols when built on the host.
+//!
+//! Only compiled with the `hosted` feature. Each item here replaces a
+//! function or variable that the C half of xv6 normally provides, with the
+//! simplest behaviour that lets the Rust modules run in a test binary.
+
+use crate::bio::{bwrite, Buf, B_DIRTY, B_VALID};
+use crate::file::Inode;
+use crate::fs::BSIZE;
+use crate::proc::{Cpu, Proc};
+use crate::sync::sleep_lock::Sleeplock;
+use crate::spinlock::Spinlock;
+use core::ffi::c_void;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// \brief Console lock normally defined in `console.c`.
+///
+/// Never dereferenced on the host because [`acquire`] and [`release`] are
+/// no-ops.
+#[no_mangle]
+pub static mut conslk: *const Spinlock = ptr::null();
+
+/// \brief Host replacement for `acquire` in `spinlock.c`.
+///
+/// Rust-side callers already serialise through their own locks, so nothing
+/// needs to happen here.
+#[no_mangle]
+pub extern "C" fn acquire(_s: *const Spinlock) {}
+
+/// \brief Host replacement for `release` in `spinlock.c`.
+#[no_mangle]
+pub extern "C" fn release(_s: *const Spinlock) {}
+
+/// \brief Host replacement for `consoleintr` in `console.c`.
+///
+/// The character source is never polled since there is no device behind it.
+#[no_mangle]
+pub extern "C" fn consoleintr(_getc: unsafe extern "C" fn() -> i32) {}
+
+/// \brief Host replacement for `ioapicenable` in `ioapic.c`.
+#[no_mangle]
+pub extern "C" fn ioapicenable(_irq: i32, _cpunum: i32) {}
+
+/// \brief Host replacement for `lapicipi` in `lapic.c`; no CPU halts on
+/// the host.
+///
+/// # Safety
+/// Always safe; `unsafe` only to match the kernel's signature.
+pub unsafe extern "C" fn lapicipi(_c: i32) {}
+
+/// \brief Host replacement for `pushcli` in `spinlock.rs`; there are no
+/// interrupts to disable.
+///
+/// # Safety
+/// Always safe; `unsafe` only to match the kernel's signature.
+pub unsafe extern "C" fn pushcli() {}
+
+/// \brief Host replacement for `popcli` in `spinlock.rs`.
+///
+/// # Safety
+/// Always safe; `unsafe` only to match the kernel's signature.
+pub unsafe extern "C" fn popcli() {}
+
+/// \brief Host replacement for `getcallerpcs` in `spinlock.c`.
+///
+/// Host frames are not laid out like the kernel's, so no PCs are recorded.
+///
+/// # Safety
+/// `pcs` must be valid for writing 10 values.
+#[no_mangle]
+pub unsafe extern "C" fn getcallerpcs(_v: *const c_void, pcs: *mut u32) {
+    ptr::write_bytes(pcs, 0, 10);
+}
+
+std::thread_local! {
+    static CPU: *mut Cpu = Box::into_raw(Box::new(MaybeUninit::<Cpu>::zeroed())) as *mut Cpu;
+}
+
+/// \brief Host replacement for `mycpu` in `proc.rs`.
+///
+/// Every thread acts as a CPU of its own, so locks can tell holders apart.
+/// The `Cpu` is zeroed and never freed.
+///
+/// # Safety
+/// Only its address and the fields written through it are meaningful.
+pub unsafe extern "C" fn mycpu() -> *mut Cpu {
+    CPU.with(|c| *c)
+}
+
+std::thread_local! {
+    static PROC: *mut Proc = {
+        static NEXTPID: AtomicU32 = AtomicU32::new(1);
+        let p = Box::into_raw(Box::new(MaybeUninit::<Proc>::zeroed())) as *mut Proc;
+        unsafe { (*p).pid = NEXTPID.fetch_add(1, Ordering::Relaxed) };
+        p
+    };
+}
+
+/// \brief Host replacement for `myproc` in `proc.rs`.
+///
+/// Every thread acts as a process of its own with a distinct PID. The
+/// `Proc` is otherwise zeroed and never freed.
+///
+/// # Safety
+/// Only `pid`, `killed` and fields written through it are meaningful.
+pub unsafe extern "C" fn myproc() -> *mut Proc {
+    PROC.with(|p| *p)
+}
+
+/// \brief Host replacement for `sleep` in `proc.rs`.
+///
+/// Threads cannot be parked on a channel here, so this only lets others
+/// run with `lk` released; callers re-check their condition anyway.
+///
+/// # Safety
+/// `lk` must be null or a lock held by this thread.
+pub unsafe extern "C" fn sleep(_chan: *const c_void, lk: *const Spinlock) {
+    if !lk.is_null() {
+        (*lk).release();
+    }
+    std::thread::yield_now();
+    if !lk.is_null() {
+        (*lk).acquire();
+    }
+}
+
+/// \brief Host replacement for `wakeup` in `proc.rs`; sleepers never block.
+///
+/// # Safety
+/// Always safe; `unsafe` only to match the kernel's signature.
+pub unsafe extern "C" fn wakeup(_chan: *const c_void) {}
+
+/// \brief Host replacement for `wakeup_one` in `proc.rs`.
+///
+/// # Safety
+/// Always safe; `unsafe` only to match the kernel's signature.
+pub unsafe fn wakeup_one(_chan: *const c_void) -> bool {
+    false
+}
+
+/// \brief A slot in `cpus` held by a thread until it exits.
+struct CpuSlot(usize);
+
+static CPU_SLOTS: AtomicU32 = AtomicU32::new(0);
+
+impl Drop for CpuSlot {
+    fn drop(&mut self) {
+        CPU_SLOTS.fetch_and(!(1 << self.0), Ordering::Relaxed);
+    }
+}
+
+std::thread_local! {
+    static CPU_INDEX: CpuSlot = loop {
+        let used = CPU_SLOTS.load(Ordering::Relaxed);
+        let free = (!used).trailing_zeros() as usize;
+        assert!(free < crate::param::NCPU, "hosted: more threads than NCPU use per-CPU data");
+        if CPU_SLOTS.compare_exchange(used, used | 1 << free, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            break CpuSlot(free);
+        }
+    };
+}
+
+/// \brief Host replacement for reading `%gs:Cpu::id` in `percpu.rs`.
+///
+/// Live threads that use per-CPU data get distinct indices below `NCPU`.
+pub fn cpu_index() -> usize {
+    CPU_INDEX.with(|s| s.0)
+}
+
+/// \brief Host replacement for `acquiresleep` in `sleeplock.c`.
+///
+/// # Safety
+/// `lk` must point to a live sleep lock.
+pub unsafe extern "C" fn acquiresleep(lk: *mut Sleeplock) {
+    (*lk).lk.acquire();
+    while ptr::read_volatile(ptr::addr_of!((*lk).locked)) != 0 {
+        sleep(lk as *const c_void, &(*lk).lk);
+    }
+    (*lk).locked = 1;
+    (*lk).pid = (*myproc()).pid as i32;
+    (*lk).lk.release();
+}
+
+/// \brief Host replacement for `releasesleep` in `sleeplock.c`.
+///
+/// # Safety
+/// `lk` must point to a live sleep lock.
+pub unsafe extern "C" fn releasesleep(lk: *mut Sleeplock) {
+    (*lk).lk.acquire();
+    (*lk).locked = 0;
+    (*lk).pid = 0;
+    (*lk).lk.release();
+}
+
+/// \brief Host replacement for `holdingsleep` in `sleeplock.c`.
+///
+/// # Safety
+/// `lk` must point to a live sleep lock.
+pub unsafe extern "C" fn holdingsleep(lk: *mut Sleeplock) -> i32 {
+    (*lk).lk.acquire();
+    let r = (*lk).locked;
+    (*lk).lk.release();
+    r as i32
+}
+
+/// \brief Blocks written by [`iderw`], by device and block number.
+static DISK: std::sync::Mutex<std::collections::BTreeMap<(u32, u32), [u8; BSIZE]>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+/// \brief Host replacement for `iderw` in `ide.c`: a disk in memory,
+/// zero-filled until written, that completes requests at once.
+///
+/// # Safety
+/// `b` must point to a buffer held by the caller.
+pub unsafe extern "C" fn iderw(b: *mut Buf) {
+    let mut disk = DISK.lock().unwrap();
+    let key = ((*b).dev, (*b).blockno);
+    if (*b).flags & B_DIRTY != 0 {
+        disk.insert(key, (*b).data);
+        (*b).flags &= !B_DIRTY;
+    } else {
+        (*b).data = disk.get(&key).copied().unwrap_or([0; BSIZE]);
+    }
+    (*b).flags |= B_VALID;
+}
+
+/// \brief Host replacement for `log_write` in `log.c`: there is no log, so
+/// the block goes straight to the disk.
+///
+/// # Safety
+/// `b` must point to a buffer held by the caller.
+pub unsafe extern "C" fn log_write(b: *mut Buf) {
+    bwrite(b);
+}
+
+/// \brief Host replacement for `begin_op` in `log.c`; there is no log.
+///
+/// # Safety
+/// None; `unsafe` only to match the C function it replaces.
+pub unsafe extern "C" fn begin_op() {}
+
+/// \brief Host replacement for `end_op` in `log.c`.
+///
+/// # Safety
+/// None; `unsafe` only to match the C function it replaces.
+pub unsafe extern "C" fn end_op() {}
+
+/// \brief Blocks handed out by [`balloc`] and not yet freed.
+static ALLOCATED: std::sync::Mutex<std::collections::BTreeSet<(u32, u32)>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+/// \brief Host replacement for `balloc` in `fs.c`. Block numbers are never
+/// reused, so every block handed out is still zero on the disk.
+///
+/// # Safety
+/// None; `unsafe` only to match the C function it replaces.
+pub unsafe extern "C" fn balloc(dev: u32) -> u32 {
+    static NEXT: AtomicU32 = AtomicU32::new(1);
+    let b = NEXT.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED.lock().unwrap().insert((dev, b));
+    b
+}
+
+/// \brief Host replacement for `bfree` in `fs.c`.
+///
+/// # Safety
+/// None; `unsafe` only to match the C function it replaces.
+pub unsafe extern "C" fn bfree(dev: u32, b: u32) {
+    if !ALLOCATED.lock().unwrap().remove(&(dev, b)) {
+        panic!("freeing free block");
+    }
+}
+
+/// \brief Blocks of `dev` allocated by [`balloc`] and not freed.
+pub fn allocated(dev: u32) -> usize {
+    ALLOCATED.lock().unwrap().range((dev, 0)..=(dev, u32::MAX)).count()
+}
+
+/// \brief Host replacement for `xv6fs_iupdate` in `fs.c`; inodes are not
+/// kept on the host disk.
+///
+/// # Safety
+/// None; `ip` is not used.
+pub unsafe extern "C" fn xv6fs_iupdate(_ip: *mut Inode) {}
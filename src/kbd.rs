@@ -70,8 +70,8 @@ static NORMALMAP: [u8; 256] = [
     NO, NO, NO, NO, b'\n', NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0xA0
     NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, b'/', NO, NO, // 0xB0
     NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0xC0
-    KEY_UP, KEY_PGUP, NO, KEY_LF, NO, NO, NO, KEY_END, KEY_DN, KEY_PGDN, KEY_INS, KEY_DEL, NO,
-    KEY_RT, NO, NO, // 0xD0
+    KEY_UP, KEY_PGUP, NO, KEY_LF, NO, KEY_RT, NO, KEY_END, KEY_DN, KEY_PGDN, KEY_INS, KEY_DEL, NO,
+    NO, NO, NO, // 0xD0
     NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0xE0
     NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0xF0
     NO, NO, NO, NO, NO, NO, NO, NO,
@@ -79,7 +79,7 @@ static NORMALMAP: [u8; 256] = [
 
 #[rustfmt::skip]
 static SHIFTMAP: [u8; 256] = [
-    NO, 033, b'!', b'@', b'#', b'$', b'%', b'^', // 0x00
+    NO, 0x1B, b'!', b'@', b'#', b'$', b'%', b'^', // 0x00
     b'&', b'*', b'(', b')', b'_', b'+', 0x08, b'\t', b'Q', b'W', b'E', b'R', b'T', b'Y', b'U',
     b'I', // 0x10
     b'O', b'P', b'{', b'}', b'\n', NO, b'A', b'S', b'D', b'F', b'G', b'H', b'J', b'K', b'L',
@@ -96,8 +96,8 @@ static SHIFTMAP: [u8; 256] = [
     NO, NO, NO, NO, b'\n', NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0xA0
     NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, b'/', NO, NO, // 0xB0
     NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0xC0
-    KEY_UP, KEY_PGUP, NO, KEY_LF, NO, NO, NO, KEY_END, KEY_DN, KEY_PGDN, KEY_INS, KEY_DEL, NO,
-    KEY_RT, NO, NO, // 0xD0
+    KEY_UP, KEY_PGUP, NO, KEY_LF, NO, KEY_RT, NO, KEY_END, KEY_DN, KEY_PGDN, KEY_INS, KEY_DEL, NO,
+    NO, NO, NO, // 0xD0
     NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0xE0
     NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0xF0
     NO, NO, NO, NO, NO, NO, NO, NO,
@@ -310,7 +310,7 @@ static CTLMAP: [u8; 256] = [
     NO,
     KEY_LF,
     NO,
-    NO,
+    KEY_RT,
     NO,
     KEY_END,
     KEY_DN,
@@ -318,7 +318,7 @@ static CTLMAP: [u8; 256] = [
     KEY_INS,
     KEY_DEL,
     NO,
-    KEY_RT,
+    NO,
     NO,
     NO, // 0xD0
     NO,
@@ -366,7 +366,7 @@ static CTLMAP: [u8; 256] = [
 #[rustfmt::skip]
 static SHIFTCODE: [u8; 256] = [
     NO, NO, NO, NO, NO, NO, NO, NO, // 0x00
-    NO, NO, NO, NO, NO, CTL, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0x10
+    NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0x10
     NO, NO, NO, NO, NO, CTL, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0x20
     NO, NO, SHIFT, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, SHIFT, NO, // 0x30
     ALT, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, NO, // 0x40
@@ -420,39 +420,202 @@ pub extern "C" fn kbdgetc() -> i32 {
     if st & KBS_DIB == 0 {
         return -1;
     }
-    let mut data = unsafe { inb(KBDATAP as u16) };
+    let data = unsafe { inb(KBDATAP as u16) };
+    let mut state = SHIFT_VAR.load(Ordering::SeqCst);
+    let c = kbd_decode(&mut state, data);
+    SHIFT_VAR.store(state, Ordering::SeqCst);
+    c
+}
+
+/// \brief Translate one scan code byte into a character.
+///
+/// Pure decoding step behind [`kbdgetc`]: `state` carries the modifier,
+/// toggle and E0-escape bits between calls and is updated in place.
+///
+/// \param state Modifier state from the previous call.
+/// \param data Raw byte read from the keyboard data port.
+/// \return Character code, or 0 for modifier, release and escape bytes.
+pub fn kbd_decode(state: &mut usize, mut data: u8) -> i32 {
     if data == 0xE0 {
-        SHIFT_VAR.fetch_or(E0ESC, Ordering::SeqCst);
+        *state |= E0ESC;
         return 0;
     } else if data & 0x80 != 0 {
         // Key released
-        data = if SHIFT_VAR.load(Ordering::SeqCst) & E0ESC != 0 {
-            data
-        } else {
-            data & 0x7F
-        };
-        SHIFT_VAR.fetch_and(
-            !(SHIFTCODE[data as usize] as usize | E0ESC),
-            Ordering::SeqCst,
-        );
+        data = if *state & E0ESC != 0 { data } else { data & 0x7F };
+        *state &= !(SHIFTCODE[data as usize] as usize | E0ESC);
         return 0;
-    } else if SHIFT_VAR.load(Ordering::SeqCst) & E0ESC != 0 {
+    } else if *state & E0ESC != 0 {
         // Last character was an E0 escape; or with 0x80
         data |= 0x80;
-        SHIFT_VAR.fetch_and(!E0ESC, Ordering::SeqCst);
+        *state &= !E0ESC;
     }
-    let mut state = SHIFT_VAR.load(Ordering::SeqCst);
-    state |= SHIFTCODE[data as usize] as usize;
-    state ^= TOGGLECODE[data as usize] as usize;
-    SHIFT_VAR.store(state, Ordering::SeqCst);
+    *state |= SHIFTCODE[data as usize] as usize;
+    *state ^= TOGGLECODE[data as usize] as usize;
 
-    let mut c = CHARCODE[state & (CTL | SHIFT) as usize][data as usize];
-    if state & CAPSLOCK as usize != 0 {
-        if b'a' <= c && c <= b'z' {
-            c += 224 // 'A' - 'a'
-        } else if b'A' <= c && c <= b'Z' {
+    let mut c = CHARCODE[*state & (CTL | SHIFT) as usize][data as usize];
+    if *state & CAPSLOCK as usize != 0 {
+        if c.is_ascii_lowercase() {
+            c -= b'a' - b'A';
+        } else if c.is_ascii_uppercase() {
             c += b'a' - b'A';
         }
     }
     c as i32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed a sequence of scan codes and collect the non-zero results.
+    fn feed(state: &mut usize, codes: &[u8]) -> Vec<i32> {
+        codes
+            .iter()
+            .map(|&b| kbd_decode(state, b))
+            .filter(|&c| c != 0)
+            .collect()
+    }
+
+    #[test]
+    fn plain_letters_and_digits() {
+        let mut st = 0;
+        assert_eq!(feed(&mut st, &[0x10, 0x11, 0x12]), [b'q' as i32, b'w' as i32, b'e' as i32]);
+        assert_eq!(feed(&mut st, &[0x02, 0x0B]), [b'1' as i32, b'0' as i32]);
+        assert_eq!(st, 0);
+    }
+
+    #[test]
+    fn release_codes_produce_nothing() {
+        let mut st = 0;
+        assert_eq!(kbd_decode(&mut st, 0x1E), b'a' as i32);
+        assert_eq!(kbd_decode(&mut st, 0x9E), 0);
+        assert_eq!(st, 0);
+    }
+
+    #[test]
+    fn shift_is_held_until_released() {
+        let mut st = 0;
+        assert_eq!(kbd_decode(&mut st, 0x2A), 0); // left shift down
+        assert_eq!(kbd_decode(&mut st, 0x1E), b'A' as i32);
+        assert_eq!(kbd_decode(&mut st, 0x02), b'!' as i32);
+        assert_eq!(kbd_decode(&mut st, 0xAA), 0); // left shift up
+        assert_eq!(kbd_decode(&mut st, 0x1E), b'a' as i32);
+    }
+
+    #[test]
+    fn right_shift_matches_left() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0x36);
+        assert_eq!(kbd_decode(&mut st, 0x0D), b'+' as i32);
+        kbd_decode(&mut st, 0xB6);
+        assert_eq!(kbd_decode(&mut st, 0x0D), b'=' as i32);
+    }
+
+    #[test]
+    fn equals_key_is_not_a_modifier() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0x0D);
+        assert_eq!(st & CTL as usize, 0);
+    }
+
+    #[test]
+    fn escape_with_and_without_shift() {
+        let mut st = 0;
+        assert_eq!(kbd_decode(&mut st, 0x01), 0x1B);
+        kbd_decode(&mut st, 0x2A);
+        assert_eq!(kbd_decode(&mut st, 0x01), 0x1B);
+    }
+
+    #[test]
+    fn control_letters() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0x1D); // ctrl down
+        assert_eq!(kbd_decode(&mut st, 0x2E), 3); // ^C
+        assert_eq!(kbd_decode(&mut st, 0x20), 4); // ^D
+        assert_eq!(kbd_decode(&mut st, 0x1C), b'\r' as i32);
+        kbd_decode(&mut st, 0x9D); // ctrl up
+        assert_eq!(kbd_decode(&mut st, 0x2E), b'c' as i32);
+    }
+
+    #[test]
+    fn caps_lock_toggles_case() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0x3A); // caps lock press
+        kbd_decode(&mut st, 0xBA); // caps lock release keeps the toggle
+        assert_eq!(kbd_decode(&mut st, 0x1E), b'A' as i32);
+        kbd_decode(&mut st, 0x2A);
+        assert_eq!(kbd_decode(&mut st, 0x1E), b'a' as i32);
+        kbd_decode(&mut st, 0xAA);
+        kbd_decode(&mut st, 0x3A);
+        assert_eq!(kbd_decode(&mut st, 0x1E), b'a' as i32);
+    }
+
+    #[test]
+    fn caps_lock_leaves_digits_alone() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0x3A);
+        assert_eq!(kbd_decode(&mut st, 0x03), b'2' as i32);
+    }
+
+    #[test]
+    fn num_and_scroll_lock_toggle_state_bits() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0x45);
+        kbd_decode(&mut st, 0x46);
+        assert_eq!(st & (NUMLOCK | SCROLLLOCK) as usize, (NUMLOCK | SCROLLLOCK) as usize);
+        kbd_decode(&mut st, 0x45);
+        assert_eq!(st & NUMLOCK as usize, 0);
+    }
+
+    #[test]
+    fn e0_arrow_keys() {
+        let mut st = 0;
+        let keys = [
+            (0x48, KEY_UP),
+            (0x50, KEY_DN),
+            (0x4B, KEY_LF),
+            (0x4D, KEY_RT),
+            (0x4F, KEY_END),
+            (0x49, KEY_PGUP),
+            (0x51, KEY_PGDN),
+            (0x52, KEY_INS),
+            (0x53, KEY_DEL),
+        ];
+        for (code, key) in keys {
+            assert_eq!(kbd_decode(&mut st, 0xE0), 0);
+            assert_eq!(kbd_decode(&mut st, code), key as i32, "scan code {:#x}", code);
+            assert_eq!(st & E0ESC, 0);
+        }
+    }
+
+    #[test]
+    fn e0_release_clears_escape() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0xE0);
+        assert_eq!(kbd_decode(&mut st, 0xC8), 0); // up-arrow release
+        assert_eq!(st, 0);
+    }
+
+    #[test]
+    fn keypad_enter_and_divide() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0xE0);
+        assert_eq!(kbd_decode(&mut st, 0x1C), b'\n' as i32);
+        kbd_decode(&mut st, 0xE0);
+        assert_eq!(kbd_decode(&mut st, 0x35), b'/' as i32);
+    }
+
+    #[test]
+    fn right_ctrl_and_alt_via_e0() {
+        let mut st = 0;
+        kbd_decode(&mut st, 0xE0);
+        kbd_decode(&mut st, 0x1D);
+        assert_ne!(st & CTL as usize, 0);
+        kbd_decode(&mut st, 0xE0);
+        kbd_decode(&mut st, 0x9D);
+        assert_eq!(st & CTL as usize, 0);
+        kbd_decode(&mut st, 0xE0);
+        kbd_decode(&mut st, 0x38);
+        assert_ne!(st & ALT as usize, 0);
+    }
+}
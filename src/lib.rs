@@ -1,40 +1,71 @@
-#![no_std]
+#![cfg_attr(not(feature = "hosted"), no_std)]
 #![feature(portable_simd)]
-#![feature(thread_local)]
 #![feature(c_size_t)]
 //! \file lib.rs
 //! \brief Core kernel crate exposing C ABI entrypoints.
+//!
+//! With the `hosted` feature the crate links against `std` on the build
+//! machine instead of the kernel: modules that need real hardware or the C
+//! kernel are left out and [`hosted`] supplies stand-ins for the C symbols
+//! the remaining modules reference, so `cargo test --features hosted` can
+//! exercise the pure-logic code without booting QEMU.
 
 // Module uses rely on explicit macro imports in each file
 
+#[cfg(all(test, not(feature = "hosted")))]
+compile_error!("unit tests run on the host: use `cargo test --features hosted`");
+
 pub mod arch;
 #[macro_use]
 pub mod console;
+#[macro_use]
+pub mod percpu;
 pub mod allocator;
+pub mod bio;
+pub mod cow;
 pub mod cpu_features;
+pub mod errno;
+#[cfg(not(feature = "hosted"))]
+pub mod fault;
 pub mod file;
+#[cfg(not(feature = "hosted"))]
 pub mod fpu_state;
 pub mod fs;
+#[cfg(feature = "hosted")]
+pub mod hosted;
 pub mod ioapic;
 pub mod kbd;
 pub mod lapic;
+pub mod memlayout;
+pub mod mmap;
 pub mod mmu;
+pub mod namei;
 pub mod param;
 pub mod pipe;
 pub mod proc;
+pub mod sched;
+#[cfg(not(feature = "hosted"))]
 pub mod simd_integration;
 pub mod simd_mem;
 pub mod simd_string;
 pub mod spinlock;
 pub mod string;
 pub mod sync;
+#[cfg(not(feature = "hosted"))]
 pub mod syscall;
+#[cfg(not(feature = "hosted"))]
+pub mod sysfile;
+#[cfg(not(feature = "hosted"))]
 pub mod sysproc;
 pub mod trap;
 pub mod traps;
 pub mod types;
 pub mod uart;
+pub mod vfs;
+#[cfg(not(feature = "hosted"))]
+pub mod vm;
 
+#[cfg(not(feature = "hosted"))]
 use core::panic::PanicInfo;
 
 /// \brief Kernel entry point once memory and CPUs are initialized.
@@ -44,11 +75,47 @@ use core::panic::PanicInfo;
 #[no_mangle]
 pub unsafe extern "C" fn kmain() {
     cpu_features::init();
+    #[cfg(not(feature = "hosted"))]
+    simd_integration::init_simd_subsystem();
     println!("Hello from {}", "Rust");
 }
 
-/// \brief Minimal panic handler used during early bring-up.
+/// \brief Panic handler: hand the message to C `panic`.
+///
+/// Printing with `println!` would take `conslk`, which may be the lock
+/// whose misuse panicked or be held by this CPU already. C `panic` prints
+/// without it, dumps the caller PCs and freezes the other CPUs.
+#[cfg(not(feature = "hosted"))]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {}
+fn panic(info: &PanicInfo) -> ! {
+    extern "C" {
+        #[link_name = "panic"]
+        fn c_panic(s: *const core::ffi::c_char) -> !;
+    }
+    let mut msg = PanicMsg { buf: [0; 128], len: 0 };
+    let _ = core::fmt::Write::write_fmt(&mut msg, format_args!("{}", info));
+    unsafe { c_panic(msg.buf.as_ptr().cast()) }
+}
+
+/// \brief A panic message for C `panic`: NUL-terminated, cut short when
+/// full, with `%` doubled since `cprintf` treats it as a format.
+#[cfg(not(feature = "hosted"))]
+struct PanicMsg {
+    buf: [u8; 128],
+    len: usize,
+}
+
+#[cfg(not(feature = "hosted"))]
+impl core::fmt::Write for PanicMsg {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &c in s.as_bytes() {
+            let esc: &[u8] = if c == b'%' { b"%%" } else { core::slice::from_ref(&c) };
+            if self.len + esc.len() >= self.buf.len() {
+                return Err(core::fmt::Error);
+            }
+            self.buf[self.len..self.len + esc.len()].copy_from_slice(esc);
+            self.len += esc.len();
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,25 @@
+//! \file memlayout.rs
+//! \brief Physical and virtual memory layout, mirroring `memlayout.h`.
+
+/// \brief Start of extended memory.
+pub const EXTMEM: u32 = 0x0010_0000;
+/// \brief Top of physical memory.
+pub const PHYSTOP: u32 = 0x0E00_0000;
+/// \brief First kernel virtual address.
+pub const KERNBASE: u32 = 0x8000_0000;
+/// \brief Address where the kernel is linked.
+pub const KERNLINK: u32 = KERNBASE + EXTMEM;
+/// \brief Other devices are at high addresses.
+pub const DEVSPACE: u32 = 0xFE00_0000;
+
+/// \brief Kernel virtual address to physical address (`V2P`).
+#[inline]
+pub fn v2p<T>(a: *const T) -> u32 {
+    (a as usize as u32).wrapping_sub(KERNBASE)
+}
+
+/// \brief Physical address to kernel virtual address (`P2V`).
+#[inline]
+pub fn p2v<T>(pa: u32) -> *mut T {
+    pa.wrapping_add(KERNBASE) as usize as *mut T
+}
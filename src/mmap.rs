@@ -0,0 +1,504 @@
+//! \file mmap.rs
+//! \brief Memory-mapped regions: `mmap`, `munmap` and their page faults.
+//!
+//! Each process keeps up to `NVMA` regions in `Proc::vmas`, placed in the
+//! window between `MMAPBASE` and `KERNBASE` so they never meet the heap.
+//! Nothing is mapped by `mmap` itself: the first access to a page faults
+//! and [`fault`] maps a zeroed page, filled from the file for file-backed
+//! regions. The PTE is writeable only with `PROT_WRITE`; without PAE there
+//! is no no-execute bit, so `PROT_EXEC` adds nothing beyond readability.
+//!
+//! `MAP_SHARED` pages are shared with children after `fork`, and dirty
+//! pages of a shared file mapping are written back to the inode when the
+//! region is unmapped, on `exec` and on `exit`. `fork` first maps every
+//! page of an accessible shared region, since a page faulted in afterwards
+//! by either process would be its own. There is no page cache, so
+//! two unrelated processes mapping the same file each get their own copy.
+//! `MAP_PRIVATE` pages are copied on write after `fork` and never written
+//! back.
+
+use crate::errno::Errno;
+use crate::file::File;
+use crate::memlayout::KERNBASE;
+use crate::mmu::{pg_round_up, PteFlags, PGSIZE};
+use core::ptr;
+
+#[cfg(not(feature = "hosted"))]
+use crate::{
+    cow,
+    file::Inode,
+    memlayout::p2v,
+    mmu::{pg_round_down, Frames, PageDirectory, Pte},
+    param::NVMA,
+    percpu::this_cpu,
+    proc::Proc,
+    vfs::{ilock, iunlock, readi, stati, writei, Stat},
+    vm::{self, switchuvm, KernelFrames},
+};
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    fn filedup(f: *mut File) -> *mut File;
+    fn fileclose(f: *mut File);
+    fn begin_op();
+    fn end_op();
+}
+
+/// \brief Pages may not be accessed.
+pub const PROT_NONE: u32 = 0x0;
+/// \brief Pages may be read.
+pub const PROT_READ: u32 = 0x1;
+/// \brief Pages may be written.
+pub const PROT_WRITE: u32 = 0x2;
+/// \brief Pages may be executed.
+pub const PROT_EXEC: u32 = 0x4;
+
+/// \brief Share the mapping with the file and with children.
+pub const MAP_SHARED: u32 = 0x01;
+/// \brief Private copy-on-write mapping.
+pub const MAP_PRIVATE: u32 = 0x02;
+/// \brief Not backed by a file; the descriptor is ignored.
+pub const MAP_ANONYMOUS: u32 = 0x20;
+
+/// \brief Lowest address handed out by `mmap`; the heap stops below it.
+pub const MMAPBASE: u32 = 0x4000_0000;
+
+/// \brief `type` of an open file backed by an inode (`FD_INODE` in `file.h`).
+#[cfg(not(feature = "hosted"))]
+const FD_INODE: i32 = 2;
+
+/// \brief Bytes written to the log per transaction, as in `filewrite`.
+#[cfg(not(feature = "hosted"))]
+const MAXWRITE: u32 = ((crate::param::MAXOPBLOCKS as u32 - 1 - 2 * 5 - 2) / 2) * 512;
+
+/// \brief One mapped region. Layout mirrors `struct vma` in `proc.h`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    /// First address, page aligned.
+    pub start: u32,
+    /// Length in bytes, a multiple of `PGSIZE`; 0 if the slot is free.
+    pub len: u32,
+    /// `PROT_*` bits.
+    pub prot: u32,
+    /// `MAP_*` bits.
+    pub flags: u32,
+    /// Backing file (holding a reference), or null when anonymous.
+    pub file: *mut File,
+    /// File offset of `start`.
+    pub off: u32,
+}
+
+impl Vma {
+    /// \brief A free slot.
+    pub const EMPTY: Vma = Vma { start: 0, len: 0, prot: 0, flags: 0, file: ptr::null_mut(), off: 0 };
+
+    /// \brief Whether the slot holds a region.
+    pub fn is_used(&self) -> bool {
+        self.len != 0
+    }
+
+    /// \brief One past the last address.
+    pub fn end(&self) -> u32 {
+        self.start + self.len
+    }
+
+    /// \brief Whether `va` lies in the region.
+    pub fn contains(&self, va: u32) -> bool {
+        self.is_used() && self.start <= va && va < self.end()
+    }
+
+    /// \brief Whether dirty pages go back to the file.
+    pub fn writes_back(&self) -> bool {
+        !self.file.is_null() && self.flags & MAP_SHARED != 0 && self.prot & PROT_WRITE != 0
+    }
+
+    /// \brief What is left of the region once `[lo, hi)` is removed.
+    ///
+    /// Returns the pieces below and above the hole; a piece is `None` when
+    /// the hole reaches that end of the region.
+    pub fn trim(&self, lo: u32, hi: u32) -> (Option<Vma>, Option<Vma>) {
+        let lo = lo.clamp(self.start, self.end());
+        let hi = hi.clamp(lo, self.end());
+        let below = (lo > self.start).then(|| Vma { len: lo - self.start, ..*self });
+        let above = (hi < self.end())
+            .then(|| Vma { start: hi, len: self.end() - hi, off: self.off + (hi - self.start), ..*self });
+        (below, above)
+    }
+}
+
+impl Default for Vma {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+/// \brief Whether a region with protection `prot` permits the access.
+pub fn allows(prot: u32, write: bool) -> bool {
+    if write {
+        prot & PROT_WRITE != 0
+    } else {
+        prot & (PROT_READ | PROT_WRITE | PROT_EXEC) != 0
+    }
+}
+
+/// \brief PTE permission bits for a page of a region with protection `prot`.
+pub fn pte_perm(prot: u32) -> PteFlags {
+    if prot & PROT_WRITE != 0 {
+        PteFlags::U | PteFlags::W
+    } else {
+        PteFlags::U
+    }
+}
+
+/// \brief Slot of the region containing `va`.
+pub fn find(vmas: &[Vma], va: u32) -> Option<usize> {
+    vmas.iter().position(|v| v.contains(va))
+}
+
+/// \brief The region holding all of `[addr, addr + len)`, if one does.
+pub fn covering(vmas: &[Vma], addr: u32, len: u32) -> Option<&Vma> {
+    let end = addr.checked_add(len)?;
+    vmas.iter().find(|v| v.contains(addr) && end <= v.end())
+}
+
+/// \brief Lowest free address range of `len` bytes in the mmap window.
+pub fn find_gap(vmas: &[Vma], len: u32) -> Option<u32> {
+    let mut start = MMAPBASE;
+    loop {
+        let end = start.checked_add(len).filter(|&e| e <= KERNBASE)?;
+        match vmas.iter().filter(|v| v.is_used() && v.start < end && start < v.end()).map(Vma::end).max() {
+            Some(next) => start = next,
+            None => return Some(start),
+        }
+    }
+}
+
+/// \brief Validate `mmap` arguments and return the length in whole pages.
+pub fn check_args(len: u32, prot: u32, flags: u32, off: u32) -> Result<u32, Errno> {
+    let sharing = flags & (MAP_SHARED | MAP_PRIVATE);
+    let known = PROT_READ | PROT_WRITE | PROT_EXEC;
+    if len == 0
+        || prot & !known != 0
+        || flags & !(MAP_SHARED | MAP_PRIVATE | MAP_ANONYMOUS) != 0
+        || (sharing != MAP_SHARED && sharing != MAP_PRIVATE)
+        || !off.is_multiple_of(PGSIZE)
+    {
+        return Err(Errno::EINVAL);
+    }
+    match pg_round_up(len) {
+        0 => Err(Errno::ENOMEM),
+        n if n > KERNBASE - MMAPBASE => Err(Errno::ENOMEM),
+        n => Ok(n),
+    }
+}
+
+/// \brief Map `len` bytes of `file` (or anonymous memory if null) at `off`.
+///
+/// Returns the address chosen for the region.
+///
+/// # Safety
+/// `p` must be the current process and `file` one of its open files.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn mmap(p: &mut Proc, len: u32, prot: u32, flags: u32, file: *mut File, off: u32) -> Result<u32, Errno> {
+    let len = check_args(len, prot, flags, off)?;
+    let file = if flags & MAP_ANONYMOUS != 0 { ptr::null_mut() } else { file };
+    if !file.is_null() {
+        let f = &*file;
+        if f.itype != FD_INODE {
+            return Err(Errno::ENODEV);
+        }
+        if f.readable == 0 || (flags & MAP_SHARED != 0 && prot & PROT_WRITE != 0 && f.writable == 0) {
+            return Err(Errno::EACCES);
+        }
+    }
+    let slot = p.vmas.iter().position(|v| !v.is_used()).ok_or(Errno::ENOMEM)?;
+    let start = find_gap(&p.vmas, len).ok_or(Errno::ENOMEM)?;
+    let file = if file.is_null() { file } else { filedup(file) };
+    p.vmas[slot] = Vma { start, len, prot, flags, file, off };
+    Ok(start)
+}
+
+/// \brief Remove `[addr, addr + len)` from every region it overlaps.
+///
+/// Dirty pages of shared file mappings are written back first. Splitting a
+/// region in two needs a free slot and fails with `ENOMEM` otherwise.
+///
+/// # Safety
+/// `p` must be the current process.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn munmap(p: &mut Proc, addr: u32, len: u32) -> Result<(), Errno> {
+    if !addr.is_multiple_of(PGSIZE) || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let hi = addr.checked_add(len).map(pg_round_up).filter(|&e| e != 0 && e <= KERNBASE).ok_or(Errno::EINVAL)?;
+    let splits = p.vmas.iter().filter(|v| v.is_used() && v.start < addr && hi < v.end()).count();
+    if splits > p.vmas.iter().filter(|v| !v.is_used()).count() {
+        return Err(Errno::ENOMEM);
+    }
+    for i in 0..NVMA {
+        let v = p.vmas[i];
+        if !v.is_used() || hi <= v.start || v.end() <= addr {
+            continue;
+        }
+        let (lo, hi) = (addr.max(v.start), hi.min(v.end()));
+        unmap_pages(p, &v, lo, hi);
+        match v.trim(lo, hi) {
+            (None, None) => {
+                if !v.file.is_null() {
+                    fileclose(v.file);
+                }
+                p.vmas[i] = Vma::EMPTY;
+            }
+            (Some(part), None) | (None, Some(part)) => p.vmas[i] = part,
+            (Some(below), Some(above)) => {
+                // A free slot was found above, before anything changed.
+                p.vmas[i] = below;
+                if let Some(j) = p.vmas.iter().position(|v| !v.is_used()) {
+                    p.vmas[j] = above;
+                    if !v.file.is_null() {
+                        filedup(v.file);
+                    }
+                }
+            }
+        }
+    }
+    switchuvm(p);
+    Ok(())
+}
+
+/// \brief Unmap every region of `p`, writing back dirty shared pages.
+///
+/// Called by `exit` and by `exec` before the old page table goes away.
+///
+/// # Safety
+/// `p` must be the current process, outside any file system operation.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn munmap_all(p: *mut Proc) {
+    let p = &mut *p;
+    for i in 0..NVMA {
+        let v = p.vmas[i];
+        if v.is_used() {
+            unmap_pages(p, &v, v.start, v.end());
+            if !v.file.is_null() {
+                fileclose(v.file);
+            }
+            p.vmas[i] = Vma::EMPTY;
+        }
+    }
+}
+
+/// \brief Write back and free the mapped pages of `v` in `[lo, hi)`.
+#[cfg(not(feature = "hosted"))]
+unsafe fn unmap_pages(p: &Proc, v: &Vma, lo: u32, hi: u32) {
+    let pgdir = PageDirectory::from_ptr(p.pgdir);
+    for va in (lo..hi).step_by(PGSIZE as usize) {
+        let Some(pte) = pgdir.walk(va, &mut KernelFrames, false).filter(|e| e.is_present()) else {
+            continue;
+        };
+        let pa = pte.addr();
+        if v.writes_back() && pte.has(PteFlags::D) {
+            write_back(v, va, p2v(pa));
+        }
+        *pte = Pte::EMPTY;
+        KernelFrames.free(pa);
+    }
+}
+
+/// \brief Write the page at `va` of `v`, held at `src`, to the file.
+///
+/// Only bytes inside the current file size are written: a mapping never
+/// extends its file.
+#[cfg(not(feature = "hosted"))]
+unsafe fn write_back(v: &Vma, va: u32, src: *const u8) {
+    let ip = (*v.file).ip as *mut Inode;
+    let off = v.off + (va - v.start);
+    ilock(ip);
+    let mut st = Stat::default();
+    stati(ip, &mut st);
+    iunlock(ip);
+    let n = st.size.saturating_sub(off).min(PGSIZE);
+    let mut done = 0;
+    while done < n {
+        let chunk = (n - done).min(MAXWRITE);
+        begin_op();
+        ilock(ip);
+        writei(ip, src.add(done as usize), off + done, chunk);
+        iunlock(ip);
+        end_op();
+        done += chunk;
+    }
+}
+
+/// \brief Map the page containing `va` if a region of `p` allows the access.
+///
+/// The page is zeroed, then filled from the file for file-backed regions.
+/// Reading may sleep, which [`sleepable`] allows only with no spinlock
+/// held; the kernel panics otherwise.
+///
+/// # Safety
+/// `p` must be the current process and `va` not yet mapped.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn fault(p: &Proc, va: u32, write: bool) -> bool {
+    let Some(v) = find(&p.vmas, va).map(|i| p.vmas[i]) else {
+        return false;
+    };
+    if !allows(v.prot, write) {
+        return false;
+    }
+    populate(p, &v, pg_round_down(va))
+}
+
+/// \brief Map the page at `page` of `v`, zeroed or read from the file.
+#[cfg(not(feature = "hosted"))]
+unsafe fn populate(p: &Proc, v: &Vma, page: u32) -> bool {
+    let Some(pa) = KernelFrames.alloc() else {
+        return false;
+    };
+    if !v.file.is_null() {
+        let ip = (*v.file).ip as *mut Inode;
+        sleepable(|| {
+            ilock(ip);
+            readi(ip, p2v(pa), v.off + (page - v.start), PGSIZE);
+            iunlock(ip);
+        });
+    }
+    if vm::map(PageDirectory::from_ptr(p.pgdir), page, PGSIZE, pa, pte_perm(v.prot)).is_err() {
+        KernelFrames.free(pa);
+        return false;
+    }
+    true
+}
+
+/// \brief Run `f`, which may sleep, on behalf of the current process.
+///
+/// `trap()` calls the page fault handler with interrupts off, and the
+/// kernel may fault on user memory with a spinlock held. Sleeping is only
+/// safe holding none, so that is checked, and interrupts are turned back
+/// on while `f` runs, as they are in a system call.
+#[cfg(not(feature = "hosted"))]
+unsafe fn sleepable(f: impl FnOnce()) {
+    if (*this_cpu()).ncli != 0 {
+        panic!("mmap: fault sleeps holding a spinlock");
+    }
+    let intr = x86::bits32::eflags::read().contains(x86::bits32::eflags::EFlags::FLAGS_IF);
+    if !intr {
+        x86::irq::enable();
+    }
+    f();
+    if !intr {
+        x86::irq::disable();
+    }
+}
+
+/// \brief Give `child` the regions of `parent` and share their mapped pages.
+///
+/// Pages of an accessible `MAP_SHARED` region that are not mapped yet are
+/// mapped in the parent first, so that both see the whole region; then
+/// they are mapped as they are. `MAP_PRIVATE` pages become copy-on-write
+/// in both. On failure the child's regions are released.
+///
+/// # Safety
+/// `parent` must be the current process and `child` a new process whose
+/// page table is installed in `child.pgdir`.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn fork(parent: &Proc, child: &mut Proc) -> Result<(), Errno> {
+    let pgdir = PageDirectory::from_ptr(parent.pgdir);
+    for (i, v) in parent.vmas.iter().enumerate().filter(|(_, v)| v.is_used()) {
+        child.vmas[i] = Vma { file: if v.file.is_null() { v.file } else { filedup(v.file) }, ..*v };
+        let prefault = v.flags & MAP_SHARED != 0 && allows(v.prot, false);
+        for va in (v.start..v.end()).step_by(PGSIZE as usize) {
+            let mapped = pgdir.walk(va, &mut KernelFrames, false).is_some_and(|e| e.is_present());
+            if prefault && !mapped && !populate(parent, v, va) {
+                x86::tlb::flush_all();
+                release(child);
+                return Err(Errno::ENOMEM);
+            }
+            let Some(pte) = pgdir.walk(va, &mut KernelFrames, false).filter(|e| e.is_present()) else {
+                continue;
+            };
+            if !cow::share(pte, PageDirectory::from_ptr(child.pgdir), va, v.flags & MAP_PRIVATE != 0) {
+                x86::tlb::flush_all();
+                release(child);
+                return Err(Errno::ENOMEM);
+            }
+        }
+    }
+    // Private pages lost PTE_W in the parent.
+    x86::tlb::flush_all();
+    Ok(())
+}
+
+/// \brief Drop a child's regions without touching its pages.
+#[cfg(not(feature = "hosted"))]
+unsafe fn release(child: &mut Proc) {
+    for v in child.vmas.iter_mut().filter(|v| v.is_used()) {
+        if !v.file.is_null() {
+            fileclose(v.file);
+        }
+        *v = Vma::EMPTY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(start: u32, pages: u32) -> Vma {
+        Vma { start, len: pages * PGSIZE, prot: PROT_READ, flags: MAP_PRIVATE, ..Vma::EMPTY }
+    }
+
+    #[test]
+    fn gaps_are_first_fit() {
+        let mut vmas = [Vma::EMPTY; 4];
+        assert_eq!(find_gap(&vmas, PGSIZE), Some(MMAPBASE));
+        vmas[2] = region(MMAPBASE, 2);
+        vmas[0] = region(MMAPBASE + 3 * PGSIZE, 1);
+        assert_eq!(find_gap(&vmas, PGSIZE), Some(MMAPBASE + 2 * PGSIZE));
+        assert_eq!(find_gap(&vmas, 2 * PGSIZE), Some(MMAPBASE + 4 * PGSIZE));
+        assert_eq!(find_gap(&vmas, KERNBASE - MMAPBASE), None);
+    }
+
+    #[test]
+    fn lookup_and_covering() {
+        let vmas = [Vma::EMPTY, region(MMAPBASE, 2)];
+        assert_eq!(find(&vmas, MMAPBASE + PGSIZE), Some(1));
+        assert_eq!(find(&vmas, MMAPBASE + 2 * PGSIZE), None);
+        assert!(covering(&vmas, MMAPBASE + 10, 2 * PGSIZE - 10).is_some());
+        assert!(covering(&vmas, MMAPBASE + 10, 2 * PGSIZE).is_none());
+        assert!(covering(&vmas, 0, 1).is_none());
+    }
+
+    #[test]
+    fn trim_keeps_offsets() {
+        let v = Vma { off: 0x3000, ..region(MMAPBASE, 4) };
+        let (below, above) = v.trim(MMAPBASE + PGSIZE, MMAPBASE + 2 * PGSIZE);
+        assert_eq!(below, Some(Vma { len: PGSIZE, ..v }));
+        assert_eq!(
+            above,
+            Some(Vma { start: MMAPBASE + 2 * PGSIZE, len: 2 * PGSIZE, off: 0x3000 + 2 * PGSIZE, ..v })
+        );
+        assert_eq!(v.trim(MMAPBASE, MMAPBASE + 4 * PGSIZE), (None, None));
+        assert_eq!(v.trim(MMAPBASE, MMAPBASE + PGSIZE).0, None);
+    }
+
+    #[test]
+    fn protection_maps_to_ptes() {
+        assert_eq!(pte_perm(PROT_READ), PteFlags::U);
+        assert_eq!(pte_perm(PROT_READ | PROT_WRITE), PteFlags::U | PteFlags::W);
+        assert!(allows(PROT_EXEC, false));
+        assert!(!allows(PROT_READ | PROT_EXEC, true));
+        assert!(!allows(PROT_NONE, false));
+    }
+
+    #[test]
+    fn argument_checks() {
+        assert_eq!(check_args(1, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS, 0), Ok(PGSIZE));
+        assert_eq!(check_args(0, PROT_READ, MAP_SHARED, 0), Err(Errno::EINVAL));
+        assert_eq!(check_args(10, PROT_READ, MAP_SHARED | MAP_PRIVATE, 0), Err(Errno::EINVAL));
+        assert_eq!(check_args(10, PROT_READ, MAP_SHARED, 100), Err(Errno::EINVAL));
+        assert_eq!(check_args(10, 0x8, MAP_SHARED, 0), Err(Errno::EINVAL));
+        assert_eq!(check_args(u32::MAX, PROT_READ, MAP_SHARED, 0), Err(Errno::ENOMEM));
+    }
+}
@@ -1,7 +1,332 @@
 use bitfield::bitfield;
-use core::ffi;
+use bitflags::bitflags;
+use core::{ffi, fmt};
 use bytemuck::Zeroable;
-use zerocopy::{FromBytes, IntoBytes as AsBytes, Unaligned};
+use zerocopy::{FromBytes, IntoBytes as AsBytes};
+
+/// \brief Interrupt enable bit in `%eflags`.
+pub const FL_IF: u32 = 0x0000_0200;
+
+/// \brief Kernel code segment selector index.
+pub const SEG_KCODE: u16 = 1;
+/// \brief Kernel data and stack segment selector index.
+pub const SEG_KDATA: u16 = 2;
+/// \brief User code segment selector index.
+pub const SEG_UCODE: u16 = 3;
+/// \brief User data and stack segment selector index.
+pub const SEG_UDATA: u16 = 4;
+/// \brief This CPU's task state segment selector index.
+pub const SEG_TSS: u16 = 5;
+/// \brief This CPU's `Cpu`, for per-CPU data through `%gs`.
+pub const SEG_KCPU: u16 = 6;
+/// \brief Descriptor privilege level for user mode.
+pub const DPL_USER: u16 = 0x3;
+
+/// \brief Selector for GDT slot `seg` with requested privilege `rpl`.
+#[inline]
+pub const fn selector(seg: u16, rpl: u16) -> u16 {
+    seg << 3 | rpl
+}
+
+/// \brief Application segment type: executable.
+pub const STA_X: u8 = 0x8;
+/// \brief Application segment type: writeable (data segments).
+pub const STA_W: u8 = 0x2;
+/// \brief Application segment type: readable (code segments).
+pub const STA_R: u8 = 0x2;
+/// \brief System segment type: available 32-bit TSS.
+pub const STS_T32A: u8 = 0x9;
+/// \brief System segment type: 32-bit call gate.
+pub const STS_CG32: u8 = 0xC;
+/// \brief System segment type: 32-bit interrupt gate.
+pub const STS_IG32: u8 = 0xE;
+/// \brief System segment type: 32-bit trap gate.
+pub const STS_TG32: u8 = 0xF;
+
+/// \brief Bytes mapped by a page.
+pub const PGSIZE: u32 = 4096;
+
+/// \brief Round `a` down to a page boundary.
+#[inline]
+pub const fn pg_round_down(a: u32) -> u32 {
+    a & !(PGSIZE - 1)
+}
+
+/// \brief Round `sz` up to a page boundary.
+#[inline]
+pub const fn pg_round_up(sz: u32) -> u32 {
+    sz.wrapping_add(PGSIZE - 1) & !(PGSIZE - 1)
+}
+
+/// \brief Entries in a page directory.
+pub const NPDENTRIES: usize = 1024;
+/// \brief Entries in a page table.
+pub const NPTENTRIES: usize = 1024;
+/// \brief Offset of the page table index in a linear address.
+const PTXSHIFT: u32 = 12;
+/// \brief Offset of the page directory index in a linear address.
+const PDXSHIFT: u32 = 22;
+
+/// \brief Page directory index of `va`.
+#[inline]
+pub const fn pdx(va: u32) -> usize {
+    ((va >> PDXSHIFT) & 0x3FF) as usize
+}
+
+/// \brief Page table index of `va`.
+#[inline]
+pub const fn ptx(va: u32) -> usize {
+    ((va >> PTXSHIFT) & 0x3FF) as usize
+}
+
+bitflags! {
+    /// \brief Flag bits of a page directory or page table entry.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct PteFlags: u32 {
+        /// Present.
+        const P = 0x001;
+        /// Writeable.
+        const W = 0x002;
+        /// User accessible.
+        const U = 0x004;
+        /// Write-through caching.
+        const PWT = 0x008;
+        /// Cache disabled.
+        const PCD = 0x010;
+        /// Accessed, set by the CPU.
+        const A = 0x020;
+        /// Dirty, set by the CPU on a write.
+        const D = 0x040;
+        /// 4 MiB page (directory entries only).
+        const PS = 0x080;
+        /// Global: survives a `%cr3` reload in the TLB.
+        const G = 0x100;
+        /// Shared copy-on-write (software, AVL bit).
+        const COW = 0x200;
+    }
+}
+
+/// \brief A page directory or page table entry: frame address and flags.
+///
+/// Layout matches `pte_t` and `pde_t` in C.
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pte(u32);
+
+impl Pte {
+    /// \brief An entry mapping nothing.
+    pub const EMPTY: Pte = Pte(0);
+
+    /// \brief Entry for frame `pa` with `flags`.
+    #[inline]
+    pub const fn new(pa: u32, flags: PteFlags) -> Self {
+        Pte((pa & !0xFFF) | (flags.bits() & 0xFFF))
+    }
+
+    /// \brief Raw bits as the MMU sees them.
+    #[inline]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// \brief Physical address of the frame (`PTE_ADDR`).
+    #[inline]
+    pub const fn addr(self) -> u32 {
+        self.0 & !0xFFF
+    }
+
+    /// \brief Flag bits (`PTE_FLAGS`).
+    #[inline]
+    pub const fn flags(self) -> PteFlags {
+        PteFlags::from_bits_retain(self.0 & 0xFFF)
+    }
+
+    /// \brief Whether the entry maps anything.
+    #[inline]
+    pub const fn is_present(self) -> bool {
+        self.0 & PteFlags::P.bits() != 0
+    }
+
+    /// \brief Whether every bit of `flags` is set.
+    #[inline]
+    pub const fn has(self, flags: PteFlags) -> bool {
+        self.flags().contains(flags)
+    }
+
+    /// \brief Same frame with `flags` instead.
+    #[inline]
+    pub const fn with_flags(self, flags: PteFlags) -> Self {
+        Pte::new(self.addr(), flags)
+    }
+}
+
+impl fmt::Debug for Pte {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pte({:#x}, {:?})", self.addr(), self.flags())
+    }
+}
+
+/// \brief Second-level table: one entry per 4 KiB page.
+#[repr(C, align(4096))]
+pub struct PageTable {
+    entries: [Pte; NPTENTRIES],
+}
+
+/// \brief Top-level table: one entry per 4 MiB, pointing at a [`PageTable`].
+///
+/// Only reached through a `Frames` that can find its page tables, so its
+/// entries are kept private.
+#[repr(C, align(4096))]
+pub struct PageDirectory {
+    entries: [Pte; NPDENTRIES],
+}
+
+/// \brief Supplier of page-table pages, and the way to reach a frame.
+///
+/// # Safety
+/// `ptr` must return a pointer to `PGSIZE` writeable bytes for every frame
+/// handed out by `alloc` and not yet freed.
+pub unsafe trait Frames {
+    /// \brief Allocate a zeroed frame and return its physical address.
+    fn alloc(&mut self) -> Option<u32>;
+    /// \brief Release the frame at `pa`.
+    fn free(&mut self, pa: u32);
+    /// \brief Pointer through which the kernel accesses frame `pa`.
+    fn ptr(&self, pa: u32) -> *mut u8;
+}
+
+/// \brief Why [`PageDirectory::map_range`] failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// A page table could not be allocated.
+    NoMemory,
+    /// The page at this address was already mapped.
+    Remap(u32),
+}
+
+impl PageDirectory {
+    /// \brief Borrow the page directory at `p`.
+    ///
+    /// # Safety
+    /// `p` must point to a page directory whose page tables were allocated
+    /// from the `Frames` later passed to its methods, and nothing else may
+    /// use it for `'a`.
+    #[inline]
+    pub unsafe fn from_ptr<'a>(p: *mut PageDirectory) -> &'a mut PageDirectory {
+        &mut *p
+    }
+
+    /// \brief Entry for `va`, allocating its page table if `alloc` is set.
+    ///
+    /// Returns `None` when the page table is missing and either `alloc` is
+    /// clear or no frame is available (`walkpgdir`).
+    pub fn walk<F: Frames>(&mut self, va: u32, frames: &mut F, alloc: bool) -> Option<&mut Pte> {
+        let pde = &mut self.entries[pdx(va)];
+        if !pde.is_present() {
+            if !alloc {
+                return None;
+            }
+            // The permissions here are overly generous, but they can be
+            // further restricted by the permissions in the page table entries.
+            *pde = Pte::new(frames.alloc()?, PteFlags::P | PteFlags::W | PteFlags::U);
+        }
+        // SAFETY: present directory entries point at page tables that came
+        // from `frames` (see `from_ptr`).
+        let table = unsafe { &mut *(frames.ptr(pde.addr()) as *mut PageTable) };
+        Some(&mut table.entries[ptx(va)])
+    }
+
+    /// \brief Copy of the entry for `va`, if its page table exists.
+    pub fn lookup<F: Frames>(&self, va: u32, frames: &F) -> Option<Pte> {
+        let pde = self.entries[pdx(va)];
+        if !pde.is_present() {
+            return None;
+        }
+        // SAFETY: as in `walk`.
+        let table = unsafe { &*(frames.ptr(pde.addr()) as *const PageTable) };
+        Some(table.entries[ptx(va)])
+    }
+
+    /// \brief Physical address `va` maps to.
+    pub fn translate<F: Frames>(&self, va: u32, frames: &F) -> Option<u32> {
+        self.lookup(va, frames).filter(|e| e.is_present()).map(|e| e.addr() | (va & (PGSIZE - 1)))
+    }
+
+    /// \brief Map the pages covering `[va, va + size)` to frames from `pa` on.
+    ///
+    /// `va` and `size` need not be page aligned (`mappages`). Entries get
+    /// `flags` plus `P`. Stops at the first failure, leaving earlier pages
+    /// mapped.
+    pub fn map_range<F: Frames>(
+        &mut self,
+        va: u32,
+        size: u32,
+        mut pa: u32,
+        flags: PteFlags,
+        frames: &mut F,
+    ) -> Result<(), MapError> {
+        let mut a = pg_round_down(va);
+        let last = pg_round_down(va.wrapping_add(size).wrapping_sub(1));
+        loop {
+            let pte = self.walk(a, frames, true).ok_or(MapError::NoMemory)?;
+            if pte.is_present() {
+                return Err(MapError::Remap(a));
+            }
+            *pte = Pte::new(pa, flags | PteFlags::P);
+            if a == last {
+                return Ok(());
+            }
+            a = a.wrapping_add(PGSIZE);
+            pa = pa.wrapping_add(PGSIZE);
+        }
+    }
+
+    /// \brief Clear the entries of the pages in `[from, to)`.
+    ///
+    /// `from` is rounded up to a page boundary. With `free` the mapped
+    /// frames are released through `frames`. Missing page tables are
+    /// skipped a whole directory entry at a time. Returns how many pages
+    /// were unmapped.
+    pub fn unmap_range<F: Frames>(&mut self, from: u32, to: u32, frames: &mut F, free: bool) -> usize {
+        let mut unmapped = 0;
+        let mut a = pg_round_up(from);
+        if a < from {
+            return 0;
+        }
+        while a < to {
+            let next = match self.walk(a, frames, false) {
+                None => ((pdx(a) + 1) as u32).checked_mul(1 << PDXSHIFT),
+                Some(pte) => {
+                    if pte.is_present() {
+                        let pa = pte.addr();
+                        *pte = Pte::EMPTY;
+                        if free {
+                            frames.free(pa);
+                        }
+                        unmapped += 1;
+                    }
+                    a.checked_add(PGSIZE)
+                }
+            };
+            match next {
+                Some(n) => a = n,
+                None => break,
+            }
+        }
+        unmapped
+    }
+
+    /// \brief Release every page table, and the frames mapped below `limit`.
+    ///
+    /// Leaves the directory empty; the caller frees its own page (`freevm`).
+    pub fn free_tables<F: Frames>(&mut self, limit: u32, frames: &mut F) {
+        self.unmap_range(0, limit, frames, true);
+        for pde in self.entries.iter_mut().filter(|e| e.is_present()) {
+            frames.free(pde.addr());
+            *pde = Pte::EMPTY;
+        }
+    }
+}
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Default, Zeroable)]
@@ -10,9 +335,9 @@ pub struct TaskState {
     /// Old link field.
     link: u32,
     /// Stack pointer for ring 0.
-    esp0: u32,
+    pub esp0: u32,
     /// Stack segment for ring 0.
-    ss0: u16,
+    pub ss0: u16,
     /// Reserved padding.
     padding1: u16,
     /// Stack pointer for ring 1.
@@ -80,26 +405,318 @@ pub struct TaskState {
     /// Trap flag.
     t: u16,
     /// I/O map base address.
-    iomb: u16,
+    pub iomb: u16,
 }
 
 bitfield! {
-    #[repr(C)]
-    #[derive(Copy, Clone, Default, Zeroable, FromBytes, AsBytes, Unaligned)] // Removed Pod
-    /// x86 segment descriptor.
-    pub struct SegDesc(MSB0 [u8]);
+    /// \brief x86 segment descriptor, laid out like `struct segdesc`.
+    ///
+    /// Build one with [`SegBuilder`]; the accessors are for inspecting or
+    /// patching an existing entry.
+    #[repr(transparent)]
+    #[derive(Copy, Clone, Default, PartialEq, Eq, Zeroable, FromBytes, AsBytes)]
+    pub struct SegDesc(u64);
+    impl Debug;
     u32;
-    get_lim_15_0, _: 15, 0;
-    get_base_15_0, _: 31, 16;
-    base_23_16, _: 39, 32;
-    segtype, _: 43, 40;
-    s, _: 44;
-    dpl, _: 46, 45;
-    p, _: 47;
-    lim_19_16, _: 51, 48;
-    avl, _: 52;
-    rsv1, _: 53;
-    db, _: 54;
-    g, _: 55;
-    base_31_24, _: 63, 56;
+    pub lim_15_0, set_lim_15_0: 15, 0;
+    pub base_15_0, set_base_15_0: 31, 16;
+    pub base_23_16, set_base_23_16: 39, 32;
+    pub u8, segtype, set_segtype: 43, 40;
+    pub s, set_s: 44;
+    pub u16, dpl, set_dpl: 46, 45;
+    pub p, set_p: 47;
+    pub lim_19_16, set_lim_19_16: 51, 48;
+    pub avl, set_avl: 52;
+    pub rsv1, set_rsv1: 53;
+    pub db, set_db: 54;
+    pub g, set_g: 55;
+    pub base_31_24, set_base_31_24: 63, 56;
+}
+
+const _: () = assert!(core::mem::size_of::<SegDesc>() == 8);
+
+impl SegDesc {
+    /// \brief The mandatory null descriptor in slot 0.
+    pub const NULL: SegDesc = SegDesc(0);
+
+    /// \brief Raw 64-bit descriptor.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// \brief Linear address the segment starts at.
+    pub fn base(&self) -> u32 {
+        self.base_15_0() | self.base_23_16() << 16 | self.base_31_24() << 24
+    }
+
+    /// \brief Last valid offset in the segment, in bytes.
+    pub fn limit(&self) -> u32 {
+        let lim = self.lim_15_0() | self.lim_19_16() << 16;
+        if self.g() {
+            lim << 12 | 0xFFF
+        } else {
+            lim
+        }
+    }
+
+    /// \brief Call gate to `sel:off`, callable from privilege `dpl`.
+    pub fn call_gate(sel: u16, off: u32, dpl: u16) -> Self {
+        SegDesc(GateDesc::new(STS_CG32, sel, off, dpl).bits())
+    }
+}
+
+/// \brief Describes a [`SegDesc`] field by field; `SEG` and `SEG16` in C.
+///
+/// ```ignore
+/// let ucode = SegBuilder::code().dpl(DPL_USER).build();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegBuilder {
+    segtype: u8,
+    base: u32,
+    limit: u32,
+    dpl: u16,
+    system: bool,
+    db: bool,
+}
+
+impl SegBuilder {
+    /// \brief Flat 4 GiB readable 32-bit code segment.
+    pub const fn code() -> Self {
+        Self::flat(STA_X | STA_R)
+    }
+
+    /// \brief Flat 4 GiB writeable 32-bit data segment.
+    pub const fn data() -> Self {
+        Self::flat(STA_W)
+    }
+
+    /// \brief Available 32-bit TSS of `size` bytes at `base`.
+    pub const fn tss(base: u32, size: u32) -> Self {
+        SegBuilder { segtype: STS_T32A, base, limit: size - 1, dpl: 0, system: true, db: false }
+    }
+
+    const fn flat(segtype: u8) -> Self {
+        SegBuilder { segtype, base: 0, limit: 0xFFFF_FFFF, dpl: 0, system: false, db: true }
+    }
+
+    /// \brief Start the segment at `base`.
+    pub const fn base(self, base: u32) -> Self {
+        SegBuilder { base, ..self }
+    }
+
+    /// \brief Make `limit` the last valid offset.
+    ///
+    /// Limits above 1 MiB are kept in 4 KiB units, so their low 12 bits
+    /// are treated as set.
+    pub const fn limit(self, limit: u32) -> Self {
+        SegBuilder { limit, ..self }
+    }
+
+    /// \brief Privilege level allowed to use the segment.
+    pub const fn dpl(self, dpl: u16) -> Self {
+        SegBuilder { dpl, ..self }
+    }
+
+    /// \brief Encode the descriptor, marked present.
+    pub fn build(self) -> SegDesc {
+        let granular = self.limit > 0xF_FFFF;
+        let lim = if granular { self.limit >> 12 } else { self.limit };
+        let mut d = SegDesc::NULL;
+        d.set_lim_15_0(lim & 0xFFFF);
+        d.set_lim_19_16(lim >> 16);
+        d.set_base_15_0(self.base & 0xFFFF);
+        d.set_base_23_16((self.base >> 16) & 0xFF);
+        d.set_base_31_24(self.base >> 24);
+        d.set_segtype(self.segtype);
+        d.set_s(!self.system);
+        d.set_dpl(self.dpl);
+        d.set_p(true);
+        d.set_db(self.db);
+        d.set_g(granular);
+        d
+    }
+}
+
+bitfield! {
+    /// \brief Interrupt, trap or call gate, laid out like `struct gatedesc`.
+    #[repr(transparent)]
+    #[derive(Copy, Clone, Default, PartialEq, Eq, Zeroable, FromBytes, AsBytes)]
+    pub struct GateDesc(u64);
+    impl Debug;
+    u32;
+    pub off_15_0, set_off_15_0: 15, 0;
+    pub u16, cs, set_cs: 31, 16;
+    pub args, set_args: 36, 32;
+    pub rsv1, set_rsv1: 39, 37;
+    pub u8, gatetype, set_gatetype: 43, 40;
+    pub s, set_s: 44;
+    pub u16, dpl, set_dpl: 46, 45;
+    pub p, set_p: 47;
+    pub off_31_16, set_off_31_16: 63, 48;
+}
+
+impl GateDesc {
+    /// \brief A gate that is not present.
+    pub const NULL: GateDesc = GateDesc(0);
+
+    /// \brief Present gate of `gatetype` to `sel:off`, usable from `dpl`.
+    pub fn new(gatetype: u8, sel: u16, off: u32, dpl: u16) -> Self {
+        let mut g = GateDesc::NULL;
+        g.set_off_15_0(off & 0xFFFF);
+        g.set_cs(sel);
+        g.set_gatetype(gatetype);
+        g.set_dpl(dpl);
+        g.set_p(true);
+        g.set_off_31_16(off >> 16);
+        g
+    }
+
+    /// \brief Interrupt gate: the handler runs with `FL_IF` cleared.
+    pub fn interrupt(sel: u16, off: u32, dpl: u16) -> Self {
+        Self::new(STS_IG32, sel, off, dpl)
+    }
+
+    /// \brief Trap gate: `FL_IF` is left alone.
+    pub fn trap(sel: u16, off: u32, dpl: u16) -> Self {
+        Self::new(STS_TG32, sel, off, dpl)
+    }
+
+    /// \brief Raw 64-bit descriptor.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// \brief Handler offset.
+    pub fn offset(&self) -> u32 {
+        self.off_15_0() | self.off_31_16() << 16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(4096))]
+    struct Page([u8; PGSIZE as usize]);
+
+    /// Frames backed by host memory; frame `i` has physical address `(i + 1) * PGSIZE`.
+    #[derive(Default)]
+    struct TestFrames {
+        pages: Vec<Box<Page>>,
+        live: Vec<bool>,
+    }
+
+    unsafe impl Frames for TestFrames {
+        fn alloc(&mut self) -> Option<u32> {
+            self.pages.push(Box::new(Page([0; PGSIZE as usize])));
+            self.live.push(true);
+            Some(self.pages.len() as u32 * PGSIZE)
+        }
+
+        fn free(&mut self, pa: u32) {
+            let i = (pa / PGSIZE) as usize - 1;
+            assert!(self.live[i], "double free of {pa:#x}");
+            self.live[i] = false;
+        }
+
+        fn ptr(&self, pa: u32) -> *mut u8 {
+            self.pages[(pa / PGSIZE) as usize - 1].0.as_ptr() as *mut u8
+        }
+    }
+
+    impl TestFrames {
+        fn live(&self) -> usize {
+            self.live.iter().filter(|&&l| l).count()
+        }
+    }
+
+    fn directory() -> Box<PageDirectory> {
+        Box::new(PageDirectory { entries: [Pte::EMPTY; NPDENTRIES] })
+    }
+
+    #[test]
+    fn entries_pack_address_and_flags() {
+        let e = Pte::new(0x1234_5678, PteFlags::W | PteFlags::U | PteFlags::P);
+        assert_eq!(e.bits(), 0x1234_5007);
+        assert_eq!(e.addr(), 0x1234_5000);
+        assert!(e.is_present() && e.has(PteFlags::W | PteFlags::U));
+        assert!(!e.has(PteFlags::COW));
+        let ro = e.with_flags(PteFlags::P | PteFlags::U | PteFlags::COW);
+        assert_eq!((ro.addr(), ro.bits() & 0xFFF), (0x1234_5000, 0x205));
+        assert!(!Pte::EMPTY.is_present());
+        assert_eq!((pdx(0x8040_3000), ptx(0x8040_3000)), (0x201, 0x3));
+    }
+
+    #[test]
+    fn map_translate_and_remap() {
+        let mut f = TestFrames::default();
+        let mut pd = directory();
+        assert_eq!(pd.walk(0x1000, &mut f, false), None);
+        pd.map_range(0x0040_0010, 2 * PGSIZE, 0x9000, PteFlags::W | PteFlags::U, &mut f).unwrap();
+        // Three pages are touched because the range is not aligned.
+        assert_eq!(pd.translate(0x0040_0010, &f), Some(0x9010));
+        assert_eq!(pd.translate(0x0040_2fff, &f), Some(0xbfff));
+        assert_eq!(pd.translate(0x0040_3000, &f), None);
+        assert_eq!(pd.lookup(0x0040_1000, &f).map(|e| e.flags()), Some(PteFlags::P | PteFlags::W | PteFlags::U));
+        assert_eq!(pd.map_range(0x0040_2000, 1, 0x5000, PteFlags::U, &mut f), Err(MapError::Remap(0x0040_2000)));
+        assert_eq!(f.live(), 1, "one page table");
+    }
+
+    #[test]
+    fn map_range_reaches_the_top_of_memory() {
+        let mut f = TestFrames::default();
+        let mut pd = directory();
+        pd.map_range(0xFFFF_E000, 0u32.wrapping_sub(0xFFFF_E000), 0xFFFF_E000, PteFlags::W, &mut f).unwrap();
+        assert_eq!(pd.translate(0xFFFF_FFFF, &f), Some(0xFFFF_FFFF));
+    }
+
+    #[test]
+    fn unmap_frees_frames_and_skips_holes() {
+        let mut f = TestFrames::default();
+        let mut pd = directory();
+        let a = f.alloc().unwrap();
+        let b = f.alloc().unwrap();
+        pd.map_range(0x1000, PGSIZE, a, PteFlags::U, &mut f).unwrap();
+        pd.map_range(0x0080_0000, PGSIZE, b, PteFlags::U, &mut f).unwrap();
+        assert_eq!(f.live(), 4);
+        assert_eq!(pd.unmap_range(0x800, 0x0080_1000, &mut f, true), 2);
+        assert_eq!(f.live(), 2, "only the page tables remain");
+        assert_eq!(pd.translate(0x1000, &f), None);
+        assert_eq!(pd.unmap_range(0xFFFF_F001, u32::MAX, &mut f, true), 0);
+        pd.free_tables(0x8000_0000, &mut f);
+        assert_eq!(f.live(), 0);
+    }
+
+    #[test]
+    fn builder_matches_the_c_seg_macros() {
+        // SEG(STA_X|STA_R, 0, 0xffffffff, 0) and friends, as seginit built them.
+        assert_eq!(SegBuilder::code().build().bits(), 0x00CF_9A00_0000_FFFF);
+        assert_eq!(SegBuilder::data().build().bits(), 0x00CF_9200_0000_FFFF);
+        assert_eq!(SegBuilder::code().dpl(DPL_USER).build().bits(), 0x00CF_FA00_0000_FFFF);
+        assert_eq!(SegBuilder::data().dpl(DPL_USER).build().bits(), 0x00CF_F200_0000_FFFF);
+        let flat = SegBuilder::data().build();
+        assert_eq!((flat.base(), flat.limit(), flat.dpl()), (0, 0xFFFF_FFFF, 0));
+    }
+
+    #[test]
+    fn tss_descriptor_is_a_byte_granular_system_segment() {
+        let d = SegBuilder::tss(0x8012_3456, 104).build();
+        assert_eq!(d.bits(), 0x8000_8912_3456_0067);
+        assert_eq!((d.base(), d.limit(), d.s(), d.g()), (0x8012_3456, 103, false, false));
+        let small = SegBuilder::data().base(0x1000).limit(0xFFFF).build();
+        assert_eq!((small.base(), small.limit(), small.g()), (0x1000, 0xFFFF, false));
+    }
+
+    #[test]
+    fn gates_split_the_handler_offset() {
+        let int = GateDesc::interrupt(selector(SEG_KCODE, 0), 0x8010_2345, 0);
+        assert_eq!(int.bits(), 0x8010_8E00_0008_2345);
+        let sys = GateDesc::trap(selector(SEG_KCODE, 0), 0x8010_2345, DPL_USER);
+        assert_eq!(sys.bits(), 0x8010_EF00_0008_2345);
+        assert_eq!((sys.offset(), sys.cs(), sys.dpl()), (0x8010_2345, 8, 3));
+        let call = SegDesc::call_gate(selector(SEG_KCODE, 0), 0x8010_2345, DPL_USER);
+        assert_eq!((call.segtype(), call.dpl(), call.s(), call.p()), (STS_CG32, 3, false, true));
+        assert_eq!(selector(SEG_UDATA, DPL_USER), 0x23);
+    }
 }
@@ -0,0 +1,364 @@
+//! \file namei.rs
+//! \brief Path name lookup and symbolic links, replacing `namex` in `fs.c`.
+//!
+//! A symbolic link is an inode of type [`T_SYMLINK`] whose contents are the
+//! path it points to. Lookup follows links found in the middle of a path,
+//! and in the last element unless asked not to (`O_NOFOLLOW`). A relative
+//! target is resolved from the directory holding the link.
+//!
+//! Following a link splices its target in front of the rest of the path, so
+//! the whole path lives in one [`MAXPATH`] buffer and lookup never recurses.
+//! At most [`MAXSYMLINKS`] links are followed in one lookup, which also ends
+//! loops of links.
+//!
+//! Lookup also crosses mount points: a directory with a file system mounted
+//! on it is replaced by that file system's root, and `..` in such a root
+//! leads back to the directory it covers.
+
+use crate::errno::Errno;
+use crate::fs::DIRSIZ;
+use core::ops::Range;
+#[cfg(not(feature = "hosted"))]
+use {
+    crate::file::{Inode, T_DIR, T_SYMLINK},
+    crate::proc::myproc,
+    crate::vfs::mount::MOUNTS,
+    crate::vfs::{dirlink, dirlookup, idup, ilock, iput, iunlock, iunlockput, iupdate},
+    crate::vfs::{readi, superblock, writei},
+    core::{ffi::CStr, ptr},
+};
+
+/// \brief Longest path, after splicing in link targets, and longest target.
+pub const MAXPATH: usize = 256;
+/// \brief Symbolic links followed in one lookup before giving up with `ELOOP`.
+pub const MAXSYMLINKS: usize = 10;
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    fn begin_op();
+    fn end_op();
+}
+
+/// \brief Find the next element of `path` at or after `pos`.
+///
+/// Returns the element's range and the position after it and any slashes
+/// that follow, so the element is the last one if that position is
+/// `path.len()`. Returns `None` if no element is left.
+///
+/// ```text
+/// "a/bb/c" -> "a", then "bb", then "c"
+/// "///a//bb" -> "a", then "bb"
+/// "" and "////" -> None
+/// ```
+pub fn skip_elem(path: &[u8], pos: usize) -> Option<(Range<usize>, usize)> {
+    let start = pos + path[pos..].iter().take_while(|&&c| c == b'/').count();
+    if start == path.len() {
+        return None;
+    }
+    let end = start + path[start..].iter().take_while(|&&c| c != b'/').count();
+    let next = end + path[end..].iter().take_while(|&&c| c == b'/').count();
+    Some((start..end, next))
+}
+
+/// \brief Copy a path element into a directory entry name, truncating it
+/// to `DIRSIZ` bytes as `skipelem` did.
+pub fn elem_name(elem: &[u8], name: &mut [u8; DIRSIZ]) {
+    let n = elem.len().min(DIRSIZ);
+    name[..n].copy_from_slice(&elem[..n]);
+    name[n..].fill(0);
+}
+
+/// \brief Make room for a link target of `tlen` bytes in front of
+/// `path[rest]`, the part of the path after the link.
+///
+/// Afterwards `path[..tlen]` is free for the target and the new path is
+/// `path[..len]`, where `len` is returned.
+pub fn splice(path: &mut [u8; MAXPATH], rest: Range<usize>, tlen: usize) -> Result<usize, Errno> {
+    if tlen > MAXPATH {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    if rest.is_empty() {
+        return Ok(tlen);
+    }
+    let len = tlen + 1 + rest.len();
+    if len > MAXPATH {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    path.copy_within(rest, tlen + 1);
+    path[tlen] = b'/';
+    Ok(len)
+}
+
+/// \brief Look up `path`, or its parent directory if `parent`, copying
+/// the last element into `name`.
+///
+/// A link in the last element is followed only if `follow`. The returned
+/// inode is referenced but not locked. Must be called inside a
+/// transaction, since it calls `iput`.
+///
+/// # Safety
+/// Must run in a process, which supplies the working directory.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn lookup(
+    path: &[u8],
+    parent: bool,
+    follow: bool,
+    name: &mut [u8; DIRSIZ],
+) -> Result<*mut Inode, Errno> {
+    let mut buf = [0u8; MAXPATH];
+    if path.len() > MAXPATH {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    buf[..path.len()].copy_from_slice(path);
+    let mut len = path.len();
+    let mut pos = 0;
+    let mut links = 0;
+
+    let mut ip = if path.first() == Some(&b'/') {
+        MOUNTS.root()
+    } else {
+        idup((*myproc()).cwd)
+    };
+    while let Some((elem, after)) = skip_elem(&buf[..len], pos) {
+        elem_name(&buf[elem], name);
+        pos = after;
+        let last = pos == len;
+        if name[..3] == *b"..\0" {
+            ip = MOUNTS.cross_up(ip);
+        }
+        ilock(ip);
+        if (*ip).itype != T_DIR {
+            iunlockput(ip);
+            return Err(Errno::ENOTDIR);
+        }
+        if parent && last {
+            // Stop one level early.
+            iunlock(ip);
+            return Ok(ip);
+        }
+        let next = dirlookup(ip, name.as_ptr(), ptr::null_mut());
+        iunlock(ip);
+        if next.is_null() {
+            iput(ip);
+            return Err(Errno::ENOENT);
+        }
+        let next = MOUNTS.cross_down(next);
+        ilock(next);
+        if (*next).itype != T_SYMLINK || (last && !follow) {
+            iunlock(next);
+            iput(ip);
+            ip = next;
+            continue;
+        }
+
+        links += 1;
+        let tlen = (*next).size as usize;
+        let spliced = if links > MAXSYMLINKS { Err(Errno::ELOOP) } else { splice(&mut buf, pos..len, tlen) };
+        let n = match spliced {
+            Ok(n) => n,
+            Err(e) => {
+                iunlockput(next);
+                iput(ip);
+                return Err(e);
+            }
+        };
+        if readi(next, buf.as_mut_ptr(), 0, tlen as u32) != tlen as i32 {
+            iunlockput(next);
+            iput(ip);
+            return Err(Errno::EIO);
+        }
+        iunlockput(next);
+        (len, pos) = (n, 0);
+        if buf[0] == b'/' {
+            iput(ip);
+            ip = MOUNTS.root();
+        }
+    }
+    if parent {
+        iput(ip);
+        return Err(Errno::ENOENT);
+    }
+    Ok(ip)
+}
+
+/// \brief Create a symbolic link at `path` pointing to `target`.
+///
+/// # Safety
+/// Must run in a process, outside any transaction.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn symlink(target: &[u8], path: &[u8]) -> Result<(), Errno> {
+    if target.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if target.len() > MAXPATH {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    begin_op();
+    let r = create_symlink(target, path);
+    end_op();
+    r
+}
+
+/// \brief The body of [`symlink`], inside its transaction.
+#[cfg(not(feature = "hosted"))]
+unsafe fn create_symlink(target: &[u8], path: &[u8]) -> Result<(), Errno> {
+    let mut name = [0u8; DIRSIZ];
+    let dp = lookup(path, true, false, &mut name)?;
+    if superblock(dp).readonly() {
+        iput(dp);
+        return Err(Errno::EROFS);
+    }
+    ilock(dp);
+    let old = dirlookup(dp, name.as_ptr(), ptr::null_mut());
+    if !old.is_null() {
+        iput(old);
+        iunlockput(dp);
+        return Err(Errno::EEXIST);
+    }
+
+    let ip = match superblock(dp).alloc_inode(T_SYMLINK) {
+        Ok(ip) => ip,
+        Err(e) => {
+            iunlockput(dp);
+            return Err(e);
+        }
+    };
+    ilock(ip);
+    (*ip).nlink = 1;
+    iupdate(ip);
+    if writei(ip, target.as_ptr(), 0, target.len() as u32) != target.len() as i32 {
+        panic!("symlink: writei");
+    }
+    if dirlink(dp, name.as_ptr(), (*ip).inum) < 0 {
+        panic!("symlink: dirlink");
+    }
+    iunlockput(ip);
+    iunlockput(dp);
+    Ok(())
+}
+
+/// \brief Copy the target of the link at `path` into `dst`, returning its
+/// length; a target longer than `dst` is cut short.
+///
+/// # Safety
+/// Must run in a process, outside any transaction; `dst` may be user
+/// memory of the current process.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn readlink(path: &[u8], dst: &mut [u8]) -> Result<usize, Errno> {
+    let mut name = [0u8; DIRSIZ];
+    begin_op();
+    let r = lookup(path, false, false, &mut name).and_then(|ip| {
+        ilock(ip);
+        let r = if (*ip).itype != T_SYMLINK {
+            Err(Errno::EINVAL)
+        } else {
+            let n = dst.len().min((*ip).size as usize);
+            if readi(ip, dst.as_mut_ptr(), 0, n as u32) == n as i32 {
+                Ok(n)
+            } else {
+                Err(Errno::EIO)
+            }
+        };
+        iunlockput(ip);
+        r
+    });
+    end_op();
+    r
+}
+
+/// \brief The inode for a path, following every link; `namei` in C.
+///
+/// Returns a referenced, unlocked inode, or null if the path does not
+/// resolve. Must be called inside a transaction.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string and the caller a process.
+#[no_mangle]
+#[cfg(not(feature = "hosted"))]
+pub unsafe extern "C" fn namei(path: *const u8) -> *mut Inode {
+    let mut name = [0u8; DIRSIZ];
+    let path = CStr::from_ptr(path.cast()).to_bytes();
+    match lookup(path, false, true, &mut name) {
+        Ok(ip) => ip,
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// \brief Like [`namei`], but a link in the last element is returned
+/// itself rather than followed.
+///
+/// # Safety
+/// As for [`namei`].
+#[no_mangle]
+#[cfg(not(feature = "hosted"))]
+pub unsafe extern "C" fn namei_nofollow(path: *const u8) -> *mut Inode {
+    let mut name = [0u8; DIRSIZ];
+    let path = CStr::from_ptr(path.cast()).to_bytes();
+    match lookup(path, false, false, &mut name) {
+        Ok(ip) => ip,
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// \brief The directory holding the last element of `path`, which is
+/// copied into `name`; `nameiparent` in C.
+///
+/// # Safety
+/// As for [`namei`]; `name` must have room for `DIRSIZ` bytes.
+#[no_mangle]
+#[cfg(not(feature = "hosted"))]
+pub unsafe extern "C" fn nameiparent(path: *const u8, name: *mut u8) -> *mut Inode {
+    let name = &mut *name.cast::<[u8; DIRSIZ]>();
+    let path = CStr::from_ptr(path.cast()).to_bytes();
+    match lookup(path, true, false, name) {
+        Ok(ip) => ip,
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elems(path: &[u8]) -> Vec<&[u8]> {
+        let mut v = Vec::new();
+        let mut pos = 0;
+        while let Some((e, next)) = skip_elem(path, pos) {
+            v.push(&path[e]);
+            pos = next;
+        }
+        v
+    }
+
+    #[test]
+    fn elements_skip_slashes() {
+        assert_eq!(elems(b"a/bb/c"), [b"a" as &[u8], b"bb", b"c"]);
+        assert_eq!(elems(b"///a//bb/"), [b"a" as &[u8], b"bb"]);
+        assert!(elems(b"").is_empty() && elems(b"////").is_empty());
+        assert_eq!(skip_elem(b"a//", 0), Some((0..1, 3)));
+
+        let mut name = [b'x'; DIRSIZ];
+        elem_name(b"bb", &mut name);
+        assert_eq!(&name[..3], b"bb\0");
+        elem_name(b"a_name_longer_than_dirsiz", &mut name);
+        assert_eq!(&name, b"a_name_longer_");
+    }
+
+    #[test]
+    fn splice_puts_the_target_before_the_rest() {
+        let mut buf = [0u8; MAXPATH];
+        buf[..9].copy_from_slice(b"lnk//x/yy");
+        let n = splice(&mut buf, 5..9, 6).unwrap();
+        buf[..6].copy_from_slice(b"/a/bcd");
+        assert_eq!(&buf[..n], b"/a/bcd/x/yy");
+
+        // The rest moves towards the front when the target is shorter.
+        let n = splice(&mut buf, 7..n, 1).unwrap();
+        buf[0] = b'z';
+        assert_eq!(&buf[..n], b"z/x/yy");
+
+        assert_eq!(splice(&mut buf, 6..6, 4), Ok(4));
+        assert_eq!(splice(&mut buf, 0..6, MAXPATH - 6), Err(Errno::ENAMETOOLONG));
+        assert_eq!(splice(&mut buf, 6..6, MAXPATH + 1), Err(Errno::ENAMETOOLONG));
+    }
+}
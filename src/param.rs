@@ -1,2 +1,11 @@
+pub const NPROC: usize = 64;
+pub const KSTACKSIZE: usize = 4096;
+pub const NCPU: usize = 8;
 pub const NOFILE: usize = 16;
-pub const NSEGS: usize = 6;
+pub const ROOTDEV: u32 = 1;
+pub const NSEGS: usize = 7;
+pub const MAXOPBLOCKS: usize = 20;
+pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
+pub const NBUF: usize = 128;
+pub const NVMA: usize = 16;
+pub const NMOUNT: usize = 8;
@@ -0,0 +1,179 @@
+//! \file percpu.rs
+//! \brief Per-CPU variables, found through `%gs`.
+//!
+//! `seginit` points the `SEG_KCPU` segment of each CPU's GDT at that CPU's
+//! `struct cpu` and loads it into `%gs`; `alltraps` loads it again after a
+//! trap from user mode. Finding the current CPU is then a single load of
+//! `%gs:Cpu::id`, instead of the APIC ID scan done by `mycpu`. `pushcli`,
+//! `popcli` and the spinlocks find the CPU's `Cpu` the same way, through
+//! [`this_cpu`], so taking a [`PreemptGuard`] never scans either.
+//!
+//! A variable declared with [`percpu!`] has one slot per CPU. Its slot may
+//! only be used while the CPU cannot switch to other code that uses it, so
+//! access needs a [`PreemptGuard`]. Slots hand out shared references only:
+//! state that changes goes in `Cell`s, which is sound because nothing else
+//! runs on the CPU while the guard lives.
+
+use crate::param::NCPU;
+use crate::proc::Cpu;
+use crate::spinlock::{popcli, pushcli};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+
+/// \brief Declare a per-CPU variable.
+///
+/// Every CPU's slot starts out as `init`, which must be a constant.
+///
+/// ```ignore
+/// percpu! {
+///     /// Context switches done by each CPU.
+///     pub static SWITCHES: Cell<u32> = Cell::new(0);
+/// }
+/// let g = PreemptGuard::new();
+/// SWITCHES.get(&g).set(SWITCHES.get(&g).get() + 1);
+/// ```
+#[macro_export]
+macro_rules! percpu {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;) => {
+        $(#[$attr])*
+        $vis static $name: $crate::percpu::PerCpu<$ty> =
+            $crate::percpu::PerCpu::new([const { $init }; $crate::param::NCPU]);
+    };
+}
+
+/// \brief Proof that the current code stays on this CPU and cannot be
+/// interrupted: interrupts are off from `new` until drop.
+pub struct PreemptGuard {
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl PreemptGuard {
+    /// \brief Disable interrupts, nesting like `pushcli`.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        unsafe { pushcli() };
+        Self { _not_send: PhantomData }
+    }
+
+    /// \brief Index of this CPU in `cpus`.
+    pub fn cpu(&self) -> usize {
+        cpu_index()
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        unsafe { popcli() };
+    }
+}
+
+/// \brief A value per CPU; see [`percpu!`].
+pub struct PerCpu<T> {
+    slots: UnsafeCell<[T; NCPU]>,
+}
+
+// Each slot is only used by its own CPU, with interrupts off, or through
+// `for_cpu` when `T` can be shared.
+unsafe impl<T> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    /// \brief Slots with the given initial values; use [`percpu!`].
+    pub const fn new(slots: [T; NCPU]) -> Self {
+        Self { slots: UnsafeCell::new(slots) }
+    }
+
+    /// \brief This CPU's slot.
+    pub fn get<'a>(&'a self, guard: &'a PreemptGuard) -> &'a T {
+        unsafe { &(*self.slots.get())[guard.cpu()] }
+    }
+
+    /// \brief The slot of CPU `cpu`, for values that other CPUs may share.
+    pub fn for_cpu(&self, cpu: usize) -> &T
+    where
+        T: Sync,
+    {
+        unsafe { &(*self.slots.get())[cpu] }
+    }
+}
+
+/// \brief Index of the calling CPU, read from its `Cpu` through `%gs`.
+#[cfg(not(feature = "hosted"))]
+fn cpu_index() -> usize {
+    let id: u32;
+    unsafe {
+        core::arch::asm!(
+            "mov {}, gs:[{off}]",
+            out(reg) id,
+            off = const core::mem::offset_of!(crate::proc::Cpu, id),
+            options(nostack, readonly, preserves_flags),
+        );
+    }
+    id as usize
+}
+
+#[cfg(feature = "hosted")]
+use crate::hosted::cpu_index;
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    static mut cpus: [Cpu; NCPU];
+}
+
+/// \brief The calling CPU's `Cpu`, found through `%gs`.
+///
+/// Interrupts must be off, or the caller could move to another CPU before
+/// using the result.
+#[cfg(not(feature = "hosted"))]
+pub fn this_cpu() -> *mut Cpu {
+    unsafe { core::ptr::addr_of_mut!(cpus[cpu_index()]) }
+}
+
+/// \brief The calling thread's stand-in `Cpu`.
+#[cfg(feature = "hosted")]
+pub fn this_cpu() -> *mut Cpu {
+    unsafe { crate::hosted::mycpu() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use std::thread;
+
+    percpu! {
+        static COUNT: Cell<u32> = Cell::new(7);
+    }
+
+    #[test]
+    fn each_cpu_has_its_own_slot() {
+        let here = {
+            let g = PreemptGuard::new();
+            COUNT.get(&g).set(COUNT.get(&g).get() + 1);
+            g.cpu()
+        };
+        let there = thread::spawn(|| {
+            let g = PreemptGuard::new();
+            (g.cpu(), COUNT.get(&g).get())
+        })
+        .join()
+        .unwrap();
+        assert_ne!(there.0, here);
+        assert_eq!(there.1, 7);
+        let g = PreemptGuard::new();
+        assert_eq!(COUNT.get(&g).get(), 8);
+    }
+
+    #[test]
+    fn shared_slots_are_visible_from_other_cpus() {
+        use core::sync::atomic::{AtomicU32, Ordering};
+        percpu! {
+            static SEEN: AtomicU32 = AtomicU32::new(0);
+        }
+        let cpu = {
+            let g = PreemptGuard::new();
+            SEEN.get(&g).store(5, Ordering::Relaxed);
+            g.cpu()
+        };
+        assert_eq!(SEEN.for_cpu(cpu).load(Ordering::Relaxed), 5);
+    }
+}
@@ -1,57 +1,174 @@
-/** Process management structures. */
+/** Process management structures, the process table and the scheduler. */
+use crate::arch::Trapframe;
+use crate::errno::Errno;
 use crate::file::{File, Inode};
-use crate::mmu;
-use crate::param;
-use crate::types::Pde;
+use crate::mmap::Vma;
+use crate::mmu::{self, PageDirectory};
+use crate::param::{self, NCPU, NPROC, NVMA};
+use crate::percpu::PerCpu;
+use crate::sched::{self, RunQueue};
+use crate::spinlock::Spinlock;
+use crate::sync::SpinLock;
 
+use core::cell::{Cell, UnsafeCell};
 use core::ffi;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
 
+#[cfg(not(feature = "hosted"))]
+use core::arch::asm;
+#[cfg(not(feature = "hosted"))]
+use crate::cow::copyuvm;
+#[cfg(not(feature = "hosted"))]
+use crate::fpu_state;
+#[cfg(not(feature = "hosted"))]
+use crate::mmap;
+#[cfg(not(feature = "hosted"))]
+use crate::param::{KSTACKSIZE, NOFILE, ROOTDEV};
+#[cfg(not(feature = "hosted"))]
+use crate::percpu::PreemptGuard;
+#[cfg(not(feature = "hosted"))]
+use crate::spinlock::{acquire, getcallerpcs, holding, initlock, release};
+#[cfg(not(feature = "hosted"))]
+use crate::trap::ticks;
+#[cfg(not(feature = "hosted"))]
+use crate::vfs::mount::{mount_root, MOUNTS};
+#[cfg(not(feature = "hosted"))]
+use crate::vfs::{idup, iput};
+#[cfg(not(feature = "hosted"))]
+use crate::vm::{allocuvm, deallocuvm, freevm, kalloc, kfree, setupkvm, switchuvm};
+
+#[cfg(feature = "hosted")]
+use crate::hosted::lapicipi;
+#[cfg(feature = "hosted")]
+pub use crate::hosted::{mycpu, myproc, sleep, wakeup, wakeup_one};
+
+// These live in the C half of the kernel and are unavailable on the host.
+#[cfg(not(feature = "hosted"))]
 extern "C" {
-    pub fn myproc() -> *const Proc;
-    pub fn growproc(n: i32) -> i32;
-    pub fn kill(pid: i32) -> i32;
-    pub fn exit();
-    pub fn fork() -> i32;
-    pub fn sleep(chan: *const ffi::c_void, lk: *const ffi::c_void);
-    pub fn wait() -> i32;
-    pub fn procdump();
+    static mut cpus: [Cpu; NCPU];
+    static ncpu: i32;
+    static _binary_initcode_start: [u8; 0];
+    static _binary_initcode_size: [u8; 0];
+
+    fn panic(s: *const ffi::c_char) -> !;
+    fn lapicid() -> i32;
+    fn lapicipi(c: i32);
+    fn swtch(old: *mut *mut Context, new: *mut Context);
+    fn trapret();
+
+    fn inituvm(pgdir: *mut PageDirectory, init: *const u8, sz: u32);
+    fn switchkvm();
+
+    fn filedup(f: *mut File) -> *mut File;
+    fn fileclose(f: *mut File);
+    fn begin_op();
+    fn end_op();
 }
 
 #[repr(C)]
 /// Per-CPU state information.
 pub struct Cpu {
     /// Local APIC ID for this CPU.
-    apicid: u8,
-    /// Scheduler context switch location.
-    scheduler: *const Context,
+    pub apicid: u8,
     /// Task state segment for interrupts.
-    ts: mmu::TaskState,
+    pub ts: mmu::TaskState,
     /// Global descriptor table for this CPU.
-    gdt: [mmu::SegDesc<u32>; param::NSEGS],
+    pub gdt: [mmu::SegDesc; param::NSEGS],
     /// Non-zero when CPU started.
-    started: u32,
+    pub started: u32,
     /// Depth of pushcli nesting.
-    ncli: i32,
+    pub ncli: i32,
     /// Interrupts enabled before pushcli.
-    intena: i32,
-    /// Currently running process on this CPU.
-    proc: *const Proc,
+    pub intena: i32,
+    /// Index in `cpus`, read through `%gs` by [`crate::percpu`].
+    pub id: u32,
+}
+
+/// \brief Scheduler state of one CPU, used only by that CPU.
+pub struct SchedCpu {
+    /// Process running on this CPU, or null.
+    pub proc: Cell<*mut Proc>,
+    /// Where `sched` switches to in order to enter `scheduler`.
+    pub scheduler: Cell<*mut Context>,
+}
+
+percpu! {
+    /// \brief Scheduler state of each CPU.
+    pub static SCHED: SchedCpu = SchedCpu { proc: Cell::new(ptr::null_mut()), scheduler: Cell::new(ptr::null_mut()) };
 }
 
 #[repr(C)]
 /// CPU context saved during kernel context switches.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub struct Context {
     /// Saved EDI register.
-    edi: u32,
+    pub edi: u32,
     /// Saved ESI register.
-    esi: u32,
+    pub esi: u32,
     /// Saved EBX register.
-    ebx: u32,
+    pub ebx: u32,
     /// Saved EBP register.
-    ebp: u32,
+    pub ebp: u32,
     /// Saved instruction pointer.
-    eip: u32,
+    pub eip: u32,
+}
+
+/// \brief Lifecycle state of a process slot.
+///
+/// Same numbering as `enum procstate` in `proc.h` so C code reading
+/// `p->state` keeps working.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProcState {
+    /// Slot is free.
+    Unused = 0,
+    /// Slot claimed by `allocproc`, not yet runnable.
+    Embryo = 1,
+    /// Blocked in `sleep` on `Proc::chan`.
+    Sleeping = 2,
+    /// Waiting for a CPU.
+    Runnable = 3,
+    /// Running on some CPU.
+    Running = 4,
+    /// Exited, waiting for the parent to reap it.
+    Zombie = 5,
+}
+
+impl ProcState {
+    /// \brief Whether a process may move from `self` to `next`.
+    ///
+    /// The allowed edges are exactly those taken by the functions in this
+    /// module: allocation (`Unused -> Embryo`, or back on failure), first
+    /// scheduling, yield/sleep/wakeup/exit, and reaping by the parent.
+    pub fn can_become(self, next: ProcState) -> bool {
+        use ProcState::*;
+        matches!(
+            (self, next),
+            (Unused, Embryo)
+                | (Embryo, Unused)
+                | (Embryo, Runnable)
+                | (Runnable, Running)
+                | (Running, Runnable)
+                | (Running, Sleeping)
+                | (Running, Zombie)
+                | (Sleeping, Runnable)
+                | (Zombie, Unused)
+        )
+    }
+
+    /// \brief Fixed-width label used by `procdump`.
+    pub fn label(self) -> &'static str {
+        match self {
+            ProcState::Unused => "unused",
+            ProcState::Embryo => "embryo",
+            ProcState::Sleeping => "sleep ",
+            ProcState::Runnable => "runble",
+            ProcState::Running => "run   ",
+            ProcState::Zombie => "zombie",
+        }
+    }
 }
 
 #[repr(C)]
@@ -60,25 +177,1397 @@ pub struct Proc {
     /// Size of process memory (bytes).
     pub sz: u32,
     /// Page table for this process.
-    pub pgdir: *const Pde,
+    pub pgdir: *mut PageDirectory,
     /// Bottom of kernel stack for this process.
-    pub kstack: *const u8,
-    /// Process state (should be enum).
-    pub procstate: u32,
+    pub kstack: *mut u8,
+    /// Process state; change it with [`Proc::set_state`].
+    state: ProcState,
     /// Process ID.
     pub pid: u32,
     /// Parent process.
-    pub parent: *const Proc,
+    pub parent: *mut Proc,
+    /// Trap frame for current syscall.
+    pub tf: *mut Trapframe,
     /// CPU context for swtch().
-    pub context: *const Context,
+    pub context: *mut Context,
     /// If non-zero, sleeping on chan.
     pub chan: *const ffi::c_void,
     /// If non-zero, have been killed.
     pub killed: i32,
     /// Open files.
-    pub ofile: [*const File; param::NOFILE],
+    pub ofile: [*mut File; param::NOFILE],
     /// Current directory.
-    pub cwd: *const Inode,
+    pub cwd: *mut Inode,
     /// Process name (debugging).
     pub name: [u8; 16],
+    /// Scheduling priority, 0 is highest (used by the MLFQ policy).
+    pub priority: u32,
+    /// Ticks left at this priority before demotion.
+    pub budget: i32,
+    /// Value of `ticks` when last dispatched.
+    pub ticks_in: u32,
+    /// Bit `c` set if the process may run on CPU `c`; atomic so a CPU
+    /// stealing work can check it without the table lock.
+    pub affinity: AtomicU32,
+    /// CPU whose run queue holds or last ran the process.
+    pub cpu: u32,
+    /// Regions set up by `mmap`.
+    pub vmas: [Vma; NVMA],
+    /// Page holding the saved FPU/SSE registers.
+    pub fpu: *mut u8,
+}
+
+impl Proc {
+    /// \brief An `Unused` slot with every field cleared.
+    pub const fn new() -> Self {
+        Proc {
+            sz: 0,
+            pgdir: ptr::null_mut(),
+            kstack: ptr::null_mut(),
+            state: ProcState::Unused,
+            pid: 0,
+            parent: ptr::null_mut(),
+            tf: ptr::null_mut(),
+            context: ptr::null_mut(),
+            chan: ptr::null(),
+            killed: 0,
+            ofile: [ptr::null_mut(); param::NOFILE],
+            cwd: ptr::null_mut(),
+            name: [0; 16],
+            priority: 0,
+            budget: 0,
+            ticks_in: 0,
+            affinity: AtomicU32::new(0),
+            cpu: 0,
+            vmas: [Vma::EMPTY; NVMA],
+            fpu: ptr::null_mut(),
+        }
+    }
+
+    /// \brief Current lifecycle state.
+    #[inline]
+    pub fn state(&self) -> ProcState {
+        self.state
+    }
+
+    /// \brief Move to `next`, panicking on a transition the kernel never makes.
+    pub fn set_state(&mut self, next: ProcState) {
+        if !self.state.can_become(next) {
+            bad_transition(self, next);
+        }
+        self.state = next;
+    }
+
+    /// \brief Printable process name for diagnostics.
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    /// \brief Set the name, truncating so it stays null-terminated.
+    pub fn set_name(&mut self, name: &[u8]) {
+        let n = name.len().min(self.name.len() - 1);
+        self.name = [0; 16];
+        self.name[..n].copy_from_slice(&name[..n]);
+    }
+}
+
+impl Default for Proc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// \brief Report an illegal state change and stop.
+#[cold]
+fn bad_transition(p: &Proc, next: ProcState) -> ! {
+    #[cfg(not(feature = "hosted"))]
+    unsafe {
+        println!("pid {} {}: {:?} -> {:?}", p.pid, p.name(), p.state, next);
+        panic(c"bad proc state transition".as_ptr())
+    }
+    #[cfg(feature = "hosted")]
+    panic!("pid {}: {:?} -> {:?}", p.pid, p.state, next)
+}
+
+/// \brief Result of scanning the table for a parent's children.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Children {
+    /// Slot index of a child that has exited.
+    Zombie(usize),
+    /// Children exist but none has exited yet.
+    Running,
+    /// No process has this parent.
+    None,
+}
+
+/// \brief Contents of the process table, reachable only through [`PtableGuard`].
+pub struct Ptable {
+    /// Process slots.
+    pub procs: [Proc; NPROC],
+    /// PID handed to the next allocated process.
+    nextpid: u32,
+    /// The first user process, which inherits orphans.
+    pub initproc: *mut Proc,
+    /// Run queues this table feeds, fixed once attached.
+    runqs: *const PerCpu<SpinLock<RunQueue>>,
+    /// Number of CPUs using `runqs`.
+    ncpu: usize,
+    /// Tick at which the next MLFQ promotion is due.
+    promote_at: u32,
+}
+
+impl Ptable {
+    /// \brief Empty table; PIDs start at 1.
+    pub const fn new() -> Self {
+        Ptable {
+            procs: [const { Proc::new() }; NPROC],
+            nextpid: 1,
+            initproc: ptr::null_mut(),
+            runqs: ptr::null(),
+            ncpu: 0,
+            promote_at: sched::TICKS_TO_PROMOTE,
+        }
+    }
+
+    /// \brief Feed the run queues of the first `n` CPUs in `runqs`.
+    ///
+    /// Must be called before any process becomes runnable.
+    pub fn attach_runqs(&mut self, runqs: &'static PerCpu<SpinLock<RunQueue>>, n: usize) {
+        assert!(n > 0 && n <= NCPU, "attach_runqs: bad cpu count");
+        self.runqs = runqs;
+        self.ncpu = n;
+    }
+
+    /// \brief Run queue of CPU `c`, with its lock.
+    pub fn runq(&self, c: usize) -> &'static SpinLock<RunQueue> {
+        assert!(c < self.ncpu, "runq: bad cpu");
+        unsafe { (*self.runqs).for_cpu(c) }
+    }
+
+    /// \brief Whether slot `i` may run on CPU `c`.
+    fn allowed(&self, i: usize, c: usize) -> bool {
+        self.procs[i].affinity.load(Ordering::Relaxed) & (1 << c) != 0
+    }
+
+    /// \brief Slot index of `p`, which must point into this table.
+    pub fn index_of(&self, p: *const Proc) -> usize {
+        let i = (p as usize).wrapping_sub(self.procs.as_ptr() as usize) / core::mem::size_of::<Proc>();
+        assert!(i < NPROC, "proc not in table");
+        i
+    }
+
+    /// \brief Move slot `i` to `next`; `cpu` is the CPU making the change.
+    ///
+    /// A process that becomes runnable is queued on `cpu` if its affinity
+    /// allows, otherwise on the least loaded CPU it may use, which is sent
+    /// an interrupt in case it is halted.
+    pub fn set_state(&mut self, i: usize, next: ProcState, cpu: usize) {
+        self.procs[i].set_state(next);
+        if next == ProcState::Runnable {
+            self.enqueue(i, cpu);
+        }
+    }
+
+    fn enqueue(&mut self, i: usize, cpu: usize) {
+        let target = if self.allowed(i, cpu) {
+            cpu
+        } else {
+            (0..self.ncpu)
+                .filter(|&c| self.allowed(i, c))
+                .min_by_key(|&c| self.runq(c).lock().len())
+                .unwrap_or(cpu)
+        };
+        self.procs[i].cpu = target as u32;
+        let lvl = sched::level(&self.procs[i]);
+        self.runq(target).lock().push(i, lvl);
+        if target != cpu {
+            unsafe { lapicipi(target as i32) };
+        }
+    }
+
+    /// \brief Whether any CPU has a process queued.
+    ///
+    /// Reads only the queue counters, so an idle CPU can call it without
+    /// taking any lock before deciding to halt.
+    pub fn has_work(&self) -> bool {
+        (0..self.ncpu).any(|c| RunQueue::peek_len(self.runq(c)) != 0)
+    }
+
+    /// \brief Take the next process for CPU `cpu` off the run queues.
+    ///
+    /// Takes the first process allowed on `cpu` from the CPU's own queue; if
+    /// there is none, steals one from the busiest other queue. Needs
+    /// only the queue locks, so CPUs pick in parallel; the slot stays
+    /// `Runnable` until [`Ptable::run`] is called with the table lock held.
+    pub fn pick(&self, cpu: usize) -> Option<usize> {
+        if let Some(i) = self.runq(cpu).lock().take(|i| self.allowed(i, cpu)) {
+            return Some(i);
+        }
+        self.steal(cpu)
+    }
+
+    /// \brief Take a process allowed on `cpu` from the busiest other queue.
+    ///
+    /// Each pair of queues is locked lowest CPU first, and the own queue is
+    /// checked again in case a wakeup filled it meanwhile.
+    fn steal(&self, cpu: usize) -> Option<usize> {
+        let mut victims: [usize; NCPU] = core::array::from_fn(|c| c);
+        let victims = &mut victims[..self.ncpu];
+        victims.sort_unstable_by_key(|&c| core::cmp::Reverse(RunQueue::peek_len(self.runq(c))));
+        victims.iter().filter(|&&v| v != cpu).find_map(|&v| {
+            let (mut own, mut victim) = if cpu < v {
+                let own = self.runq(cpu).lock();
+                (own, self.runq(v).lock())
+            } else {
+                let victim = self.runq(v).lock();
+                (self.runq(cpu).lock(), victim)
+            };
+            let allowed = |i| self.allowed(i, cpu);
+            own.take(allowed).or_else(|| victim.take(allowed))
+        })
+    }
+
+    /// \brief Mark slot `i`, just picked by CPU `cpu`, `Running` at `now`.
+    pub fn run(&mut self, i: usize, cpu: usize, now: u32) {
+        if sched::MLFQ {
+            self.promote(now);
+        }
+        let p = &mut self.procs[i];
+        p.set_state(ProcState::Running);
+        p.ticks_in = now;
+        p.cpu = cpu as u32;
+    }
+
+    /// \brief Move every process up one level once `TICKS_TO_PROMOTE` has passed.
+    fn promote(&mut self, now: u32) {
+        if (now.wrapping_sub(self.promote_at) as i32) < 0 {
+            return;
+        }
+        self.promote_at = now.wrapping_add(sched::TICKS_TO_PROMOTE);
+        for c in 0..self.ncpu {
+            self.runq(c).lock().promote();
+        }
+        for p in self.procs.iter_mut() {
+            if p.state != ProcState::Unused && p.priority > 0 {
+                p.priority -= 1;
+            }
+        }
+    }
+
+    /// \brief Take the running process `p` off CPU `cpu` at `now`.
+    ///
+    /// Under MLFQ its time on the CPU is charged against its budget before
+    /// it moves to `next`.
+    pub fn deschedule(&mut self, p: *const Proc, next: ProcState, cpu: usize, now: u32) {
+        let i = self.index_of(p);
+        if sched::MLFQ {
+            sched::charge(&mut self.procs[i], now);
+        }
+        self.set_state(i, next, cpu);
+    }
+
+    /// \brief Set the priority of process `pid` and refill its budget.
+    ///
+    /// A queued process moves to its new level. One a CPU has already
+    /// picked but not yet run is left alone. Returns false when no process
+    /// has that PID.
+    pub fn set_priority(&mut self, pid: i32, prio: u32) -> bool {
+        let Some(i) = self.find(pid) else {
+            return false;
+        };
+        let runnable = self.procs[i].state == ProcState::Runnable;
+        let (c, lvl) = (self.procs[i].cpu as usize, sched::level(&self.procs[i]));
+        let mut q = self.runq(c).lock();
+        let queued = runnable && q.remove(i, lvl);
+        self.procs[i].priority = prio;
+        self.procs[i].budget = sched::DEFAULT_BUDGET;
+        if queued {
+            q.push(i, sched::level(&self.procs[i]));
+        }
+        true
+    }
+
+    /// \brief Priority of process `pid`, if it exists.
+    pub fn priority(&self, pid: i32) -> Option<u32> {
+        self.find(pid).map(|i| self.procs[i].priority)
+    }
+
+    /// \brief Restrict process `pid` to the CPUs set in `mask`.
+    ///
+    /// A process queued on a CPU it may no longer use moves to one it may.
+    /// A running one moves when it next gives up its CPU. Fails with
+    /// `EINVAL` when `mask` names no online CPU and `ESRCH` when no process
+    /// has that PID.
+    pub fn set_affinity(&mut self, pid: i32, mask: u32) -> Result<(), Errno> {
+        let online = if self.ncpu >= 32 { !0 } else { (1u32 << self.ncpu) - 1 };
+        if mask & online == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let i = self.find(pid).ok_or(Errno::ESRCH)?;
+        self.procs[i].affinity.store(mask, Ordering::Relaxed);
+        let c = self.procs[i].cpu as usize;
+        if self.procs[i].state == ProcState::Runnable && !self.allowed(i, c) {
+            let lvl = sched::level(&self.procs[i]);
+            if self.runq(c).lock().remove(i, lvl) {
+                self.enqueue(i, c);
+            }
+        }
+        Ok(())
+    }
+
+    /// \brief Slot holding live process `pid`.
+    fn find(&self, pid: i32) -> Option<usize> {
+        self.procs.iter().position(|p| p.state != ProcState::Unused && p.pid as i32 == pid)
+    }
+
+    /// \brief Claim an `Unused` slot, mark it `Embryo` and give it a PID.
+    pub fn alloc(&mut self) -> Option<&mut Proc> {
+        let p = self.procs.iter_mut().find(|p| p.state == ProcState::Unused)?;
+        p.set_state(ProcState::Embryo);
+        p.pid = self.nextpid;
+        p.priority = sched::DEFAULT_PRIORITY;
+        p.budget = sched::DEFAULT_BUDGET;
+        p.affinity.store(!0, Ordering::Relaxed);
+        self.nextpid += 1;
+        Some(p)
+    }
+
+    /// \brief Return a reaped or never-started slot to `Unused`.
+    ///
+    /// The caller has already released the kernel stack and page table.
+    pub fn free(p: &mut Proc) {
+        p.set_state(ProcState::Unused);
+        p.kstack = ptr::null_mut();
+        p.fpu = ptr::null_mut();
+        p.pgdir = ptr::null_mut();
+        p.pid = 0;
+        p.parent = ptr::null_mut();
+        p.name[0] = 0;
+        p.killed = 0;
+    }
+
+    /// \brief Make every process sleeping on `chan` runnable on `cpu`.
+    pub fn wakeup(&mut self, chan: *const ffi::c_void, cpu: usize) {
+        for i in 0..NPROC {
+            if self.procs[i].state == ProcState::Sleeping && self.procs[i].chan == chan {
+                self.set_state(i, ProcState::Runnable, cpu);
+            }
+        }
+    }
+
+    /// \brief Make the first process sleeping on `chan` runnable on `cpu`.
+    ///
+    /// Returns false if nothing was sleeping there.
+    pub fn wakeup_one(&mut self, chan: *const ffi::c_void, cpu: usize) -> bool {
+        match self.procs.iter().position(|p| p.state == ProcState::Sleeping && p.chan == chan) {
+            Some(i) => {
+                self.set_state(i, ProcState::Runnable, cpu);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// \brief Flag process `pid` as killed, waking it if asleep.
+    ///
+    /// A woken process is queued on `cpu`. Returns false when no process
+    /// has that PID.
+    pub fn kill(&mut self, pid: i32, cpu: usize) -> bool {
+        match self.procs.iter().position(|p| p.pid as i32 == pid) {
+            Some(i) => {
+                self.procs[i].killed = 1;
+                if self.procs[i].state == ProcState::Sleeping {
+                    self.set_state(i, ProcState::Runnable, cpu);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// \brief Hand every child of `from` to `to`.
+    ///
+    /// Returns true if any of them is already a zombie, in which case `to`
+    /// needs a wakeup to reap it.
+    pub fn reparent(&mut self, from: *const Proc, to: *mut Proc) -> bool {
+        let mut zombie = false;
+        for p in self.procs.iter_mut() {
+            if ptr::eq(p.parent, from) {
+                p.parent = to;
+                zombie |= p.state == ProcState::Zombie;
+            }
+        }
+        zombie
+    }
+
+    /// \brief Look for a child of `parent` that can be reaped.
+    pub fn children(&self, parent: *const Proc) -> Children {
+        let mut found = Children::None;
+        for (i, p) in self.procs.iter().enumerate() {
+            if !ptr::eq(p.parent, parent) {
+                continue;
+            }
+            if p.state == ProcState::Zombie {
+                return Children::Zombie(i);
+            }
+            found = Children::Running;
+        }
+        found
+    }
+}
+
+impl Default for Ptable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// \brief The process table together with the spinlock guarding it.
+///
+/// The lock is the C `struct spinlock` so that `acquire` disables
+/// interrupts and `sched` can check `holding`, exactly as in `proc.c`.
+pub struct ProcTable {
+    lock: Spinlock,
+    table: UnsafeCell<Ptable>,
+}
+
+// Access to `table` is serialised by `lock`.
+unsafe impl Sync for ProcTable {}
+
+/// \brief Proof that the current CPU holds the process table lock.
+pub struct PtableGuard {
+    table: &'static ProcTable,
+}
+
+impl ProcTable {
+    /// \brief Unlocked, empty table.
+    pub const fn new() -> Self {
+        ProcTable { lock: Spinlock::new(c"ptable"), table: UnsafeCell::new(Ptable::new()) }
+    }
+
+    /// \brief Acquire the table lock.
+    #[cfg(not(feature = "hosted"))]
+    pub fn lock(&'static self) -> PtableGuard {
+        unsafe { acquire(&self.lock) };
+        PtableGuard { table: self }
+    }
+
+    /// \brief Whether `lk` is this table's lock.
+    pub fn is_lock(&self, lk: *const Spinlock) -> bool {
+        ptr::eq(&self.lock, lk)
+    }
+
+    /// \brief Table contents without taking the lock.
+    ///
+    /// # Safety
+    /// Only for diagnostics that must not block, such as `procdump`, and
+    /// for [`Ptable::has_work`] and [`Ptable::pick`], which touch nothing
+    /// but the run queues and atomics.
+    pub unsafe fn unlocked(&self) -> &Ptable {
+        &*self.table.get()
+    }
+}
+
+impl Default for ProcTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PtableGuard {
+    /// \brief Raw pointer to slot `i`, valid after the guard is dropped.
+    pub fn slot(&mut self, i: usize) -> *mut Proc {
+        &mut self.procs[i]
+    }
+}
+
+impl Deref for PtableGuard {
+    type Target = Ptable;
+    fn deref(&self) -> &Ptable {
+        unsafe { &*self.table.table.get() }
+    }
+}
+
+impl DerefMut for PtableGuard {
+    fn deref_mut(&mut self) -> &mut Ptable {
+        unsafe { &mut *self.table.table.get() }
+    }
+}
+
+#[cfg(not(feature = "hosted"))]
+impl Drop for PtableGuard {
+    fn drop(&mut self) {
+        unsafe { release(&self.table.lock) };
+    }
+}
+
+/// \brief The system-wide process table.
+pub static PTABLE: ProcTable = ProcTable::new();
+
+/// \brief Initialise the process table lock.
+///
+/// # Safety
+/// Must run once on the boot CPU, after `mpinit` has counted the CPUs and
+/// before any process exists.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn pinit() {
+    initlock(ptr::addr_of!(PTABLE.lock) as *mut Spinlock, c"ptable".as_ptr() as *const u8);
+    PTABLE.lock().attach_runqs(&sched::RUNQ, ncpu as usize);
+}
+
+/// \brief Current value of the timer tick counter.
+#[cfg(not(feature = "hosted"))]
+fn now() -> u32 {
+    ticks()
+}
+
+/// \brief Index of `c` in `cpus`.
+///
+/// # Safety
+/// `c` must point into `cpus`.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn cpu_index_of(c: *const Cpu) -> u32 {
+    c.offset_from(ptr::addr_of!(cpus) as *const Cpu) as u32
+}
+
+/// \brief Index of the current CPU in `cpus`.
+///
+/// # Safety
+/// Must be called with interrupts disabled, after `seginit`.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn cpuid() -> i32 {
+    PreemptGuard::new().cpu() as i32
+}
+
+/// \brief Per-CPU state of the calling CPU.
+///
+/// # Safety
+/// Must be called with interrupts disabled so the caller is not moved to
+/// another CPU between reading the APIC ID and using the result.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn mycpu() -> *mut Cpu {
+    if x86::bits32::eflags::read().contains(x86::bits32::eflags::EFlags::FLAGS_IF) {
+        panic(c"mycpu called with interrupts enabled\n".as_ptr());
+    }
+    let apicid = lapicid();
+    // APIC IDs are not guaranteed to be contiguous.
+    let all = &mut *ptr::addr_of_mut!(cpus);
+    match all[..ncpu as usize].iter_mut().find(|c| c.apicid as i32 == apicid) {
+        Some(c) => c,
+        None => panic(c"unknown apicid\n".as_ptr()),
+    }
+}
+
+/// \brief Process running on this CPU, or null.
+///
+/// # Safety
+/// Must be called after `seginit` on this CPU.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn myproc() -> *mut Proc {
+    let g = PreemptGuard::new();
+    SCHED.get(&g).proc.get()
+}
+
+/// \brief Allocate a slot and set up its kernel stack.
+///
+/// The new process's context returns into `forkret` and then `trapret`.
+/// Returns null when the table is full or memory is exhausted.
+#[cfg(not(feature = "hosted"))]
+unsafe fn allocproc() -> *mut Proc {
+    let p: *mut Proc = match PTABLE.lock().alloc() {
+        Some(p) => p,
+        None => return ptr::null_mut(),
+    };
+
+    (*p).kstack = kalloc();
+    (*p).fpu = kalloc();
+    if (*p).kstack.is_null() || (*p).fpu.is_null() {
+        for page in [(*p).kstack, (*p).fpu] {
+            if !page.is_null() {
+                kfree(page);
+            }
+        }
+        let _pt = PTABLE.lock();
+        Ptable::free(&mut *p);
+        return ptr::null_mut();
+    }
+    fpu_state::init_proc(p);
+    let mut sp = (*p).kstack.add(KSTACKSIZE);
+
+    // Leave room for trap frame.
+    sp = sp.sub(core::mem::size_of::<Trapframe>());
+    (*p).tf = sp as *mut Trapframe;
+
+    // Set up new context to start executing at forkret,
+    // which returns to trapret.
+    sp = sp.sub(4);
+    *(sp as *mut u32) = trapret as *const () as u32;
+
+    sp = sp.sub(core::mem::size_of::<Context>());
+    (*p).context = sp as *mut Context;
+    *(*p).context = Context { eip: forkret as *const () as u32, ..Context::default() };
+
+    p
+}
+
+/// \brief Set up the first user process from the embedded `initcode`.
+///
+/// # Safety
+/// Must run once on the boot CPU, after `pinit` and `kinit2`.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn userinit() {
+    let p = allocproc();
+    PTABLE.lock().initproc = p;
+
+    (*p).pgdir = setupkvm();
+    if (*p).pgdir.is_null() {
+        panic(c"userinit: out of memory?".as_ptr());
+    }
+    let size = ptr::addr_of!(_binary_initcode_size) as usize as u32;
+    inituvm((*p).pgdir, ptr::addr_of!(_binary_initcode_start) as *const u8, size);
+    (*p).sz = mmu::PGSIZE;
+
+    let tf = &mut *(*p).tf;
+    *tf = Trapframe::default();
+    tf.cs = (mmu::SEG_UCODE << 3) | mmu::DPL_USER;
+    tf.ds = (mmu::SEG_UDATA << 3) | mmu::DPL_USER;
+    tf.es = tf.ds;
+    tf.ss = tf.ds;
+    tf.eflags = mmu::FL_IF;
+    tf.esp = mmu::PGSIZE;
+    tf.eip = 0; // beginning of initcode.S
+
+    (*p).set_name(b"initcode");
+    // cwd is set by forkret, once the root file system is mounted.
+
+    // Taking the lock publishes the writes above to the CPU that picks
+    // this process up.
+    let mut pt = PTABLE.lock();
+    let i = pt.index_of(p);
+    pt.set_state(i, ProcState::Runnable, cpuid() as usize);
+}
+
+/// \brief Grow or shrink the current process's memory by `n` bytes.
+///
+/// The heap may not grow into the `mmap` window at `MMAPBASE`. Returns 0
+/// on success, -1 on failure.
+///
+/// # Safety
+/// Must be called by a process, in a system call.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn growproc(n: i32) -> i32 {
+    let curproc = myproc();
+    let mut sz = (*curproc).sz;
+    if n > 0 {
+        if sz.checked_add(n as u32).is_none_or(|end| end > mmap::MMAPBASE) {
+            return -1;
+        }
+        sz = allocuvm((*curproc).pgdir, sz, sz.wrapping_add(n as u32)) as u32;
+    } else if n < 0 {
+        sz = deallocuvm((*curproc).pgdir, sz, sz.wrapping_add(n as u32)) as u32;
+    }
+    if n != 0 && sz == 0 {
+        return -1;
+    }
+    (*curproc).sz = sz;
+    switchuvm(curproc);
+    0
+}
+
+/// \brief Create a child that returns from the current system call with 0.
+///
+/// Returns the child's PID, or -1 if no slot or memory is available.
+///
+/// # Safety
+/// Must be called by a process, in a system call, with no locks held.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn fork() -> i32 {
+    let curproc = myproc();
+
+    let np = allocproc();
+    if np.is_null() {
+        return -1;
+    }
+
+    // Copy process state from proc.
+    (*np).pgdir = copyuvm((*curproc).pgdir, (*curproc).sz);
+    if (*np).pgdir.is_null() || mmap::fork(&*curproc, &mut *np).is_err() {
+        if !(*np).pgdir.is_null() {
+            freevm((*np).pgdir);
+        }
+        kfree((*np).kstack);
+        kfree((*np).fpu);
+        let _pt = PTABLE.lock();
+        Ptable::free(&mut *np);
+        return -1;
+    }
+    (*np).sz = (*curproc).sz;
+    (*np).parent = curproc;
+    *(*np).tf = *(*curproc).tf;
+
+    // Clear %eax so that fork returns 0 in the child.
+    (*(*np).tf).eax = 0;
+
+    for fd in 0..NOFILE {
+        if !(*curproc).ofile[fd].is_null() {
+            (*np).ofile[fd] = filedup((*curproc).ofile[fd]);
+        }
+    }
+    (*np).cwd = idup((*curproc).cwd);
+    (*np).name = (*curproc).name;
+    (*np).affinity.store((*curproc).affinity.load(Ordering::Relaxed), Ordering::Relaxed);
+    fpu_state::fork(curproc, np);
+
+    let pid = (*np).pid as i32;
+    let mut pt = PTABLE.lock();
+    let i = pt.index_of(np);
+    pt.set_state(i, ProcState::Runnable, cpuid() as usize);
+    pid
+}
+
+/// \brief Exit the current process. Does not return.
+///
+/// The process stays a zombie until its parent reaps it in `wait`.
+///
+/// # Safety
+/// Must be called by a process other than init, with no locks held and
+/// outside any file system operation.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn exit() -> ! {
+    let curproc = myproc();
+    if curproc == PTABLE.unlocked().initproc {
+        panic(c"init exiting".as_ptr());
+    }
+
+    // Write back and drop mapped regions, then close all open files.
+    mmap::munmap_all(curproc);
+    for f in (*curproc).ofile.iter_mut() {
+        if !f.is_null() {
+            fileclose(*f);
+            *f = ptr::null_mut();
+        }
+    }
+
+    begin_op();
+    iput((*curproc).cwd);
+    end_op();
+    (*curproc).cwd = ptr::null_mut();
+
+    let mut pt = PTABLE.lock();
+
+    // Parent might be sleeping in wait().
+    let cpu = cpuid() as usize;
+    pt.wakeup((*curproc).parent as *const ffi::c_void, cpu);
+
+    // Pass abandoned children to init.
+    let initproc = pt.initproc;
+    if pt.reparent(curproc, initproc) {
+        pt.wakeup(initproc as *const ffi::c_void, cpu);
+    }
+
+    // Jump into the scheduler, never to return.
+    pt.deschedule(curproc, ProcState::Zombie, cpuid() as usize, now());
+    sched(&pt);
+    panic(c"zombie exit".as_ptr())
+}
+
+/// \brief Wait for a child to exit and return its PID.
+///
+/// Returns -1 if the caller has no children or has been killed.
+///
+/// # Safety
+/// Must be called by a process, with no locks held.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn wait() -> i32 {
+    let curproc = myproc();
+    let mut pt = PTABLE.lock();
+    loop {
+        match pt.children(curproc) {
+            Children::Zombie(i) => {
+                let p = &mut *pt.slot(i);
+                let pid = p.pid as i32;
+                kfree(p.kstack);
+                kfree(p.fpu);
+                freevm(p.pgdir);
+                Ptable::free(p);
+                return pid;
+            }
+            // No point waiting if we don't have any children.
+            Children::None => return -1,
+            Children::Running if (*curproc).killed != 0 => return -1,
+            // Wait for children to exit. (See wakeup call in exit.)
+            Children::Running => sleep_locked(&mut pt, curproc as *const ffi::c_void),
+        }
+    }
+}
+
+/// \brief Per-CPU process scheduler. Never returns.
+///
+/// Each CPU calls this after setting itself up. It repeatedly takes a
+/// process from its own run queue (or steals one), switches to it, and
+/// regains control when that process calls `sched`. A CPU with nothing to
+/// run halts until the next interrupt without touching the table lock.
+///
+/// # Safety
+/// Must be called once per CPU, on its own stack, once `pinit`, `seginit`
+/// and `idtinit` have run.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn scheduler() -> ! {
+    let me = cpuid() as usize;
+    // This stack never runs on another CPU, so the slot stays ours.
+    let s: *const SchedCpu = SCHED.get(&PreemptGuard::new());
+    (*s).proc.set(ptr::null_mut());
+
+    loop {
+        // Interrupts stay off from the check until `sti; hlt`, so a wakeup
+        // interrupt sent in between ends the halt instead of being lost.
+        x86::irq::disable();
+        let pt = PTABLE.unlocked();
+        // Otherwise everything queued is pinned elsewhere.
+        let Some(i) = pt.has_work().then(|| pt.pick(me)).flatten() else {
+            asm!("sti", "hlt", options(nomem, nostack));
+            continue;
+        };
+        let mut pt = PTABLE.lock();
+        pt.run(i, me, now());
+
+        // Switch to chosen process. It is the process's job to
+        // release the table lock and then reacquire it before
+        // jumping back to us.
+        let p = pt.slot(i);
+        (*s).proc.set(p);
+        switchuvm(p);
+        swtch((*s).scheduler.as_ptr(), (*p).context);
+        switchkvm();
+
+        // Process is done running for now.
+        // It should have changed its state before coming back.
+        (*s).proc.set(ptr::null_mut());
+    }
+}
+
+/// \brief Switch from the current process back to the scheduler.
+///
+/// The caller holds only the table lock (proved by `_pt`) and has already
+/// moved the process out of `Running`. `intena` is saved and restored
+/// because it belongs to this kernel thread, not to the CPU.
+#[cfg(not(feature = "hosted"))]
+unsafe fn sched(_pt: &PtableGuard) {
+    let p = myproc();
+
+    if holding(&PTABLE.lock) == 0 {
+        panic(c"sched ptable.lock".as_ptr());
+    }
+    if (*mycpu()).ncli != 1 {
+        panic(c"sched locks".as_ptr());
+    }
+    if (*p).state() == ProcState::Running {
+        panic(c"sched running".as_ptr());
+    }
+    if x86::bits32::eflags::read().contains(x86::bits32::eflags::EFlags::FLAGS_IF) {
+        panic(c"sched interruptible".as_ptr());
+    }
+    let intena = (*mycpu()).intena;
+    fpu_state::switch_out(p);
+    swtch(&mut (*p).context, SCHED.get(&PreemptGuard::new()).scheduler.get());
+    (*mycpu()).intena = intena;
+}
+
+/// \brief Give up the CPU for one scheduling round.
+///
+/// # Safety
+/// Must be called by a running process holding no locks.
+#[cfg(not(feature = "hosted"))]
+#[export_name = "yield"]
+pub unsafe extern "C" fn yield_cpu() {
+    let mut pt = PTABLE.lock();
+    pt.deschedule(myproc(), ProcState::Runnable, cpuid() as usize, now());
+    sched(&pt);
+}
+
+/// \brief First code run by a forked child, on its way to `trapret`.
+///
+/// # Safety
+/// Only `scheduler` may enter it, through the context `allocproc` built,
+/// with the table lock held.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn forkret() {
+    static mut FIRST: bool = true;
+    // Still holding the table lock from scheduler.
+    release(&PTABLE.lock);
+
+    if FIRST {
+        // Some initialization functions must be run in the context
+        // of a regular process (e.g., they call sleep), and thus cannot
+        // be run from main().
+        FIRST = false;
+        mount_root(ROOTDEV);
+        (*myproc()).cwd = MOUNTS.root();
+    }
+
+    // Return to "caller", actually trapret (see allocproc).
+}
+
+/// \brief Sleep on `chan` while already holding the table lock.
+#[cfg(not(feature = "hosted"))]
+unsafe fn sleep_locked(pt: &mut PtableGuard, chan: *const ffi::c_void) {
+    let p = myproc();
+    (*p).chan = chan;
+    pt.deschedule(p, ProcState::Sleeping, cpuid() as usize, now());
+    sched(pt);
+    (*p).chan = ptr::null();
+}
+
+/// \brief Atomically release `lk` and sleep on `chan`.
+///
+/// `lk` is reacquired before returning. Holding the table lock from
+/// before `lk` is released until the process is marked sleeping means no
+/// `wakeup` can be missed.
+///
+/// # Safety
+/// Must be called by a process holding `lk`, a live spinlock, and no
+/// other spinlock.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn sleep(chan: *const ffi::c_void, lk: *const Spinlock) {
+    if myproc().is_null() {
+        panic(c"sleep".as_ptr());
+    }
+    if PTABLE.is_lock(lk) {
+        panic(c"sleep: ptable.lock".as_ptr());
+    }
+
+    let mut pt = PTABLE.lock();
+    if !lk.is_null() {
+        release(lk);
+    }
+    sleep_locked(&mut pt, chan);
+    drop(pt);
+    if !lk.is_null() {
+        acquire(lk);
+    }
+}
+
+/// \brief Set the scheduling priority of process `pid`.
+///
+/// Returns false if there is no such process.
+///
+/// # Safety
+/// Must not be called with the table lock held.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn setpriority(pid: i32, prio: u32) -> bool {
+    PTABLE.lock().set_priority(pid, prio)
+}
+
+/// \brief Scheduling priority of process `pid`, if it exists.
+///
+/// # Safety
+/// Must not be called with the table lock held.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn getpriority(pid: i32) -> Option<u32> {
+    PTABLE.lock().priority(pid)
+}
+
+/// \brief Restrict process `pid` to the CPUs set in `mask`.
+///
+/// A process that bars itself from its CPU gives it up at once.
+#[cfg(not(feature = "hosted"))]
+pub fn setaffinity(pid: i32, mask: u32) -> Result<(), Errno> {
+    PTABLE.lock().set_affinity(pid, mask)?;
+    unsafe {
+        if (*myproc()).pid as i32 == pid && mask & (1 << cpuid()) == 0 {
+            yield_cpu();
+        }
+    }
+    Ok(())
+}
+
+/// \brief Wake up all processes sleeping on `chan`.
+///
+/// # Safety
+/// Must be called after `pinit`, without the table lock held.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn wakeup(chan: *const ffi::c_void) {
+    let mut pt = PTABLE.lock();
+    pt.wakeup(chan, cpuid() as usize);
+}
+
+/// \brief Wake one process sleeping on `chan`, if there is one.
+///
+/// # Safety
+/// As for [`wakeup`].
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn wakeup_one(chan: *const ffi::c_void) -> bool {
+    let mut pt = PTABLE.lock();
+    pt.wakeup_one(chan, cpuid() as usize)
+}
+
+/// \brief Kill the process with the given PID.
+///
+/// The victim exits the next time it returns to user space (see `trap`).
+/// Returns -1 if there is no such process.
+///
+/// # Safety
+/// Must not be called with the table lock held.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn kill(pid: i32) -> i32 {
+    let mut pt = PTABLE.lock();
+    if pt.kill(pid, cpuid() as usize) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// \brief Print a process listing to the console for debugging.
+///
+/// Runs when the user types ^P. Takes no lock to avoid wedging a stuck
+/// machine further.
+///
+/// # Safety
+/// The listing may be inconsistent; it must only be used for debugging.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn procdump() {
+    for p in PTABLE.unlocked().procs.iter() {
+        if p.state() == ProcState::Unused {
+            continue;
+        }
+        print!("{}\t{}\t{}\t", p.pid, p.name(), p.state().label());
+        if p.state() == ProcState::Sleeping {
+            let mut pc = [0u32; 10];
+            let ebp = ((*p.context).ebp as *const u32).add(2);
+            getcallerpcs(ebp as *const ffi::c_void, pc.as_mut_ptr());
+            for &a in pc.iter().take_while(|&&a| a != 0) {
+                print!(" {:#x}", a);
+            }
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ProcState; 6] = [
+        ProcState::Unused,
+        ProcState::Embryo,
+        ProcState::Sleeping,
+        ProcState::Runnable,
+        ProcState::Running,
+        ProcState::Zombie,
+    ];
+
+    fn table() -> Box<Ptable> {
+        table_with(1)
+    }
+
+    /// Table feeding `n` empty run queues, leaked for the rest of the test.
+    fn table_with(n: usize) -> Box<Ptable> {
+        let runqs = PerCpu::new(core::array::from_fn(|_| SpinLock::new(c"runq", RunQueue::new())));
+        let mut pt = Box::new(Ptable::new());
+        pt.attach_runqs(Box::leak(Box::new(runqs)), n);
+        pt
+    }
+
+    /// Pick for `cpu` and run the result, as `scheduler` does.
+    fn dispatch(pt: &mut Ptable, cpu: usize, now: u32) -> Option<usize> {
+        let i = pt.pick(cpu)?;
+        pt.run(i, cpu, now);
+        Some(i)
+    }
+
+    /// Allocate a process and drive it to `Running`.
+    fn running(pt: &mut Ptable) -> *mut Proc {
+        let p = pt.alloc().unwrap();
+        p.set_state(ProcState::Runnable);
+        p.set_state(ProcState::Running);
+        p
+    }
+
+    #[test]
+    fn state_values_match_proc_h() {
+        for (i, s) in ALL.iter().enumerate() {
+            assert_eq!(*s as u32, i as u32);
+        }
+    }
+
+    #[test]
+    fn no_state_becomes_itself() {
+        for s in ALL {
+            assert!(!s.can_become(s));
+        }
+    }
+
+    #[test]
+    fn only_zombies_and_embryos_are_freed() {
+        for s in ALL {
+            let ok = matches!(s, ProcState::Embryo | ProcState::Zombie);
+            assert_eq!(s.can_become(ProcState::Unused), ok, "{:?}", s);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn illegal_transition_panics() {
+        let mut p = Proc::new();
+        p.set_state(ProcState::Running);
+    }
+
+    #[test]
+    fn labels_are_fixed_width() {
+        for s in ALL {
+            assert_eq!(s.label().len(), 6);
+        }
+    }
+
+    #[test]
+    fn alloc_hands_out_increasing_pids_until_full() {
+        let mut pt = table();
+        for pid in 1..=NPROC as u32 {
+            let p = pt.alloc().unwrap();
+            assert_eq!(p.pid, pid);
+            assert_eq!(p.state(), ProcState::Embryo);
+        }
+        assert!(pt.alloc().is_none());
+
+        Ptable::free(&mut pt.procs[3]);
+        assert_eq!(pt.alloc().unwrap().pid, NPROC as u32 + 1);
+    }
+
+    #[test]
+    fn wakeup_only_touches_matching_sleepers() {
+        let mut pt = table();
+        let chan = 0x1000 as *const ffi::c_void;
+        let a = running(&mut pt);
+        let b = running(&mut pt);
+        let c = running(&mut pt);
+        unsafe {
+            (*a).chan = chan;
+            (*a).set_state(ProcState::Sleeping);
+            (*b).chan = 0x2000 as *const ffi::c_void;
+            (*b).set_state(ProcState::Sleeping);
+            (*c).chan = chan;
+        }
+        pt.wakeup(chan, 0);
+        unsafe {
+            assert_eq!((*a).state(), ProcState::Runnable);
+            assert_eq!((*b).state(), ProcState::Sleeping);
+            assert_eq!((*c).state(), ProcState::Running);
+        }
+    }
+
+    #[test]
+    fn wakeup_one_wakes_a_single_sleeper() {
+        let mut pt = table();
+        let chan = 0x1000 as *const ffi::c_void;
+        let a = running(&mut pt);
+        let b = running(&mut pt);
+        unsafe {
+            for p in [a, b] {
+                (*p).chan = chan;
+                (*p).set_state(ProcState::Sleeping);
+            }
+        }
+        assert!(pt.wakeup_one(chan, 0));
+        unsafe {
+            assert_eq!((*a).state(), ProcState::Runnable);
+            assert_eq!((*b).state(), ProcState::Sleeping);
+        }
+        assert!(pt.wakeup_one(chan, 0));
+        assert!(!pt.wakeup_one(chan, 0));
+    }
+
+    #[test]
+    fn kill_sets_flag_and_wakes_sleeper() {
+        let mut pt = table();
+        let p = running(&mut pt);
+        unsafe { (*p).set_state(ProcState::Sleeping) };
+        let pid = unsafe { (*p).pid as i32 };
+        assert!(pt.kill(pid, 0));
+        unsafe {
+            assert_eq!((*p).killed, 1);
+            assert_eq!((*p).state(), ProcState::Runnable);
+        }
+        assert!(!pt.kill(999, 0));
+    }
+
+    #[test]
+    fn children_and_reparenting() {
+        let mut pt = table();
+        let parent = running(&mut pt);
+        let init = running(&mut pt);
+        assert_eq!(pt.children(parent), Children::None);
+
+        let kid = running(&mut pt);
+        unsafe { (*kid).parent = parent };
+        assert_eq!(pt.children(parent), Children::Running);
+
+        unsafe { (*kid).set_state(ProcState::Zombie) };
+        assert_eq!(pt.children(parent), Children::Zombie(2));
+
+        assert!(pt.reparent(parent, init));
+        assert_eq!(pt.children(parent), Children::None);
+        assert_eq!(pt.children(init), Children::Zombie(2));
+
+        Ptable::free(&mut pt.procs[2]);
+        assert_eq!(pt.children(init), Children::None);
+        assert!(!pt.reparent(parent, init));
+    }
+
+    #[test]
+    fn dispatch_runs_what_became_runnable() {
+        let mut pt = table();
+        assert_eq!(dispatch(&mut pt, 0, 0), None);
+        pt.alloc().unwrap();
+        pt.set_state(0, ProcState::Runnable, 0);
+        assert_eq!(dispatch(&mut pt, 0, 7), Some(0));
+        assert_eq!(pt.procs[0].state(), ProcState::Running);
+        assert_eq!(pt.procs[0].ticks_in, 7);
+        assert_eq!(dispatch(&mut pt, 0, 8), None);
+
+        let p: *const Proc = &pt.procs[0];
+        pt.deschedule(p, ProcState::Runnable, 0, 9);
+        assert_eq!(dispatch(&mut pt, 0, 10), Some(0));
+    }
+
+    #[test]
+    fn priority_of_live_processes_only() {
+        let mut pt = table();
+        let pid = pt.alloc().unwrap().pid as i32;
+        assert_eq!(pt.priority(pid), Some(sched::DEFAULT_PRIORITY));
+        pt.procs[0].budget = 1;
+        assert!(pt.set_priority(pid, 3));
+        assert_eq!(pt.priority(pid), Some(3));
+        assert_eq!(pt.procs[0].budget, sched::DEFAULT_BUDGET);
+        assert!(!pt.set_priority(pid + 1, 0));
+        assert_eq!(pt.priority(pid + 1), None);
+    }
+
+    #[cfg(feature = "sched_mlfq")]
+    #[test]
+    fn mlfq_dispatches_by_priority() {
+        let mut pt = table();
+        for _ in 0..3 {
+            pt.alloc().unwrap();
+        }
+        pt.set_priority(1, 2);
+        pt.set_priority(2, 1);
+        pt.set_priority(3, 3);
+        for i in 0..3 {
+            pt.set_state(i, ProcState::Runnable, 0);
+        }
+        // Reprioritising a queued process moves it to the new level.
+        pt.set_priority(3, 0);
+        let order: Vec<_> = core::iter::from_fn(|| dispatch(&mut pt, 0, 0)).collect();
+        assert_eq!(order, [2, 1, 0]);
+    }
+
+    #[test]
+    fn wakeup_queues_on_the_waking_cpu() {
+        let mut pt = table_with(2);
+        let p = running(&mut pt);
+        let chan = 0x1000 as *const ffi::c_void;
+        unsafe {
+            (*p).chan = chan;
+            (*p).set_state(ProcState::Sleeping);
+        }
+        assert!(!pt.has_work());
+        pt.wakeup(chan, 1);
+        assert!(pt.has_work());
+        assert_eq!((pt.runq(0).lock().len(), pt.runq(1).lock().len()), (0, 1));
+        assert_eq!(dispatch(&mut pt, 1, 0), Some(0));
+        assert_eq!(unsafe { (*p).cpu }, 1);
+    }
+
+    #[test]
+    fn affinity_redirects_to_least_loaded_allowed_cpu() {
+        let mut pt = table_with(3);
+        for _ in 0..3 {
+            pt.alloc().unwrap();
+        }
+        pt.set_state(0, ProcState::Runnable, 1);
+        assert_eq!(pt.set_affinity(2, 0b110), Ok(()));
+        assert_eq!(pt.set_affinity(3, 0b110), Ok(()));
+        assert_eq!(pt.set_affinity(3, 0b1000), Err(Errno::EINVAL));
+        assert_eq!(pt.set_affinity(99, 0b1), Err(Errno::ESRCH));
+
+        pt.set_state(1, ProcState::Runnable, 0);
+        assert_eq!(pt.procs[1].cpu, 2);
+        pt.set_state(2, ProcState::Runnable, 0);
+        assert_eq!(pt.runq(1).lock().len() + pt.runq(2).lock().len(), 3);
+        assert_eq!(pt.runq(0).lock().len(), 0);
+    }
+
+    #[test]
+    fn affinity_moves_a_queued_process_off_a_barred_cpu() {
+        let mut pt = table_with(2);
+        for _ in 0..2 {
+            pt.alloc().unwrap();
+        }
+        pt.set_state(0, ProcState::Runnable, 0);
+        pt.set_state(1, ProcState::Runnable, 0);
+        pt.set_affinity(pt.procs[0].pid as i32, 0b10).unwrap();
+        assert_eq!((pt.runq(0).lock().len(), pt.runq(1).lock().len()), (1, 1));
+        assert_eq!(pt.procs[0].cpu, 1);
+        assert_eq!(dispatch(&mut pt, 0, 0), Some(1));
+        assert_eq!(dispatch(&mut pt, 0, 0), None);
+        assert_eq!(dispatch(&mut pt, 1, 0), Some(0));
+    }
+
+    #[test]
+    fn idle_cpu_steals_from_busiest_queue() {
+        let mut pt = table_with(3);
+        for _ in 0..4 {
+            pt.alloc().unwrap();
+        }
+        pt.set_state(0, ProcState::Runnable, 1);
+        pt.set_state(1, ProcState::Runnable, 2);
+        pt.set_state(2, ProcState::Runnable, 2);
+        assert_eq!(dispatch(&mut pt, 0, 0), Some(1));
+        assert_eq!(pt.procs[1].cpu, 0);
+
+        // A process pinned to its queue cannot be stolen.
+        pt.set_affinity(pt.procs[2].pid as i32, 0b100).unwrap();
+        assert_eq!(dispatch(&mut pt, 0, 0), Some(0));
+        assert_eq!(dispatch(&mut pt, 0, 0), None);
+        assert_eq!(dispatch(&mut pt, 2, 0), Some(2));
+    }
+
+    #[test]
+    fn reprioritising_a_picked_process_does_not_queue_it_again() {
+        let mut pt = table_with(2);
+        let pid = pt.alloc().unwrap().pid as i32;
+        pt.set_state(0, ProcState::Runnable, 1);
+        let i = pt.pick(0).unwrap();
+        assert!(pt.set_priority(pid, 2));
+        assert!(!pt.has_work());
+        pt.run(i, 0, 0);
+        assert_eq!((pt.procs[0].state(), pt.procs[0].cpu), (ProcState::Running, 0));
+    }
+
+    #[test]
+    fn names_are_truncated_and_terminated() {
+        let mut p = Proc::new();
+        p.set_name(b"initcode");
+        assert_eq!(p.name(), "initcode");
+        p.set_name(b"a-very-long-process-name");
+        assert_eq!(p.name(), "a-very-long-pro");
+        assert_eq!(p.name[15], 0);
+    }
 }
@@ -0,0 +1,326 @@
+//! \file sched.rs
+//! \brief Per-CPU run queues and the scheduling policy.
+//!
+//! Every CPU has a [`RunQueue`] in [`RUNQ`], behind its own lock, holding
+//! the process-table slots that are runnable on it. The table in
+//! [`crate::proc`] queues a process on the CPU that woke it (subject to the
+//! process's affinity mask), each CPU dispatches from its own queue, and an
+//! idle CPU steals from the busiest other queue. Dispatching takes only
+//! queue locks; the table lock is needed just to mark the process running.
+//!
+//! A queue has one FIFO per priority level. Two policies decide how the
+//! levels are used:
+//!
+//! - Round robin (default): every process sits on level 0.
+//! - Multi-level feedback queue, selected with the `sched_mlfq` feature.
+//!   Level 0 is the highest priority. A process that uses up its budget drops
+//!   one level, and every `TICKS_TO_PROMOTE` ticks every process moves up one
+//!   level so nothing starves.
+//!
+//! Time is measured in `trap::ticks`, passed in by the caller so this module
+//! stays free of hardware access.
+
+use crate::param::NPROC;
+use crate::proc::Proc;
+use crate::sync::SpinLock;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// \brief Timer ticks per second (`TPS` in `pdx.h`).
+pub const TPS: u32 = 1000;
+/// \brief Lowest priority level; levels run from 0 to `MAXPRIO`.
+pub const MAXPRIO: u32 = 7;
+/// \brief Number of FIFOs in a run queue.
+pub const NPRIO: usize = MAXPRIO as usize + 1;
+/// \brief Priority given to new processes.
+pub const DEFAULT_PRIORITY: u32 = 0;
+/// \brief Ticks a process may run at one level before it is demoted.
+pub const DEFAULT_BUDGET: i32 = 3 * TPS as i32;
+/// \brief Interval in ticks between promotions of every process.
+pub const TICKS_TO_PROMOTE: u32 = 20 * TPS;
+
+/// \brief True when the kernel is built with the MLFQ policy.
+pub const MLFQ: bool = cfg!(feature = "sched_mlfq");
+
+/// \brief Run-queue level for `p` under the configured policy.
+pub fn level(p: &Proc) -> usize {
+    if MLFQ {
+        p.priority as usize
+    } else {
+        0
+    }
+}
+
+/// \brief Charge the ticks since dispatch; demote when the budget runs out.
+pub fn charge(p: &mut Proc, now: u32) {
+    p.budget -= now.wrapping_sub(p.ticks_in) as i32;
+    if p.budget <= 0 {
+        p.priority = (p.priority + 1).min(MAXPRIO);
+        p.budget = DEFAULT_BUDGET;
+    }
+}
+
+/// \brief FIFO of process-table slot indices.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadyList {
+    slots: [u8; NPROC],
+    head: usize,
+    len: usize,
+}
+
+impl ReadyList {
+    /// \brief Empty list.
+    pub const fn new() -> Self {
+        ReadyList { slots: [0; NPROC], head: 0, len: 0 }
+    }
+
+    /// \brief Number of queued slots.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// \brief Whether the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// \brief Append slot `i` at the tail.
+    ///
+    /// A slot is queued at most once, so the list can never overflow.
+    pub fn push(&mut self, i: usize) {
+        assert!(self.len < NPROC, "ready list overflow");
+        self.slots[(self.head + self.len) % NPROC] = i as u8;
+        self.len += 1;
+    }
+
+    /// \brief Remove and return the slot at the head.
+    pub fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let i = self.slots[self.head] as usize;
+        self.head = (self.head + 1) % NPROC;
+        self.len -= 1;
+        Some(i)
+    }
+
+    /// \brief Remove slot `i` wherever it is, keeping the others in order.
+    ///
+    /// Returns false if `i` was not queued.
+    pub fn remove(&mut self, i: usize) -> bool {
+        match self.position(|s| s == i) {
+            Some(pos) => {
+                self.remove_at(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// \brief Remove and return the first slot satisfying `pred`.
+    pub fn take(&mut self, pred: impl Fn(usize) -> bool) -> Option<usize> {
+        let pos = self.position(pred)?;
+        let i = self.slots[(self.head + pos) % NPROC] as usize;
+        self.remove_at(pos);
+        Some(i)
+    }
+
+    fn position(&self, pred: impl Fn(usize) -> bool) -> Option<usize> {
+        (0..self.len).find(|&k| pred(self.slots[(self.head + k) % NPROC] as usize))
+    }
+
+    fn remove_at(&mut self, pos: usize) {
+        for k in pos..self.len - 1 {
+            self.slots[(self.head + k) % NPROC] = self.slots[(self.head + k + 1) % NPROC];
+        }
+        self.len -= 1;
+    }
+}
+
+impl Default for ReadyList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// \brief Runnable slots assigned to one CPU, one FIFO per level.
+///
+/// The lists are only changed with the queue's lock in [`RUNQ`] held;
+/// `nready` can be read without it so an idle CPU can tell whether there is
+/// anything to do before taking any lock.
+#[derive(Debug)]
+pub struct RunQueue {
+    ready: [ReadyList; NPRIO],
+    nready: AtomicU32,
+}
+
+impl RunQueue {
+    /// \brief Empty queue.
+    pub const fn new() -> Self {
+        RunQueue { ready: [ReadyList::new(); NPRIO], nready: AtomicU32::new(0) }
+    }
+
+    /// \brief Number of queued slots.
+    pub fn len(&self) -> usize {
+        self.nready.load(Ordering::Relaxed) as usize
+    }
+
+    /// \brief Number of slots queued on `q`, read without taking its lock.
+    pub fn peek_len(q: &SpinLock<RunQueue>) -> usize {
+        // Only the atomic counter is touched, never the lists.
+        unsafe { (*ptr::addr_of!((*q.data_ptr()).nready)).load(Ordering::Relaxed) as usize }
+    }
+
+    /// \brief Whether nothing is queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// \brief Number of slots queued at level `lvl`.
+    pub fn queued(&self, lvl: usize) -> usize {
+        self.ready[lvl].len()
+    }
+
+    /// \brief Queue slot `i` at the tail of level `lvl`.
+    pub fn push(&mut self, i: usize, lvl: usize) {
+        self.ready[lvl].push(i);
+        self.nready.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// \brief Take slot `i` off level `lvl`, e.g. before its priority changes.
+    pub fn remove(&mut self, i: usize, lvl: usize) -> bool {
+        let found = self.ready[lvl].remove(i);
+        if found {
+            self.nready.fetch_sub(1, Ordering::Relaxed);
+        }
+        found
+    }
+
+    /// \brief Head of the highest-priority non-empty level.
+    pub fn pop(&mut self) -> Option<usize> {
+        self.take(|_| true)
+    }
+
+    /// \brief First slot satisfying `pred`, searching levels in priority order.
+    ///
+    /// Used by a thief that may only take processes allowed on its CPU.
+    pub fn take(&mut self, pred: impl Fn(usize) -> bool) -> Option<usize> {
+        let i = self.ready.iter_mut().find_map(|l| l.take(&pred))?;
+        self.nready.fetch_sub(1, Ordering::Relaxed);
+        Some(i)
+    }
+
+    /// \brief Move every queued slot up one level, keeping FIFO order.
+    pub fn promote(&mut self) {
+        for lvl in 1..NPRIO {
+            while let Some(i) = self.ready[lvl].pop() {
+                self.ready[lvl - 1].push(i);
+            }
+        }
+    }
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+percpu! {
+    /// \brief Run queue of each CPU.
+    ///
+    /// Other CPUs use it too, to queue wakeups and to steal, so it is reached
+    /// with [`PerCpu::for_cpu`](crate::percpu::PerCpu::for_cpu). A CPU holding
+    /// two queue locks took the lower-numbered CPU's first.
+    pub static RUNQ: SpinLock<RunQueue> = SpinLock::new(c"runq", RunQueue::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_list_is_fifo_and_wraps() {
+        let mut l = ReadyList::new();
+        for round in 0..3 {
+            for i in 0..NPROC {
+                l.push((i + round) % NPROC);
+            }
+            assert_eq!(l.len(), NPROC);
+            for i in 0..NPROC {
+                assert_eq!(l.pop(), Some((i + round) % NPROC));
+            }
+            assert!(l.is_empty());
+        }
+        assert_eq!(l.pop(), None);
+    }
+
+    #[test]
+    fn ready_list_remove_keeps_order() {
+        let mut l = ReadyList::new();
+        for i in [5, 9, 2, 7] {
+            l.push(i);
+        }
+        assert!(l.remove(2));
+        assert!(!l.remove(2));
+        assert_eq!(l.take(|i| i > 5), Some(9));
+        assert_eq!(l.pop(), Some(5));
+        assert_eq!(l.pop(), Some(7));
+        assert_eq!(l.pop(), None);
+    }
+
+    #[test]
+    fn run_queue_prefers_lower_levels_then_fifo() {
+        let mut q = RunQueue::new();
+        for (i, lvl) in [(0, 3), (1, 1), (2, 3), (3, 1)] {
+            q.push(i, lvl);
+        }
+        assert_eq!(q.len(), 4);
+        let order: Vec<_> = core::iter::from_fn(|| q.pop()).collect();
+        assert_eq!(order, [1, 3, 0, 2]);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn run_queue_take_skips_ineligible_slots() {
+        let mut q = RunQueue::new();
+        q.push(4, 0);
+        q.push(6, 2);
+        assert_eq!(q.take(|i| i != 4), Some(6));
+        assert_eq!(q.take(|i| i != 4), None);
+        assert_eq!(q.len(), 1);
+    }
+
+    #[test]
+    fn run_queue_promote_and_remove() {
+        let mut q = RunQueue::new();
+        q.push(0, 2);
+        q.push(1, 0);
+        q.push(2, 1);
+        q.promote();
+        assert_eq!((q.queued(0), q.queued(1), q.queued(2)), (2, 1, 0));
+        assert!(q.remove(0, 1));
+        assert!(!q.remove(0, 1));
+        assert_eq!(q.len(), 2);
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(q.pop(), Some(2));
+    }
+
+    #[test]
+    fn charge_demotes_when_budget_is_spent() {
+        let mut p = Proc::new();
+        p.budget = DEFAULT_BUDGET;
+        p.ticks_in = 100;
+        charge(&mut p, 100 + DEFAULT_BUDGET as u32 - 1);
+        assert_eq!((p.priority, p.budget), (0, 1));
+
+        p.ticks_in = 5000;
+        charge(&mut p, 5001);
+        assert_eq!((p.priority, p.budget), (1, DEFAULT_BUDGET));
+
+        p.priority = MAXPRIO;
+        p.ticks_in = 0;
+        charge(&mut p, DEFAULT_BUDGET as u32);
+        assert_eq!(p.priority, MAXPRIO);
+    }
+}
@@ -1,10 +1,10 @@
 #![allow(dead_code)]
 
-use super::fpu_state::{FpuManager, FpuState};
+use super::fpu_state;
 use super::simd_mem::{memcpy_fast, memset_fast};
 use super::simd_string::{memchr_fast_slice, strcmp_fast_slice, strlen_fast_slice};
 use core::ffi::{c_char, c_int, c_size_t as size_t, c_void};
-use core::mem::MaybeUninit;
+use core::marker::PhantomData;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(feature = "simd_debug_print")]
@@ -24,32 +24,65 @@ macro_rules! debug_cprintf {
     ($($arg:tt)*) => {{}};
 }
 
-#[thread_local]
-static mut KERNEL_FPU_STATE: MaybeUninit<FpuManager> = MaybeUninit::uninit();
-/// Indicates whether `KERNEL_FPU_STATE` has been initialized.
+/// Set once the boot CPU can execute SSE, by [`init_simd_subsystem`].
+/// Before that `mycpu()` may not work yet and FPU sections are no-ops.
 static SIMD_INITIALIZED: AtomicBool = AtomicBool::new(false);
 
 /// \brief Enter a critical section that allows use of SIMD/FPU instructions.
 ///
-/// This function safely restores the kernel's FPU state if the subsystem has
-/// been initialized via [`init_simd_subsystem`]. It becomes a no-op otherwise.
+/// Disables interrupts and saves the FPU registers of the process that owns
+/// them, if any. Sections nest; only the outermost one does any work. It
+/// becomes a no-op before [`init_simd_subsystem`].
+///
+/// # Safety
+/// Must be paired with [`kernel_fpu_end`] on the same CPU.
 #[no_mangle]
 pub unsafe extern "C" fn kernel_fpu_begin() {
     debug_cprintf!("kernel_fpu_begin\n");
     if SIMD_INITIALIZED.load(Ordering::Relaxed) {
-        KERNEL_FPU_STATE.assume_init_mut().begin_use();
+        fpu_state::kernel_begin();
     }
 }
 
 /// \brief Exit the SIMD/FPU critical section.
 ///
 /// If the FPU subsystem has not been initialized, this function simply
-/// returns. Otherwise it saves the current FPU state for later use.
+/// returns. Otherwise the outermost call lets the interrupted process
+/// reload its registers and re-enables interrupts.
+///
+/// # Safety
+/// Must follow a [`kernel_fpu_begin`] on the same CPU.
 #[no_mangle]
 pub unsafe extern "C" fn kernel_fpu_end() {
     debug_cprintf!("kernel_fpu_end\n");
     if SIMD_INITIALIZED.load(Ordering::Relaxed) {
-        KERNEL_FPU_STATE.assume_init_mut().end_use();
+        fpu_state::kernel_end();
+    }
+}
+
+/// \brief A kernel FPU section that ends when dropped.
+///
+/// Hold one around calls to [`memcpy_fast`], [`memset_fast`] and other SIMD
+/// code. It cannot be sent to another CPU.
+pub struct KernelFpuGuard {
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl KernelFpuGuard {
+    /// \brief Begin a section with [`kernel_fpu_begin`].
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        // SAFETY: the matching `kernel_fpu_end` runs in `drop`, and the
+        // guard cannot leave this CPU.
+        unsafe { kernel_fpu_begin() };
+        Self { _not_send: PhantomData }
+    }
+}
+
+impl Drop for KernelFpuGuard {
+    fn drop(&mut self) {
+        // SAFETY: `new` began the section on this CPU.
+        unsafe { kernel_fpu_end() }
     }
 }
 
@@ -60,6 +93,7 @@ pub unsafe extern "C" fn rust_memcpy(
     n: size_t,
 ) -> *mut c_void {
     debug_cprintf!("rust_memcpy called\n");
+    let _fpu = KernelFpuGuard::new();
     memcpy_fast(dst as *mut u8, src as *const u8, n);
     dst
 }
@@ -78,6 +112,7 @@ pub unsafe extern "C" fn rust_memmove(
 #[no_mangle]
 pub unsafe extern "C" fn rust_memset(dst: *mut c_void, c: c_int, n: size_t) -> *mut c_void {
     debug_cprintf!("rust_memset called\n");
+    let _fpu = KernelFpuGuard::new();
     memset_fast(dst as *mut u8, c as u8, n);
     dst
 }
@@ -145,11 +180,13 @@ pub unsafe extern "C" fn rust_strchr(s: *const c_char, c: c_int) -> *mut c_char
 
 #[no_mangle]
 pub unsafe extern "C" fn rust_copy_page(dst: *mut u8, src: *const u8) {
+    let _fpu = KernelFpuGuard::new();
     memcpy_fast(dst, src, 4096);
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn rust_zero_page(dst: *mut u8) {
+    let _fpu = KernelFpuGuard::new();
     memset_fast(dst, 0, 4096);
 }
 
@@ -199,13 +236,12 @@ pub unsafe extern "C" fn rust_ip_checksum(addr: *const u8, mut count: c_int) ->
 
 /// \brief Initialize SIMD and FPU support for the kernel.
 ///
-/// This must be invoked before any calls to [`kernel_fpu_begin`] or
-/// [`kernel_fpu_end`]. It configures the processor's FPU and marks the
-/// thread-local state as ready for use.
+/// Called by `kmain` on the boot CPU once CPU features are known, so that
+/// SSE works before `fpuinit`; every CPU still runs `fpuinit` later. From
+/// here on [`kernel_fpu_begin`] and [`kernel_fpu_end`] take effect.
 #[no_mangle]
 pub unsafe extern "C" fn init_simd_subsystem() {
     debug_cprintf!("init_simd_subsystem called\n");
-    crate::fpu_state::init_fpu();
-    KERNEL_FPU_STATE.write(FpuManager::new());
+    fpu_state::init_fpu();
     SIMD_INITIALIZED.store(true, Ordering::Relaxed);
 }
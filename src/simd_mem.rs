@@ -203,3 +203,87 @@ pub unsafe fn memset_fast(dst: *mut u8, val: u8, len: usize) {
 
     memset_scalar(dst, val, len);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_features;
+
+    #[test]
+    fn repeat_byte_fills_each_lane() {
+        assert_eq!(repeat_byte(0xAB, 0), 0);
+        assert_eq!(repeat_byte(0xAB, 1), 0xAB);
+        assert_eq!(repeat_byte(0x5A, 8), 0x5A5A_5A5A_5A5A_5A5A);
+    }
+
+    #[test]
+    fn memcpy_all_lengths_and_offsets() {
+        cpu_features::init();
+        let src: Vec<u8> = (0..200u32).map(|i| (i * 7 + 3) as u8).collect();
+        for off in 0..4 {
+            for len in 0..150 {
+                let mut dst = vec![0xEEu8; 200];
+                unsafe { memcpy_fast(dst.as_mut_ptr().add(off), src.as_ptr().add(off), len) };
+                assert_eq!(&dst[off..off + len], &src[off..off + len]);
+                assert!(dst[..off].iter().all(|&b| b == 0xEE));
+                assert!(dst[off + len..].iter().all(|&b| b == 0xEE), "overrun at len {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn memcpy_zero_length_is_noop() {
+        let src = [1u8; 4];
+        let mut dst = [0u8; 4];
+        unsafe { memcpy_fast(dst.as_mut_ptr(), src.as_ptr(), 0) };
+        assert_eq!(dst, [0; 4]);
+    }
+
+    #[test]
+    fn memcpy_full_page() {
+        cpu_features::init();
+        let src: Vec<u8> = (0..4096u32).map(|i| (i ^ (i >> 8)) as u8).collect();
+        let mut dst = vec![0u8; 4096];
+        unsafe { memcpy_fast(dst.as_mut_ptr(), src.as_ptr(), 4096) };
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn memset_all_lengths_and_offsets() {
+        cpu_features::init();
+        for off in 0..4 {
+            for len in 0..150 {
+                let mut dst = vec![0x11u8; 200];
+                unsafe { memset_fast(dst.as_mut_ptr().add(off), 0xC3, len) };
+                assert!(dst[off..off + len].iter().all(|&b| b == 0xC3));
+                assert!(dst[..off].iter().all(|&b| b == 0x11));
+                assert!(dst[off + len..].iter().all(|&b| b == 0x11), "overrun at len {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn memset_zero_page() {
+        cpu_features::init();
+        let mut dst = vec![0xFFu8; 4096];
+        unsafe { memset_fast(dst.as_mut_ptr(), 0, 4096) };
+        assert!(dst.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn scalar_paths_agree() {
+        let src: Vec<u8> = (0..64u8).collect();
+        let mut a = vec![0u8; 64];
+        let mut b = vec![0u8; 64];
+        unsafe {
+            memcpy_scalar(a.as_mut_ptr(), src.as_ptr(), 64);
+            memcpy_mmx(b.as_mut_ptr(), src.as_ptr(), 64);
+        }
+        assert_eq!(a, b);
+        unsafe {
+            memset_scalar(a.as_mut_ptr(), 9, 33);
+            memset_mmx(b.as_mut_ptr(), 9, 33);
+        }
+        assert_eq!(a, b);
+    }
+}
@@ -96,7 +96,6 @@ fn strcmp_scalar(s1: &[u8], s2: &[u8]) -> i32 {
 /// \param s2 Second byte slice to compare.
 /// \return Negative if `s1 < s2`, positive if `s1 > s2`, and `0` if equal.
 unsafe fn strcmp_sse42(s1: &[u8], s2: &[u8]) -> i32 {
-    use core::arch::x86_64::*;
     const MODE: i32 =
         _SIDD_UBYTE_OPS | _SIDD_CMP_EQUAL_EACH | _SIDD_NEGATIVE_POLARITY | _SIDD_LEAST_SIGNIFICANT;
     let len1 = strlen_scalar(s1);
@@ -153,7 +152,6 @@ unsafe fn memchr_sse2(haystack: &[u8], needle: u8) -> Option<usize> {
     let len = haystack.len();
     let mut offset = 0;
 
-    use core::arch::x86::*;
     let needle_xmm = _mm_set1_epi8(needle as i8);
 
     while offset + 16 <= len {
@@ -204,7 +202,6 @@ unsafe fn count_bytes_sse2(haystack: &[u8], needle: u8) -> usize {
     let mut offset = 0;
     let mut count = 0;
 
-    use core::arch::x86::*;
     let needle_xmm = _mm_set1_epi8(needle as i8);
 
     while offset + 16 <= len {
@@ -234,3 +231,122 @@ pub fn count_bytes_fast_slice(haystack: &[u8], needle: u8) -> usize {
     }
     count_bytes_scalar(haystack, needle)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_features;
+
+    /// Build a NUL-terminated buffer of `len` non-zero bytes plus `pad`
+    /// trailing bytes after the terminator.
+    fn cstr(len: usize, pad: usize) -> Vec<u8> {
+        let mut v: Vec<u8> = (0..len).map(|i| b'a' + (i % 26) as u8).collect();
+        v.push(0);
+        v.extend(core::iter::repeat(b'z').take(pad));
+        v
+    }
+
+    #[test]
+    fn strlen_matches_scalar_for_all_lengths() {
+        cpu_features::init();
+        for len in 0..100 {
+            for pad in [0, 1, 15, 16, 17] {
+                let s = cstr(len, pad);
+                assert_eq!(strlen_fast_slice(&s), len);
+                assert_eq!(strlen_scalar(&s), len);
+            }
+        }
+    }
+
+    #[test]
+    fn strlen_without_terminator_is_slice_length() {
+        cpu_features::init();
+        for len in 0..64 {
+            let s = vec![b'x'; len];
+            assert_eq!(strlen_fast_slice(&s), len);
+        }
+    }
+
+    #[test]
+    fn strlen_empty() {
+        assert_eq!(strlen_fast_slice(&[]), 0);
+        assert_eq!(strlen_fast_slice(&[0]), 0);
+    }
+
+    #[test]
+    fn strcmp_equal_strings() {
+        cpu_features::init();
+        for len in 0..70 {
+            let a = cstr(len, 3);
+            let b = cstr(len, 0);
+            assert_eq!(strcmp_fast_slice(&a, &b), 0);
+        }
+    }
+
+    #[test]
+    fn strcmp_sign_follows_first_difference() {
+        cpu_features::init();
+        for len in 1..70 {
+            for at in [0, len / 2, len - 1] {
+                let a = cstr(len, 0);
+                let mut b = a.clone();
+                b[at] += 1;
+                assert!(strcmp_fast_slice(&a, &b) < 0, "len {} at {}", len, at);
+                assert!(strcmp_fast_slice(&b, &a) > 0, "len {} at {}", len, at);
+                assert_eq!(
+                    strcmp_fast_slice(&a, &b).signum(),
+                    strcmp_scalar(&a, &b).signum()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn strcmp_prefix_sorts_first() {
+        cpu_features::init();
+        assert!(strcmp_fast_slice(b"abc\0", b"abcd\0") < 0);
+        assert!(strcmp_fast_slice(b"abcd\0", b"abc\0") > 0);
+        assert!(strcmp_fast_slice(b"\0", b"a\0") < 0);
+    }
+
+    #[test]
+    fn memchr_finds_first_occurrence() {
+        cpu_features::init();
+        for len in 1..80 {
+            let hay: Vec<u8> = (0..len).map(|i| (i % 7) as u8 + 1).collect();
+            for needle in 1..=8u8 {
+                assert_eq!(
+                    memchr_fast_slice(&hay, needle),
+                    memchr_scalar(&hay, needle),
+                    "len {} needle {}",
+                    len,
+                    needle
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn memchr_in_tail_after_simd_blocks() {
+        cpu_features::init();
+        let mut hay = vec![0u8; 37];
+        hay[36] = 9;
+        assert_eq!(memchr_fast_slice(&hay, 9), Some(36));
+        assert_eq!(memchr_fast_slice(&hay, 1), None);
+        assert_eq!(memchr_fast_slice(&[], 0), None);
+    }
+
+    #[test]
+    fn count_bytes_matches_scalar() {
+        cpu_features::init();
+        for len in 0..100 {
+            let hay: Vec<u8> = (0..len).map(|i| (i * 31 % 5) as u8).collect();
+            for needle in 0..6u8 {
+                assert_eq!(
+                    count_bytes_fast_slice(&hay, needle),
+                    count_bytes_scalar(&hay, needle)
+                );
+            }
+        }
+    }
+}
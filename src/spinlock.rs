@@ -1,11 +1,21 @@
 //! \file spinlock.rs
 //! \brief Spinlock mechanism for mutual exclusion.
+//!
+//! [`Spinlock`] keeps the layout of the C `struct spinlock` and is
+//! implemented here; `acquire`, `release`, `holding` and `initlock` are
+//! exported for the C half of the kernel. Rust code normally uses the
+//! data-owning [`crate::sync::SpinLock`] built on top of it.
 
+use crate::percpu::this_cpu;
 use crate::proc::Cpu;
+use crate::sync::lockdep::{self, Ctx};
+use core::cell::UnsafeCell;
 use core::ffi;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
 
 /// \brief Spinlock structure used for mutual exclusion.
-/// 
+///
 /// Mirrors the C implementation for interoperability.
 ///
 /// Fields:
@@ -14,34 +24,255 @@ use core::ffi;
 /// - `cpu`: Pointer to the CPU holding the lock when locked.
 /// - `pcs`: Call stack program counters captured at lock acquisition.
 #[repr(C)]
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Default)]
 pub struct Spinlock {
     /// \brief Indicates lock state: `0` == unlocked, `1` == locked.
-    pub locked: u32,
+    pub locked: AtomicU32,
     /// \brief Name of the lock (null-terminated C string).
     pub name:   *const u8,
     /// \brief CPU currently holding the lock.
-    pub cpu:    *const Cpu,
+    pub cpu:    AtomicPtr<Cpu>,
     /// \brief Call stack PCs for debugging (captured on acquire).
-    pub pcs:    [u32; 10],
+    pub pcs:    UnsafeCell<[u32; 10]>,
 }
 
-extern "C" {
-    /// \brief Acquire the spinlock, spinning until it becomes available.
+// `cpu` and `pcs` are only written by the holder.
+unsafe impl Sync for Spinlock {}
+
+impl Spinlock {
+    /// \brief Unlocked spinlock suitable for a `static` initialiser.
+    ///
+    /// \param name Null-terminated lock name shown in diagnostics.
+    pub const fn new(name: &'static ffi::CStr) -> Self {
+        Spinlock {
+            locked: AtomicU32::new(0),
+            name: name.as_ptr() as *const u8,
+            cpu: AtomicPtr::new(ptr::null_mut()),
+            pcs: UnsafeCell::new([0; 10]),
+        }
+    }
+
+    /// \brief Acquire the lock, spinning until it becomes available.
+    ///
+    /// Interrupts stay disabled until the matching [`Spinlock::release`],
+    /// so an interrupt handler on this CPU cannot deadlock on the lock.
+    /// The lock is taken with `xchg` alone, which every x86 has, so no
+    /// `cmpxchg` or `cmpxchg8b` is needed. Acquiring a lock this CPU
+    /// already holds panics.
+    #[inline(always)]
+    pub fn acquire(&self) {
+        unsafe { pushcli() }; // disable interrupts to avoid deadlock.
+        lockdep::acquire(self.addr(), self.name as *const (), self.name, Ctx::Cpu, frame());
+        if self.holding() {
+            misuse(c"acquire");
+        }
+        while self.locked.swap(1, Ordering::Acquire) != 0 {
+            core::hint::spin_loop();
+        }
+        self.acquired(frame());
+    }
+
+    /// \brief Acquire the lock if it is free, without spinning.
     ///
-    /// \param s Pointer to the spinlock to acquire.
-    pub fn acquire(s: *const Spinlock);
+    /// Interrupts are disabled only if the lock was taken.
+    #[inline(always)]
+    pub fn try_acquire(&self) -> bool {
+        unsafe { pushcli() };
+        if self.holding() {
+            misuse(c"acquire");
+        }
+        if self.locked.swap(1, Ordering::Acquire) != 0 {
+            unsafe { popcli() };
+            return false;
+        }
+        lockdep::acquired(self.addr(), self.name as *const (), self.name, Ctx::Cpu, frame());
+        self.acquired(frame());
+        true
+    }
+
+    /// \brief Release the lock and undo the `pushcli` of the acquire.
+    pub fn release(&self) {
+        if !self.holding() {
+            misuse(c"release");
+        }
+        lockdep::release(self.addr(), Ctx::Cpu);
+        unsafe { (*self.pcs.get())[0] = 0 };
+        self.cpu.store(ptr::null_mut(), Ordering::Relaxed);
+        self.locked.store(0, Ordering::Release);
+        unsafe { popcli() };
+    }
+
+    /// \brief Whether this CPU holds the lock.
+    pub fn holding(&self) -> bool {
+        unsafe {
+            pushcli();
+            let r = self.locked.load(Ordering::Relaxed) != 0 && self.cpu.load(Ordering::Relaxed) == this_cpu();
+            popcli();
+            r
+        }
+    }
 
-    /// \brief Release a previously acquired spinlock.
+    /// \brief The lock's name, for diagnostics.
+    pub fn name(&self) -> &ffi::CStr {
+        if self.name.is_null() {
+            return c"";
+        }
+        unsafe { ffi::CStr::from_ptr(self.name as *const ffi::c_char) }
+    }
+
+    fn addr(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
+    /// \brief Record this CPU and the call stack above `frame` as the holder.
     ///
-    /// \param s Pointer to the spinlock to release.
-    pub fn release(s: *const Spinlock);
+    /// Interrupts are disabled, so `this_cpu` is stable.
+    fn acquired(&self, frame: *const ffi::c_void) {
+        unsafe {
+            self.cpu.store(this_cpu(), Ordering::Relaxed);
+            getcallerpcs(frame, (*self.pcs.get()).as_mut_ptr());
+        }
+    }
+}
 
+/// \brief Stop on lock misuse through C `panic`, which prints without
+/// taking `conslk` and freezes the other CPUs; a Rust `panic!` would print
+/// through `conslk` and recurse if that is the lock misused.
+#[cfg(not(feature = "hosted"))]
+#[cold]
+fn misuse(what: &'static ffi::CStr) -> ! {
+    unsafe { panic(what.as_ptr()) }
+}
+
+/// \brief Stop on lock misuse; the host has no C `panic`.
+#[cfg(feature = "hosted")]
+#[cold]
+fn misuse(what: &'static ffi::CStr) -> ! {
+    panic!("{}", what.to_str().unwrap())
+}
+
+/// \brief Argument for `getcallerpcs` that makes the recorded stack start
+/// at the caller of the function this is inlined into.
+#[inline(always)]
+pub fn frame() -> *const ffi::c_void {
+    #[cfg(target_arch = "x86")]
+    unsafe {
+        let ebp: *const u32;
+        core::arch::asm!("mov {}, ebp", out(reg) ebp, options(nomem, nostack, preserves_flags));
+        ebp.add(2) as *const ffi::c_void
+    }
+    #[cfg(not(target_arch = "x86"))]
+    ptr::null()
+}
+
+/// \brief Acquire the spinlock, spinning until it becomes available.
+///
+/// \param s Pointer to the spinlock to acquire.
+///
+/// # Safety
+/// `s` must point to an initialised spinlock.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn acquire(s: *const Spinlock) {
+    (*s).acquire();
+}
+
+/// \brief Release a previously acquired spinlock.
+///
+/// \param s Pointer to the spinlock to release.
+///
+/// # Safety
+/// `s` must point to an initialised spinlock.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn release(s: *const Spinlock) {
+    (*s).release();
+}
+
+/// \brief Report whether this CPU holds the spinlock.
+///
+/// \param s Pointer to the spinlock to inspect.
+///
+/// # Safety
+/// `s` must point to an initialised spinlock.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn holding(s: *const Spinlock) -> i32 {
+    (*s).holding() as i32
+}
+
+/// \brief Initialise the spinlock as unlocked with the given name.
+///
+/// \param s Pointer to the spinlock to initialise.
+/// \param name Null-terminated lock name.
+///
+/// # Safety
+/// `s` must be valid for writes and not in use; `name` must outlive it.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn initlock(s: *mut Spinlock, name: *const u8) {
+    ptr::addr_of_mut!((*s).name).write(name);
+    (*s).locked.store(0, Ordering::Relaxed);
+    (*s).cpu.store(ptr::null_mut(), Ordering::Relaxed);
+}
+
+/// \brief Disable interrupts, counting nested calls.
+///
+/// Like `cli`, except that it takes one [`popcli`] per `pushcli` to undo,
+/// and interrupts come back on only if they were on at the outermost
+/// `pushcli`. The count lives in this CPU's `Cpu`, found through `%gs`.
+///
+/// # Safety
+/// `seginit` must have run on this CPU.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn pushcli() {
+    let enabled = eflags::read().contains(EFlags::FLAGS_IF);
+    x86::irq::disable();
+    let c = &mut *this_cpu();
+    if c.ncli == 0 {
+        c.intena = enabled as i32;
+    }
+    c.ncli += 1;
+}
+
+/// \brief Undo one [`pushcli`], re-enabling interrupts at the outermost level.
+///
+/// # Safety
+/// Must match an earlier `pushcli` on this CPU.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn popcli() {
+    if eflags::read().contains(EFlags::FLAGS_IF) {
+        panic(c"popcli - interruptible".as_ptr());
+    }
+    let c = &mut *this_cpu();
+    c.ncli -= 1;
+    if c.ncli < 0 {
+        panic(c"popcli".as_ptr());
+    }
+    if c.ncli == 0 && c.intena != 0 {
+        x86::irq::enable();
+    }
+}
+
+#[cfg(feature = "hosted")]
+pub use crate::hosted::{acquire, popcli, pushcli, release};
+
+extern "C" {
     /// \brief Capture the caller’s program counters into `pcs`.
     ///
     /// Used internally to record the call stack when acquiring the lock.
     ///
     /// \param v Unused placeholder for ABI compatibility.
     /// \param pcs Pointer to an array of `u32` where PCs will be stored.
-    pub fn getcallerpcs(v: ffi::c_void, pcs: *const u32);
+    pub fn getcallerpcs(v: *const ffi::c_void, pcs: *mut u32);
+}
+
+#[cfg(not(feature = "hosted"))]
+use x86::bits32::eflags::{self, EFlags};
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    fn panic(s: *const ffi::c_char) -> !;
 }
@@ -5,7 +5,8 @@ use core::slice;
 #[allow(non_camel_case_types)]
 pub type c_char = i8;
 
-#[no_mangle]
+// Exporting these on the host would shadow libc's versions in test binaries
+#[cfg_attr(not(feature = "hosted"), no_mangle)]
 pub extern "C" fn strlen(s: *const c_char) -> isize {
     unsafe {
         let mut n = 0;
@@ -16,7 +17,7 @@ pub extern "C" fn strlen(s: *const c_char) -> isize {
     }
 }
 
-#[no_mangle]
+#[cfg_attr(not(feature = "hosted"), no_mangle)]
 pub extern "C" fn strncmp(s: *const c_char, t: *const c_char, n: usize) -> isize {
     //This will give weird results if you pass in a negative number
     let s_slice = unsafe { slice::from_raw_parts(s, n) };
@@ -39,7 +40,7 @@ pub extern "C" fn strncmp(s: *const c_char, t: *const c_char, n: usize) -> isize
     }
     0
 }
-#[no_mangle]
+#[cfg_attr(not(feature = "hosted"), no_mangle)]
 pub extern "C" fn strncpy(s: *mut c_char, t: *const c_char, n: isize) -> *const c_char {
     if n <= 0 {
         return s;
@@ -55,7 +56,7 @@ pub extern "C" fn strncpy(s: *mut c_char, t: *const c_char, n: isize) -> *const
 
     return s;
 }
-#[no_mangle]
+#[cfg_attr(not(feature = "hosted"), no_mangle)]
 pub extern "C" fn safestrcpy(s: *mut c_char, t: *const c_char, n: isize) -> *const c_char {
     if n <= 0 {
         return s;
@@ -74,3 +75,79 @@ pub extern "C" fn safestrcpy(s: *mut c_char, t: *const c_char, n: isize) -> *con
 
     return s;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(s: &[u8]) -> Vec<c_char> {
+        s.iter().map(|&b| b as c_char).collect()
+    }
+
+    #[test]
+    fn strlen_counts_to_nul() {
+        assert_eq!(strlen(c(b"\0").as_ptr()), 0);
+        assert_eq!(strlen(c(b"xv6\0").as_ptr()), 3);
+        assert_eq!(strlen(c(b"ab\0cd\0").as_ptr()), 2);
+    }
+
+    #[test]
+    fn strncmp_equal_and_prefix() {
+        let a = c(b"hello\0");
+        let b = c(b"hello\0");
+        assert_eq!(strncmp(a.as_ptr(), b.as_ptr(), 6), 0);
+        let short = c(b"help\0\0");
+        assert_eq!(strncmp(a.as_ptr(), short.as_ptr(), 3), 0);
+        assert!(strncmp(a.as_ptr(), short.as_ptr(), 6) < 0);
+        assert!(strncmp(short.as_ptr(), a.as_ptr(), 6) > 0);
+    }
+
+    #[test]
+    fn strncmp_stops_at_nul() {
+        let a = c(b"ab\0x");
+        let b = c(b"ab\0y");
+        assert_eq!(strncmp(a.as_ptr(), b.as_ptr(), 4), 0);
+    }
+
+    #[test]
+    fn strncmp_zero_length() {
+        let a = c(b"a\0");
+        let b = c(b"b\0");
+        assert_eq!(strncmp(a.as_ptr(), b.as_ptr(), 0), 0);
+    }
+
+    #[test]
+    fn strncpy_copies_n_bytes() {
+        let src = c(b"abcdef");
+        let mut dst = c(b"......");
+        strncpy(dst.as_mut_ptr(), src.as_ptr(), 4);
+        assert_eq!(dst, c(b"abcd.."));
+    }
+
+    #[test]
+    fn strncpy_nonpositive_length_is_noop() {
+        let src = c(b"abc");
+        let mut dst = c(b"...");
+        assert_eq!(strncpy(dst.as_mut_ptr(), src.as_ptr(), 0), dst.as_ptr());
+        strncpy(dst.as_mut_ptr(), src.as_ptr(), -3);
+        assert_eq!(dst, c(b"..."));
+    }
+
+    #[test]
+    fn safestrcpy_always_terminates() {
+        let src = c(b"abcdef");
+        let mut dst = c(b"......");
+        safestrcpy(dst.as_mut_ptr(), src.as_ptr(), 4);
+        assert_eq!(dst, c(b"abc\0.."));
+        safestrcpy(dst.as_mut_ptr(), src.as_ptr(), 1);
+        assert_eq!(dst[0], 0);
+    }
+
+    #[test]
+    fn safestrcpy_nonpositive_length_is_noop() {
+        let src = c(b"abc");
+        let mut dst = c(b"...");
+        safestrcpy(dst.as_mut_ptr(), src.as_ptr(), 0);
+        assert_eq!(dst, c(b"..."));
+    }
+}
@@ -0,0 +1,475 @@
+//! \file lockdep.rs
+//! \brief Lock-order validator for debug builds (`lockdep` feature).
+//!
+//! Every lock belongs to a class: its name for a [`crate::spinlock::Spinlock`]
+//! or sleep lock, so that all locks initialised with the same name share
+//! one, and its address for a [`super::TicketLock`]. Each CPU, and each
+//! process holding sleep locks, has a stack of the locks it holds. Taking
+//! lock B while holding A records the edge A -> B in a graph of classes.
+//!
+//! Taking B while B already leads to A in that graph means two paths take
+//! the same locks in opposite orders, and may deadlock. Taking a lock the
+//! same context already holds deadlocks for sure. Either way a report with
+//! the call chains of both acquisitions is printed, once per pair of
+//! classes, before the kernel hangs. Locks of one class may nest, as xv6
+//! does with inodes and buffers.
+//!
+//! Without the feature every hook compiles to nothing.
+
+use core::ffi::c_void;
+
+/// \brief Whose lock stack an acquisition goes on.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Ctx {
+    /// Held with interrupts off by whatever runs on this CPU.
+    Cpu,
+    /// Held by the current process, possibly across sleeps.
+    Proc,
+}
+
+/// \brief Check and record that `lock` of class `key` is about to be taken.
+///
+/// `name` is a NUL-terminated name for reports, or null. `frame` comes from
+/// [`crate::spinlock::frame`] in the locking function.
+#[inline(always)]
+pub fn acquire(lock: *const (), key: *const (), name: *const u8, ctx: Ctx, frame: *const c_void) {
+    #[cfg(feature = "lockdep")]
+    imp::acquire(lock as usize, key as usize, name, ctx, frame, true);
+    #[cfg(not(feature = "lockdep"))]
+    let _ = (lock, key, name, ctx, frame);
+}
+
+/// \brief Record that `lock` was taken by a try-lock, which cannot wait and
+/// so is not checked.
+#[inline(always)]
+pub fn acquired(lock: *const (), key: *const (), name: *const u8, ctx: Ctx, frame: *const c_void) {
+    #[cfg(feature = "lockdep")]
+    imp::acquire(lock as usize, key as usize, name, ctx, frame, false);
+    #[cfg(not(feature = "lockdep"))]
+    let _ = (lock, key, name, ctx, frame);
+}
+
+/// \brief Record that `lock` is no longer held.
+#[inline(always)]
+pub fn release(lock: *const (), ctx: Ctx) {
+    #[cfg(feature = "lockdep")]
+    imp::release(lock as usize, ctx);
+    #[cfg(not(feature = "lockdep"))]
+    let _ = (lock, ctx);
+}
+
+/// \brief `acquiresleep` is about to wait for sleep lock `lk`.
+///
+/// # Safety
+/// `name` must be null or NUL-terminated and outlive the lock.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn lockdep_acquire_sleep(lk: *const c_void, name: *const u8) {
+    acquire(lk as *const (), name as *const (), name, Ctx::Proc, crate::spinlock::frame());
+}
+
+/// \brief `releasesleep` released sleep lock `lk`.
+///
+/// # Safety
+/// Always safe; `unsafe` for symmetry with [`lockdep_acquire_sleep`].
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn lockdep_release_sleep(lk: *const c_void) {
+    release(lk as *const (), Ctx::Proc);
+}
+
+#[cfg(feature = "lockdep")]
+mod imp {
+    use super::Ctx;
+    use crate::param::{NCPU, NPROC};
+    use crate::proc::{mycpu, myproc};
+    use crate::spinlock::{getcallerpcs, popcli, pushcli};
+    use core::cell::UnsafeCell;
+    use core::ffi::{c_void, CStr};
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    const MAX_CLASSES: usize = 64;
+    const MAX_EDGES: usize = 256;
+    const MAX_HELD: usize = 8;
+    const MAX_CTX: usize = NCPU + NPROC;
+
+    type Pcs = [u32; 10];
+
+    #[derive(Clone, Copy)]
+    struct Held {
+        lock: usize,
+        class: usize,
+        pcs: Pcs,
+    }
+
+    const NO_HELD: Held = Held { lock: 0, class: 0, pcs: [0; 10] };
+
+    /// \brief Locks held by one CPU or process; `owner` 0 is a free slot.
+    struct Context {
+        owner: usize,
+        depth: usize,
+        held: [Held; MAX_HELD],
+    }
+
+    /// \brief Where the first `from` then `to` acquisition happened.
+    #[derive(Clone, Copy)]
+    struct Edge {
+        from: usize,
+        to: usize,
+        pcs_from: Pcs,
+        pcs_to: Pcs,
+    }
+
+    struct Class {
+        key: usize,
+        name: *const u8,
+    }
+
+    struct Graph {
+        classes: [Class; MAX_CLASSES],
+        nclasses: usize,
+        /// Bit `b` of `after[a]`: class `b` was taken while holding `a`.
+        after: [u64; MAX_CLASSES],
+        /// Bit `b` of `reported[a]`: `a` against `b` was already reported.
+        reported: [u64; MAX_CLASSES],
+        edges: [Edge; MAX_EDGES],
+        nedges: usize,
+        contexts: [Context; MAX_CTX],
+        /// Out of table space; nothing more is checked.
+        off: bool,
+    }
+
+    /// \brief The graph, behind a lock that is itself not validated.
+    struct Locked {
+        busy: AtomicBool,
+        graph: UnsafeCell<Graph>,
+    }
+
+    unsafe impl Sync for Locked {}
+
+    static GRAPH: Locked = Locked {
+        busy: AtomicBool::new(false),
+        graph: UnsafeCell::new(Graph {
+            classes: [const { Class { key: 0, name: core::ptr::null() } }; MAX_CLASSES],
+            nclasses: 0,
+            after: [0; MAX_CLASSES],
+            reported: [0; MAX_CLASSES],
+            edges: [Edge { from: 0, to: 0, pcs_from: [0; 10], pcs_to: [0; 10] }; MAX_EDGES],
+            nedges: 0,
+            contexts: [const { Context { owner: 0, depth: 0, held: [NO_HELD; MAX_HELD] } }; MAX_CTX],
+            off: false,
+        }),
+    };
+
+    /// \brief Run `f` on the graph with interrupts off and the graph locked.
+    fn with_graph<R>(f: impl FnOnce(&mut Graph) -> R) -> R {
+        unsafe {
+            pushcli();
+            while GRAPH.busy.swap(true, Ordering::Acquire) {
+                core::hint::spin_loop();
+            }
+            let r = f(&mut *GRAPH.graph.get());
+            GRAPH.busy.store(false, Ordering::Release);
+            popcli();
+            r
+        }
+    }
+
+    /// \brief Identity of the current CPU, and of the current process if any.
+    ///
+    /// Interrupts must be off, as they are inside [`with_graph`].
+    fn owners() -> (usize, usize) {
+        unsafe { (mycpu() as usize, myproc() as usize) }
+    }
+
+    /// \brief What a report says about one lock.
+    #[derive(Clone, Copy)]
+    struct Lock {
+        name: *const u8,
+        key: usize,
+        pcs: Pcs,
+    }
+
+    /// \brief A problem found by [`Graph::check`], printed after the graph
+    /// is unlocked since printing takes locks too.
+    enum Report {
+        Recursive { lock: Lock, first: Pcs },
+        Inversion { held: Lock, taking: Lock, earlier: Option<Edge>, direct: bool, names: [(*const u8, usize); 2] },
+    }
+
+    impl Graph {
+        fn class(&mut self, key: usize, name: *const u8) -> Option<usize> {
+            if let Some(c) = self.classes[..self.nclasses].iter().position(|c| c.key == key) {
+                return Some(c);
+            }
+            if self.nclasses == MAX_CLASSES {
+                return None;
+            }
+            self.classes[self.nclasses] = Class { key, name };
+            self.nclasses += 1;
+            Some(self.nclasses - 1)
+        }
+
+        fn context(&mut self, owner: usize) -> Option<usize> {
+            if owner == 0 {
+                return None;
+            }
+            let i = self.contexts.iter().position(|c| c.owner == owner);
+            let i = i.or_else(|| self.contexts.iter().position(|c| c.owner == 0))?;
+            self.contexts[i].owner = owner;
+            Some(i)
+        }
+
+        fn reaches(&self, from: usize, to: usize) -> bool {
+            let (mut seen, mut frontier) = (0u64, 1u64 << from);
+            while frontier != 0 {
+                seen |= frontier;
+                let mut next = 0;
+                for (c, after) in self.after[..self.nclasses].iter().enumerate() {
+                    if frontier & (1 << c) != 0 {
+                        next |= after;
+                    }
+                }
+                if next & (1 << to) != 0 {
+                    return true;
+                }
+                frontier = next & !seen;
+            }
+            false
+        }
+
+        /// \brief The first recorded edge out of `from` towards `to`.
+        fn edge_towards(&self, from: usize, to: usize) -> Option<Edge> {
+            self.edges[..self.nedges]
+                .iter()
+                .find(|e| e.from == from && (e.to == to || self.reaches(e.to, to)))
+                .copied()
+        }
+
+        fn lock(&self, class: usize, pcs: Pcs) -> Lock {
+            Lock { name: self.classes[class].name, key: self.classes[class].key, pcs }
+        }
+
+        /// \brief Compare taking `lock` of `class` with what `ctxs` hold,
+        /// adding the new edges.
+        fn check(&mut self, ctxs: &[usize], lock: usize, class: usize, pcs: &Pcs) -> Option<Report> {
+            let mut report = None;
+            for &ci in ctxs {
+                for h in 0..self.contexts[ci].depth.min(MAX_HELD) {
+                    let held = self.contexts[ci].held[h];
+                    if held.lock == lock {
+                        if self.reported[class] & (1 << class) == 0 {
+                            self.reported[class] |= 1 << class;
+                            report = Some(Report::Recursive { lock: self.lock(class, *pcs), first: held.pcs });
+                        }
+                        continue;
+                    }
+                    let a = held.class;
+                    if a == class || self.after[a] & (1 << class) != 0 {
+                        continue;
+                    }
+                    if self.reaches(class, a) && self.reported[a] & (1 << class) == 0 {
+                        self.reported[a] |= 1 << class;
+                        self.reported[class] |= 1 << a;
+                        let earlier = self.edge_towards(class, a);
+                        report = Some(Report::Inversion {
+                            held: self.lock(a, held.pcs),
+                            taking: self.lock(class, *pcs),
+                            direct: self.after[class] & (1 << a) != 0,
+                            earlier,
+                            names: earlier.map_or([(core::ptr::null(), 0); 2], |e| {
+                                let (f, t) = (&self.classes[e.from], &self.classes[e.to]);
+                                [(f.name, f.key), (t.name, t.key)]
+                            }),
+                        });
+                    }
+                    self.after[a] |= 1 << class;
+                    if self.nedges < MAX_EDGES {
+                        self.edges[self.nedges] = Edge { from: a, to: class, pcs_from: held.pcs, pcs_to: *pcs };
+                        self.nedges += 1;
+                    }
+                }
+            }
+            report
+        }
+    }
+
+    pub fn acquire(lock: usize, key: usize, name: *const u8, ctx: Ctx, frame: *const c_void, check: bool) {
+        let mut pcs = [0; 10];
+        unsafe { getcallerpcs(frame, pcs.as_mut_ptr()) };
+        let report = with_graph(|g| {
+            let (cpu, proc) = owners();
+            if g.off {
+                return None;
+            }
+            let Some(class) = g.class(key, name) else {
+                g.off = true;
+                return None;
+            };
+            let mine = if ctx == Ctx::Proc && proc != 0 { proc } else { cpu };
+            let ctxs = [g.context(cpu), if proc != 0 { g.context(proc) } else { None }];
+            let Some(ci) = g.context(mine) else {
+                g.off = true;
+                return None;
+            };
+            let mut report = None;
+            if check {
+                let ctxs: [usize; 2] = [ctxs[0].unwrap_or(ci), ctxs[1].unwrap_or(ci)];
+                let n = if ctxs[0] == ctxs[1] { 1 } else { 2 };
+                report = g.check(&ctxs[..n], lock, class, &pcs);
+            }
+            let c = &mut g.contexts[ci];
+            if c.depth < MAX_HELD {
+                c.held[c.depth] = Held { lock, class, pcs };
+            }
+            c.depth += 1;
+            // Contexts claimed only to be looked at are given back.
+            for i in ctxs.into_iter().flatten() {
+                if g.contexts[i].depth == 0 {
+                    g.contexts[i].owner = 0;
+                }
+            }
+            report
+        });
+        if let Some(r) = report {
+            print(r);
+        }
+    }
+
+    pub fn release(lock: usize, ctx: Ctx) {
+        with_graph(|g| {
+            let (cpu, proc) = owners();
+            let mine = if ctx == Ctx::Proc && proc != 0 { proc } else { cpu };
+            // Normally the releasing context took the lock, but a process
+            // may finish with a lock taken before it migrated.
+            let i = g.contexts.iter().position(|c| c.owner == mine && holds(c, lock));
+            let Some(i) = i.or_else(|| g.contexts.iter().position(|c| c.owner != 0 && holds(c, lock))) else {
+                return;
+            };
+            let c = &mut g.contexts[i];
+            let n = c.depth.min(MAX_HELD);
+            let h = (0..n).rev().find(|&h| c.held[h].lock == lock).unwrap();
+            c.held.copy_within(h + 1..n, h);
+            c.depth -= 1;
+            if c.depth == 0 {
+                c.owner = 0;
+            }
+        });
+    }
+
+    fn holds(c: &Context, lock: usize) -> bool {
+        c.held[..c.depth.min(MAX_HELD)].iter().any(|h| h.lock == lock)
+    }
+
+    /// \brief Whether `key` was already reported against any class.
+    #[cfg(test)]
+    pub fn reported(key: usize) -> bool {
+        with_graph(|g| g.classes[..g.nclasses].iter().position(|c| c.key == key).is_some_and(|c| g.reported[c] != 0))
+    }
+
+    struct Name(*const u8, usize);
+
+    impl core::fmt::Display for Name {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            if self.0.is_null() {
+                write!(f, "lock {:#x}", self.1)
+            } else {
+                let s = unsafe { CStr::from_ptr(self.0 as *const core::ffi::c_char) };
+                write!(f, "\"{}\"", s.to_str().unwrap_or("?"))
+            }
+        }
+    }
+
+    macro_rules! out {
+        ($($arg:tt)*) => {{
+            #[cfg(feature = "hosted")]
+            std::eprintln!($($arg)*);
+            #[cfg(not(feature = "hosted"))]
+            println!($($arg)*);
+        }};
+    }
+
+    fn print(r: Report) {
+        match r {
+            Report::Recursive { lock, first } => {
+                out!("lockdep: recursive acquisition of {}", Name(lock.name, lock.key));
+                out!("  first taken at {:x?}", first);
+                out!("  taken again at {:x?}", lock.pcs);
+            }
+            Report::Inversion { held, taking, earlier, direct, names } => {
+                out!(
+                    "lockdep: lock order inversion: taking {} while holding {}",
+                    Name(taking.name, taking.key),
+                    Name(held.name, held.key)
+                );
+                out!("  {} taken at {:x?}", Name(held.name, held.key), held.pcs);
+                out!("  {} taken at {:x?}", Name(taking.name, taking.key), taking.pcs);
+                if let Some(e) = earlier {
+                    let via = if direct { "" } else { " (then on to the first through other locks)" };
+                    out!("  earlier order{}:", via);
+                    out!("  {} taken at {:x?}", Name(names[0].0, names[0].1), e.pcs_from);
+                    out!("  {} taken at {:x?}", Name(names[1].0, names[1].1), e.pcs_to);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "lockdep"))]
+mod tests {
+    use super::*;
+    use core::ptr;
+
+    fn key(s: &'static core::ffi::CStr) -> *const () {
+        s.as_ptr() as *const ()
+    }
+
+    #[test]
+    fn opposite_orders_are_reported() {
+        let (a, b) = (c"lockdep test a", c"lockdep test b");
+        let (la, lb) = (0x100 as *const (), 0x200 as *const ());
+        let f = ptr::null();
+        acquire(la, key(a), a.as_ptr() as *const u8, Ctx::Cpu, f);
+        acquire(lb, key(b), b.as_ptr() as *const u8, Ctx::Cpu, f);
+        release(lb, Ctx::Cpu);
+        release(la, Ctx::Cpu);
+        assert!(!imp::reported(key(a) as usize));
+
+        acquire(lb, key(b), b.as_ptr() as *const u8, Ctx::Cpu, f);
+        acquire(la, key(a), a.as_ptr() as *const u8, Ctx::Cpu, f);
+        release(la, Ctx::Cpu);
+        release(lb, Ctx::Cpu);
+        assert!(imp::reported(key(a) as usize));
+    }
+
+    #[test]
+    fn transitive_cycles_are_reported() {
+        let names = [c"lockdep test x", c"lockdep test y", c"lockdep test z"];
+        let f = ptr::null();
+        for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+            let (li, lj) = ((10 + i) as *const (), (10 + j) as *const ());
+            acquire(li, key(names[i]), ptr::null(), Ctx::Cpu, f);
+            acquire(lj, key(names[j]), ptr::null(), Ctx::Cpu, f);
+            release(lj, Ctx::Cpu);
+            release(li, Ctx::Cpu);
+        }
+        assert!(imp::reported(key(names[2]) as usize));
+    }
+
+    #[test]
+    fn recursion_is_reported_and_same_class_nesting_is_not() {
+        let (r, n) = (c"lockdep test recursive", c"lockdep test nested");
+        let f = ptr::null();
+        acquire(20 as *const (), key(n), ptr::null(), Ctx::Proc, f);
+        acquire(21 as *const (), key(n), ptr::null(), Ctx::Proc, f);
+        release(21 as *const (), Ctx::Proc);
+        release(20 as *const (), Ctx::Proc);
+        assert!(!imp::reported(key(n) as usize));
+
+        acquire(22 as *const (), key(r), ptr::null(), Ctx::Proc, f);
+        acquire(22 as *const (), key(r), ptr::null(), Ctx::Proc, f);
+        release(22 as *const (), Ctx::Proc);
+        release(22 as *const (), Ctx::Proc);
+        assert!(imp::reported(key(r) as usize));
+    }
+}
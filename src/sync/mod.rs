@@ -1,6 +1,15 @@
 // src/sync/mod.rs
+pub mod lockdep;
 pub mod primitives;
+pub mod rw_lock;
+pub mod seq_lock;
+pub mod sleep_lock;
 pub mod ticket_lock; // Renamed module
+pub mod wait_queue;
 
-pub use primitives::SpinLock;
+pub use primitives::{SpinLock, SpinLockGuard};
+pub use rw_lock::{RwLock, RwLockReadGuard, RwLockWriteGuard, SleepRwLock, SleepRwLockReadGuard, SleepRwLockWriteGuard};
+pub use seq_lock::{SeqLock, SeqLockWriteGuard};
+pub use sleep_lock::{SleepLock, SleepLockGuard};
 pub use ticket_lock::{TicketLock, TicketLockGuard}; // Updated exports
+pub use wait_queue::{Condvar, WaitQueue};
@@ -1,40 +1,158 @@
 //! Basic synchronization primitives.
 
-use core::sync::atomic::{AtomicU32, Ordering};
-use crate::cpu_features; // For has_cmpxchg8b
-
-pub struct SpinLock {
-    locked: AtomicU32,
-}
-
-impl SpinLock {
-    pub const fn new() -> Self {
-        Self { locked: AtomicU32::new(0) }
-    }
-
-    pub fn try_lock(&self) -> bool {
-        if cpu_features::has_cmpxchg8b() { // Assuming cmpxchg8b implies working 32-bit CAS
-            self.locked.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).is_ok()
-        } else {
-            // Fallback for systems without cmpxchg8b (e.g., very old i386 before P5 with cx8)
-            // True bare-metal interrupt disable/enable is complex and platform specific.
-            // This placeholder indicates where such logic would go.
-            // For xv6 on i386, cmpxchg8b should generally be available if we target P5+.
-            // Our i386.json has +cx8, so this path is less likely unless on very old CPU.
-            // todo!("Implement interrupt disable/enable or alternative non-CAS lock mechanism for try_lock");
-            // For compilation, let's return false for now in the todo path.
-            false
+use crate::spinlock::Spinlock;
+use core::cell::UnsafeCell;
+use core::ffi::CStr;
+use core::ops::{Deref, DerefMut};
+
+/// \brief A spinlock that owns the data it protects.
+///
+/// Built on the C-compatible [`Spinlock`], so it behaves exactly like
+/// `acquire`/`release` in C: interrupts are disabled while the lock is held,
+/// taking it twice on one CPU panics, and the holder's CPU and call stack
+/// are recorded for debugging.
+pub struct SpinLock<T: ?Sized> {
+    raw: Spinlock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    /// \brief Unlocked lock named `name`, suitable for a `static`.
+    pub const fn new(name: &'static CStr, data: T) -> Self {
+        Self { raw: Spinlock::new(name), data: UnsafeCell::new(data) }
+    }
+
+    /// \brief Consume the lock, returning the data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// \brief Spin until the lock is ours.
+    #[inline(never)] // The recorded call stack starts at our caller.
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        self.raw.acquire();
+        SpinLockGuard { lock: self }
+    }
+
+    /// \brief Take the lock only if it is free.
+    #[inline(never)]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        if !self.raw.try_acquire() {
+            return None;
         }
+        Some(SpinLockGuard { lock: self })
+    }
+
+    /// \brief Whether this CPU holds the lock.
+    pub fn holding(&self) -> bool {
+        self.raw.holding()
+    }
+
+    /// \brief The underlying C lock, for C interfaces such as `sleep`.
+    pub fn raw(&self) -> &Spinlock {
+        &self.raw
     }
 
-    pub fn lock(&self) {
-        // Simple spin loop
-        while !self.try_lock() {
-            core::hint::spin_loop();
+    /// \brief Mutable access without locking; `&mut self` proves exclusivity.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// \brief Raw pointer to the data, for fields that are safe to read
+    /// without the lock, such as atomics.
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+/// \brief Access to the data of a held [`SpinLock`]; releases it on drop.
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T: ?Sized> SpinLockGuard<'_, T> {
+    /// \brief The held C lock, for `sleep` to release and retake.
+    pub(crate) fn raw(&self) -> &Spinlock {
+        &self.lock.raw
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn try_lock_fails_while_held() {
+        let lock = SpinLock::new(c"test", 0u32);
+        let g = lock.try_lock().expect("free lock");
+        let other = thread::scope(|s| s.spawn(|| lock.try_lock().is_none()).join().unwrap());
+        assert!(other);
+        drop(g);
+        assert!(lock.try_lock().is_some());
+    }
+
+    #[test]
+    fn lock_excludes_other_threads() {
+        let lock = Arc::new(SpinLock::new(c"count", 0usize));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        *lock.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
         }
+        assert_eq!(*lock.lock(), 4_000);
+    }
+
+    #[test]
+    fn guard_tracks_holder() {
+        let lock = SpinLock::new(c"held", ());
+        assert!(!lock.holding());
+        let g = lock.lock();
+        assert!(lock.holding());
+        assert!(!thread::scope(|s| s.spawn(|| lock.holding()).join().unwrap()));
+        drop(g);
+        assert!(!lock.holding());
+        assert_eq!(lock.raw().name(), c"held");
     }
 
-    pub fn unlock(&self) {
-        self.locked.store(0, Ordering::Release);
+    #[test]
+    #[should_panic(expected = "acquire")]
+    fn relocking_on_the_same_cpu_panics() {
+        let lock = SpinLock::new(c"twice", ());
+        let _g = lock.lock();
+        let _h = lock.try_lock();
     }
 }
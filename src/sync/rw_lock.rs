@@ -0,0 +1,464 @@
+//! \file rw_lock.rs
+//! \brief Readers–writer locks, spinning and sleeping.
+//!
+//! Both locks are fair: everyone takes a ticket and is let in in ticket
+//! order, with consecutive readers admitted together. A waiting writer
+//! therefore holds back readers that arrive after it, so a steady stream
+//! of readers cannot starve writers. It also means a CPU or process must
+//! not take a read lock it already holds, as a writer may be queued
+//! between the two.
+
+use super::lockdep::{self, Ctx};
+use super::primitives::SpinLock;
+use super::wait_queue::WaitQueue;
+use crate::spinlock::{frame, popcli, pushcli};
+use core::cell::UnsafeCell;
+use core::ffi::{c_void, CStr};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// \brief A spinning readers–writer lock that owns the data it protects.
+///
+/// Like [`super::SpinLock`], interrupts stay disabled while it is held,
+/// for reading or writing.
+pub struct RwLock<T: ?Sized> {
+    /// Next ticket to hand out.
+    next: AtomicU32,
+    /// Ticket a reader must have to get in.
+    read: AtomicU32,
+    /// Ticket a writer must have to get in.
+    write: AtomicU32,
+    name: &'static CStr,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// \brief Unlocked lock named `name`, suitable for a `static`.
+    pub const fn new(name: &'static CStr, data: T) -> Self {
+        Self {
+            next: AtomicU32::new(0),
+            read: AtomicU32::new(0),
+            write: AtomicU32::new(0),
+            name,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// \brief Consume the lock, returning the data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// \brief Spin until the data may be read alongside other readers.
+    #[inline(never)] // Keep a frame for lockdep's call chain.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        unsafe { pushcli() };
+        self.lockdep_acquire(frame());
+        let me = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.read.load(Ordering::Acquire) != me {
+            core::hint::spin_loop();
+        }
+        // Let the next ticket in too if it is a reader.
+        self.read.fetch_add(1, Ordering::Release);
+        RwLockReadGuard { lock: self }
+    }
+
+    /// \brief Spin until the data is ours alone.
+    #[inline(never)]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        unsafe { pushcli() };
+        self.lockdep_acquire(frame());
+        let me = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.write.load(Ordering::Acquire) != me {
+            core::hint::spin_loop();
+        }
+        RwLockWriteGuard { lock: self }
+    }
+
+    /// \brief Take a read lock only if nobody writes or waits to.
+    #[inline(never)]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        unsafe { pushcli() };
+        let me = self.read.load(Ordering::Relaxed);
+        if self.next.compare_exchange(me, me.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed).is_err() {
+            unsafe { popcli() };
+            return None;
+        }
+        self.read.fetch_add(1, Ordering::Release);
+        lockdep::acquired(self.addr(), self.key(), self.key() as *const u8, Ctx::Cpu, frame());
+        Some(RwLockReadGuard { lock: self })
+    }
+
+    /// \brief Take the write lock only if nobody holds or waits for the lock.
+    #[inline(never)]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        unsafe { pushcli() };
+        let me = self.write.load(Ordering::Relaxed);
+        if self.next.compare_exchange(me, me.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed).is_err() {
+            unsafe { popcli() };
+            return None;
+        }
+        lockdep::acquired(self.addr(), self.key(), self.key() as *const u8, Ctx::Cpu, frame());
+        Some(RwLockWriteGuard { lock: self })
+    }
+
+    /// \brief Mutable access without locking; `&mut self` proves exclusivity.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// \brief The lock's name, for diagnostics.
+    pub fn name(&self) -> &'static CStr {
+        self.name
+    }
+
+    fn addr(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
+    fn key(&self) -> *const () {
+        self.name.as_ptr() as *const ()
+    }
+
+    #[inline(always)]
+    fn lockdep_acquire(&self, frame: *const c_void) {
+        lockdep::acquire(self.addr(), self.key(), self.key() as *const u8, Ctx::Cpu, frame);
+    }
+
+    fn read_unlock(&self) {
+        lockdep::release(self.addr(), Ctx::Cpu);
+        // A reader's turn as seen by writers ends when it leaves.
+        self.write.fetch_add(1, Ordering::Release);
+        unsafe { popcli() };
+    }
+
+    fn write_unlock(&self) {
+        lockdep::release(self.addr(), Ctx::Cpu);
+        self.read.fetch_add(1, Ordering::Release);
+        self.write.fetch_add(1, Ordering::Release);
+        unsafe { popcli() };
+    }
+}
+
+/// \brief Shared access to the data of an [`RwLock`]; unlocks on drop.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+/// \brief Exclusive access to the data of an [`RwLock`]; unlocks on drop.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
+
+/// \brief Ticket state of a [`SleepRwLock`].
+struct RwState {
+    next: u32,
+    /// Ticket allowed to try next.
+    serving: u32,
+    readers: u32,
+    writer: bool,
+}
+
+/// \brief A readers–writer lock whose waiters sleep, for processes only.
+///
+/// Either side may be held across operations that sleep.
+pub struct SleepRwLock<T: ?Sized> {
+    state: SpinLock<RwState>,
+    queue: WaitQueue,
+    name: &'static CStr,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SleepRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for SleepRwLock<T> {}
+
+impl<T> SleepRwLock<T> {
+    /// \brief Unlocked lock named `name`, suitable for a `static`.
+    pub const fn new(name: &'static CStr, data: T) -> Self {
+        Self {
+            state: SpinLock::new(c"sleep rwlock", RwState { next: 0, serving: 0, readers: 0, writer: false }),
+            queue: WaitQueue::new(),
+            name,
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SleepRwLock<T> {
+    /// \brief Sleep until the data may be read alongside other readers.
+    #[inline(never)]
+    pub fn read(&self) -> SleepRwLockReadGuard<'_, T> {
+        self.lockdep_acquire(frame());
+        let mut s = self.state.lock();
+        let me = s.next;
+        s.next = s.next.wrapping_add(1);
+        self.queue.wait_while(&mut s, |s| s.serving != me || s.writer);
+        s.readers += 1;
+        s.serving = s.serving.wrapping_add(1);
+        drop(s);
+        // The next ticket may be a reader that can come in with us.
+        self.queue.notify_all();
+        SleepRwLockReadGuard { lock: self }
+    }
+
+    /// \brief Sleep until the data is ours alone.
+    #[inline(never)]
+    pub fn write(&self) -> SleepRwLockWriteGuard<'_, T> {
+        self.lockdep_acquire(frame());
+        let mut s = self.state.lock();
+        let me = s.next;
+        s.next = s.next.wrapping_add(1);
+        self.queue.wait_while(&mut s, |s| s.serving != me || s.writer || s.readers != 0);
+        s.writer = true;
+        s.serving = s.serving.wrapping_add(1);
+        SleepRwLockWriteGuard { lock: self }
+    }
+
+    /// \brief Take a read lock only if nobody writes or waits to.
+    #[inline(never)]
+    pub fn try_read(&self) -> Option<SleepRwLockReadGuard<'_, T>> {
+        let mut s = self.state.lock();
+        if s.serving != s.next || s.writer {
+            return None;
+        }
+        s.next = s.next.wrapping_add(1);
+        s.serving = s.serving.wrapping_add(1);
+        s.readers += 1;
+        lockdep::acquired(self.addr(), self.key(), self.key() as *const u8, Ctx::Proc, frame());
+        Some(SleepRwLockReadGuard { lock: self })
+    }
+
+    /// \brief Take the write lock only if nobody holds or waits for the lock.
+    #[inline(never)]
+    pub fn try_write(&self) -> Option<SleepRwLockWriteGuard<'_, T>> {
+        let mut s = self.state.lock();
+        if s.serving != s.next || s.writer || s.readers != 0 {
+            return None;
+        }
+        s.next = s.next.wrapping_add(1);
+        s.serving = s.serving.wrapping_add(1);
+        s.writer = true;
+        lockdep::acquired(self.addr(), self.key(), self.key() as *const u8, Ctx::Proc, frame());
+        Some(SleepRwLockWriteGuard { lock: self })
+    }
+
+    /// \brief The lock's name, for diagnostics.
+    pub fn name(&self) -> &'static CStr {
+        self.name
+    }
+
+    fn addr(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
+    fn key(&self) -> *const () {
+        self.name.as_ptr() as *const ()
+    }
+
+    #[inline(always)]
+    fn lockdep_acquire(&self, frame: *const c_void) {
+        lockdep::acquire(self.addr(), self.key(), self.key() as *const u8, Ctx::Proc, frame);
+    }
+
+    fn unlock(&self, writer: bool) {
+        let mut s = self.state.lock();
+        if writer {
+            s.writer = false;
+        } else {
+            s.readers -= 1;
+        }
+        let wake = s.readers == 0;
+        drop(s);
+        lockdep::release(self.addr(), Ctx::Proc);
+        if wake {
+            self.queue.notify_all();
+        }
+    }
+}
+
+/// \brief Shared access to the data of a [`SleepRwLock`]; unlocks on drop.
+pub struct SleepRwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a SleepRwLock<T>,
+}
+
+/// \brief Exclusive access to the data of a [`SleepRwLock`]; unlocks on drop.
+pub struct SleepRwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a SleepRwLock<T>,
+}
+
+impl<T: ?Sized> Deref for SleepRwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SleepRwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock(false);
+    }
+}
+
+impl<T: ?Sized> Deref for SleepRwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SleepRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SleepRwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    #[test]
+    fn readers_share_and_writers_exclude() {
+        let lock = RwLock::new(c"rw test", 1u32);
+        let r = lock.read();
+        assert!(thread::scope(|s| s.spawn(|| lock.try_read().is_some()).join().unwrap()));
+        assert!(lock.try_write().is_none());
+        drop(r);
+        let mut w = lock.try_write().expect("free lock");
+        *w = 2;
+        assert!(thread::scope(|s| s.spawn(|| lock.try_read().is_none()).join().unwrap()));
+        drop(w);
+        assert_eq!(*lock.read(), 2);
+    }
+
+    #[test]
+    fn waiting_writer_holds_back_new_readers() {
+        let lock = Arc::new(RwLock::new(c"rw fair", 0u32));
+        let r = lock.read();
+        let (tx, rx) = mpsc::channel();
+        let writer = {
+            let lock = Arc::clone(&lock);
+            thread::spawn(move || {
+                let mut w = lock.write();
+                *w += 1;
+                tx.send(()).unwrap();
+            })
+        };
+        while lock.next.load(Ordering::Relaxed) != 2 {
+            thread::yield_now();
+        }
+        assert!(lock.try_read().is_none());
+        assert!(rx.try_recv().is_err());
+        drop(r);
+        rx.recv().unwrap();
+        writer.join().unwrap();
+        assert_eq!(*lock.try_read().expect("free lock"), 1);
+    }
+
+    #[test]
+    fn readers_never_see_a_half_done_write() {
+        let lock = Arc::new(RwLock::new(c"rw pair", (0u64, 0u64)));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        if i % 2 == 0 {
+                            let mut w = lock.write();
+                            w.0 += 1;
+                            w.1 += 1;
+                        } else {
+                            let r = lock.read();
+                            assert_eq!(r.0, r.1);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*lock.read(), (2_000, 2_000));
+    }
+
+    #[test]
+    fn sleeping_readers_share_and_writers_exclude() {
+        let lock = SleepRwLock::new(c"sleep rw test", 1u32);
+        let r = lock.read();
+        assert!(thread::scope(|s| s.spawn(|| lock.try_read().is_some()).join().unwrap()));
+        assert!(lock.try_write().is_none());
+        drop(r);
+        *lock.write() = 2;
+        assert_eq!(*lock.try_read().expect("free lock"), 2);
+    }
+
+    #[test]
+    fn sleeping_lock_counts_under_contention() {
+        let lock = Arc::new(SleepRwLock::new(c"sleep rw count", (0usize, 0usize)));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        if i % 2 == 0 {
+                            let mut w = lock.write();
+                            w.0 += 1;
+                            w.1 += 1;
+                        } else {
+                            let r = lock.read();
+                            assert_eq!(r.0, r.1);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*lock.read(), (400, 400));
+    }
+}
@@ -0,0 +1,159 @@
+//! \file seq_lock.rs
+//! \brief Sequence locks for small, hot, mostly-read data.
+//!
+//! Readers of a [`SeqLock`] take no lock at all: they copy the data and
+//! retry if a writer was active meanwhile, which the sequence number tells
+//! them. That suits values like the tick count, read everywhere and
+//! written by one interrupt handler, but only plain `Copy` data, since a
+//! reader may briefly see a torn value before it retries.
+
+use super::primitives::{SpinLock, SpinLockGuard};
+use core::cell::UnsafeCell;
+use core::ffi::CStr;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{fence, AtomicU32, Ordering};
+
+/// \brief Data read without locking and written under a spinlock.
+pub struct SeqLock<T: Copy> {
+    /// Odd while a write is in progress.
+    seq: AtomicU32,
+    writer: SpinLock<()>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    /// \brief Lock named `name` holding `data`, suitable for a `static`.
+    pub const fn new(name: &'static CStr, data: T) -> Self {
+        Self { seq: AtomicU32::new(0), writer: SpinLock::new(name, ()), data: UnsafeCell::new(data) }
+    }
+
+    /// \brief A consistent copy of the data.
+    ///
+    /// Spins while a write is in progress, so it must not be called by the
+    /// CPU that is writing, for instance from an interrupt handler.
+    pub fn read(&self) -> T {
+        loop {
+            let seq = self.read_begin();
+            let data = unsafe { ptr::read_volatile(self.data.get()) };
+            if !self.read_retry(seq) {
+                return data;
+            }
+        }
+    }
+
+    /// \brief Start an optimistic read; pass the result to
+    /// [`SeqLock::read_retry`] after reading.
+    pub fn read_begin(&self) -> u32 {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                return seq;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// \brief Whether a write happened since `seq` was returned by
+    /// [`SeqLock::read_begin`], so what was read must be thrown away.
+    pub fn read_retry(&self, seq: u32) -> bool {
+        fence(Ordering::Acquire);
+        self.seq.load(Ordering::Relaxed) != seq
+    }
+
+    /// \brief Take the writer lock; readers retry until the guard is dropped.
+    ///
+    /// Interrupts stay disabled meanwhile, as with [`SpinLock`].
+    pub fn write(&self) -> SeqLockWriteGuard<'_, T> {
+        let guard = self.writer.lock();
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        SeqLockWriteGuard { lock: self, _guard: guard }
+    }
+
+    /// \brief Replace the data.
+    pub fn set(&self, data: T) {
+        *self.write() = data;
+    }
+
+    /// \brief Number of writes so far, times two.
+    pub fn sequence(&self) -> u32 {
+        self.seq.load(Ordering::Relaxed)
+    }
+}
+
+/// \brief Exclusive access to the data of a [`SeqLock`]; ends the write on drop.
+pub struct SeqLockWriteGuard<'a, T: Copy> {
+    lock: &'a SeqLock<T>,
+    _guard: SpinLockGuard<'a, ()>,
+}
+
+impl<T: Copy> Deref for SeqLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: Copy> DerefMut for SeqLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: Copy> Drop for SeqLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.seq.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn writes_bump_the_sequence() {
+        let lock = SeqLock::new(c"seq test", 1u32);
+        assert_eq!(lock.read(), 1);
+        let seq = lock.read_begin();
+        {
+            let mut w = lock.write();
+            assert_eq!(lock.sequence() & 1, 1);
+            *w = 2;
+        }
+        assert!(lock.read_retry(seq));
+        lock.set(3);
+        assert_eq!((lock.read(), lock.sequence()), (3, 4));
+    }
+
+    #[test]
+    fn readers_only_see_whole_values() {
+        let lock = Arc::new(SeqLock::new(c"seq pair", (0u64, 0u64)));
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let (lock, done) = (Arc::clone(&lock), Arc::clone(&done));
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        let (a, b) = lock.read();
+                        assert_eq!(a, b);
+                    }
+                })
+            })
+            .collect();
+        for i in 1..=10_000 {
+            lock.set((i, i));
+        }
+        done.store(true, Ordering::Relaxed);
+        for r in readers {
+            r.join().unwrap();
+        }
+        assert_eq!(lock.read(), (10_000, 10_000));
+    }
+}
@@ -0,0 +1,211 @@
+//! \file sleep_lock.rs
+//! \brief Long-term locks for processes, like `sleeplock.c`.
+//!
+//! A [`SleepLock`] may be held across operations that sleep, such as disk
+//! I/O, because waiters give up the CPU instead of spinning. It can only be
+//! taken by a process, never by an interrupt handler.
+
+use super::lockdep::{self, Ctx};
+use super::primitives::SpinLock;
+use super::wait_queue::WaitQueue;
+use crate::errno::Errno;
+use crate::proc::myproc;
+use crate::spinlock::Spinlock;
+use crate::spinlock::frame;
+use core::cell::UnsafeCell;
+use core::ffi::{c_void, CStr};
+use core::ops::{Deref, DerefMut};
+
+/// \brief C `struct sleeplock`, for structures shared with C such as
+/// [`crate::file::Inode`]; used through `acquiresleep`/`releasesleep`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Sleeplock {
+    /// \brief Non-zero while held.
+    pub locked: u32,
+    /// \brief Protects the other fields.
+    pub lk: Spinlock,
+    /// \brief Name of the lock (null-terminated C string).
+    pub name: *const u8,
+    /// \brief Process holding the lock.
+    pub pid: i32,
+}
+
+impl Sleeplock {
+    /// \brief Unlocked lock, as set up by `initsleeplock`.
+    pub const fn new(name: &'static CStr) -> Self {
+        Sleeplock { locked: 0, lk: Spinlock::new(c"sleep lock"), name: name.as_ptr() as *const u8, pid: 0 }
+    }
+}
+
+/// \brief Who holds a [`SleepLock`].
+struct Holder {
+    locked: bool,
+    /// Process holding the lock, for `holding` and debugging.
+    pid: u32,
+}
+
+/// \brief A lock whose waiters sleep, owning the data it protects.
+pub struct SleepLock<T: ?Sized> {
+    holder: SpinLock<Holder>,
+    queue: WaitQueue,
+    name: &'static CStr,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SleepLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SleepLock<T> {}
+
+/// \brief PID of the current process.
+fn mypid() -> u32 {
+    unsafe { (*myproc()).pid }
+}
+
+impl<T> SleepLock<T> {
+    /// \brief Unlocked lock named `name`, suitable for a `static`.
+    pub const fn new(name: &'static CStr, data: T) -> Self {
+        Self {
+            holder: SpinLock::new(c"sleep lock", Holder { locked: false, pid: 0 }),
+            queue: WaitQueue::new(),
+            name,
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SleepLock<T> {
+    /// \brief Sleep until the lock is free, then take it.
+    #[inline(never)] // Keep a frame for lockdep's call chain.
+    pub fn lock(&self) -> SleepLockGuard<'_, T> {
+        self.lockdep_acquire(frame());
+        let mut h = self.holder.lock();
+        self.queue.wait_while(&mut h, |h| h.locked);
+        h.locked = true;
+        h.pid = mypid();
+        SleepLockGuard { lock: self }
+    }
+
+    /// \brief Like [`SleepLock::lock`], but fail with `EINTR` if the
+    /// process is killed while waiting.
+    #[inline(never)]
+    pub fn lock_interruptible(&self) -> Result<SleepLockGuard<'_, T>, Errno> {
+        self.lockdep_acquire(frame());
+        let mut h = self.holder.lock();
+        if let Err(e) = self.queue.wait_while_interruptible(&mut h, |h| h.locked) {
+            lockdep::release(self.addr(), Ctx::Proc);
+            return Err(e);
+        }
+        h.locked = true;
+        h.pid = mypid();
+        Ok(SleepLockGuard { lock: self })
+    }
+
+    /// \brief Take the lock only if it is free.
+    #[inline(never)]
+    pub fn try_lock(&self) -> Option<SleepLockGuard<'_, T>> {
+        let mut h = self.holder.lock();
+        if h.locked {
+            return None;
+        }
+        h.locked = true;
+        h.pid = mypid();
+        lockdep::acquired(self.addr(), self.key(), self.key() as *const u8, Ctx::Proc, frame());
+        Some(SleepLockGuard { lock: self })
+    }
+
+    /// \brief Whether the current process holds the lock.
+    pub fn holding(&self) -> bool {
+        let h = self.holder.lock();
+        h.locked && h.pid == mypid()
+    }
+
+    /// \brief The lock's name, for diagnostics.
+    pub fn name(&self) -> &'static CStr {
+        self.name
+    }
+
+    fn addr(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
+    /// \brief Lockdep class: sleep locks with the same name share one.
+    fn key(&self) -> *const () {
+        self.name.as_ptr() as *const ()
+    }
+
+    /// \brief Checked before waiting, as waiting is what may deadlock.
+    #[inline(always)]
+    fn lockdep_acquire(&self, frame: *const c_void) {
+        lockdep::acquire(self.addr(), self.key(), self.key() as *const u8, Ctx::Proc, frame);
+    }
+}
+
+/// \brief Access to the data of a held [`SleepLock`]; releases it on drop.
+///
+/// The holder may sleep, and even move to another CPU, with the guard.
+pub struct SleepLockGuard<'a, T: ?Sized> {
+    lock: &'a SleepLock<T>,
+}
+
+impl<T: ?Sized> Deref for SleepLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SleepLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SleepLockGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut h = self.lock.holder.lock();
+        h.locked = false;
+        h.pid = 0;
+        drop(h);
+        lockdep::release(self.lock.addr(), Ctx::Proc);
+        // Wake them all: a woken waiter that was killed may leave without
+        // taking the lock.
+        self.lock.queue.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn holder_is_the_locking_process() {
+        let lock = SleepLock::new(c"test", 1u32);
+        let g = lock.try_lock().expect("free lock");
+        assert!(lock.holding());
+        assert!(thread::scope(|s| s.spawn(|| !lock.holding() && lock.try_lock().is_none()).join().unwrap()));
+        drop(g);
+        assert!(!lock.holding());
+        assert_eq!(lock.name(), c"test");
+    }
+
+    #[test]
+    fn waiters_take_turns() {
+        let lock = Arc::new(SleepLock::new(c"count", 0usize));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        *lock.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*lock.lock(), 800);
+    }
+}
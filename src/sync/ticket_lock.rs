@@ -1,9 +1,14 @@
 // #![no_std] // Removed: crate-level attribute should be in the root module
 
+use crate::proc::{mycpu, Cpu};
+use crate::spinlock::{frame, getcallerpcs, popcli, pushcli};
+use super::lockdep::{self, Ctx};
 use core::cell::UnsafeCell;
+use core::ffi::c_void;
 // use core::fmt; // Removed: unused
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicU32, Ordering}; // Changed AtomicU64 to AtomicU32
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering}; // Changed AtomicU64 to AtomicU32
 
 // QuaternionTickets struct removed as it was unused.
 
@@ -26,6 +31,18 @@ impl AtomicTicketLockState {
         self.current_ticket.load(order)
     }
 
+    // Take the next ticket only if it would be served right away.
+    fn try_fetch_ticket(&self) -> bool {
+        let current = self.current_ticket.load(Ordering::Acquire);
+        self.next_ticket
+            .compare_exchange(current, current.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.next_ticket.load(Ordering::Relaxed) != self.current_ticket.load(Ordering::Relaxed)
+    }
+
     // Atomically increment next_ticket and return the old ticket value (my_ticket)
     fn fetch_my_ticket(&self, order: Ordering) -> u32 {
         self.next_ticket.fetch_add(1, order)
@@ -42,10 +59,28 @@ impl AtomicTicketLockState {
     }
 }
 
+/// \brief The calling CPU; interrupts are disabled just long enough to ask.
+fn this_cpu() -> *mut Cpu {
+    unsafe {
+        pushcli();
+        let c = mycpu();
+        popcli();
+        c
+    }
+}
+
 // --- Ticket Lock --- (Renamed from QuaternionSpinlock)
+//
+// Like the C `struct spinlock`, a held lock records the CPU that took it
+// and the call stack at that point, for `holding()` and for the deadlock
+// report of `lock_with_spin_limit`. `lock` leaves interrupts alone, so an
+// interrupt handler that takes the same lock must only ever be run with
+// it held through `lock_irqsave`.
 #[derive(Debug)]
 pub struct TicketLock<T: ?Sized> {
     state: AtomicTicketLockState,
+    cpu: AtomicPtr<Cpu>,
+    pcs: UnsafeCell<[u32; 10]>,
     data: UnsafeCell<T>,
 }
 
@@ -62,24 +97,115 @@ impl<T> TicketLock<T> {
     pub const fn new(data: T) -> Self {
         Self {
             state: AtomicTicketLockState::new(0, 0), // Initial tickets
+            cpu: AtomicPtr::new(ptr::null_mut()),
+            pcs: UnsafeCell::new([0; 10]),
             data: UnsafeCell::new(data),
         }
     }
 }
 
 impl<T: ?Sized> TicketLock<T> {
+    /// \brief Spin until the lock is ours. Taking it twice on one CPU panics.
+    #[inline(always)]
     pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        self.lock_with_spin_limit(u32::MAX)
+    }
+
+    /// \brief Like [`TicketLock::lock`], but panic with the holder's CPU and
+    /// call stack after `limit` spins, as the lock is then probably
+    /// deadlocked. `u32::MAX` means no limit: it spins forever.
+    #[inline(never)] // Keep a frame for `getcallerpcs`.
+    pub fn lock_with_spin_limit(&self, limit: u32) -> TicketLockGuard<'_, T> {
+        // No name, so each ticket lock is a lockdep class of its own.
+        lockdep::acquire(self.addr(), self.addr(), ptr::null(), Ctx::Cpu, frame());
+        if self.holding() {
+            panic!("TicketLock: already held by this CPU");
+        }
         let my_ticket = self.state.fetch_my_ticket(Ordering::Relaxed);
+        let mut spins = 0u32;
         while self.state.load_current_ticket(Ordering::Acquire) != my_ticket {
+            if limit != u32::MAX {
+                if spins == limit {
+                    self.deadlock(limit);
+                }
+                spins += 1;
+            }
             core::hint::spin_loop();
         }
-        TicketLockGuard { lock: self }
+        self.acquired(frame());
+        TicketLockGuard { lock: self, irq: false }
+    }
+
+    /// \brief Take the lock only if nobody holds or is waiting for it.
+    #[inline(never)]
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        if !self.state.try_fetch_ticket() {
+            return None;
+        }
+        lockdep::acquired(self.addr(), self.addr(), ptr::null(), Ctx::Cpu, frame());
+        self.acquired(frame());
+        Some(TicketLockGuard { lock: self, irq: false })
+    }
+
+    /// \brief Like [`TicketLock::lock`], with interrupts disabled until the
+    /// guard is dropped.
+    #[inline(always)]
+    pub fn lock_irqsave(&self) -> TicketLockGuard<'_, T> {
+        unsafe { pushcli() };
+        let mut guard = self.lock();
+        guard.irq = true;
+        guard
+    }
+
+    /// \brief Like [`TicketLock::try_lock`], with interrupts disabled until
+    /// the guard is dropped.
+    #[inline(always)]
+    pub fn try_lock_irqsave(&self) -> Option<TicketLockGuard<'_, T>> {
+        unsafe { pushcli() };
+        match self.try_lock() {
+            Some(mut guard) => {
+                guard.irq = true;
+                Some(guard)
+            }
+            None => {
+                unsafe { popcli() };
+                None
+            }
+        }
+    }
+
+    /// \brief Whether this CPU holds the lock.
+    pub fn holding(&self) -> bool {
+        self.state.is_locked() && self.cpu.load(Ordering::Relaxed) == this_cpu()
+    }
+
+    fn addr(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
+    /// \brief Record this CPU and the call stack above `frame` as the holder.
+    fn acquired(&self, frame: *const c_void) {
+        self.cpu.store(this_cpu(), Ordering::Relaxed);
+        unsafe { getcallerpcs(frame, (*self.pcs.get()).as_mut_ptr()) };
     }
 
-    // pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> { ... }
+    #[cold]
+    fn deadlock(&self, limit: u32) -> ! {
+        // The holder may be changing these; they are only a hint.
+        let pcs = unsafe { ptr::read_volatile(self.pcs.get()) };
+        panic!(
+            "TicketLock: no progress after {} spins; held by cpu {:p} from {:x?}",
+            limit,
+            self.cpu.load(Ordering::Relaxed),
+            pcs
+        );
+    }
 
     /// Safety: Called by TicketLockGuard::drop
     fn unlock_internal(&self) {
+        lockdep::release(self.addr(), Ctx::Cpu);
+        unsafe { (*self.pcs.get())[0] = 0 };
+        self.cpu.store(ptr::null_mut(), Ordering::Relaxed);
         self.state.release_ticket(Ordering::Release);
     }
 }
@@ -87,6 +213,7 @@ impl<T: ?Sized> TicketLock<T> {
 // --- Ticket Lock Guard --- (Renamed from QuaternionGuard)
 pub struct TicketLockGuard<'a, T: ?Sized> {
     lock: &'a TicketLock<T>,
+    irq: bool, // Taken with pushcli, to be undone on drop.
 }
 
 impl<T: ?Sized> Deref for TicketLockGuard<'_, T> {
@@ -105,7 +232,119 @@ impl<T: ?Sized> DerefMut for TicketLockGuard<'_, T> {
 impl<T: ?Sized> Drop for TicketLockGuard<'_, T> {
     fn drop(&mut self) {
         self.lock.unlock_internal();
+        if self.irq {
+            unsafe { popcli() };
+        }
     }
 }
 
-// Test module removed as per subtask instructions
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    #[test]
+    fn guard_gives_access_to_data() {
+        let lock = TicketLock::new(5u32);
+        {
+            let mut g = lock.lock();
+            *g += 1;
+        }
+        assert_eq!(*lock.lock(), 6);
+    }
+
+    #[test]
+    fn tickets_advance_on_each_acquire() {
+        let lock = TicketLock::new(());
+        for i in 0..10 {
+            let _g = lock.lock();
+            assert_eq!(lock.state.next_ticket.load(Ordering::Relaxed), i + 1);
+            assert_eq!(lock.state.current_ticket.load(Ordering::Relaxed), i);
+        }
+        assert_eq!(lock.state.current_ticket.load(Ordering::Relaxed), 10);
+    }
+
+    #[test]
+    fn default_uses_default_data() {
+        let lock: TicketLock<u64> = Default::default();
+        assert_eq!(*lock.lock(), 0);
+    }
+
+    #[test]
+    fn contended_increments_are_not_lost() {
+        let lock = Arc::new(TicketLock::new(0usize));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        *lock.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*lock.lock(), 4_000);
+    }
+
+    #[test]
+    fn try_lock_fails_while_held() {
+        let lock = TicketLock::new(1u8);
+        let g = lock.try_lock().expect("free lock");
+        assert!(lock.try_lock().is_none());
+        drop(g);
+        assert_eq!(*lock.try_lock().expect("released lock"), 1);
+    }
+
+    #[test]
+    fn holding_is_per_cpu() {
+        let lock = Arc::new(TicketLock::new(()));
+        let g = lock.lock_irqsave();
+        assert!(lock.holding());
+        let other = Arc::clone(&lock);
+        assert!(!thread::spawn(move || other.holding()).join().unwrap());
+        drop(g);
+        assert!(!lock.holding());
+    }
+
+    #[test]
+    #[should_panic(expected = "already held")]
+    fn relocking_on_the_same_cpu_panics() {
+        let lock = TicketLock::new(());
+        let _g = lock.lock();
+        let _h = lock.lock();
+    }
+
+    #[test]
+    fn spin_limit_reports_the_holder() {
+        let lock = Arc::new(TicketLock::new(()));
+        let (held_tx, held_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let holder = {
+            let lock = Arc::clone(&lock);
+            thread::spawn(move || {
+                let _g = lock.lock();
+                held_tx.send(()).unwrap();
+                done_rx.recv().unwrap();
+            })
+        };
+        held_rx.recv().unwrap();
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            lock.lock_with_spin_limit(1_000);
+        }))
+        .unwrap_err();
+        assert!(err.downcast_ref::<String>().unwrap().contains("held by cpu"));
+        done_tx.send(()).unwrap();
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn unsized_data_through_guard() {
+        let lock: &TicketLock<[u8]> = &TicketLock::new([1u8, 2, 3]);
+        lock.lock()[1] = 9;
+        assert_eq!(&*lock.lock(), &[1, 9, 3]);
+    }
+}
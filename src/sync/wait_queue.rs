@@ -0,0 +1,117 @@
+//! \file wait_queue.rs
+//! \brief Blocking until a condition protected by a [`SpinLock`] holds.
+//!
+//! A [`WaitQueue`] is a typed front end to the scheduler's `sleep` and
+//! `wakeup`: its address is the sleep channel, and waiting releases the
+//! guarded spinlock atomically with going to sleep, so no notification
+//! can be missed. Wakeups may be spurious, which is why every wait is
+//! phrased as "while the condition holds".
+
+use super::primitives::SpinLockGuard;
+use crate::errno::Errno;
+use crate::proc::{myproc, sleep, wakeup, wakeup_one};
+use core::ffi::c_void;
+
+/// \brief Processes waiting for something to change.
+pub struct WaitQueue {
+    // Gives each queue its own address, which is its sleep channel.
+    _chan: u8,
+}
+
+/// \brief A [`WaitQueue`] used the way `std::sync::Condvar` is.
+pub type Condvar = WaitQueue;
+
+impl WaitQueue {
+    /// \brief A queue nobody waits on, suitable for a `static`.
+    pub const fn new() -> Self {
+        Self { _chan: 0 }
+    }
+
+    fn chan(&self) -> *const c_void {
+        self as *const Self as *const c_void
+    }
+
+    /// \brief Sleep until notified, with the guarded lock released meanwhile.
+    ///
+    /// The lock is held again on return. Must be called by a process.
+    pub fn wait<T: ?Sized>(&self, guard: &mut SpinLockGuard<'_, T>) {
+        unsafe { sleep(self.chan(), guard.raw()) };
+    }
+
+    /// \brief Sleep for as long as `cond` holds for the guarded data.
+    pub fn wait_while<T: ?Sized>(&self, guard: &mut SpinLockGuard<'_, T>, mut cond: impl FnMut(&mut T) -> bool) {
+        while cond(guard) {
+            self.wait(guard);
+        }
+    }
+
+    /// \brief Like [`WaitQueue::wait_while`], but give up with `EINTR`
+    /// once the current process has been killed.
+    ///
+    /// `kill` wakes a sleeping process, so the wait ends promptly.
+    pub fn wait_while_interruptible<T: ?Sized>(
+        &self,
+        guard: &mut SpinLockGuard<'_, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> Result<(), Errno> {
+        while cond(guard) {
+            if unsafe { (*myproc()).killed } != 0 {
+                return Err(Errno::EINTR);
+            }
+            self.wait(guard);
+        }
+        Ok(())
+    }
+
+    /// \brief Wake one waiting process, if any; returns whether one was.
+    pub fn notify_one(&self) -> bool {
+        unsafe { wakeup_one(self.chan()) }
+    }
+
+    /// \brief Wake every waiting process.
+    pub fn notify_all(&self) {
+        unsafe { wakeup(self.chan()) };
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::SpinLock;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn wait_while_sees_the_notified_change() {
+        let state = Arc::new((SpinLock::new(c"flag", false), WaitQueue::new()));
+        let waiter = {
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                let (lock, queue) = &*state;
+                let mut g = lock.lock();
+                queue.wait_while(&mut g, |ready| !*ready);
+                assert!(*g);
+            })
+        };
+        *state.0.lock() = true;
+        state.1.notify_all();
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn killed_process_stops_waiting() {
+        let lock = SpinLock::new(c"never", ());
+        let queue = WaitQueue::new();
+        let mut g = lock.lock();
+        unsafe { (*myproc()).killed = 1 };
+        assert_eq!(queue.wait_while_interruptible(&mut g, |_| true), Err(Errno::EINTR));
+        unsafe { (*myproc()).killed = 0 };
+        assert!(lock.holding());
+    }
+}
@@ -1,7 +1,464 @@
 //! \file syscall.rs
-//! \brief Rust declarations for C system call helpers.
+//! \brief System call dispatch and typed argument fetching.
+//!
+//! User code makes a system call with `INT T_SYSCALL`. The call number is in
+//! `%eax` and the arguments are on the user stack: the saved `%esp` points at
+//! the return address pushed by the C library stub, followed by the first
+//! argument. Every fetcher checks user addresses against [`Proc::sz`] before
+//! dereferencing them; the kernel can then read user memory directly because
+//! the process page table is loaded while the call runs. Strings are copied
+//! into kernel buffers before use, since a shared mapping lets another
+//! process change them at any time.
+//!
+//! Rust handlers return a [`SysResult`]; [`syscall`] translates it into the
+//! value placed in `%eax` (see [`Errno::to_user`]). Handlers still
+//! implemented in C (`sysfile.c`) keep their `int (*)(void)` signature and
+//! return `-errno` on failure; the C fetchers remain available to them
+//! through the `extern "C"` exports at the bottom of this file, and fail
+//! the same way.
 
+use crate::errno::Errno;
+use crate::fault;
+use crate::file::File;
+use crate::mmap;
+use crate::mmu::pg_round_up;
+use crate::param::NOFILE;
+use crate::proc::{myproc, Proc};
+use crate::sysfile;
+use crate::sysproc;
+use core::{mem, slice};
+
+/// \brief Result type returned by Rust system call handlers.
+///
+/// `Ok` carries the value handed back to user space; `Err` is turned into
+/// the failure return value at the trap boundary.
+pub type SysResult = Result<i32, Errno>;
+
+// System call numbers -- must match syscall.h and usys.S.
+pub const SYS_FORK: usize = 1;
+pub const SYS_EXIT: usize = 2;
+pub const SYS_WAIT: usize = 3;
+pub const SYS_PIPE: usize = 4;
+pub const SYS_READ: usize = 5;
+pub const SYS_KILL: usize = 6;
+pub const SYS_EXEC: usize = 7;
+pub const SYS_FSTAT: usize = 8;
+pub const SYS_CHDIR: usize = 9;
+pub const SYS_DUP: usize = 10;
+pub const SYS_GETPID: usize = 11;
+pub const SYS_SBRK: usize = 12;
+pub const SYS_SLEEP: usize = 13;
+pub const SYS_UPTIME: usize = 14;
+pub const SYS_OPEN: usize = 15;
+pub const SYS_WRITE: usize = 16;
+pub const SYS_MKNOD: usize = 17;
+pub const SYS_UNLINK: usize = 18;
+pub const SYS_LINK: usize = 19;
+pub const SYS_MKDIR: usize = 20;
+pub const SYS_CLOSE: usize = 21;
+pub const SYS_HALT: usize = 22;
+pub const SYS_DATE: usize = 23;
+pub const SYS_SETPRIORITY: usize = 24;
+pub const SYS_GETPRIORITY: usize = 25;
+pub const SYS_COWSTAT: usize = 26;
+pub const SYS_MMAP: usize = 27;
+pub const SYS_MUNMAP: usize = 28;
+pub const SYS_SYMLINK: usize = 29;
+pub const SYS_READLINK: usize = 30;
+pub const SYS_MOUNT: usize = 31;
+pub const SYS_UMOUNT: usize = 32;
+pub const SYS_SETAFFINITY: usize = 33;
+
+/// \brief Number of slots in the dispatch table (highest number + 1).
+const NSYSCALL: usize = SYS_SETAFFINITY + 1;
+
+// Handlers that still live in sysfile.c.
 extern "C" {
-    /// \brief Fetch an integer argument from the system call.
-    pub fn argint(n: i32, ip: *mut i32) -> i32;
+    fn sys_close() -> i32;
+    fn sys_dup() -> i32;
+    fn sys_exec() -> i32;
+    fn sys_fstat() -> i32;
+    fn sys_pipe() -> i32;
+    fn sys_read() -> i32;
+    fn sys_write() -> i32;
+}
+
+/// \brief Implementation backing one system call number.
+#[derive(Copy, Clone)]
+enum Handler {
+    /// Rust handler built on the typed argument layer.
+    Rust(unsafe fn() -> SysResult),
+    /// C handler that reports failure by returning `-errno`.
+    C(unsafe extern "C" fn() -> i32),
+}
+
+/// \brief Dispatch table entry.
+#[derive(Copy, Clone)]
+struct Syscall {
+    /// Name used in traces and diagnostics.
+    name: &'static str,
+    /// Function invoked for this number.
+    handler: Handler,
+}
+
+impl Syscall {
+    /// \brief Table entry for a Rust handler.
+    const fn rust(name: &'static str, f: unsafe fn() -> SysResult) -> Option<Self> {
+        Some(Self { name, handler: Handler::Rust(f) })
+    }
+
+    /// \brief Table entry for a C handler.
+    const fn c(name: &'static str, f: unsafe extern "C" fn() -> i32) -> Option<Self> {
+        Some(Self { name, handler: Handler::C(f) })
+    }
+
+    /// \brief Run the handler and produce the value for `%eax`.
+    unsafe fn invoke(&self) -> i32 {
+        match self.handler {
+            Handler::Rust(f) => to_user(f()),
+            Handler::C(f) => match f() {
+                v if v < 0 => match Errno::from_i32(-v) {
+                    Some(e) => e.to_user(),
+                    None => panic!("{}: bad error {}", self.name, v),
+                },
+                v => v,
+            },
+        }
+    }
+}
+
+/// \brief Build the dispatch table indexed by `SYS_*` number.
+const fn table() -> [Option<Syscall>; NSYSCALL] {
+    let mut t = [None; NSYSCALL];
+    t[SYS_FORK] = Syscall::rust("fork", sysproc::sys_fork);
+    t[SYS_EXIT] = Syscall::rust("exit", sysproc::sys_exit);
+    t[SYS_WAIT] = Syscall::rust("wait", sysproc::sys_wait);
+    t[SYS_PIPE] = Syscall::c("pipe", sys_pipe);
+    t[SYS_READ] = Syscall::c("read", sys_read);
+    t[SYS_KILL] = Syscall::rust("kill", sysproc::sys_kill);
+    t[SYS_EXEC] = Syscall::c("exec", sys_exec);
+    t[SYS_FSTAT] = Syscall::c("fstat", sys_fstat);
+    t[SYS_CHDIR] = Syscall::rust("chdir", sysfile::sys_chdir);
+    t[SYS_DUP] = Syscall::c("dup", sys_dup);
+    t[SYS_GETPID] = Syscall::rust("getpid", sysproc::sys_getpid);
+    t[SYS_SBRK] = Syscall::rust("sbrk", sysproc::sys_sbrk);
+    t[SYS_SLEEP] = Syscall::rust("sleep", sysproc::sys_sleep);
+    t[SYS_UPTIME] = Syscall::rust("uptime", sysproc::sys_uptime);
+    t[SYS_OPEN] = Syscall::rust("open", sysfile::sys_open);
+    t[SYS_WRITE] = Syscall::c("write", sys_write);
+    t[SYS_MKNOD] = Syscall::rust("mknod", sysfile::sys_mknod);
+    t[SYS_UNLINK] = Syscall::rust("unlink", sysfile::sys_unlink);
+    t[SYS_LINK] = Syscall::rust("link", sysfile::sys_link);
+    t[SYS_MKDIR] = Syscall::rust("mkdir", sysfile::sys_mkdir);
+    t[SYS_CLOSE] = Syscall::c("close", sys_close);
+    t[SYS_HALT] = Syscall::rust("halt", sysproc::sys_halt);
+    #[cfg(feature = "sched_mlfq")]
+    {
+        t[SYS_SETPRIORITY] = Syscall::rust("setpriority", sysproc::sys_setpriority);
+        t[SYS_GETPRIORITY] = Syscall::rust("getpriority", sysproc::sys_getpriority);
+    }
+    t[SYS_COWSTAT] = Syscall::rust("cowstat", sysproc::sys_cowstat);
+    t[SYS_MMAP] = Syscall::rust("mmap", sysproc::sys_mmap);
+    t[SYS_MUNMAP] = Syscall::rust("munmap", sysproc::sys_munmap);
+    t[SYS_SYMLINK] = Syscall::rust("symlink", sysproc::sys_symlink);
+    t[SYS_READLINK] = Syscall::rust("readlink", sysproc::sys_readlink);
+    t[SYS_MOUNT] = Syscall::rust("mount", sysproc::sys_mount);
+    t[SYS_UMOUNT] = Syscall::rust("umount", sysproc::sys_umount);
+    t[SYS_SETAFFINITY] = Syscall::rust("setaffinity", sysproc::sys_setaffinity);
+    t
+}
+
+/// \brief System call dispatch table.
+static SYSCALLS: [Option<Syscall>; NSYSCALL] = table();
+
+/// \brief Translate a handler result into the user-visible return value.
+#[inline]
+fn to_user(r: SysResult) -> i32 {
+    match r {
+        Ok(v) => v,
+        Err(e) => e.to_user(),
+    }
+}
+
+/// \brief Entry point from `trap()` for `T_SYSCALL`.
+///
+/// Looks up the number in `%eax`, runs the handler and stores its return
+/// value back into `%eax` of the saved trap frame.
+///
+/// # Safety
+/// Must be called by `trap()` for the current process, whose `tf` is the
+/// trap frame of the system call.
+#[no_mangle]
+pub unsafe extern "C" fn syscall() {
+    let curproc = &*myproc();
+    let tf = curproc.tf;
+    let num = (*tf).eax as usize;
+    let ret = match SYSCALLS.get(num) {
+        Some(Some(call)) => {
+            let ret = call.invoke();
+            if cfg!(feature = "print_syscalls") {
+                println!("{} -> {}", call.name, ret);
+            }
+            ret
+        }
+        _ => {
+            println!("{} {}: unknown sys call {}", curproc.pid, curproc.name(), num);
+            Errno::ENOSYS.to_user()
+        }
+    };
+    (*tf).eax = ret as u32;
+}
+
+// --- Typed argument layer ---
+
+/// \brief End of the block of process memory holding `addr`.
+///
+/// Process memory is `[0, sz)` plus the regions set up by `mmap`, which
+/// must allow the access (a write when `write` is set). Like the C
+/// fetchers, an address past the end is rejected even for an empty range.
+fn user_limit(p: &Proc, addr: u32, write: bool) -> Result<u32, Errno> {
+    if addr < p.sz {
+        return Ok(p.sz);
+    }
+    match mmap::covering(&p.vmas, addr, 0) {
+        Some(v) if mmap::allows(v.prot, write) => Ok(v.end()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// \brief Check that `[addr, addr + len)` lies inside one block of process memory.
+#[inline]
+fn check_user_range(p: &Proc, addr: u32, len: u32, write: bool) -> Result<(), Errno> {
+    match addr.checked_add(len) {
+        Some(end) if end <= user_limit(p, addr, write)? => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// \brief Check a range of the current process and map any missing pages.
+///
+/// After this the kernel can touch the range without faulting on a lazy
+/// heap page, an unfilled `mmap` page or, for a `write`, a copy-on-write
+/// page.
+unsafe fn user_range(addr: u32, len: u32, write: bool) -> Result<(), Errno> {
+    let p = &*myproc();
+    check_user_range(p, addr, len, write)?;
+    fault::populate(p, addr, len, write)
+}
+
+/// \brief Fetch the 32-bit word at user address `addr`.
+///
+/// # Safety
+/// Must be called from a system call with the current process's page
+/// table installed.
+pub unsafe fn fetch_u32(addr: u32) -> Result<u32, Errno> {
+    user_range(addr, 4, false)?;
+    Ok(core::ptr::read_unaligned(addr as usize as *const u32))
+}
+
+/// \brief Copy the NUL-terminated string at user address `addr`, NUL
+/// included, into `buf`.
+///
+/// Returns the copy without its terminator. Fails with `ENAMETOOLONG` if
+/// the string and its NUL do not fit in `buf`.
+///
+/// # Safety
+/// Same requirements as [`fetch_u32`].
+pub unsafe fn fetch_cstr(addr: u32, buf: &mut [u8]) -> Result<&[u8], Errno> {
+    let p = &*myproc();
+    let limit = user_limit(p, addr, false)?;
+    // Copy a page at a time so only the pages the string spans get mapped.
+    let mut start = addr;
+    let mut len = 0;
+    while start < limit {
+        let end = pg_round_up(start + 1).min(limit);
+        fault::populate(p, start, end - start, false)?;
+        let chunk = slice::from_raw_parts(start as usize as *const u8, (end - start) as usize);
+        for &b in chunk {
+            *buf.get_mut(len).ok_or(Errno::ENAMETOOLONG)? = b;
+            if b == 0 {
+                return Ok(&buf[..len]);
+            }
+            len += 1;
+        }
+        start = end;
+    }
+    Err(Errno::EFAULT)
+}
+
+/// \brief Fetch the `n`th word-sized argument without interpreting it.
+///
+/// # Safety
+/// Same requirements as [`fetch_u32`].
+pub unsafe fn arg_raw(n: usize) -> Result<u32, Errno> {
+    let esp = (*(*myproc()).tf).esp;
+    let off = (4 + 4 * n) as u32;
+    fetch_u32(esp.checked_add(off).ok_or(Errno::EFAULT)?)
+}
+
+/// \brief Fetch the `n`th argument as a signed integer.
+///
+/// # Safety
+/// Same requirements as [`fetch_u32`].
+pub unsafe fn arg_i32(n: usize) -> Result<i32, Errno> {
+    arg_raw(n).map(|v| v as i32)
+}
+
+/// \brief Fetch the `n`th argument as a pointer to `len` values of `T`.
+///
+/// The whole range must lie inside writeable process memory. User pointers
+/// carry no alignment guarantee, so callers should access the target with
+/// unaligned reads and writes unless `T` has alignment 1.
+///
+/// # Safety
+/// Same requirements as [`fetch_u32`].
+pub unsafe fn arg_ptr<T>(n: usize, len: usize) -> Result<*mut T, Errno> {
+    arg_range::<T>(n, len, true).map(|addr| addr as usize as *mut T)
+}
+
+/// \brief Like [`arg_ptr`] for a range the kernel only reads.
+///
+/// The range may lie in a read-only `mmap` region.
+///
+/// # Safety
+/// Same requirements as [`fetch_u32`].
+pub unsafe fn arg_ptr_ro<T>(n: usize, len: usize) -> Result<*const T, Errno> {
+    arg_range::<T>(n, len, false).map(|addr| addr as usize as *const T)
+}
+
+unsafe fn arg_range<T>(n: usize, len: usize, write: bool) -> Result<u32, Errno> {
+    let addr = arg_raw(n)?;
+    let bytes = len
+        .checked_mul(mem::size_of::<T>())
+        .and_then(|b| u32::try_from(b).ok())
+        .ok_or(Errno::EFAULT)?;
+    user_range(addr, bytes, write)?;
+    Ok(addr)
+}
+
+/// \brief Copy the `n`th argument, a NUL-terminated string, into `buf`;
+/// see [`fetch_cstr`].
+///
+/// # Safety
+/// Same requirements as [`fetch_u32`].
+pub unsafe fn arg_cstr(n: usize, buf: &mut [u8]) -> Result<&[u8], Errno> {
+    fetch_cstr(arg_raw(n)?, buf)
+}
+
+/// \brief Fetch the `n`th argument as an open file descriptor.
+///
+/// # Safety
+/// Same requirements as [`fetch_u32`]; the reference is valid while the
+/// descriptor stays open.
+pub unsafe fn arg_fd(n: usize) -> Result<&'static File, Errno> {
+    let fd = arg_i32(n)?;
+    if fd < 0 || fd as usize >= NOFILE {
+        return Err(Errno::EBADF);
+    }
+    (*myproc()).ofile[fd as usize].as_ref().ok_or(Errno::EBADF)
+}
+
+// --- C ABI fetchers for sysfile.c and exec ---
+
+/// \brief Fetch the int at `addr` from the current process.
+///
+/// # Safety
+/// Must run in a process; `ip` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn fetchint(addr: u32, ip: *mut i32) -> i32 {
+    match fetch_u32(addr) {
+        Ok(v) => {
+            *ip = v as i32;
+            0
+        }
+        Err(e) => -(e as i32),
+    }
+}
+
+/// \brief Copy the NUL-terminated string at `addr` into the `max` bytes
+/// at `buf`.
+///
+/// Returns the length of the string, not including the NUL.
+///
+/// # Safety
+/// Must run in a process; `buf` must be writable for `max` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fetchstr(addr: u32, buf: *mut u8, max: i32) -> i32 {
+    if max < 0 {
+        return -(Errno::EINVAL as i32);
+    }
+    match fetch_cstr(addr, slice::from_raw_parts_mut(buf, max as usize)) {
+        Ok(s) => s.len() as i32,
+        Err(e) => -(e as i32),
+    }
+}
+
+/// \brief Fetch the `n`th 32-bit system call argument.
+///
+/// # Safety
+/// Must run in a process inside a system call; `ip` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn argint(n: i32, ip: *mut i32) -> i32 {
+    match arg_i32(n as usize) {
+        Ok(v) => {
+            *ip = v;
+            0
+        }
+        Err(e) => -(e as i32),
+    }
+}
+
+/// \brief Fetch the `n`th argument as a pointer to `size` writeable bytes.
+///
+/// # Safety
+/// Must run in a process inside a system call; `pp` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn argptr(n: i32, pp: *mut *mut u8, size: i32) -> i32 {
+    if size < 0 {
+        return -(Errno::EINVAL as i32);
+    }
+    match arg_ptr::<u8>(n as usize, size as usize) {
+        Ok(p) => {
+            *pp = p;
+            0
+        }
+        Err(e) => -(e as i32),
+    }
+}
+
+/// \brief Fetch the `n`th argument as a pointer to `size` bytes to read.
+///
+/// # Safety
+/// Must run in a process inside a system call; `pp` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn argptr_ro(n: i32, pp: *mut *const u8, size: i32) -> i32 {
+    if size < 0 {
+        return -(Errno::EINVAL as i32);
+    }
+    match arg_ptr_ro::<u8>(n as usize, size as usize) {
+        Ok(p) => {
+            *pp = p;
+            0
+        }
+        Err(e) => -(e as i32),
+    }
+}
+
+/// \brief Copy the `n`th argument, a string, into the `max` bytes at
+/// `buf`.
+///
+/// Returns the length of the string, not including the NUL.
+///
+/// # Safety
+/// Must run in a process inside a system call; `buf` must be writable for
+/// `max` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn argstr(n: i32, buf: *mut u8, max: i32) -> i32 {
+    if max < 0 {
+        return -(Errno::EINVAL as i32);
+    }
+    match arg_cstr(n as usize, slice::from_raw_parts_mut(buf, max as usize)) {
+        Ok(s) => s.len() as i32,
+        Err(e) => -(e as i32),
+    }
 }
@@ -0,0 +1,374 @@
+//! \file sysfile.rs
+//! \brief File system calls that name a path: `open`, `mkdir`, `mknod`,
+//! `chdir`, `link` and `unlink`, moved here from `sysfile.c`.
+//!
+//! Paths are resolved with [`lookup`], so each failure keeps its own code,
+//! and the file system holding an inode is reached through [`superblock`].
+//! The calls on open descriptors, `exec` and `pipe` stay in `sysfile.c`.
+
+use crate::errno::Errno;
+use crate::file::{File, Inode, FD_INODE, T_DEV, T_DIR, T_FILE, T_SYMLINK};
+use crate::fs::{Dirent, DIRSIZ};
+use crate::namei::{lookup, MAXPATH};
+use crate::proc::myproc;
+use crate::syscall::{arg_cstr, arg_i32, SysResult};
+use crate::vfs::mount::MOUNTS;
+use crate::vfs::{dirlink, dirlookup, ilock, iput, iunlock, iunlockput, iupdate, readi, superblock, writei};
+use core::mem::size_of;
+use core::ptr;
+use zerocopy::IntoBytes;
+
+/// \brief `open` flag: write only.
+pub const O_WRONLY: i32 = 0x001;
+/// \brief `open` flag: read and write.
+pub const O_RDWR: i32 = 0x002;
+/// \brief `open` flag: create a missing file.
+pub const O_CREATE: i32 = 0x200;
+/// \brief `open` flag: open a link in the last element itself.
+pub const O_NOFOLLOW: i32 = 0x400;
+
+extern "C" {
+    fn begin_op();
+    fn end_op();
+    fn filealloc() -> *mut File;
+    fn fileclose(f: *mut File);
+}
+
+/// \brief Run `f` inside a file system transaction.
+unsafe fn in_op<T>(f: impl FnOnce() -> T) -> T {
+    begin_op();
+    let r = f();
+    end_op();
+    r
+}
+
+/// \brief Give `f` the lowest free descriptor of the current process,
+/// taking over the caller's reference.
+unsafe fn fdalloc(f: *mut File) -> Result<i32, Errno> {
+    let ofile = &mut (*myproc()).ofile;
+    let fd = ofile.iter().position(|f| f.is_null()).ok_or(Errno::EMFILE)?;
+    ofile[fd] = f;
+    Ok(fd as i32)
+}
+
+/// \brief Create `path` as an inode of type `itype`, returned locked.
+///
+/// An existing regular file is returned as it is when a file is asked
+/// for; if `follow` is set and `path` names a link, the file at its end
+/// is. Fails with `EEXIST` for anything else that exists, `EISDIR` for an
+/// existing directory where a file was asked for, and `EROFS` on a
+/// read-only file system.
+///
+/// # Safety
+/// Must run in a process, inside a transaction.
+unsafe fn create(path: &[u8], itype: i16, major: i16, minor: i16, follow: bool) -> Result<*mut Inode, Errno> {
+    let mut name = [0u8; DIRSIZ];
+    let dp = lookup(path, true, false, &mut name)?;
+    ilock(dp);
+
+    let ip = dirlookup(dp, name.as_ptr(), ptr::null_mut());
+    if !ip.is_null() {
+        iunlockput(dp);
+        ilock(ip);
+        let ip = if itype == T_FILE && (*ip).itype == T_SYMLINK && follow {
+            iunlockput(ip);
+            let ip = lookup(path, false, true, &mut name)?;
+            ilock(ip);
+            ip
+        } else {
+            ip
+        };
+        if itype == T_FILE && (*ip).itype == T_FILE {
+            return Ok(ip);
+        }
+        let e = if itype == T_FILE && (*ip).itype == T_DIR { Errno::EISDIR } else { Errno::EEXIST };
+        iunlockput(ip);
+        return Err(e);
+    }
+
+    let ip = match superblock(dp).alloc_inode(itype) {
+        Ok(ip) => ip,
+        Err(e) => {
+            iunlockput(dp);
+            return Err(e);
+        }
+    };
+    ilock(ip);
+    (*ip).major = major;
+    (*ip).minor = minor;
+    (*ip).nlink = 1;
+    iupdate(ip);
+
+    if itype == T_DIR {
+        // Create . and .. entries.
+        (*dp).nlink += 1; // for ".."
+        iupdate(dp);
+        // No nlink += 1 for ".": avoid cyclic ref count.
+        if dirlink(ip, c".".as_ptr().cast(), (*ip).inum) < 0 || dirlink(ip, c"..".as_ptr().cast(), (*dp).inum) < 0 {
+            panic!("create dots");
+        }
+    }
+    if dirlink(dp, name.as_ptr(), (*ip).inum) < 0 {
+        panic!("create: dirlink");
+    }
+    iunlockput(dp);
+    Ok(ip)
+}
+
+/// \brief Whether the directory `dp` holds nothing but "." and "..".
+///
+/// # Safety
+/// `dp` must be a directory locked by the caller.
+unsafe fn is_dir_empty(dp: *mut Inode) -> bool {
+    let sz = size_of::<Dirent>() as u32;
+    let mut de = Dirent::default();
+    (2 * sz..(*dp).size).step_by(sz as usize).all(|off| {
+        if readi(dp, de.as_mut_bytes().as_mut_ptr(), off, sz) != sz as i32 {
+            panic!("isdirempty: readi");
+        }
+        de.inum == 0
+    })
+}
+
+/// Opens a file.
+///
+/// Arguments: the path and the `O_*` flags of `fcntl.h`. Returns the new
+/// descriptor. Fails as path lookup does, with `EISDIR` or `ELOOP` when a
+/// directory or a link is opened for writing, `EROFS` when a file on a
+/// read-only file system is, `ENFILE` or `EMFILE` when the system or the
+/// process has no file left, and as `create` does for `O_CREATE`.
+///
+/// # Safety
+/// Must be called from the system call table for the current process.
+pub unsafe fn sys_open() -> SysResult {
+    let mut buf = [0u8; MAXPATH];
+    let path = arg_cstr(0, &mut buf)?;
+    let omode = arg_i32(1)?;
+    in_op(|| open(path, omode))
+}
+
+/// \brief The body of [`sys_open`], inside its transaction.
+unsafe fn open(path: &[u8], omode: i32) -> SysResult {
+    let follow = omode & O_NOFOLLOW == 0;
+    let write = omode & (O_WRONLY | O_RDWR) != 0;
+    let ip = if omode & O_CREATE != 0 {
+        create(path, T_FILE, 0, 0, follow)?
+    } else {
+        let mut name = [0u8; DIRSIZ];
+        let ip = lookup(path, false, follow, &mut name)?;
+        ilock(ip);
+        ip
+    };
+    let denied = match (*ip).itype {
+        T_DIR if write => Some(Errno::EISDIR),
+        T_SYMLINK if write => Some(Errno::ELOOP),
+        T_FILE if write && superblock(ip).readonly() => Some(Errno::EROFS),
+        _ => None,
+    };
+    if let Some(e) = denied {
+        iunlockput(ip);
+        return Err(e);
+    }
+
+    let f = filealloc();
+    if f.is_null() {
+        iunlockput(ip);
+        return Err(Errno::ENFILE);
+    }
+    let fd = fdalloc(f).inspect_err(|_| {
+        fileclose(f);
+        iunlockput(ip);
+    })?;
+    iunlock(ip);
+
+    (*f).itype = FD_INODE;
+    (*f).ip = ip;
+    (*f).off = 0;
+    (*f).readable = (omode & O_WRONLY == 0) as u8;
+    (*f).writable = write as u8;
+    Ok(fd)
+}
+
+/// Creates a directory.
+///
+/// Arguments: the path. Fails as path lookup does and as `create` does.
+///
+/// # Safety
+/// Must be called from the system call table for the current process.
+pub unsafe fn sys_mkdir() -> SysResult {
+    let mut buf = [0u8; MAXPATH];
+    let path = arg_cstr(0, &mut buf)?;
+    in_op(|| create(path, T_DIR, 0, 0, false).map(|ip| iunlockput(ip)))?;
+    Ok(0)
+}
+
+/// Creates a device file.
+///
+/// Arguments: the path and the device's major and minor numbers. Fails as
+/// path lookup does and as `create` does.
+///
+/// # Safety
+/// Must be called from the system call table for the current process.
+pub unsafe fn sys_mknod() -> SysResult {
+    let mut buf = [0u8; MAXPATH];
+    let path = arg_cstr(0, &mut buf)?;
+    let major = arg_i32(1)?;
+    let minor = arg_i32(2)?;
+    in_op(|| create(path, T_DEV, major as i16, minor as i16, false).map(|ip| iunlockput(ip)))?;
+    Ok(0)
+}
+
+/// Changes the working directory.
+///
+/// Arguments: the path. Fails as path lookup does, and with `ENOTDIR` if
+/// the path is not a directory.
+///
+/// # Safety
+/// Must be called from the system call table for the current process.
+pub unsafe fn sys_chdir() -> SysResult {
+    let mut buf = [0u8; MAXPATH];
+    let path = arg_cstr(0, &mut buf)?;
+    let mut name = [0u8; DIRSIZ];
+    in_op(|| {
+        let ip = lookup(path, false, true, &mut name)?;
+        ilock(ip);
+        if (*ip).itype != T_DIR {
+            iunlockput(ip);
+            return Err(Errno::ENOTDIR);
+        }
+        iunlock(ip);
+        let p = &mut *myproc();
+        iput(p.cwd);
+        p.cwd = ip;
+        Ok(0)
+    })
+}
+
+/// Creates a new path for an existing file.
+///
+/// Arguments: the existing path and the new one. Fails as path lookup
+/// does, with `EPERM` for a directory, `EROFS` on a read-only file system,
+/// `EXDEV` if the paths are on different file systems and `EEXIST` if the
+/// new path exists.
+///
+/// # Safety
+/// Must be called from the system call table for the current process.
+pub unsafe fn sys_link() -> SysResult {
+    let (mut obuf, mut nbuf) = ([0u8; MAXPATH], [0u8; MAXPATH]);
+    let old = arg_cstr(0, &mut obuf)?;
+    let new = arg_cstr(1, &mut nbuf)?;
+    in_op(|| link(old, new))?;
+    Ok(0)
+}
+
+/// \brief The body of [`sys_link`], inside its transaction.
+unsafe fn link(old: &[u8], new: &[u8]) -> Result<(), Errno> {
+    let mut name = [0u8; DIRSIZ];
+    let ip = lookup(old, false, true, &mut name)?;
+    ilock(ip);
+    let denied = if (*ip).itype == T_DIR {
+        Some(Errno::EPERM)
+    } else if superblock(ip).readonly() {
+        Some(Errno::EROFS)
+    } else {
+        None
+    };
+    if let Some(e) = denied {
+        iunlockput(ip);
+        return Err(e);
+    }
+
+    (*ip).nlink += 1;
+    iupdate(ip);
+    iunlock(ip);
+
+    let r = lookup(new, true, false, &mut name).and_then(|dp| {
+        ilock(dp);
+        let r = if (*dp).dev != (*ip).dev {
+            Err(Errno::EXDEV)
+        } else {
+            superblock(dp).inode_ops().link(dp, &name, (*ip).inum)
+        };
+        iunlockput(dp);
+        r
+    });
+    if r.is_err() {
+        ilock(ip);
+        (*ip).nlink -= 1;
+        iupdate(ip);
+        iunlockput(ip);
+    } else {
+        iput(ip);
+    }
+    r
+}
+
+/// Removes a path.
+///
+/// Arguments: the path. Fails as path lookup does, with `EINVAL` for "."
+/// or "..", `ENOTEMPTY` for a directory with entries, `EBUSY` for one with
+/// a file system mounted on it and `EROFS` on a read-only file system.
+///
+/// # Safety
+/// Must be called from the system call table for the current process.
+pub unsafe fn sys_unlink() -> SysResult {
+    let mut buf = [0u8; MAXPATH];
+    let path = arg_cstr(0, &mut buf)?;
+    in_op(|| {
+        let mut name = [0u8; DIRSIZ];
+        let dp = lookup(path, true, false, &mut name)?;
+        ilock(dp);
+        let r = unlink(dp, &name);
+        iunlockput(dp);
+        r
+    })?;
+    Ok(0)
+}
+
+/// \brief Remove the entry `name` from the directory `dp`, locked by the
+/// caller inside a transaction.
+unsafe fn unlink(dp: *mut Inode, name: &[u8; DIRSIZ]) -> Result<(), Errno> {
+    if superblock(dp).readonly() {
+        return Err(Errno::EROFS);
+    }
+    // Cannot unlink "." or "..".
+    if name[..2] == *b".\0" || name[..3] == *b"..\0" {
+        return Err(Errno::EINVAL);
+    }
+    let mut off = 0;
+    let ip = dirlookup(dp, name.as_ptr(), &mut off);
+    if ip.is_null() {
+        return Err(Errno::ENOENT);
+    }
+    ilock(ip);
+    if (*ip).nlink < 1 {
+        panic!("unlink: nlink < 1");
+    }
+    if (*ip).itype == T_DIR {
+        let busy = if !is_dir_empty(ip) {
+            Some(Errno::ENOTEMPTY)
+        } else if MOUNTS.is_covered(ip) {
+            Some(Errno::EBUSY)
+        } else {
+            None
+        };
+        if let Some(e) = busy {
+            iunlockput(ip);
+            return Err(e);
+        }
+    }
+
+    let de = Dirent::default();
+    let sz = size_of::<Dirent>() as u32;
+    if writei(dp, de.as_bytes().as_ptr(), off, sz) != sz as i32 {
+        panic!("unlink: writei");
+    }
+    if (*ip).itype == T_DIR {
+        (*dp).nlink -= 1;
+        iupdate(dp);
+    }
+    (*ip).nlink -= 1;
+    iupdate(ip);
+    iunlockput(ip);
+    Ok(())
+}
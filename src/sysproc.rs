@@ -3,108 +3,246 @@
 //!
 //! All handlers wrap core kernel primitives exposed through other modules. Each
 //! function mirrors the corresponding C implementation but leverages Rust's
-//! safety features where feasible. The module exposes C ABI symbols so the
-//! existing C kernel can invoke these handlers directly.
-use crate::proc::{exit, fork, growproc, kill, myproc, sleep, wait};
-use crate::syscall::argint;
-use crate::trap::ticks;
+//! safety features where feasible. Handlers are invoked through the dispatch
+//! table in [`crate::syscall`] and fetch their arguments with its typed
+//! accessors.
+use crate::cow::{self, CowStat};
+use crate::errno::Errno;
+use crate::fault;
+use crate::file::File;
+use crate::mmap;
+use crate::namei::{self, MAXPATH};
+use crate::proc::{exit, fork, growproc, kill, myproc, setaffinity, wait};
+#[cfg(feature = "sched_mlfq")]
+use crate::proc::{getpriority, setpriority};
+#[cfg(feature = "sched_mlfq")]
+use crate::sched::MAXPRIO;
+use crate::syscall::{arg_cstr, arg_fd, arg_i32, arg_ptr, SysResult};
+use crate::trap::{TICK, TICKS};
+use crate::vfs::mount;
 use x86::io::outw;
 
-use core::ffi::c_void;
-
 /// Creates a child process.
 ///
 /// Wraps the core `fork` routine exposed from the process module.
-/// Returns the child's PID to the parent and 0 to the child or -1 on failure.
-#[no_mangle]
-pub unsafe extern "C" fn sys_fork() -> i32 {
-    fork()
+/// Returns the child's PID to the parent and 0 to the child.
+pub unsafe fn sys_fork() -> SysResult {
+    match fork() {
+        pid if pid < 0 => Err(Errno::ENOMEM),
+        pid => Ok(pid),
+    }
 }
 
 /// Terminates the current process.
 ///
-/// This call never returns to the caller. A return value of 0 merely
-/// satisfies the C ABI expectations.
-#[no_mangle]
-pub unsafe extern "C" fn sys_exit() -> i32 {
-    exit();
-    0
+/// This call never returns to the caller.
+pub unsafe fn sys_exit() -> SysResult {
+    exit()
 }
 
 /// Sends a kill signal to a process.
 ///
-/// The PID is read from the first system call argument. If parsing fails,
-/// `-1` is returned.
-#[no_mangle]
-pub unsafe extern "C" fn sys_kill() -> i32 {
-    let mut pid: i32 = 0;
-    if argint(0, &mut pid as *mut i32) < 0 {
-        -1
-    } else {
-        kill(pid)
+/// The PID is read from the first system call argument. Fails with
+/// `ESRCH` when no process has that PID.
+pub unsafe fn sys_kill() -> SysResult {
+    let pid = arg_i32(0)?;
+    match kill(pid) {
+        r if r < 0 => Err(Errno::ESRCH),
+        r => Ok(r),
     }
 }
 
 /// Adjusts the process data segment size.
 ///
 /// The increment in bytes is read from the first system call argument. The
-/// previous segment size is returned on success or `-1` on failure.
-#[no_mangle]
-pub unsafe extern "C" fn sys_sbrk() -> i32 {
-    let mut n: i32 = 0;
-    if argint(0, &mut n as *mut i32) < 0 {
-        return -1;
+/// previous segment size is returned on success. With `lazy_sbrk` growing
+/// only reserves the range; pages are allocated when first touched.
+pub unsafe fn sys_sbrk() -> SysResult {
+    let n = arg_i32(0)?;
+    let p = myproc();
+    let addr = (*p).sz;
+    if fault::LAZY {
+        fault::grow_lazy(p, n)?;
+    } else if growproc(n) < 0 {
+        return Err(Errno::ENOMEM);
     }
-    let addr = (*myproc()).sz;
-    if growproc(n) < 0 {
-        return -1;
-    }
-    addr as i32
+    Ok(addr as i32)
 }
 
 /// Sleeps for a number of clock ticks.
 ///
 /// The duration in ticks is provided as the first system call argument.
-/// The process may be interrupted if it is killed during the sleep.
-#[no_mangle]
-pub unsafe extern "C" fn sys_sleep() -> i32 {
-    let mut n: i32 = 0;
-    if argint(0, &mut n as *mut i32) < 0 {
-        return -1;
-    }
-    let start = ticks;
-    while ((ticks - start) as i32) < n {
-        if (*myproc()).killed != 0 {
-            return -1;
-        }
-        sleep(&ticks as *const u32 as *const c_void, core::ptr::null());
-    }
-    0
+/// Fails with `EINTR` if the process is killed during the sleep.
+pub unsafe fn sys_sleep() -> SysResult {
+    let n = arg_i32(0)?;
+    let mut t = TICKS.lock();
+    let start = *t;
+    TICK.wait_while_interruptible(&mut t, |t| (t.wrapping_sub(start) as i32) < n)?;
+    Ok(0)
 }
 
 /// Waits for a child process to exit.
 ///
-/// Returns the PID of the terminated child or `-1` on failure.
-#[no_mangle]
-pub unsafe extern "C" fn sys_wait() -> i32 {
-    wait()
+/// Returns the PID of the terminated child, or `ECHILD` when there is
+/// nothing to wait for.
+pub unsafe fn sys_wait() -> SysResult {
+    match wait() {
+        pid if pid < 0 => Err(Errno::ECHILD),
+        pid => Ok(pid),
+    }
 }
 
 /// Retrieves the current process identifier.
-#[no_mangle]
-pub unsafe extern "C" fn sys_getpid() -> i32 {
-    (*myproc()).pid as i32
+pub unsafe fn sys_getpid() -> SysResult {
+    Ok((*myproc()).pid as i32)
 }
 
 /// Reports the number of ticks since boot.
-#[no_mangle]
-pub unsafe extern "C" fn sys_uptime() -> i32 {
-    ticks as i32
+pub unsafe fn sys_uptime() -> SysResult {
+    Ok(*TICKS.lock() as i32)
 }
 
 /// Powers off the machine via the QEMU "isa-debug-exit" port.
-#[no_mangle]
-pub unsafe extern "C" fn sys_halt() -> i32 {
+pub unsafe fn sys_halt() -> SysResult {
     outw(0x604, 0x0 | 0x2000);
-    0
+    Ok(0)
+}
+
+/// Sets the scheduling priority of a process.
+///
+/// Takes the PID and the new priority (0 is highest, up to `MAXPRIO`). The
+/// process's budget is refilled. Fails with `EINVAL` for an out-of-range
+/// priority and `ESRCH` when no process has that PID.
+#[cfg(feature = "sched_mlfq")]
+pub unsafe fn sys_setpriority() -> SysResult {
+    let pid = arg_i32(0)?;
+    let prio = arg_i32(1)?;
+    if !(0..=MAXPRIO as i32).contains(&prio) {
+        return Err(Errno::EINVAL);
+    }
+    if setpriority(pid, prio as u32) {
+        Ok(0)
+    } else {
+        Err(Errno::ESRCH)
+    }
+}
+
+/// Reports the scheduling priority of a process.
+///
+/// The PID is read from the first argument. Fails with `ESRCH` when no
+/// process has that PID.
+#[cfg(feature = "sched_mlfq")]
+pub unsafe fn sys_getpriority() -> SysResult {
+    let pid = arg_i32(0)?;
+    getpriority(pid).map(|p| p as i32).ok_or(Errno::ESRCH)
+}
+
+/// Restricts a process to a set of CPUs.
+///
+/// Takes the PID and a mask with bit `c` set for each CPU `c` the process
+/// may run on; a queued process moves at once, a running one when it
+/// next gives up its CPU. Fails with `EINVAL` when the mask names no
+/// online CPU and `ESRCH` when no process has that PID.
+pub unsafe fn sys_setaffinity() -> SysResult {
+    let pid = arg_i32(0)?;
+    let mask = arg_i32(1)?;
+    setaffinity(pid, mask as u32).map(|()| 0)
+}
+
+/// Reports the copy-on-write fork counters.
+///
+/// Fills the `struct cowstat` pointed to by the first argument with the
+/// counters since boot.
+pub unsafe fn sys_cowstat() -> SysResult {
+    let st = arg_ptr::<CowStat>(0, 1)?;
+    st.write_unaligned(cow::stats());
+    Ok(0)
+}
+
+/// Maps anonymous memory or part of a file into the address space.
+///
+/// Arguments: address hint (ignored), length, `PROT_*` bits, `MAP_*` bits,
+/// file descriptor (ignored with `MAP_ANONYMOUS`) and a page-aligned file
+/// offset. Returns the address of the region. Fails with `EINVAL` for bad
+/// arguments, `EBADF` for a bad descriptor, `ENODEV` if it is not a file,
+/// `EACCES` if its open mode does not permit the mapping and `ENOMEM` when
+/// no slot or address range is free. Pages are filled on first access.
+pub unsafe fn sys_mmap() -> SysResult {
+    let len = arg_i32(1)? as u32;
+    let prot = arg_i32(2)? as u32;
+    let flags = arg_i32(3)? as u32;
+    let off = arg_i32(5)? as u32;
+    let file = if flags & mmap::MAP_ANONYMOUS != 0 {
+        core::ptr::null_mut()
+    } else {
+        arg_fd(4)? as *const File as *mut File
+    };
+    mmap::mmap(&mut *myproc(), len, prot, flags, file, off).map(|addr| addr as i32)
+}
+
+/// Removes mappings in a range of the address space.
+///
+/// Takes a page-aligned address and a length. Dirty pages of shared file
+/// mappings are written back. Unmapped parts of the range are ignored.
+pub unsafe fn sys_munmap() -> SysResult {
+    let addr = arg_i32(0)? as u32;
+    let len = arg_i32(1)? as u32;
+    mmap::munmap(&mut *myproc(), addr, len).map(|()| 0)
+}
+
+/// Creates a symbolic link.
+///
+/// Arguments: the target path, stored as given, and the path of the new
+/// link. Fails with `EEXIST` if the link path exists, `ENOENT` if its
+/// directory does not or the target is empty, `ENAMETOOLONG` for a path
+/// or target that does not fit in `MAXPATH` and `EROFS` on a read-only file
+/// system.
+pub unsafe fn sys_symlink() -> SysResult {
+    let (mut tbuf, mut pbuf) = ([0u8; MAXPATH], [0u8; MAXPATH]);
+    let target = arg_cstr(0, &mut tbuf)?;
+    let path = arg_cstr(1, &mut pbuf)?;
+    namei::symlink(target, path).map(|()| 0)
+}
+
+/// Reads the target of a symbolic link.
+///
+/// Arguments: the link's path, a buffer and its size. Copies at most that
+/// many bytes of the target, without a terminating NUL, and returns the
+/// number copied. Fails with `EINVAL` if the path is not a link.
+pub unsafe fn sys_readlink() -> SysResult {
+    let mut pbuf = [0u8; MAXPATH];
+    let path = arg_cstr(0, &mut pbuf)?;
+    let n = arg_i32(2)?;
+    if n < 0 {
+        return Err(Errno::EINVAL);
+    }
+    let buf = arg_ptr::<u8>(1, n as usize)?;
+    namei::readlink(path, core::slice::from_raw_parts_mut(buf, n as usize)).map(|n| n as i32)
+}
+
+/// Mounts a file system.
+///
+/// Arguments: the device, the directory to mount it on and the name of the
+/// file system type, such as `xv6fs`. Fails with `ENODEV` for an unknown
+/// type, `ENOTDIR` if the path is not a directory and `EBUSY` if the device
+/// or the directory is already in use.
+pub unsafe fn sys_mount() -> SysResult {
+    let dev = arg_i32(0)?;
+    let (mut pbuf, mut tbuf) = ([0u8; MAXPATH], [0u8; MAXPATH]);
+    let path = arg_cstr(1, &mut pbuf)?;
+    let fstype = arg_cstr(2, &mut tbuf)?;
+    if dev < 0 {
+        return Err(Errno::ENXIO);
+    }
+    mount::mount_at(fstype, dev as u32, path).map(|()| 0)
+}
+
+/// Unmounts the file system mounted on a directory.
+///
+/// Arguments: the directory. Fails with `EINVAL` if nothing is mounted
+/// there and `EBUSY` for `/` or while files in it are in use.
+pub unsafe fn sys_umount() -> SysResult {
+    let mut pbuf = [0u8; MAXPATH];
+    let path = arg_cstr(0, &mut pbuf)?;
+    mount::umount_at(path).map(|()| 0)
 }
@@ -1,3 +1,78 @@
+//! \file trap.rs
+//! \brief The interrupt descriptor table shared by all CPUs, and the clock.
+
+use crate::sync::{SpinLock, WaitQueue};
+use core::ptr;
+#[cfg(not(feature = "hosted"))]
+use crate::{
+    mmu::{selector, GateDesc, DPL_USER, SEG_KCODE},
+    traps::T_SYSCALL,
+};
+#[cfg(not(feature = "hosted"))]
+use x86::dtables::{lidt, DescriptorTablePointer};
+
+/// \brief Timer ticks since boot, advanced by CPU 0's timer interrupt.
+///
+/// A process waiting for the count to reach some value checks it with the
+/// lock held and waits on [`TICK`], so a tick cannot slip in unnoticed.
+pub static TICKS: SpinLock<u32> = SpinLock::new(c"time", 0);
+
+/// \brief Processes waiting for [`TICKS`] to advance.
+pub static TICK: WaitQueue = WaitQueue::new();
+
+/// \brief Count one timer tick and wake everything waiting for it.
+#[no_mangle]
+pub extern "C" fn clockintr() {
+    let mut t = TICKS.lock();
+    *t = t.wrapping_add(1);
+    TICK.notify_all();
+}
+
+/// \brief Current tick count, read without the lock.
+///
+/// For callers that only need a recent value, such as the scheduler and
+/// the timer's preemption check; an aligned 32-bit load cannot tear.
+#[no_mangle]
+pub extern "C" fn ticks() -> u32 {
+    unsafe { ptr::read_volatile(TICKS.data_ptr()) }
+}
+
+#[cfg(not(feature = "hosted"))]
 extern "C" {
-    pub static ticks: u32;
+    /// Entry points of the 256 vectors, in `vectors.S`.
+    static vectors: [u32; 256];
+}
+
+/// \brief Interrupt descriptor table, filled by [`tvinit`].
+#[cfg(not(feature = "hosted"))]
+static mut IDT: [GateDesc; 256] = [GateDesc::NULL; 256];
+
+/// \brief Point every vector at its entry in `vectors.S`.
+///
+/// Hardware interrupts and exceptions use interrupt gates so they run with
+/// interrupts disabled; only `T_SYSCALL` is a trap gate, and the only one
+/// user code may raise with `int`.
+///
+/// # Safety
+/// Must run once on the boot CPU before any CPU calls [`idtinit`].
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn tvinit() {
+    let idt = &mut *ptr::addr_of_mut!(IDT);
+    let kcode = selector(SEG_KCODE, 0);
+    for (gate, &off) in idt.iter_mut().zip(vectors.iter()) {
+        *gate = GateDesc::interrupt(kcode, off, 0);
+    }
+    let sys = T_SYSCALL as usize;
+    idt[sys] = GateDesc::trap(kcode, vectors[sys], DPL_USER);
+}
+
+/// \brief Load the interrupt descriptor table on this CPU.
+///
+/// # Safety
+/// [`tvinit`] must have run.
+#[cfg(not(feature = "hosted"))]
+#[no_mangle]
+pub unsafe extern "C" fn idtinit() {
+    lidt(&DescriptorTablePointer::new_from_slice(&*ptr::addr_of!(IDT)));
 }
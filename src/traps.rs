@@ -1 +1,2 @@
 pub const IRQ_COM1: i32 = 4;
+pub const T_SYSCALL: i32 = 64;
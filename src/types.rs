@@ -1 +1,7 @@
-pub type Pde = u32;
+//! \file types.rs
+//! \brief Names for the C kernel's basic types.
+
+pub use crate::mmu::Pte;
+
+/// \brief A page directory entry (`pde_t`); same layout as a [`Pte`].
+pub type Pde = Pte;
@@ -0,0 +1,330 @@
+//! \file mod.rs
+//! \brief Virtual file system: what a file system provides, and the inode
+//! entry points the rest of the kernel calls.
+//!
+//! A file system type implements [`FileSystem`], whose `mount` attaches one
+//! device and returns its [`SuperOps`]. Those hand out the [`InodeOps`] and
+//! [`FileOps`] used on the inodes of that device. Inodes stay the shared
+//! `struct inode` of `file.h`: an inode belongs to the file system mounted
+//! on its `dev`, found in the mount table [`mount::MOUNTS`].
+//!
+//! `ilock`, `iput`, `readi`, `dirlookup` and the other functions below keep
+//! the names and signatures `fs.c` used to export, so `sysfile.c`, `file.c`,
+//! `exec.c` and the Rust callers reach whichever file system holds the
+//! inode without knowing which it is. The xv6 file system, [`xv6fs`], is
+//! the first implementation and holds the root.
+
+pub mod mount;
+#[cfg(not(feature = "hosted"))]
+pub mod xv6fs;
+
+use crate::errno::Errno;
+use crate::file::Inode;
+use crate::fs::DIRSIZ;
+use core::ptr;
+use mount::MOUNTS;
+
+/// \brief A kind of file system, named in `mount`.
+pub trait FileSystem: Sync {
+    /// \brief Name user programs pass to `mount`, such as `xv6fs`.
+    fn name(&self) -> &'static [u8];
+
+    /// \brief Attach the file system on `dev`, reading its superblock.
+    ///
+    /// # Safety
+    /// Called by the mount table only, in a process and outside any
+    /// transaction; it may sleep.
+    unsafe fn mount(&'static self, dev: u32) -> Result<&'static dyn SuperOps, Errno>;
+}
+
+/// \brief One mounted file system.
+pub trait SuperOps: Sync {
+    /// \brief The root directory, referenced and unlocked.
+    ///
+    /// # Safety
+    /// Must run in a process.
+    unsafe fn root(&self) -> *mut Inode;
+
+    /// \brief Whether changes to the file system are refused.
+    fn readonly(&self) -> bool;
+
+    /// \brief Allocate an inode of type `itype`, returned referenced and
+    /// unlocked with no links.
+    ///
+    /// # Safety
+    /// Must run inside a transaction.
+    unsafe fn alloc_inode(&self, itype: i16) -> Result<*mut Inode, Errno>;
+
+    /// \brief Whether the file system is still in use: an inode other than
+    /// `root` is referenced, or `root` is referenced by more than the mount
+    /// and the caller of `umount`.
+    ///
+    /// # Safety
+    /// Called with the mount table locked, so it must not sleep.
+    unsafe fn busy(&self, root: *mut Inode) -> bool;
+
+    /// \brief Detach the file system once [`busy`](SuperOps::busy) has
+    /// returned false and the mount table has dropped its references.
+    ///
+    /// # Safety
+    /// Must run in a process, outside any transaction.
+    unsafe fn unmount(&self);
+
+    /// \brief Operations on the inodes of this file system.
+    fn inode_ops(&self) -> &dyn InodeOps;
+
+    /// \brief Operations on the contents of its inodes.
+    fn file_ops(&self) -> &dyn FileOps;
+}
+
+/// \brief Operations on inodes; see `fs.c` for the locking and reference
+/// rules they follow.
+///
+/// Every `ip` and `dp` passed in is a referenced inode of this file system.
+pub trait InodeOps: Sync {
+    /// \brief Take another reference to `ip`.
+    ///
+    /// # Safety
+    /// May be called with spinlocks held, so it must not sleep.
+    unsafe fn dup(&self, ip: *mut Inode) -> *mut Inode;
+
+    /// \brief Lock `ip`, reading it from the device if needed.
+    ///
+    /// # Safety
+    /// Must run in a process.
+    unsafe fn lock(&self, ip: *mut Inode);
+
+    /// \brief Unlock `ip`.
+    ///
+    /// # Safety
+    /// `ip` must be locked by the caller.
+    unsafe fn unlock(&self, ip: *mut Inode);
+
+    /// \brief Drop a reference, freeing the inode if it was the last one
+    /// and it has no links.
+    ///
+    /// # Safety
+    /// `ip` must be unlocked; must run inside a transaction.
+    unsafe fn put(&self, ip: *mut Inode);
+
+    /// \brief Write the inode's fields back to the device.
+    ///
+    /// # Safety
+    /// `ip` must be locked by the caller, inside a transaction.
+    unsafe fn update(&self, ip: *mut Inode);
+
+    /// \brief Status of the inode.
+    ///
+    /// # Safety
+    /// `ip` must be locked by the caller.
+    unsafe fn stat(&self, ip: *mut Inode) -> Stat;
+
+    /// \brief Find `name` in the directory `dp`, setting `*poff` to the
+    /// entry's offset. The inode found is referenced, not locked.
+    ///
+    /// # Safety
+    /// `dp` must be a directory locked by the caller.
+    unsafe fn lookup(&self, dp: *mut Inode, name: &[u8; DIRSIZ], poff: Option<&mut u32>) -> Option<*mut Inode>;
+
+    /// \brief Add the entry `name` for inode `inum` to the directory `dp`;
+    /// fails with `EEXIST` if `name` is present.
+    ///
+    /// # Safety
+    /// `dp` must be a directory locked by the caller, inside a transaction.
+    unsafe fn link(&self, dp: *mut Inode, name: &[u8; DIRSIZ], inum: u32) -> Result<(), Errno>;
+}
+
+/// \brief Reading and writing the contents of inodes, devices included.
+pub trait FileOps: Sync {
+    /// \brief Read up to `n` bytes at `off` into `dst`, returning how many
+    /// were read.
+    ///
+    /// # Safety
+    /// `ip` must be locked by the caller and `dst` writable for `n` bytes;
+    /// it may be user memory of the current process.
+    unsafe fn read(&self, ip: *mut Inode, dst: *mut u8, off: u32, n: u32) -> Result<u32, Errno>;
+
+    /// \brief Write `n` bytes from `src` at `off`.
+    ///
+    /// # Safety
+    /// `ip` must be locked by the caller, inside a transaction, and `src`
+    /// readable for `n` bytes.
+    unsafe fn write(&self, ip: *mut Inode, src: *const u8, off: u32, n: u32) -> Result<u32, Errno>;
+}
+
+/// \brief Layout of `struct stat` in `stat.h`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    /// \brief Type of file.
+    pub itype: i16,
+    /// \brief Device holding the file.
+    pub dev: i32,
+    /// \brief Inode number.
+    pub ino: u32,
+    /// \brief Number of links to the file.
+    pub nlink: i16,
+    /// \brief Size of the file in bytes.
+    pub size: u32,
+}
+
+/// \brief File system types `mount` knows, by name.
+#[cfg(not(feature = "hosted"))]
+static FILESYSTEMS: [&dyn FileSystem; 1] = [&xv6fs::XV6FS];
+
+/// \brief The file system type called `name`.
+#[cfg(not(feature = "hosted"))]
+pub fn find(name: &[u8]) -> Option<&'static dyn FileSystem> {
+    FILESYSTEMS.iter().copied().find(|fs| fs.name() == name)
+}
+
+/// \brief Copy a C directory entry name, which ends at a NUL or after
+/// `DIRSIZ` bytes, into a NUL-padded array.
+///
+/// # Safety
+/// `name` must be readable up to its NUL or for `DIRSIZ` bytes.
+unsafe fn dir_name(name: *const u8) -> [u8; DIRSIZ] {
+    let mut out = [0u8; DIRSIZ];
+    for (i, c) in out.iter_mut().enumerate() {
+        *c = *name.add(i);
+        if *c == 0 {
+            break;
+        }
+    }
+    out
+}
+
+/// \brief The mounted file system holding `ip`.
+///
+/// # Safety
+/// `ip` must be a referenced inode.
+pub unsafe fn superblock(ip: *mut Inode) -> &'static dyn SuperOps {
+    MOUNTS.sb((*ip).dev).expect("vfs: no file system on device")
+}
+
+/// \brief Take another reference to `ip`.
+///
+/// # Safety
+/// `ip` must be a referenced inode.
+#[no_mangle]
+pub unsafe extern "C" fn idup(ip: *mut Inode) -> *mut Inode {
+    superblock(ip).inode_ops().dup(ip)
+}
+
+/// \brief Lock `ip`, reading it from its device if needed.
+///
+/// # Safety
+/// `ip` must be a referenced inode and the caller a process.
+#[no_mangle]
+pub unsafe extern "C" fn ilock(ip: *mut Inode) {
+    superblock(ip).inode_ops().lock(ip)
+}
+
+/// \brief Unlock `ip`.
+///
+/// # Safety
+/// `ip` must be locked by the caller.
+#[no_mangle]
+pub unsafe extern "C" fn iunlock(ip: *mut Inode) {
+    superblock(ip).inode_ops().unlock(ip)
+}
+
+/// \brief Drop a reference to `ip`.
+///
+/// # Safety
+/// `ip` must be a referenced, unlocked inode; must run inside a
+/// transaction.
+#[no_mangle]
+pub unsafe extern "C" fn iput(ip: *mut Inode) {
+    superblock(ip).inode_ops().put(ip)
+}
+
+/// \brief Common idiom: unlock, then put.
+///
+/// # Safety
+/// As for [`iunlock`] and [`iput`].
+#[no_mangle]
+pub unsafe extern "C" fn iunlockput(ip: *mut Inode) {
+    let ops = superblock(ip).inode_ops();
+    ops.unlock(ip);
+    ops.put(ip);
+}
+
+/// \brief Write the fields of `ip` back to its device.
+///
+/// # Safety
+/// `ip` must be locked by the caller, inside a transaction.
+#[no_mangle]
+pub unsafe extern "C" fn iupdate(ip: *mut Inode) {
+    superblock(ip).inode_ops().update(ip)
+}
+
+/// \brief Copy stat information from `ip`.
+///
+/// # Safety
+/// `ip` must be locked by the caller and `st` writable.
+#[no_mangle]
+pub unsafe extern "C" fn stati(ip: *mut Inode, st: *mut Stat) {
+    *st = superblock(ip).inode_ops().stat(ip);
+}
+
+/// \brief Read data from `ip`; returns the bytes read or `-errno`.
+///
+/// # Safety
+/// `ip` must be locked by the caller and `dst` writable for `n` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn readi(ip: *mut Inode, dst: *mut u8, off: u32, n: u32) -> i32 {
+    match superblock(ip).file_ops().read(ip, dst, off, n) {
+        Ok(n) => n as i32,
+        Err(e) => -(e as i32),
+    }
+}
+
+/// \brief Write data to `ip`; returns the bytes written or `-errno`.
+///
+/// # Safety
+/// `ip` must be locked by the caller, inside a transaction, and `src`
+/// readable for `n` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn writei(ip: *mut Inode, src: *const u8, off: u32, n: u32) -> i32 {
+    match superblock(ip).file_ops().write(ip, src, off, n) {
+        Ok(n) => n as i32,
+        Err(e) => -(e as i32),
+    }
+}
+
+/// \brief Look for `name` in the directory `dp`, setting `*poff` (if not
+/// null) to the entry's offset; returns null if it is absent.
+///
+/// # Safety
+/// `dp` must be a locked directory and `name` a directory entry name.
+#[no_mangle]
+pub unsafe extern "C" fn dirlookup(dp: *mut Inode, name: *const u8, poff: *mut u32) -> *mut Inode {
+    superblock(dp).inode_ops().lookup(dp, &dir_name(name), poff.as_mut()).unwrap_or(ptr::null_mut())
+}
+
+/// \brief Write a new directory entry (`name`, `inum`) into `dp`; returns
+/// `-EEXIST` if `name` is present.
+///
+/// # Safety
+/// `dp` must be a locked directory, inside a transaction, and `name` a
+/// directory entry name.
+#[no_mangle]
+pub unsafe extern "C" fn dirlink(dp: *mut Inode, name: *const u8, inum: u32) -> i32 {
+    match superblock(dp).inode_ops().link(dp, &dir_name(name), inum) {
+        Ok(()) => 0,
+        Err(e) => -(e as i32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dir_names_stop_at_nul_or_dirsiz() {
+        assert_eq!(unsafe { dir_name(c"..".as_ptr().cast()) }[..3], *b"..\0");
+        let long = c"a_name_longer_than_dirsiz";
+        assert_eq!(&unsafe { dir_name(long.as_ptr().cast()) }, b"a_name_longer_");
+    }
+}
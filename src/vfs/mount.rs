@@ -0,0 +1,458 @@
+//! \file mount.rs
+//! \brief The mount table: which file system is on each device, and where
+//! it is attached in the tree.
+//!
+//! Each [`Mount`] holds a reference to the root of its file system and to
+//! the directory it covers, so both stay in the inode cache and can be
+//! compared by address. Path lookup steps from a covered directory into the
+//! mounted root, and from a mounted root's `..` back to the covered
+//! directory; the root of `/` covers nothing.
+//!
+//! Readers of the table spin briefly under an [`RwLock`]. Mounting and
+//! unmounting also take a [`SleepLock`], since attaching a file system
+//! reads its device.
+
+use super::{FileSystem, SuperOps};
+use crate::errno::Errno;
+use crate::file::Inode;
+use crate::param::NMOUNT;
+use crate::sync::{RwLock, SleepLock};
+#[cfg(not(feature = "hosted"))]
+use {
+    super::{find, iput},
+    crate::file::T_DIR,
+    crate::fs::DIRSIZ,
+    crate::namei::lookup,
+    core::ptr,
+};
+
+#[cfg(not(feature = "hosted"))]
+extern "C" {
+    fn begin_op();
+    fn end_op();
+}
+
+#[cfg(feature = "hosted")]
+use crate::hosted::{begin_op, end_op};
+
+/// \brief A mounted file system.
+#[derive(Clone, Copy)]
+pub struct Mount {
+    /// \brief Device holding the file system.
+    pub dev: u32,
+    /// \brief The file system itself.
+    pub sb: &'static dyn SuperOps,
+    /// \brief Directory it is mounted on; null for `/`.
+    pub covered: *mut Inode,
+    /// \brief Its root directory.
+    pub root: *mut Inode,
+}
+
+// The inodes are referenced by the mount and only compared, or passed to
+// their file system, while the table is locked.
+unsafe impl Send for Mount {}
+unsafe impl Sync for Mount {}
+
+/// \brief Mounted file systems, at most `NMOUNT`.
+pub struct MountTable {
+    mounts: RwLock<[Option<Mount>; NMOUNT]>,
+    /// Held across `mount` and `umount`, which may sleep.
+    change: SleepLock<()>,
+}
+
+/// \brief The kernel's mount table.
+pub static MOUNTS: MountTable = MountTable::new();
+
+/// \brief The file system mounted on `dev` in `mounts`.
+fn on_dev(mounts: &[Option<Mount>], dev: u32) -> Option<&'static dyn SuperOps> {
+    mounts.iter().flatten().find(|m| m.dev == dev).map(|m| m.sb)
+}
+
+impl MountTable {
+    /// \brief An empty table.
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self { mounts: RwLock::new(c"mounts", [None; NMOUNT]), change: SleepLock::new(c"mount", ()) }
+    }
+
+    /// \brief The file system mounted on `dev`.
+    pub fn sb(&self, dev: u32) -> Option<&'static dyn SuperOps> {
+        on_dev(&*self.mounts.read(), dev)
+    }
+
+    /// \brief The root of `/`, referenced.
+    ///
+    /// # Safety
+    /// Must run in a process.
+    pub unsafe fn root(&self) -> *mut Inode {
+        let mounts = self.mounts.read();
+        let m = mounts.iter().flatten().find(|m| m.covered.is_null()).expect("vfs: no root file system");
+        m.sb.inode_ops().dup(m.root)
+    }
+
+    /// \brief Whether a file system is mounted on `ip`.
+    pub fn is_covered(&self, ip: *mut Inode) -> bool {
+        self.mounts.read().iter().flatten().any(|m| m.covered == ip)
+    }
+
+    /// \brief Step from the directory `ip` into the root of the file system
+    /// mounted on it, if any, trading the reference to `ip` for one to the
+    /// root.
+    ///
+    /// # Safety
+    /// `ip` must be referenced and unlocked; must run inside a transaction.
+    pub unsafe fn cross_down(&self, ip: *mut Inode) -> *mut Inode {
+        let root = {
+            let mounts = self.mounts.read();
+            match mounts.iter().flatten().find(|m| m.covered == ip) {
+                Some(m) => m.sb.inode_ops().dup(m.root),
+                None => return ip,
+            }
+        };
+        self.put(ip);
+        root
+    }
+
+    /// \brief Step from the root `ip` of a mounted file system back to the
+    /// directory it covers, so `..` leaves the file system; `ip` is
+    /// returned as it is otherwise.
+    ///
+    /// # Safety
+    /// As for [`cross_down`](Self::cross_down).
+    pub unsafe fn cross_up(&self, ip: *mut Inode) -> *mut Inode {
+        let covered = {
+            let mounts = self.mounts.read();
+            match mounts.iter().flatten().find(|m| m.root == ip && !m.covered.is_null()) {
+                Some(m) => {
+                    let sb = on_dev(&*mounts, (*m.covered).dev).expect("vfs: no file system on device");
+                    sb.inode_ops().dup(m.covered)
+                }
+                None => return ip,
+            }
+        };
+        self.put(ip);
+        covered
+    }
+
+    /// \brief Mount the file system `fs` of device `dev` on the directory
+    /// `covered`, or as `/` if it is null.
+    ///
+    /// On success the mount keeps the caller's reference to `covered`.
+    /// Fails with `EBUSY` if `dev` is mounted, `covered` already has a file
+    /// system on it or is the root of one, `/` is mounted twice or the
+    /// table is full, and otherwise with the error of
+    /// [`FileSystem::mount`].
+    ///
+    /// # Safety
+    /// `covered` must be a referenced, unlocked directory or null; must run
+    /// in a process, outside any transaction.
+    pub unsafe fn mount(&self, fs: &'static dyn FileSystem, dev: u32, covered: *mut Inode) -> Result<(), Errno> {
+        let _change = self.change.lock();
+        {
+            let mounts = self.mounts.read();
+            let clash = |m: &Mount| {
+                m.dev == dev
+                    || (covered.is_null() && m.covered.is_null())
+                    || (!covered.is_null() && (m.covered == covered || m.root == covered))
+            };
+            if mounts.iter().flatten().any(clash) || mounts.iter().all(Option::is_some) {
+                return Err(Errno::EBUSY);
+            }
+        }
+        let sb = fs.mount(dev)?;
+        let root = sb.root();
+        let mut mounts = self.mounts.write();
+        let slot = mounts.iter_mut().find(|m| m.is_none()).expect("mount: no free slot");
+        *slot = Some(Mount { dev, sb, covered, root });
+        Ok(())
+    }
+
+    /// \brief Unmount the file system whose root is `root`.
+    ///
+    /// The caller holds a reference to `root`, which is dropped along with
+    /// the mount's on success. Fails with `EINVAL` if `root` is not the
+    /// root of a mount, and with `EBUSY` for `/`, while another file system
+    /// is mounted inside it or while [`SuperOps::busy`].
+    ///
+    /// # Safety
+    /// `root` must be referenced and unlocked; must run in a process,
+    /// outside any transaction.
+    pub unsafe fn umount(&self, root: *mut Inode) -> Result<(), Errno> {
+        let _change = self.change.lock();
+        let m = {
+            let mut mounts = self.mounts.write();
+            let i = mounts.iter().position(|m| matches!(m, Some(m) if m.root == root)).ok_or(Errno::EINVAL)?;
+            let m = mounts[i].unwrap();
+            let nested = mounts.iter().flatten().any(|n| !n.covered.is_null() && (*n.covered).dev == m.dev);
+            if m.covered.is_null() || nested || m.sb.busy(root) {
+                return Err(Errno::EBUSY);
+            }
+            mounts[i] = None;
+            m
+        };
+        begin_op();
+        // The caller's reference to the root, then the mount's.
+        m.sb.inode_ops().put(root);
+        m.sb.inode_ops().put(m.root);
+        self.put(m.covered);
+        end_op();
+        m.sb.unmount();
+        Ok(())
+    }
+
+    /// \brief Drop a reference to `ip` through its file system.
+    unsafe fn put(&self, ip: *mut Inode) {
+        self.sb((*ip).dev).expect("vfs: no file system on device").inode_ops().put(ip);
+    }
+}
+
+/// \brief Mount the file system on `dev` as `/`, during boot.
+///
+/// # Safety
+/// Must run in the first process, before any path is looked up.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn mount_root(dev: u32) {
+    if let Err(e) = MOUNTS.mount(&super::xv6fs::XV6FS, dev, ptr::null_mut()) {
+        panic!("mount_root: {}", e.description());
+    }
+}
+
+/// \brief Mount the file system `fstype` of device `dev` on the directory
+/// `path`.
+///
+/// Fails with `ENODEV` for an unknown type, `ENOTDIR` if `path` is not a
+/// directory, and as [`MountTable::mount`] does.
+///
+/// # Safety
+/// Must run in a process, outside any transaction.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn mount_at(fstype: &[u8], dev: u32, path: &[u8]) -> Result<(), Errno> {
+    let fs = find(fstype).ok_or(Errno::ENODEV)?;
+    let mut name = [0u8; DIRSIZ];
+    begin_op();
+    let dir = lookup(path, false, true, &mut name).and_then(|ip| {
+        super::ilock(ip);
+        let isdir = (*ip).itype == T_DIR;
+        super::iunlock(ip);
+        if isdir {
+            Ok(ip)
+        } else {
+            iput(ip);
+            Err(Errno::ENOTDIR)
+        }
+    });
+    end_op();
+    let dir = dir?;
+    MOUNTS.mount(fs, dev, dir).inspect_err(|_| {
+        begin_op();
+        iput(dir);
+        end_op();
+    })
+}
+
+/// \brief Unmount the file system mounted on `path`.
+///
+/// # Safety
+/// Must run in a process, outside any transaction.
+#[cfg(not(feature = "hosted"))]
+pub unsafe fn umount_at(path: &[u8]) -> Result<(), Errno> {
+    let mut name = [0u8; DIRSIZ];
+    begin_op();
+    let root = lookup(path, false, true, &mut name);
+    end_op();
+    let root = root?;
+    MOUNTS.umount(root).inspect_err(|_| {
+        begin_op();
+        iput(root);
+        end_op();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{FileOps, InodeOps, Stat};
+    use super::*;
+    use crate::fs::DIRSIZ;
+    use core::ptr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    /// A file system of loose inodes, counting references like `fs.c`.
+    struct FakeFs {
+        dev: u32,
+        root: *mut Inode,
+        mounted: AtomicBool,
+        live: Mutex<Vec<usize>>,
+    }
+
+    unsafe impl Sync for FakeFs {}
+
+    impl FakeFs {
+        fn leak(dev: u32) -> &'static FakeFs {
+            let root = Box::into_raw(Box::new(Inode { dev, inum: 1, ..Default::default() }));
+            let live = Mutex::new(vec![root as usize]);
+            Box::leak(Box::new(FakeFs { dev, root, mounted: AtomicBool::new(false), live }))
+        }
+
+        fn inode(&self, inum: u32) -> *mut Inode {
+            let ip = Box::into_raw(Box::new(Inode { dev: self.dev, inum, refc: 1, ..Default::default() }));
+            self.live.lock().unwrap().push(ip as usize);
+            ip
+        }
+
+        fn refs(&self, ip: *mut Inode) -> i32 {
+            unsafe { (*ip).refc }
+        }
+    }
+
+    impl FileSystem for FakeFs {
+        fn name(&self) -> &'static [u8] {
+            b"fake"
+        }
+
+        unsafe fn mount(&'static self, dev: u32) -> Result<&'static dyn SuperOps, Errno> {
+            if dev != self.dev {
+                return Err(Errno::ENXIO);
+            }
+            self.mounted.store(true, Ordering::Relaxed);
+            Ok(self)
+        }
+    }
+
+    impl SuperOps for FakeFs {
+        unsafe fn root(&self) -> *mut Inode {
+            self.dup(self.root)
+        }
+
+        fn readonly(&self) -> bool {
+            false
+        }
+
+        unsafe fn alloc_inode(&self, _itype: i16) -> Result<*mut Inode, Errno> {
+            Err(Errno::ENOSPC)
+        }
+
+        unsafe fn busy(&self, root: *mut Inode) -> bool {
+            let live = self.live.lock().unwrap();
+            (*root).refc > 2 || live.iter().any(|&ip| ip != root as usize && (*(ip as *mut Inode)).refc > 0)
+        }
+
+        unsafe fn unmount(&self) {
+            self.mounted.store(false, Ordering::Relaxed);
+        }
+
+        fn inode_ops(&self) -> &dyn InodeOps {
+            self
+        }
+
+        fn file_ops(&self) -> &dyn FileOps {
+            self
+        }
+    }
+
+    impl InodeOps for FakeFs {
+        unsafe fn dup(&self, ip: *mut Inode) -> *mut Inode {
+            (*ip).refc += 1;
+            ip
+        }
+        unsafe fn lock(&self, _ip: *mut Inode) {}
+        unsafe fn unlock(&self, _ip: *mut Inode) {}
+        unsafe fn put(&self, ip: *mut Inode) {
+            assert!((*ip).refc > 0, "put: no references");
+            (*ip).refc -= 1;
+        }
+        unsafe fn update(&self, _ip: *mut Inode) {}
+        unsafe fn stat(&self, _ip: *mut Inode) -> Stat {
+            Stat::default()
+        }
+        unsafe fn lookup(&self, _dp: *mut Inode, _name: &[u8; DIRSIZ], _poff: Option<&mut u32>) -> Option<*mut Inode> {
+            None
+        }
+        unsafe fn link(&self, _dp: *mut Inode, _name: &[u8; DIRSIZ], _inum: u32) -> Result<(), Errno> {
+            Err(Errno::EROFS)
+        }
+    }
+
+    impl FileOps for FakeFs {
+        unsafe fn read(&self, _ip: *mut Inode, _dst: *mut u8, _off: u32, _n: u32) -> Result<u32, Errno> {
+            Ok(0)
+        }
+        unsafe fn write(&self, _ip: *mut Inode, _src: *const u8, _off: u32, _n: u32) -> Result<u32, Errno> {
+            Err(Errno::EROFS)
+        }
+    }
+
+    #[test]
+    fn lookup_crosses_mount_points_both_ways() {
+        let table = MountTable::new();
+        let (rootfs, usb) = (FakeFs::leak(60), FakeFs::leak(61));
+        unsafe {
+            table.mount(rootfs, 60, ptr::null_mut()).unwrap();
+            let top = table.root();
+            assert_eq!((top, rootfs.refs(top)), (rootfs.root, 2));
+            rootfs.put(top);
+
+            let dir = rootfs.inode(7);
+            table.mount(usb, 61, dir).unwrap();
+            assert!(table.is_covered(dir) && usb.mounted.load(Ordering::Relaxed));
+            assert!(table.sb(61).is_some() && table.sb(62).is_none());
+
+            // The mount keeps its reference to `dir` while lookup trades ours.
+            let ip = table.cross_down(rootfs.dup(dir));
+            assert_eq!((ip, usb.refs(ip), rootfs.refs(dir)), (usb.root, 2, 1));
+            let ip = table.cross_up(ip);
+            assert_eq!((ip, usb.refs(usb.root), rootfs.refs(dir)), (dir, 1, 2));
+            // `..` of `/` stays put, as do plain directories.
+            assert_eq!(table.cross_up(rootfs.root), rootfs.root);
+            let other = rootfs.inode(8);
+            assert_eq!((table.cross_down(other), table.cross_up(other)), (other, other));
+        }
+    }
+
+    #[test]
+    fn mount_refuses_clashes() {
+        let table = MountTable::new();
+        let (rootfs, a, b) = (FakeFs::leak(70), FakeFs::leak(71), FakeFs::leak(72));
+        unsafe {
+            table.mount(rootfs, 70, ptr::null_mut()).unwrap();
+            assert_eq!(table.mount(a, 71, ptr::null_mut()), Err(Errno::EBUSY));
+            let dir = rootfs.inode(3);
+            assert_eq!(table.mount(rootfs, 70, dir), Err(Errno::EBUSY));
+            assert_eq!(table.mount(a, 72, dir), Err(Errno::ENXIO));
+            table.mount(a, 71, dir).unwrap();
+            assert_eq!(table.mount(b, 72, dir), Err(Errno::EBUSY));
+            assert_eq!(table.mount(b, 72, a.root), Err(Errno::EBUSY));
+            assert!(!b.mounted.load(Ordering::Relaxed));
+        }
+    }
+
+    #[test]
+    fn umount_waits_until_unused() {
+        let table = MountTable::new();
+        let (rootfs, a, b) = (FakeFs::leak(80), FakeFs::leak(81), FakeFs::leak(82));
+        unsafe {
+            table.mount(rootfs, 80, ptr::null_mut()).unwrap();
+            assert_eq!(table.umount(rootfs.root), Err(Errno::EBUSY));
+            let dir = rootfs.inode(4);
+            table.mount(a, 81, dir).unwrap();
+            assert_eq!(table.umount(dir), Err(Errno::EINVAL));
+
+            // An open file inside, then a file system mounted inside.
+            let file = a.inode(9);
+            assert_eq!(table.umount(a.dup(a.root)), Err(Errno::EBUSY));
+            a.put(a.root);
+            a.put(file);
+            let sub = a.inode(5);
+            table.mount(b, 82, sub).unwrap();
+            assert_eq!(table.umount(a.dup(a.root)), Err(Errno::EBUSY));
+            a.put(a.root);
+            table.umount(b.dup(b.root)).unwrap();
+            assert_eq!((a.refs(sub), b.refs(b.root)), (0, 0));
+
+            table.umount(a.dup(a.root)).unwrap();
+            assert!(!a.mounted.load(Ordering::Relaxed) && table.sb(81).is_none());
+            assert_eq!((a.refs(a.root), rootfs.refs(dir)), (0, 0));
+            assert!(!table.is_covered(dir));
+        }
+    }
+}
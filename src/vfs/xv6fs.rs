@@ -0,0 +1,260 @@
+//! \file xv6fs.rs
+//! \brief The xv6 file system of `fs.c`, behind the VFS traits.
+//!
+//! [`XV6FS`] is the file system type. Each device mounted with it takes one
+//! of `NMOUNT` [`Xv6Super`]s, which holds the device's superblock and the
+//! log `log.c` keeps for it; `fs.c` reaches the superblock through
+//! [`xv6fs_sb`]. All of them share the inode cache of `fs.c`.
+
+use super::{FileOps, FileSystem, InodeOps, Stat, SuperOps};
+use crate::errno::Errno;
+use crate::file::{Inode, T_DEV};
+use crate::fs::{Format, Superblock, DIRSIZ, ROOTINO};
+use crate::param::NMOUNT;
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
+
+/// \brief `struct log` of `log.c`, only handled by pointer.
+#[repr(C)]
+pub struct Log {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn readsb(dev: i32, sb: *mut Superblock);
+    fn initlog(dev: i32, sb: *const Superblock) -> *mut Log;
+    fn closelog(log: *mut Log);
+    fn logdirty(dev: i32, sb: *const Superblock) -> i32;
+    fn iget(dev: u32, inum: u32) -> *mut Inode;
+    fn xv6fs_busy(dev: u32, root: *mut Inode) -> i32;
+    fn xv6fs_ialloc(dev: u32, itype: i16) -> *mut Inode;
+    fn xv6fs_idup(ip: *mut Inode) -> *mut Inode;
+    fn xv6fs_ilock(ip: *mut Inode);
+    fn xv6fs_iunlock(ip: *mut Inode);
+    fn xv6fs_iput(ip: *mut Inode);
+    fn xv6fs_iupdate(ip: *mut Inode);
+    fn xv6fs_stati(ip: *mut Inode, st: *mut Stat);
+    fn xv6fs_readi(ip: *mut Inode, dst: *mut u8, off: u32, n: u32) -> i32;
+    fn xv6fs_writei(ip: *mut Inode, src: *const u8, off: u32, n: u32) -> i32;
+    fn xv6fs_dirlookup(dp: *mut Inode, name: *const u8, poff: *mut u32) -> *mut Inode;
+    fn xv6fs_dirlink(dp: *mut Inode, name: *const u8, inum: u32) -> i32;
+}
+
+/// \brief The xv6 file system type.
+pub struct Xv6Fs;
+
+/// \brief The only instance.
+pub static XV6FS: Xv6Fs = Xv6Fs;
+
+/// \brief One mounted xv6 file system; it is its own superblock and
+/// operations.
+pub struct Xv6Super {
+    /// \brief Whether the slot holds a mount.
+    used: AtomicBool,
+    dev: AtomicU32,
+    /// \brief The device's superblock, written only while the slot is free.
+    sb: UnsafeCell<Superblock>,
+    /// \brief The device's log; null for a read-only format, which is not
+    /// logged.
+    log: AtomicPtr<Log>,
+}
+
+// Slots are filled and emptied under the mount table's change lock, and
+// `sb` does not change while `used` is set.
+unsafe impl Sync for Xv6Super {}
+
+/// \brief A slot for each file system the mount table can hold.
+static SUPERS: [Xv6Super; NMOUNT] = [const { Xv6Super::new() }; NMOUNT];
+
+impl Xv6Super {
+    const fn new() -> Self {
+        Self {
+            used: AtomicBool::new(false),
+            dev: AtomicU32::new(0),
+            sb: UnsafeCell::new(Superblock {
+                size: 0,
+                nblocks: 0,
+                ninodes: 0,
+                nlog: 0,
+                logstart: 0,
+                inodestart: 0,
+                bmapstart: 0,
+                magic: 0,
+                version: 0,
+            }),
+            log: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn dev(&self) -> u32 {
+        self.dev.load(Ordering::Relaxed)
+    }
+
+    /// \brief The device's superblock.
+    pub fn superblock(&self) -> &Superblock {
+        unsafe { &*self.sb.get() }
+    }
+
+    /// \brief The on-disk format, checked when mounting.
+    pub fn format(&self) -> Format {
+        self.superblock().format().expect("xv6fs: unknown format")
+    }
+}
+
+/// \brief The xv6 file system mounted on `dev`.
+pub fn mounted(dev: u32) -> &'static Xv6Super {
+    SUPERS
+        .iter()
+        .find(|s| s.used.load(Ordering::Acquire) && s.dev() == dev)
+        .expect("xv6fs: device not mounted")
+}
+
+/// \brief The superblock of the xv6 file system on `dev`, for `fs.c`.
+///
+/// The pointer stays valid while an inode of the file system is referenced.
+#[no_mangle]
+pub extern "C" fn xv6fs_sb(dev: u32) -> *mut Superblock {
+    mounted(dev).sb.get()
+}
+
+impl FileSystem for Xv6Fs {
+    fn name(&self) -> &'static [u8] {
+        b"xv6fs"
+    }
+
+    /// Fails with `EINVAL` if `dev` does not hold an xv6 file system of a
+    /// known format. A version 1 file system is mounted read-only, without
+    /// a log, and fails with `EROFS` if its log needs replaying, since that
+    /// would write to it.
+    unsafe fn mount(&'static self, dev: u32) -> Result<&'static dyn SuperOps, Errno> {
+        let s = SUPERS.iter().find(|s| !s.used.load(Ordering::Acquire)).ok_or(Errno::EBUSY)?;
+        let sb = &mut *s.sb.get();
+        readsb(dev as i32, sb);
+        let fmt = match sb.format() {
+            Some(fmt) if sb.fits() => fmt,
+            _ => return Err(Errno::EINVAL),
+        };
+        if !fmt.writable() && logdirty(dev as i32, sb) != 0 {
+            return Err(Errno::EROFS);
+        }
+        crate::println!(
+            "sb: size {} nblocks {} ninodes {} nlog {} logstart {} inodestart {} bmap start {}",
+            sb.size,
+            sb.nblocks,
+            sb.ninodes,
+            sb.nlog,
+            sb.logstart,
+            sb.inodestart,
+            sb.bmapstart
+        );
+        s.dev.store(dev, Ordering::Relaxed);
+        if fmt.writable() {
+            s.log.store(initlog(dev as i32, sb), Ordering::Relaxed);
+        } else {
+            crate::println!("fs: version 1 file system, mounted read-only");
+        }
+        s.used.store(true, Ordering::Release);
+        Ok(s)
+    }
+}
+
+impl SuperOps for Xv6Super {
+    unsafe fn root(&self) -> *mut Inode {
+        iget(self.dev(), ROOTINO)
+    }
+
+    fn readonly(&self) -> bool {
+        !self.format().writable()
+    }
+
+    unsafe fn alloc_inode(&self, itype: i16) -> Result<*mut Inode, Errno> {
+        if self.readonly() {
+            return Err(Errno::EROFS);
+        }
+        Ok(xv6fs_ialloc(self.dev(), itype))
+    }
+
+    unsafe fn busy(&self, root: *mut Inode) -> bool {
+        xv6fs_busy(self.dev(), root) != 0
+    }
+
+    unsafe fn unmount(&self) {
+        let log = self.log.swap(ptr::null_mut(), Ordering::Relaxed);
+        if !log.is_null() {
+            closelog(log);
+        }
+        self.used.store(false, Ordering::Release);
+    }
+
+    fn inode_ops(&self) -> &dyn InodeOps {
+        self
+    }
+
+    fn file_ops(&self) -> &dyn FileOps {
+        self
+    }
+}
+
+impl InodeOps for Xv6Super {
+    unsafe fn dup(&self, ip: *mut Inode) -> *mut Inode {
+        xv6fs_idup(ip)
+    }
+
+    unsafe fn lock(&self, ip: *mut Inode) {
+        xv6fs_ilock(ip)
+    }
+
+    unsafe fn unlock(&self, ip: *mut Inode) {
+        xv6fs_iunlock(ip)
+    }
+
+    unsafe fn put(&self, ip: *mut Inode) {
+        xv6fs_iput(ip)
+    }
+
+    unsafe fn update(&self, ip: *mut Inode) {
+        xv6fs_iupdate(ip)
+    }
+
+    unsafe fn stat(&self, ip: *mut Inode) -> Stat {
+        let mut st = Stat::default();
+        xv6fs_stati(ip, &mut st);
+        st
+    }
+
+    unsafe fn lookup(&self, dp: *mut Inode, name: &[u8; DIRSIZ], poff: Option<&mut u32>) -> Option<*mut Inode> {
+        let poff = poff.map_or(ptr::null_mut(), |p| p as *mut u32);
+        let ip = xv6fs_dirlookup(dp, name.as_ptr(), poff);
+        (!ip.is_null()).then_some(ip)
+    }
+
+    unsafe fn link(&self, dp: *mut Inode, name: &[u8; DIRSIZ], inum: u32) -> Result<(), Errno> {
+        if self.readonly() {
+            return Err(Errno::EROFS);
+        }
+        match xv6fs_dirlink(dp, name.as_ptr(), inum) {
+            0 => Ok(()),
+            _ => Err(Errno::EEXIST),
+        }
+    }
+}
+
+impl FileOps for Xv6Super {
+    unsafe fn read(&self, ip: *mut Inode, dst: *mut u8, off: u32, n: u32) -> Result<u32, Errno> {
+        match xv6fs_readi(ip, dst, off, n) {
+            n if n < 0 => Err(Errno::EIO),
+            n => Ok(n as u32),
+        }
+    }
+
+    unsafe fn write(&self, ip: *mut Inode, src: *const u8, off: u32, n: u32) -> Result<u32, Errno> {
+        if (*ip).itype != T_DEV && self.readonly() {
+            return Err(Errno::EROFS);
+        }
+        match xv6fs_writei(ip, src, off, n) {
+            n if n < 0 => Err(Errno::EIO),
+            n => Ok(n as u32),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! \file vm.rs
+//! \brief Building and tearing down page tables on top of [`crate::mmu`].
+//!
+//! Replaces `setupkvm`, `walkpgdir`, `mappages`, `allocuvm`, `deallocuvm`,
+//! `freevm`, `seginit` and `switchuvm` from `vm.c`, keeping their C
+//! signatures for the callers that are still in C. Page-table pages and user pages both come from
+//! `kalloc` through [`KernelFrames`] and are reached through the kernel's
+//! direct map of physical memory.
+//!
+//! Every page table maps the kernel like this:
+//!
+//!   KERNBASE..KERNBASE+EXTMEM: 0..EXTMEM (I/O space)
+//!   KERNLINK..data:            EXTMEM..V2P(data), kernel text and rodata
+//!   data..KERNBASE+PHYSTOP:    V2P(data)..PHYSTOP, kernel data and free memory
+//!   DEVSPACE..0:               mapped direct (devices such as the ioapic)
+//!
+//! and `0..KERNBASE` holds the process's own memory.
+
+use crate::errno::Errno;
+use crate::fpu_state;
+use crate::memlayout::{p2v, v2p, DEVSPACE, EXTMEM, KERNBASE, KERNLINK, PHYSTOP};
+use crate::mmu::{
+    pg_round_up, selector, Frames, MapError, PageDirectory, Pte, PteFlags, SegBuilder, TaskState, DPL_USER, PGSIZE,
+    SEG_KCODE, SEG_KCPU, SEG_KDATA, SEG_TSS, SEG_UCODE, SEG_UDATA,
+};
+use crate::param::KSTACKSIZE;
+use crate::proc::{cpu_index_of, mycpu, Cpu, Proc};
+use crate::simd_integration::rust_zero_page;
+use crate::spinlock::{popcli, pushcli};
+use core::ffi::{c_char, c_void};
+use core::{mem, ptr};
+use x86::dtables::{lgdt, DescriptorTablePointer};
+use x86::segmentation::{load_gs, SegmentSelector};
+
+extern "C" {
+    pub fn kalloc() -> *mut u8;
+    pub fn kfree(v: *mut u8);
+    fn panic(s: *const c_char) -> !;
+
+    /// First address of the kernel's writeable data (`kernel.ld`).
+    static data: [u8; 0];
+}
+
+const _: () = assert!(KERNBASE + PHYSTOP <= DEVSPACE, "PHYSTOP too high");
+
+/// \brief Frames from `kalloc`, reached through the kernel's direct map.
+pub struct KernelFrames;
+
+unsafe impl Frames for KernelFrames {
+    fn alloc(&mut self) -> Option<u32> {
+        // SAFETY: `kalloc` returns null or a free page, which is ours now.
+        unsafe {
+            let mem = kalloc();
+            if mem.is_null() {
+                return None;
+            }
+            rust_zero_page(mem);
+            Some(v2p(mem))
+        }
+    }
+
+    fn free(&mut self, pa: u32) {
+        if pa == 0 {
+            // SAFETY: the message is a NUL-terminated literal.
+            unsafe { panic(c"kfree".as_ptr()) }
+        }
+        // SAFETY: the caller is dropping its only use of the frame.
+        unsafe { kfree(p2v(pa)) }
+    }
+
+    fn ptr(&self, pa: u32) -> *mut u8 {
+        p2v(pa)
+    }
+}
+
+/// \brief Map `[va, va + size)` to frames from `pa` on, with `flags`.
+///
+/// Fails with `ENOMEM` when a page table cannot be allocated. Mapping over
+/// a present page is a kernel bug and panics.
+pub fn map(pgdir: &mut PageDirectory, va: u32, size: u32, pa: u32, flags: PteFlags) -> Result<(), Errno> {
+    match pgdir.map_range(va, size, pa, flags, &mut KernelFrames) {
+        Ok(()) => Ok(()),
+        Err(MapError::NoMemory) => Err(Errno::ENOMEM),
+        // SAFETY: the message is a NUL-terminated literal.
+        Err(MapError::Remap(_)) => unsafe { panic(c"remap".as_ptr()) },
+    }
+}
+
+/// \brief Return the entry of `pgdir` for `va`, or null if its page table
+/// is missing and `alloc` is 0 or cannot be allocated.
+///
+/// # Safety
+/// `pgdir` must be a page directory built by [`setupkvm`].
+#[no_mangle]
+pub unsafe extern "C" fn walkpgdir(pgdir: *mut PageDirectory, va: *const c_void, alloc: i32) -> *mut Pte {
+    PageDirectory::from_ptr(pgdir)
+        .walk(va as usize as u32, &mut KernelFrames, alloc != 0)
+        .map_or(ptr::null_mut(), |pte| pte as *mut Pte)
+}
+
+/// \brief Map `[va, va + size)` to physical memory from `pa` on.
+///
+/// `va` and `size` need not be page aligned. Returns -1 if a page table
+/// could not be allocated.
+///
+/// # Safety
+/// `pgdir` must be a page directory built by [`setupkvm`].
+#[no_mangle]
+pub unsafe extern "C" fn mappages(pgdir: *mut PageDirectory, va: *mut c_void, size: u32, pa: u32, perm: i32) -> i32 {
+    let flags = PteFlags::from_bits_retain(perm as u32);
+    match map(PageDirectory::from_ptr(pgdir), va as usize as u32, size, pa, flags) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// \brief One fixed kernel mapping present in every page table.
+struct Kmap {
+    virt: u32,
+    phys_start: u32,
+    phys_end: u32,
+    flags: PteFlags,
+}
+
+/// \brief The kernel's mappings; `data` is only known at link time.
+fn kmap() -> [Kmap; 4] {
+    let kdata = ptr::addr_of!(data) as *const u8;
+    [
+        Kmap { virt: KERNBASE, phys_start: 0, phys_end: EXTMEM, flags: PteFlags::W },
+        Kmap { virt: KERNLINK, phys_start: EXTMEM, phys_end: v2p(kdata), flags: PteFlags::empty() },
+        Kmap { virt: kdata as usize as u32, phys_start: v2p(kdata), phys_end: PHYSTOP, flags: PteFlags::W },
+        Kmap { virt: DEVSPACE, phys_start: DEVSPACE, phys_end: 0, flags: PteFlags::W },
+    ]
+}
+
+/// \brief Allocate a page directory holding only the kernel's mappings.
+///
+/// Returns null when out of memory.
+///
+/// # Safety
+/// Must be called after `kinit1`.
+#[no_mangle]
+pub unsafe extern "C" fn setupkvm() -> *mut PageDirectory {
+    let Some(pa) = KernelFrames.alloc() else {
+        return ptr::null_mut();
+    };
+    let pgdir = p2v::<PageDirectory>(pa);
+    let d = PageDirectory::from_ptr(pgdir);
+    for k in kmap() {
+        if map(d, k.virt, k.phys_end.wrapping_sub(k.phys_start), k.phys_start, k.flags).is_err() {
+            freevm(pgdir);
+            return ptr::null_mut();
+        }
+    }
+    pgdir
+}
+
+/// \brief Grow a process from `oldsz` to `newsz` bytes with zeroed pages.
+///
+/// The sizes need not be page aligned. Returns the new size, or 0 after
+/// undoing the partial growth when memory runs out or `newsz` reaches
+/// `KERNBASE`.
+///
+/// # Safety
+/// `pgdir` must be a page directory built by [`setupkvm`].
+#[no_mangle]
+pub unsafe extern "C" fn allocuvm(pgdir: *mut PageDirectory, oldsz: u32, newsz: u32) -> i32 {
+    if newsz >= KERNBASE {
+        return 0;
+    }
+    if newsz < oldsz {
+        return oldsz as i32;
+    }
+    let d = PageDirectory::from_ptr(pgdir);
+    let mut a = pg_round_up(oldsz);
+    while a < newsz {
+        let Some(pa) = KernelFrames.alloc() else {
+            println!("allocuvm out of memory");
+            d.unmap_range(oldsz, newsz, &mut KernelFrames, true);
+            return 0;
+        };
+        if map(d, a, PGSIZE, pa, PteFlags::W | PteFlags::U).is_err() {
+            println!("allocuvm out of memory (2)");
+            d.unmap_range(oldsz, newsz, &mut KernelFrames, true);
+            KernelFrames.free(pa);
+            return 0;
+        }
+        a += PGSIZE;
+    }
+    newsz as i32
+}
+
+/// \brief Shrink a process from `oldsz` to `newsz` bytes, freeing its pages.
+///
+/// The sizes need not be page aligned, `newsz` need not be below `oldsz`,
+/// and `oldsz` may exceed what is actually mapped. Returns the new size.
+///
+/// # Safety
+/// `pgdir` must be a page directory built by [`setupkvm`].
+#[no_mangle]
+pub unsafe extern "C" fn deallocuvm(pgdir: *mut PageDirectory, oldsz: u32, newsz: u32) -> i32 {
+    if newsz >= oldsz {
+        return oldsz as i32;
+    }
+    PageDirectory::from_ptr(pgdir).unmap_range(newsz, oldsz, &mut KernelFrames, true);
+    newsz as i32
+}
+
+/// \brief Free a page directory, its page tables and all user memory.
+///
+/// # Safety
+/// `pgdir` must be a page directory built by [`setupkvm`] that is not in
+/// use by any CPU.
+#[no_mangle]
+pub unsafe extern "C" fn freevm(pgdir: *mut PageDirectory) {
+    if pgdir.is_null() {
+        panic(c"freevm: no pgdir".as_ptr());
+    }
+    PageDirectory::from_ptr(pgdir).free_tables(KERNBASE, &mut KernelFrames);
+    KernelFrames.free(v2p(pgdir));
+}
+
+/// \brief Set up this CPU's kernel and user segment descriptors.
+///
+/// Run once on entry on each CPU, with interrupts disabled. All segments
+/// are flat; user code needs its own descriptors because the CPU forbids
+/// an interrupt from CPL 0 to a DPL 3 code segment. `%gs` is left pointing
+/// at this CPU's `Cpu`, for [`crate::percpu`].
+///
+/// # Safety
+/// Must run on the CPU being set up, before anything uses its segments.
+#[no_mangle]
+pub unsafe extern "C" fn seginit() {
+    let c = &mut *mycpu();
+    c.gdt[SEG_KCODE as usize] = SegBuilder::code().build();
+    c.gdt[SEG_KDATA as usize] = SegBuilder::data().build();
+    c.gdt[SEG_UCODE as usize] = SegBuilder::code().dpl(DPL_USER).build();
+    c.gdt[SEG_UDATA as usize] = SegBuilder::data().dpl(DPL_USER).build();
+    c.id = cpu_index_of(c);
+    let base = c as *mut Cpu as u32;
+    c.gdt[SEG_KCPU as usize] = SegBuilder::data().base(base).limit(mem::size_of::<Cpu>() as u32 - 1).build();
+    lgdt(&DescriptorTablePointer::new_from_slice(&c.gdt));
+    load_gs(SegmentSelector::from_raw(selector(SEG_KCPU, 0)));
+}
+
+/// \brief Switch the TSS and page table to those of process `p`.
+///
+/// # Safety
+/// `p` must be a process with a kernel stack and a page directory that
+/// is about to run, or is running, on this CPU.
+#[no_mangle]
+pub unsafe extern "C" fn switchuvm(p: *mut Proc) {
+    if p.is_null() {
+        panic(c"switchuvm: no process".as_ptr());
+    }
+    if (*p).kstack.is_null() {
+        panic(c"switchuvm: no kstack".as_ptr());
+    }
+    if (*p).pgdir.is_null() {
+        panic(c"switchuvm: no pgdir".as_ptr());
+    }
+
+    pushcli();
+    let c = &mut *mycpu();
+    let ts = ptr::addr_of!(c.ts) as u32;
+    c.gdt[SEG_TSS as usize] = SegBuilder::tss(ts, mem::size_of::<TaskState>() as u32).build();
+    c.ts.ss0 = selector(SEG_KDATA, 0);
+    c.ts.esp0 = (*p).kstack as u32 + KSTACKSIZE as u32;
+    // IOPL=0 in eflags *and* iomb beyond the TSS limit forbids I/O
+    // instructions (e.g. inb and outb) from user space.
+    c.ts.iomb = 0xFFFF;
+    fpu_state::switch_in();
+    x86::task::load_tr(SegmentSelector::from_raw(selector(SEG_TSS, 0)));
+    x86::controlregs::cr3_write(v2p((*p).pgdir) as u64);
+    popcli();
+}
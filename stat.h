@@ -2,6 +2,7 @@
 #define T_DIR 1  // Directory
 #define T_FILE 2 // File
 #define T_DEV 3  // Device
+#define T_SYMLINK 4  // Symbolic link
 
 struct stat {
   short type;  // Type of file
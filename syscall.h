@@ -23,3 +23,13 @@
 #define SYS_halt    SYS_close+1
 // student system calls begin here. Follow the existing pattern.
 #define SYS_date    SYS_halt+1
+#define SYS_setpriority SYS_date+1
+#define SYS_getpriority SYS_setpriority+1
+#define SYS_cowstat SYS_getpriority+1
+#define SYS_mmap    SYS_cowstat+1
+#define SYS_munmap  SYS_mmap+1
+#define SYS_symlink SYS_munmap+1
+#define SYS_readlink SYS_symlink+1
+#define SYS_mount SYS_readlink+1
+#define SYS_umount SYS_mount+1
+#define SYS_setaffinity SYS_umount+1
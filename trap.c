@@ -8,37 +8,7 @@
 #include "traps.h"
 #include "spinlock.h"
 
-// Interrupt descriptor table (shared by all CPUs).
-struct gatedesc idt[256];
-extern uint vectors[];  // in vectors.S: array of 256 entry pointers
-#ifdef PDX_XV6
-// set alignment to 32-bit for ticks. See Intel® 64 and IA-32 Architectures
-// Software Developer’s Manual, Vol 3A, 8.1.1 Guaranteed Atomic Operations.
-uint ticks __attribute__ ((aligned (4)));
-#else
-struct spinlock tickslock;
-uint ticks;
-#endif // PDX_XV6
-
-void
-tvinit(void)
-{
-  int i;
-
-  for(i = 0; i < 256; i++)
-    SETGATE(idt[i], 0, SEG_KCODE<<3, vectors[i], 0);
-  SETGATE(idt[T_SYSCALL], 1, SEG_KCODE<<3, vectors[T_SYSCALL], DPL_USER);
-
-#ifndef PDX_XV6
-  initlock(&tickslock, "time");
-#endif // PDX_XV6
-}
-
-void
-idtinit(void)
-{
-  lidt(idt, sizeof(idt));
-}
+// The IDT is built by tvinit and loaded by idtinit in trap.rs.
 
 //PAGEBREAK: 41
 void
@@ -56,17 +26,9 @@ trap(struct trapframe *tf)
 
   switch(tf->trapno){
   case T_IRQ0 + IRQ_TIMER:
-    if(cpuid() == 0){
-#ifdef PDX_XV6
-      atom_inc((int *)&ticks);
-      wakeup(&ticks);
-#else
-      acquire(&tickslock);
-      ticks++;
-      wakeup(&ticks);
-      release(&tickslock);
-#endif // PDX_XV6
-    }
+    // The tick count and its waiters live in trap.rs.
+    if(cpuid() == 0)
+      clockintr();
     lapiceoi();
     break;
   case T_IRQ0 + IRQ_IDE:
@@ -84,12 +46,27 @@ trap(struct trapframe *tf)
     uartintr();
     lapiceoi();
     break;
+  case T_IRQ0 + IRQ_WAKEUP:
+    // Another CPU queued work here; the scheduler loop finds it.
+    lapiceoi();
+    break;
   case T_IRQ0 + 7:
   case T_IRQ0 + IRQ_SPURIOUS:
     cprintf("cpu%d: spurious interrupt at %x:%x\n",
             cpuid(), tf->cs, tf->eip);
     lapiceoi();
     break;
+  case T_DEVICE:
+    // First FPU/SSE instruction since the last switch (CR0.TS is set).
+    fpufault();
+    break;
+  case T_PGFLT:
+    // Copy-on-write and lazily allocated pages are resolved here,
+    // whether the access came from user space or from the kernel
+    // touching user memory (CR0_WP is set).
+    if(pgfault(rcr2(), tf->err) == 0)
+      break;
+    // Otherwise it is a bad trap like any other.
 
   //PAGEBREAK: 13
   default:
@@ -117,7 +94,7 @@ trap(struct trapframe *tf)
   // If interrupts were on while locks held, would need to check nlock.
   if(myproc() && myproc()->state == RUNNING &&
 #ifdef PDX_XV6
-    tf->trapno == T_IRQ0+IRQ_TIMER && ticks%SCHED_INTERVAL==0)
+    tf->trapno == T_IRQ0+IRQ_TIMER && ticks()%SCHED_INTERVAL==0)
 #else
     tf->trapno == T_IRQ0+IRQ_TIMER)
 #endif // PDX_XV6
@@ -3,6 +3,58 @@
 #include "fcntl.h"
 #include "user.h"
 #include "x86.h"
+#include "errno.h"
+
+// Error code of the last failed system call. Only set when the kernel
+// and usys.S are built with ERRNO_ABI; otherwise it stays 0.
+int errno;
+
+static char *errstr[] = {
+  [0]            "Success",
+  [EPERM]        "Operation not permitted",
+  [ENOENT]       "No such file or directory",
+  [ESRCH]        "No such process",
+  [EINTR]        "Interrupted system call",
+  [EIO]          "I/O error",
+  [ENXIO]        "No such device or address",
+  [E2BIG]        "Argument list too long",
+  [ENOEXEC]      "Exec format error",
+  [EBADF]        "Bad file descriptor",
+  [ECHILD]       "No child processes",
+  [EAGAIN]       "Resource temporarily unavailable",
+  [ENOMEM]       "Out of memory",
+  [EACCES]       "Permission denied",
+  [EFAULT]       "Bad address",
+  [EBUSY]        "Device or resource busy",
+  [EEXIST]       "File exists",
+  [EXDEV]        "Cross-device link",
+  [ENODEV]       "No such device",
+  [ENOTDIR]      "Not a directory",
+  [EISDIR]       "Is a directory",
+  [EINVAL]       "Invalid argument",
+  [ENFILE]       "Too many open files in system",
+  [EMFILE]       "Too many open files",
+  [ENOTTY]       "Not a typewriter",
+  [EFBIG]        "File too large",
+  [ENOSPC]       "No space left on device",
+  [ESPIPE]       "Illegal seek",
+  [EROFS]        "Read-only file system",
+  [EMLINK]       "Too many links",
+  [EPIPE]        "Broken pipe",
+  [ERANGE]       "Result too large",
+  [ENAMETOOLONG] "File name too long",
+  [ENOSYS]       "Function not implemented",
+  [ENOTEMPTY]    "Directory not empty",
+  [ELOOP]        "Too many levels of symbolic links",
+};
+
+char*
+strerror(int e)
+{
+  if(e < 0 || e >= (int)(sizeof(errstr)/sizeof(errstr[0])) || errstr[e] == 0)
+    return "Unknown error";
+  return errstr[e];
+}
 
 char*
 strcpy(char *s, char *t)
@@ -81,6 +133,21 @@ stat(char *n, struct stat *st)
   return r;
 }
 
+// Like stat, but a symbolic link is described itself, not its target.
+int
+lstat(char *n, struct stat *st)
+{
+  int fd;
+  int r;
+
+  fd = open(n, O_RDONLY|O_NOFOLLOW);
+  if(fd < 0)
+    return -1;
+  r = fstat(fd, st);
+  close(fd);
+  return r;
+}
+
 #ifdef PDX_XV6
 int
 atoi(const char *s)
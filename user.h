@@ -1,5 +1,6 @@
 struct stat;
 struct rtcdate;
+struct cowstat;
 
 // system calls
 int fork(void);
@@ -24,9 +25,20 @@ char* sbrk(int);
 int sleep(int);
 int uptime(void);
 int halt(void);
+int setpriority(int pid, int priority);
+int getpriority(int pid);
+int cowstat(struct cowstat*);
+void* mmap(void*, uint, int, int, int, uint);
+int munmap(void*, uint);
+int symlink(char*, char*);
+int readlink(char*, char*, int);
+int mount(int, char*, char*);
+int umount(char*);
+int setaffinity(int pid, uint mask);
 
 // ulib.c
 int stat(char*, struct stat*);
+int lstat(char*, struct stat*);
 char* strcpy(char*, char*);
 void *memmove(void*, void*, int);
 char* strchr(const char*, char c);
@@ -39,3 +51,5 @@ void* malloc(uint);
 void free(void*);
 int atoi(const char*);
 int atoo(const char*);
+extern int errno;
+char* strerror(int);
@@ -8,6 +8,9 @@
 #include "traps.h"
 #include "memlayout.h"
 
+// blocks in writetest1's file: reaches the doubly-indirect tree
+#define BIGFILE (NDIRECT + 3*NINDIRECT)
+
 char buf[8192];
 char name[3];
 char *echoargv[] = { "echo", "ALL", "TESTS", "PASSED", 0 };
@@ -197,7 +200,7 @@ writetest1(void)
     exit();
   }
 
-  for(i = 0; i < MAXFILE; i++){
+  for(i = 0; i < BIGFILE; i++){
     ((int*)buf)[0] = i;
     if(write(fd, buf, 512) != 512){
       printf(stdout, "error: write big file failed\n", i);
@@ -217,7 +220,7 @@ writetest1(void)
   for(;;){
     i = read(fd, buf, 512);
     if(i == 0){
-      if(n == MAXFILE - 1){
+      if(n != BIGFILE){
         printf(stdout, "read only %d blocks from big", n);
         exit();
       }
@@ -760,6 +763,202 @@ linktest(void)
   printf(1, "linktest ok\n");
 }
 
+void
+symlinktest(void)
+{
+  int fd;
+  struct stat st;
+
+  printf(1, "symlinktest\n");
+
+  unlink("sf1");
+  unlink("sl1");
+  unlink("sl2");
+  unlink("sd/sf2");
+  unlink("sd");
+  unlink("sld");
+
+  fd = open("sf1", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "hello", 5) != 5){
+    printf(1, "create sf1 failed\n");
+    exit();
+  }
+  close(fd);
+
+  if(symlink("sf1", "sl1") < 0){
+    printf(1, "symlink sf1 sl1 failed\n");
+    exit();
+  }
+  if(symlink("sf1", "sl1") >= 0){
+    printf(1, "symlink over sl1 succeeded! oops\n");
+    exit();
+  }
+  fd = open("sl1", O_RDONLY);
+  if(fd < 0 || read(fd, buf, sizeof(buf)) != 5){
+    printf(1, "read through sl1 failed\n");
+    exit();
+  }
+  close(fd);
+  memset(buf, 0, sizeof(buf));
+  if(readlink("sl1", buf, sizeof(buf)) != 3 || strcmp(buf, "sf1") != 0){
+    printf(1, "readlink sl1 failed\n");
+    exit();
+  }
+  if(stat("sl1", &st) < 0 || st.type != T_FILE ||
+     lstat("sl1", &st) < 0 || st.type != T_SYMLINK || st.size != 3){
+    printf(1, "stat sl1 failed\n");
+    exit();
+  }
+  if(open("sl1", O_NOFOLLOW|O_RDWR) >= 0){
+    printf(1, "opened link sl1 for writing! oops\n");
+    exit();
+  }
+  fd = open("sl1", O_CREATE|O_RDWR);
+  if(fd < 0 || read(fd, buf, sizeof(buf)) != 5){
+    printf(1, "open-or-create through sl1 failed\n");
+    exit();
+  }
+  close(fd);
+  if(open("sl1", O_CREATE|O_NOFOLLOW|O_RDWR) >= 0){
+    printf(1, "created over link sl1! oops\n");
+    exit();
+  }
+
+  if(mkdir("sd") < 0 || symlink("sd", "sld") < 0 ||
+     (fd = open("sld/sf2", O_CREATE|O_RDWR)) < 0){
+    printf(1, "create through link sld failed\n");
+    exit();
+  }
+  close(fd);
+  if(stat("sd/sf2", &st) < 0){
+    printf(1, "sd/sf2 missing\n");
+    exit();
+  }
+
+  if(symlink("sl2", "sl2") < 0){
+    printf(1, "symlink sl2 sl2 failed\n");
+    exit();
+  }
+  if(open("sl2", O_RDONLY) >= 0){
+    printf(1, "opened link loop sl2! oops\n");
+    exit();
+  }
+
+  unlink("sf1");
+  if(open("sl1", O_RDONLY) >= 0 || lstat("sl1", &st) < 0){
+    printf(1, "dangling link sl1 wrong\n");
+    exit();
+  }
+  unlink("sl1");
+  unlink("sl2");
+  unlink("sld");
+  if(stat("sd", &st) < 0){
+    printf(1, "unlink sld removed sd\n");
+    exit();
+  }
+  unlink("sd/sf2");
+  unlink("sd");
+
+  printf(1, "symlinktest ok\n");
+}
+
+// the root file system cannot be mounted twice or unmounted,
+// and bad mounts are refused
+void
+mounttest(void)
+{
+  int fd;
+
+  printf(1, "mounttest\n");
+
+  unlink("mf");
+  unlink("md");
+  if(mkdir("md") < 0){
+    printf(1, "mkdir md failed\n");
+    exit();
+  }
+  if(mount(ROOTDEV, "md", "xv6fs") >= 0){
+    printf(1, "mounted root device twice! oops\n");
+    exit();
+  }
+  if(mount(ROOTDEV + 1, "md", "nofs") >= 0){
+    printf(1, "mounted unknown file system! oops\n");
+    exit();
+  }
+  fd = open("mf", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf(1, "create mf failed\n");
+    exit();
+  }
+  close(fd);
+  if(mount(ROOTDEV + 1, "mf", "xv6fs") >= 0){
+    printf(1, "mounted on file mf! oops\n");
+    exit();
+  }
+  if(umount("/") >= 0){
+    printf(1, "unmounted / ! oops\n");
+    exit();
+  }
+  if(umount("md") >= 0){
+    printf(1, "unmounted md with nothing mounted! oops\n");
+    exit();
+  }
+  if(open("md/../mf", O_RDONLY) < 0){
+    printf(1, "md/../mf missing\n");
+    exit();
+  }
+  unlink("mf");
+  if(unlink("md") < 0){
+    printf(1, "unlink md failed\n");
+    exit();
+  }
+
+  printf(1, "mounttest ok\n");
+}
+
+// a process can be pinned to a CPU and still runs there,
+// and masks naming no CPU or missing processes are refused
+void
+affinitytest(void)
+{
+  int i, pid;
+
+  printf(1, "affinitytest\n");
+
+  if(setaffinity(getpid(), 0) >= 0){
+    printf(1, "empty affinity mask accepted! oops\n");
+    exit();
+  }
+  if(setaffinity(-1, 1) >= 0){
+    printf(1, "affinity set for missing process! oops\n");
+    exit();
+  }
+  pid = fork();
+  if(pid < 0){
+    printf(1, "fork failed\n");
+    exit();
+  }
+  if(pid == 0){
+    if(setaffinity(getpid(), 1) < 0){
+      printf(1, "setaffinity failed\n");
+      exit();
+    }
+    for(i = 0; i < 100; i++)
+      sleep(0);
+    exit();
+  }
+  if(wait() != pid){
+    printf(1, "wait failed\n");
+    exit();
+  }
+  if(setaffinity(getpid(), ~0) < 0){
+    printf(1, "setaffinity ~0 failed\n");
+    exit();
+  }
+
+  printf(1, "affinitytest ok\n");
+}
+
 // test concurrent create/link/unlink of the same file
 void
 concreate(void)
@@ -1789,6 +1988,9 @@ main(int argc, char *argv[])
   bigfile();
   subdir();
   linktest();
+  symlinktest();
+  mounttest();
+  affinitytest();
   unlinkread();
   dirfile();
   iref();